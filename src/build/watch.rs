@@ -152,6 +152,7 @@ impl WatchMode {
         
         // Parse
         let mut parser = ShellParser::new(content, dialect)?;
+        parser.set_strict_dialect(self.args.strict_dialect);
         let ast = parser.parse()
             .context("Failed to parse script")?;
         
@@ -242,7 +243,7 @@ impl WatchMode {
             }
             ASTNode::Command { name, args, .. } if name == "source" || name == "." => {
                 if let Some(first_arg) = args.first() {
-                    if let ASTNode::String(path, _) = first_arg.as_ref() {
+                    if let ASTNode::String(path, _, _) = first_arg.as_ref() {
                         let source_path = if Path::new(path).is_relative() {
                             self.script_path.parent()
                                 .unwrap_or(Path::new("."))
@@ -257,7 +258,7 @@ impl WatchMode {
                     }
                 }
             }
-            ASTNode::If { condition, then_block, elif_blocks, else_block } => {
+            ASTNode::If { condition, then_block, elif_blocks, else_block, .. } => {
                 self.find_sourced_files_in_ast(condition, files);
                 self.find_sourced_files_in_ast(then_block, files);
                 for (cond, block) in elif_blocks {
@@ -268,7 +269,7 @@ impl WatchMode {
                     self.find_sourced_files_in_ast(block, files);
                 }
             }
-            ASTNode::While { condition, body } | ASTNode::Until { condition, body } => {
+            ASTNode::While { condition, body, .. } | ASTNode::Until { condition, body, .. } => {
                 self.find_sourced_files_in_ast(condition, files);
                 self.find_sourced_files_in_ast(body, files);
             }
@@ -70,6 +70,11 @@ pub struct Args {
     #[arg(long)]
     pub launcher: bool,
     
+    /// Fail on features the script's shell lacks, such as arrays in a
+    /// `#!/bin/sh` script, instead of warning
+    #[arg(long, global = true)]
+    pub strict_dialect: bool,
+    
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -109,7 +114,7 @@ pub fn run(args: Args) -> Result<()> {
             init_project(&name)?;
         }
         Some(Commands::Check { scripts }) => {
-            check_scripts(&scripts)?;
+            check_scripts(&scripts, args.strict_dialect)?;
         }
         Some(Commands::Features { shell }) => {
             show_features(shell.as_deref())?;
@@ -133,12 +138,14 @@ fn init_project(name: &str) -> Result<()> {
     Ok(())
 }
 
-fn check_scripts(scripts: &[PathBuf]) -> Result<()> {
+fn check_scripts(scripts: &[PathBuf], strict_dialect: bool) -> Result<()> {
     use crate::parser::{ShellParser, shell_dialect::ShellDialect};
     use crate::resolver::TerminalDetector;
     use std::fs;
     
-    for script in scripts {
+    let scripts = script_files(scripts)?;
+    let mut failed = 0;
+    for script in &scripts {
         println!("Checking {}...", script.display());
         
         let content = fs::read_to_string(script)
@@ -146,9 +153,15 @@ fn check_scripts(scripts: &[PathBuf]) -> Result<()> {
         
         let dialect = detect_shell_dialect(&content, script);
         let mut parser = ShellParser::new(content, dialect)?;
+        parser.set_strict_dialect(strict_dialect);
         
-        match parser.parse() {
-            Ok(ast) => {
+        // Report every syntax error so one run shows all that blocks conversion
+        let (ast, errors) = parser.parse_with_diagnostics();
+        for warning in parser.warnings() {
+            println!("⚠ {}", format_diagnostic(script, warning, "Warning"));
+        }
+        match errors.len() {
+            0 => {
                 println!("✓ {} - Valid {} script", script.display(), format!("{:?}", dialect));
                 
                 // Analyze terminal requirements
@@ -173,15 +186,59 @@ fn check_scripts(scripts: &[PathBuf]) -> Result<()> {
                     println!("  Dependencies: {}", deps.join(", "));
                 }
             }
-            Err(e) => {
-                println!("✗ {} - Parse error: {}", script.display(), e);
+            count => {
+                for error in &errors {
+                    println!("✗ {}", format_diagnostic(script, error, "Parse error"));
+                }
+                println!("✗ {} - {} error{}", script.display(), count, if count == 1 { "" } else { "s" });
+                failed += 1;
             }
         }
     }
     
+    if failed > 0 {
+        anyhow::bail!("{} of {} script(s) have errors", failed, scripts.len());
+    }
     Ok(())
 }
 
+/// The given scripts, with directories replaced by the shell scripts found
+/// in them (by extension, recursively, in name order).
+fn script_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    use crate::parser::shell_dialect::ShellDialect;
+    
+    let mut scripts = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            scripts.push(path.clone());
+            continue;
+        }
+        
+        let mut entries = std::fs::read_dir(path)
+            .with_context(|| format!("Failed to read directory {}", path.display()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort();
+        for entry in entries {
+            if entry.is_dir() {
+                scripts.extend(script_files(&[entry])?);
+            } else if ShellDialect::from_extension(&entry).is_some() {
+                scripts.push(entry);
+            }
+        }
+    }
+    Ok(scripts)
+}
+
+/// `script.sh:42:7 - Parse error: message`, and the hint on the next line.
+fn format_diagnostic(script: &std::path::Path, error: &crate::parser::ParseError, kind: &str) -> String {
+    let mut text = format!("{}:{} - {}: {}", script.display(), error.span, kind, error.message);
+    if let Some(hint) = &error.hint {
+        text.push_str(&format!("\n    hint: {}", hint));
+    }
+    text
+}
+
 fn show_features(shell: Option<&str>) -> Result<()> {
     use crate::parser::shell_dialect::{ShellDialect, ShellFeature};
     
@@ -223,6 +280,13 @@ fn show_features(shell: Option<&str>) -> Result<()> {
         ShellFeature::FunctionKeyword,
         ShellFeature::LocalKeyword,
         ShellFeature::SelectLoop,
+        ShellFeature::PatternSubstitution,
+        ShellFeature::AnsiCQuoting,
+        ShellFeature::SourceBuiltin,
+        ShellFeature::HereString,
+        ShellFeature::CombinedRedirect,
+        ShellFeature::PipeStderr,
+        ShellFeature::ArithmeticCommand,
     ];
     
     println!("Shell Feature Support Matrix:");
@@ -288,7 +352,19 @@ fn convert_single_file(args: &Args) -> Result<()> {
     
     // Parse the script
     let mut parser = ShellParser::new(content, dialect)?;
-    let ast = parser.parse()?;
+    parser.set_strict_dialect(args.strict_dialect);
+    let (ast, errors) = parser.parse_with_diagnostics();
+    if !args.quiet {
+        for warning in parser.warnings() {
+            eprintln!("{}", format_diagnostic(&args.input, warning, "Warning"));
+        }
+    }
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln!("{}", format_diagnostic(&args.input, error, "Parse error"));
+        }
+        anyhow::bail!("{} has {} error(s)", args.input.display(), errors.len());
+    }
     
     // Analyze terminal requirements
     let terminal_analysis = crate::resolver::TerminalDetector::analyze(&ast);
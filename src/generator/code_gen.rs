@@ -1,15 +1,51 @@
-use crate::parser::{AST, ASTNode, ast::*};
+use crate::parser::{AST, ASTNode, ShellDialect, ShellParser, ast::*, parser::unescape_text};
 use crate::resolver::{DependencyResolver, FileClassification, TerminalDetector, TerminalRequirement};
 use super::rust_project::{RustProject, CrateDependency};
-use anyhow::{Result, Context};
+use anyhow::{Result, Context, bail};
 use std::collections::HashMap;
 
 pub struct CodeGenerator {
     ast: AST,
     project: RustProject,
     indent_level: usize,
+    // Nonzero while generating code whose stdout may be redirected or
+    // captured, where output has to go through the runtime
+    redirect_depth: usize,
+    hoisted: Vec<String>,
+    temp_counter: usize,
+    // Labels of the enclosing loops, innermost last, so `break` and
+    // `continue` reach the loop from inside labeled `case` blocks
+    loop_labels: Vec<String>,
+    // Whether the code being generated is a function body, where `return`
+    // is allowed
+    in_function: bool,
+    // Whether the script turns on errexit or xtrace somewhere, so generated
+    // code has to check for them. An ERR trap needs the errexit checks too.
+    errexit: bool,
+    xtrace: bool,
+    // Whether the script turns on failglob, so each top-level statement is
+    // one a pattern that matches nothing can abandon
+    failglob: bool,
+    // Whether the script traps DEBUG, or signals, which an EXIT trap also
+    // catches, so generated code runs the traps around its commands
+    debug_trap: bool,
+    signal_trap: bool,
+    // Nonzero while generating commands whose failure doesn't trigger
+    // errexit: conditions, the left of `&&` and `||`, negated pipelines
+    // and substitutions
+    errexit_exempt: usize,
+    // Temporary files of the `=(...)` substitutions in the statement being
+    // generated, removed after it
+    temp_files: Vec<String>,
     variables: HashMap<String, String>,
     functions: HashMap<String, String>,
+    // Script file named in the location comment before each statement, and
+    // the line of the last comment so a line gets only one
+    source_name: String,
+    source_line: usize,
+    // Whether the script reads `$LINENO`, so each line tells the runtime
+    // where it is
+    track_lines: bool,
 }
 
 impl CodeGenerator {
@@ -27,15 +63,44 @@ impl CodeGenerator {
             project.description = description.clone();
         }
         
+        let traps = trapped_conditions(&ast.root);
+        let errexit = turns_on_option(&ast.root, "errexit") || traps.iter().any(|name| name == "ERR");
+        let xtrace = turns_on_option(&ast.root, "xtrace");
+        let failglob = turns_on_option(&ast.root, "failglob");
+        let debug_trap = traps.iter().any(|name| name == "DEBUG");
+        let signal_trap = traps.iter().any(|name| !matches!(name.as_str(), "ERR" | "DEBUG" | "RETURN"));
+        let track_lines = mentions_lineno(&ast.root);
+        
         Self {
             ast,
             project,
             indent_level: 0,
+            redirect_depth: 0,
+            hoisted: Vec::new(),
+            temp_counter: 0,
+            loop_labels: Vec::new(),
+            in_function: false,
+            errexit,
+            xtrace,
+            failglob,
+            debug_trap,
+            signal_trap,
+            errexit_exempt: 0,
+            temp_files: Vec::new(),
             variables: HashMap::new(),
             functions: HashMap::new(),
+            source_name: script_name.to_string(),
+            source_line: 0,
+            track_lines,
         }
     }
     
+    /// Name the script file in the generated location comments, e.g.
+    /// `// deploy.sh:42:7`. Defaults to the script name.
+    pub fn set_source_name(&mut self, name: &str) {
+        self.source_name = name.to_string();
+    }
+    
     pub fn generate(mut self) -> Result<RustProject> {
         // Analyze terminal requirements
         let terminal_analysis = TerminalDetector::analyze(&self.ast);
@@ -45,6 +110,18 @@ impl CodeGenerator {
             self.project.add_dependency(CrateDependency::new(crate_name, version));
         }
         
+        // `[[ =~ ]]` matches with the regex crate
+        self.project.add_dependency(CrateDependency::new("regex", "1.10"));
+        
+        // `trap` installs signal handlers with signal-hook
+        self.project.add_dependency(CrateDependency::new("signal-hook", "0.3"));
+        
+        // The terminal-aware runtime prompts, colors and strips escapes itself
+        if terminal_analysis.needs_terminal() {
+            self.project.add_dependency(CrateDependency::new("dialoguer", "0.11"));
+            self.project.add_dependency(CrateDependency::new("colored", "2.1"));
+        }
+        
         // Generate main.rs with terminal support
         let main_content = self.generate_main_with_terminal(&terminal_analysis)?;
         self.project.add_file("src/main.rs".into(), main_content);
@@ -105,7 +182,7 @@ impl CodeGenerator {
         code.push_str("#[command(version, about, long_about = None)]\n");
         code.push_str("struct Args {\n");
         code.push_str("    /// Arguments passed to the script\n");
-        code.push_str("    #[arg(trailing_var_arg = true)]\n");
+        code.push_str("    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]\n");
         code.push_str("    args: Vec<String>,\n");
         code.push_str("}\n\n");
         
@@ -131,12 +208,19 @@ impl CodeGenerator {
         }
         
         code.push_str("    // Initialize shell runtime\n");
-        code.push_str("    let mut runtime = shell_runtime::ShellRuntime::new(args.args)?;\n");
+        code.push_str("    // clap drops a leading `--`, which the script still sees as an argument\n");
+        code.push_str("    let mut script_args = args.args;\n");
+        code.push_str("    if std::env::args().nth(1).as_deref() == Some(\"--\") {\n");
+        code.push_str("        script_args.insert(0, \"--\".to_string());\n");
+        code.push_str("    }\n");
+        code.push_str("    let mut runtime = shell_runtime::ShellRuntime::new(script_args)?;\n");
+        code.push_str(&self.generate_dialect_setup());
         code.push_str("    \n");
         
         // Generate the main script logic
         code.push_str("    // Execute main script\n");
-        code.push_str("    script_main(&mut runtime)?;\n");
+        code.push_str("    let result = script_main(&mut runtime);\n");
+        code.push_str("    runtime.finish(result)?;\n");
         code.push_str("    \n");
         code.push_str("    Ok(())\n");
         code.push_str("}\n\n");
@@ -162,67 +246,332 @@ impl CodeGenerator {
         Ok(code)
     }
     
+    /// Runtime settings that make generated code behave like the script's
+    /// own shell.
+    fn generate_dialect_setup(&self) -> String {
+        let dialect = self.ast.metadata.dialect;
+        let mut setup = String::new();
+        if matches!(dialect, ShellDialect::Zsh | ShellDialect::Fish | ShellDialect::PowerShell | ShellDialect::Csh | ShellDialect::Tcsh) {
+            setup.push_str("    runtime.set_ksh_arrays(false);  // arrays count from 1\n");
+        }
+        if dialect == ShellDialect::PowerShell {
+            setup.push_str("    runtime.set_shopt(&[\"nocasematch\"], true)?;  // comparisons ignore case\n");
+        }
+        setup
+    }
+    
+    /// Whether the script's shell splits the value of an unquoted `$name`
+    /// on `IFS`, as sh does and zsh, fish, PowerShell and csh don't.
+    fn splits_fields(&self) -> bool {
+        !matches!(
+            self.ast.metadata.dialect,
+            ShellDialect::Zsh | ShellDialect::Fish | ShellDialect::PowerShell | ShellDialect::Csh | ShellDialect::Tcsh
+        )
+    }
+    
     fn generate_node(&mut self, node: &ASTNode) -> Result<String> {
         match node {
+            ASTNode::Script(statements) if self.failglob => self.generate_top_level(statements),
+            
             ASTNode::Script(statements) | ASTNode::Block(statements) => {
-                let mut code = String::new();
-                for stmt in statements {
-                    code.push_str(&self.indent());
-                    code.push_str(&self.generate_node(stmt)?);
-                    if !code.ends_with('\n') {
-                        code.push('\n');
-                    }
+                self.generate_statements(statements.iter().map(|s| s.as_ref()))
+            }
+            
+            ASTNode::Command { background: true, .. } => self.generate_background(node),
+            
+            ASTNode::Command { name, args, redirections, .. } if name == "exec" => {
+                self.generate_exec(args, redirections)
+            }
+            
+            ASTNode::Command { name, args, redirections, .. } => {
+                let command = if redirections.is_empty() {
+                    self.generate_command(name, args)?
+                } else {
+                    self.generate_with_redirects(redirections, |gen| gen.generate_command(name, args))?
+                };
+                match self.trace_builtin(name, args) {
+                    Some(trace) => Ok(format!("{}\n{}{}", trace, self.indent(), command)),
+                    None => Ok(command),
                 }
-                Ok(code)
             }
             
-            ASTNode::Command { name, args, .. } => {
-                self.generate_command(name, args)
+            ASTNode::Redirected { body, redirections } => {
+                self.generate_with_redirects(redirections, |gen| gen.generate_node(body))
+            }
+            
+            ASTNode::Pipeline(commands) => {
+                self.generate_pipeline(commands)
+            }
+            
+            ASTNode::Subshell(body) => self.generate_subshell(body),
+            
+            ASTNode::Background(body) => self.generate_background(body),
+            
+            ASTNode::AndList(left, right) => {
+                self.generate_and_or(left, "==", right)
+            }
+            
+            ASTNode::OrList(left, right) => {
+                self.generate_and_or(left, "!=", right)
+            }
+            
+            ASTNode::Negated(pipeline) => {
+                let pipeline_code = self.generate_exempt(pipeline)?;
+                Ok(format!(
+                    "{}\n{}runtime.set_exit_status(if runtime.last_exit_status() == 0 {{ 1 }} else {{ 0 }});",
+                    pipeline_code, self.indent()
+                ))
+            }
+            
+            ASTNode::Assignment { name, index, value, append, export, readonly, local, .. } => {
+                let mut statements = Vec::new();
+                if *local {
+                    statements.push(format!("runtime.declare_local(\"{}\")?;", name));
+                }
+                statements.push(self.generate_assignment(name, index.as_deref(), value, *export, *append)?);
+                if self.xtrace && index.is_none() && !matches!(value.as_ref(), ASTNode::Array(_)) {
+                    statements.push(format!("runtime.trace_assignment(\"{}\")?;", name));
+                }
+                if *readonly {
+                    let readonly = VariableAttributes { readonly: true, ..Default::default() };
+                    statements.push(format!("runtime.declare(\"{}\", {})?;", name, var_attributes(&readonly)));
+                }
+                Ok(statements.join(&format!("\n{}", self.indent())))
             }
             
-            ASTNode::Assignment { name, value, export, .. } => {
-                self.generate_assignment(name, value, *export)
+            ASTNode::Declaration { builtin, attributes, removed, local, options, variables, .. } => {
+                self.generate_declaration(builtin, attributes, removed, *local, options, variables)
             }
             
-            ASTNode::If { condition, then_block, elif_blocks, else_block } => {
+            ASTNode::If { condition, then_block, elif_blocks, else_block, .. } => {
                 self.generate_if(condition, then_block, elif_blocks, else_block)
             }
             
-            ASTNode::While { condition, body } => {
+            ASTNode::While { condition, body, .. } => {
                 self.generate_while(condition, body)
             }
             
-            ASTNode::For { variable, items, body } => {
+            ASTNode::For { variable, items, body, .. } => {
                 self.generate_for(variable, items, body)
             }
             
-            ASTNode::Function { name, body } => {
+            ASTNode::Case { expr, cases, .. } => {
+                self.generate_case(expr, cases)
+            }
+            
+            ASTNode::Repeat { count, body, .. } => {
+                self.generate_repeat(count, body)
+            }
+            
+            ASTNode::Function { name, body, .. } => {
                 self.generate_function(name, body)
             }
             
-            ASTNode::String(s, _) => {
+            ASTNode::AnonymousFunction { body, args, .. } => {
+                self.generate_anonymous_function(body, args)
+            }
+            
+            ASTNode::String(s, StringType::Unquoted, _) if split_tilde(s).is_some() => {
+                let (user, rest) = split_tilde(s).unwrap();
+                if rest.is_empty() {
+                    Ok(format!("runtime.tilde(\"{}\")", escape_string(user)))
+                } else {
+                    Ok(format!("format!(\"{{}}{{}}\", runtime.tilde(\"{}\"), \"{}\")", escape_string(user), escape_string(&unescape_text(rest))))
+                }
+            }
+            
+            // Outside word lists and patterns escapes are just quoting
+            ASTNode::String(s, StringType::Unquoted, _) if s.contains('\\') => {
+                Ok(format!("\"{}\"", escape_string(&unescape_text(s))))
+            }
+            
+            ASTNode::String(s, _, _) | ASTNode::Glob(s, _) => {
                 Ok(format!("\"{}\"", escape_string(s)))
             }
             
-            ASTNode::Variable(name) => {
+            ASTNode::Number(n) => {
+                Ok(format!("\"{}\"", n))
+            }
+            
+            ASTNode::Variable(name, _) => {
                 Ok(format!("runtime.get_var(\"{}\")?", name))
             }
             
+            ASTNode::CommandSubstitution(body, _) => {
+                self.generate_command_substitution(body)
+            }
+            
+            ASTNode::FileSubstitution(body, _) => {
+                self.generate_file_substitution(body)
+            }
+            
+            ASTNode::ParameterExpansion { name, expansion_type: ExpansionType::Element(index), .. } => {
+                let index = self.hoist_arithmetic(index)?;
+                Ok(format!("runtime.array_element(\"{}\", {})", name, index))
+            }
+            
+            ASTNode::ParameterExpansion { name, expansion_type: ExpansionType::Subscript(subscript), .. } => {
+                // An arithmetic index needs the runtime mutably, so the
+                // element is looked up ahead of the statement
+                let subscript = self.generate_node(subscript)?;
+                let temp = format!("element_{}", self.temp_counter);
+                self.temp_counter += 1;
+                self.hoisted.push(format!("let {} = runtime.element(\"{}\", {})?;", temp, name, str_arg(subscript)));
+                Ok(temp)
+            }
+            
+            ASTNode::ParameterExpansion { name, expansion_type: ExpansionType::Length, .. } => {
+                match array_name(name) {
+                    Some(array) => Ok(format!("runtime.array_len(\"{}\").to_string()", array)),
+                    // zsh counts the elements of an array named without a subscript
+                    None if self.ast.metadata.dialect == ShellDialect::Zsh => {
+                        Ok(format!("runtime.zsh_length(\"{}\")?.to_string()", name))
+                    }
+                    None => Ok(format!("runtime.get_var(\"{}\")?.chars().count().to_string()", name)),
+                }
+            }
+            
+            ASTNode::ParameterExpansion { name, expansion_type: ExpansionType::Keys, .. } => {
+                Ok(format!("runtime.array_keys(\"{}\").join(\" \")", name))
+            }
+            
+            ASTNode::ParameterExpansion { name, expansion_type: ExpansionType::Substring { offset, length }, .. }
+                if array_name(name).is_some() =>
+            {
+                let offset = self.generate_offset(offset)?;
+                let length = match length {
+                    Some(length) => format!("Some({})", self.generate_offset(length)?),
+                    None => "None".to_string(),
+                };
+                Ok(format!(
+                    "runtime.array_slice(\"{}\", {}, {})?.join(\" \")",
+                    array_name(name).unwrap_or(name), offset, length
+                ))
+            }
+            
+            ASTNode::ParameterExpansion { name, expansion_type: ExpansionType::Flags(flags), .. } => {
+                let flags: Vec<String> = flags.iter().map(generate_parameter_flag).collect();
+                Ok(format!("runtime.expand_flags(\"{}\", &[{}])", name, flags.join(", ")))
+            }
+            
+            ASTNode::ParameterExpansion { name, expansion_type, .. } => {
+                self.generate_parameter_expansion(name, expansion_type)
+            }
+            
+            ASTNode::QualifiedGlob { .. } => {
+                // Outside a word list the matches become one word
+                Ok(format!("{}.join(\" \")", self.generate_fields(node)?))
+            }
+            
+            ASTNode::ArithmeticExpansion(expr, _) => {
+                Ok(format!("{}.to_string()", self.hoist_arithmetic(expr)?))
+            }
+            
+            ASTNode::ArithmeticCommand(expr, _) => {
+                let value = self.hoist_arithmetic(expr)?;
+                Ok(format!("runtime.set_exit_status(if {} != 0 {{ 0 }} else {{ 1 }});", value))
+            }
+            
+            ASTNode::Error { message, span } => {
+                bail!("{}:{}: cannot convert a statement with a syntax error: {}", self.source_name, span, message)
+            }
+            
+            ASTNode::Test { expr, extended, .. } => {
+                let test = self.generate_test(expr, *extended)?;
+                let temp = format!("test_{}", self.temp_counter);
+                self.temp_counter += 1;
+                self.hoisted.push(format!("let {}: bool = {};", temp, test));
+                Ok(format!("runtime.set_exit_status(if {} {{ 0 }} else {{ 1 }});", temp))
+            }
+            
+            ASTNode::Concat(parts, _) => {
+                let start = self.hoisted.len();
+                let mut part_strs = Vec::new();
+                let mut ends = Vec::new();
+                for part in parts {
+                    part_strs.push(self.generate_node(part)?);
+                    ends.push(self.hoisted.len());
+                }
+                self.hoist_in_order(&mut part_strs, start, &ends);
+                Ok(format!("format!(\"{}\", {})", "{}".repeat(part_strs.len()), part_strs.join(", ")))
+            }
+            
+            ASTNode::Quoted(parts, _) => {
+                // The literal text goes in the format string
+                let start = self.hoisted.len();
+                let mut template = String::new();
+                let mut args = Vec::new();
+                let mut ends = Vec::new();
+                for part in parts {
+                    match part.as_ref() {
+                        ASTNode::String(text, _, _) => {
+                            template.push_str(&escape_string(text).replace('{', "{{").replace('}', "}}"));
+                        }
+                        other => {
+                            template.push_str("{}");
+                            args.push(self.generate_node(other)?);
+                            ends.push(self.hoisted.len());
+                        }
+                    }
+                }
+                self.hoist_in_order(&mut args, start, &ends);
+                if template == "{}" {
+                    return Ok(args.remove(0));
+                }
+                Ok(format!("format!(\"{}\", {})", template, args.join(", ")))
+            }
+            
             ASTNode::Exit(code) => {
-                if let Some(code) = code {
-                    let code_str = self.generate_node(code)?;
-                    Ok(format!("std::process::exit({});", code_str))
-                } else {
-                    Ok("std::process::exit(0);".to_string())
+                // The `Exit` error unwinds to `runtime.finish`, which runs the
+                // EXIT trap. Without a status it is the last command's.
+                match code.as_deref() {
+                    None => Ok("return Err(shell_runtime::Exit(runtime.last_exit_status()).into());".to_string()),
+                    Some(ASTNode::Number(n)) => {
+                        Ok(format!("return Err(shell_runtime::Exit({}).into());", (*n as i64).rem_euclid(256)))
+                    }
+                    Some(code) => {
+                        let code_str = self.generate_node(code)?;
+                        Ok(format!("return runtime.exit({});", str_arg(code_str)))
+                    }
+                }
+            }
+            
+            ASTNode::Break(count) => {
+                match self.loop_label(*count) {
+                    Some(label) => Ok(format!("break {};", label)),
+                    None => Ok("break;".to_string()),
+                }
+            }
+            
+            ASTNode::Continue(count) => {
+                match self.loop_label(*count) {
+                    Some(label) => Ok(format!("continue {};", label)),
+                    None => Ok("continue;".to_string()),
                 }
             }
             
             ASTNode::Return(value) => {
-                if let Some(val) = value {
-                    let val_str = self.generate_node(val)?;
-                    Ok(format!("return Ok({});", val_str))
-                } else {
-                    Ok("return Ok(());".to_string())
+                if !self.in_function {
+                    return Ok(format!(
+                        "runtime.write_err(\"return: can only `return' from a function or sourced script\\n\")?;\n{}runtime.set_exit_status(2);",
+                        self.indent()
+                    ));
+                }
+                // Without a value the status is the last command's
+                let status = match value.as_deref() {
+                    None => None,
+                    Some(ASTNode::Number(n)) => Some(format!("runtime.set_exit_status({});", (*n as i64).rem_euclid(256))),
+                    Some(ASTNode::String(text, _, _)) if text.parse::<i64>().is_ok() => {
+                        Some(format!("runtime.set_exit_status({});", text.parse::<i64>()?.rem_euclid(256)))
+                    }
+                    Some(value) => {
+                        let value_str = self.generate_node(value)?;
+                        Some(format!("runtime.set_return_status({})?;", str_arg(value_str)))
+                    }
+                };
+                match status {
+                    Some(status) => Ok(format!("{}\n{}return Ok(());", status, self.indent())),
+                    None => Ok("return Ok(());".to_string()),
                 }
             }
             
@@ -230,28 +579,254 @@ impl CodeGenerator {
         }
     }
     
+    fn generate_statements<'n>(&mut self, statements: impl IntoIterator<Item = &'n ASTNode>) -> Result<String> {
+        let outer_hoisted = std::mem::take(&mut self.hoisted);
+        let outer_temp_files = std::mem::take(&mut self.temp_files);
+        let mut code = String::new();
+        for stmt in statements {
+            if let Some(span) = stmt.span().filter(|span| span.line != self.source_line) {
+                code.push_str(&format!("{}// {}:{}\n", self.indent(), self.source_name, span));
+                if self.track_lines {
+                    code.push_str(&format!("{}runtime.set_line({});\n", self.indent(), span.line));
+                }
+                self.source_line = span.line;
+            }
+            let stmt_code = self.generate_node(stmt)?;
+            if self.debug_trap && matches!(
+                stmt,
+                ASTNode::Command { .. } | ASTNode::Pipeline(_) | ASTNode::Test { .. } | ASTNode::ArithmeticCommand(_, _)
+                    | ASTNode::Assignment { .. }
+            ) {
+                code.push_str(&format!("{}runtime.run_debug_trap()?;\n", self.indent()));
+            }
+            // Substitutions used by this statement are evaluated first
+            for hoisted in std::mem::take(&mut self.hoisted) {
+                code.push_str(&self.indent());
+                code.push_str(&hoisted);
+                code.push('\n');
+            }
+            code.push_str(&self.indent());
+            code.push_str(&stmt_code);
+            if !code.ends_with('\n') {
+                code.push('\n');
+            }
+            // `=(...)` files only last as long as the command using them
+            for file in std::mem::take(&mut self.temp_files) {
+                code.push_str(&format!("{}runtime.remove_temp_file(&{});\n", self.indent(), file));
+            }
+            if self.checks_errexit(stmt) {
+                code.push_str(&format!("{}runtime.check_errexit()?;\n", self.indent()));
+            }
+            // Signals that arrived during the statement
+            if self.signal_trap && !matches!(stmt, ASTNode::Exit(_) | ASTNode::Return(_) | ASTNode::Break(_) | ASTNode::Continue(_)) {
+                code.push_str(&format!("{}runtime.run_traps()?;\n", self.indent()));
+            }
+        }
+        self.hoisted = outer_hoisted;
+        self.temp_files = outer_temp_files;
+        Ok(code)
+    }
+    
+    /// The statements of a script with failglob on, each run so that a
+    /// pattern without matches only abandons that statement.
+    fn generate_top_level(&mut self, statements: &[Box<ASTNode>]) -> Result<String> {
+        let mut code = String::new();
+        for stmt in statements {
+            self.indent_level += 1;
+            let body = self.generate_statements([stmt.as_ref()]);
+            self.indent_level -= 1;
+            code.push_str(&format!("{}runtime.top_level(|runtime| {{\n", self.indent()));
+            code.push_str(&body?);
+            code.push_str(&format!("{}    Ok(())\n{}}})?;\n", self.indent(), self.indent()));
+        }
+        Ok(code)
+    }
+    
+    /// xtrace for a builtin the generated code runs itself, when its words
+    /// are all literal. The runtime traces the commands it runs, and echo
+    /// traces its expanded words.
+    fn trace_builtin(&self, name: &str, args: &[Box<ASTNode>]) -> Option<String> {
+        if !self.xtrace || name == "echo" || !INLINE_BUILTINS.contains(&name) {
+            return None;
+        }
+        let mut words = Vec::new();
+        for arg in args {
+            match arg.as_ref() {
+                ASTNode::String(s, _, _) | ASTNode::Glob(s, _) => words.push(format!("\"{}\"", escape_string(s))),
+                ASTNode::Number(n) => words.push(format!("\"{}\"", n)),
+                _ => return None,
+            }
+        }
+        Some(format!("runtime.trace(\"{}\", &[{}] as &[&str])?;", name, words.join(", ")))
+    }
+    
+    /// Whether errexit can end the script after `statement`: a command that
+    /// isn't part of a condition. Compound commands check the commands in
+    /// them instead.
+    fn checks_errexit(&self, statement: &ASTNode) -> bool {
+        self.errexit && self.errexit_exempt == 0 && matches!(
+            statement,
+            ASTNode::Command { .. } | ASTNode::Pipeline(_) | ASTNode::Test { .. } | ASTNode::ArithmeticCommand(_, _)
+        )
+    }
+    
+    /// Code for a command whose failure doesn't trigger errexit. Functions
+    /// it calls are exempt too, so with errexit the runtime is told.
+    fn generate_exempt(&mut self, node: &ASTNode) -> Result<String> {
+        self.errexit_exempt += 1;
+        let code = self.generate_node(node);
+        self.errexit_exempt -= 1;
+        if !self.errexit {
+            return code;
+        }
+        Ok(format!(
+            "runtime.enter_condition();\n{}{}\n{}runtime.leave_condition();",
+            self.indent(), code?, self.indent()
+        ))
+    }
+    
+    /// `left && right` or `left || right`: run `right` only when the exit
+    /// status of `left` compares to 0 with `test`. Substitutions in `right`
+    /// are evaluated inside the branch so they are skipped with it.
+    fn generate_and_or(&mut self, left: &ASTNode, test: &str, right: &ASTNode) -> Result<String> {
+        let left_code = self.generate_exempt(left)?;
+        self.indent_level += 1;
+        let right_code = self.generate_statements([right]);
+        self.indent_level -= 1;
+        
+        Ok(format!(
+            "{}\n{}if runtime.last_exit_status() {} 0 {{\n{}{}}}",
+            left_code, self.indent(), test, right_code?, self.indent()
+        ))
+    }
+    
+    fn generate_pipeline(&mut self, commands: &[Box<ASTNode>]) -> Result<String> {
+        let mut code = String::from("runtime.run_pipeline(vec![\n");
+        
+        self.indent_level += 1;
+        self.redirect_depth += 1;
+        for command in commands {
+            code.push_str(&format!("{}shell_runtime::stage(move |runtime| {{\n", self.indent()));
+            self.indent_level += 1;
+            let stage = self.generate_statements([command.as_ref()]);
+            code.push_str(&stage?);
+            code.push_str(&format!("{}Ok(())\n", self.indent()));
+            self.indent_level -= 1;
+            code.push_str(&format!("{}}}),\n", self.indent()));
+        }
+        self.redirect_depth -= 1;
+        self.indent_level -= 1;
+        
+        code.push_str(&format!("{}])?;", self.indent()));
+        Ok(code)
+    }
+    
+    /// `( ... )`: the commands run with a copy of the runtime, like a
+    /// pipeline stage of its own.
+    fn generate_subshell(&mut self, body: &ASTNode) -> Result<String> {
+        self.indent_level += 1;
+        self.redirect_depth += 1;
+        let code = self.generate_statements([body]);
+        self.redirect_depth -= 1;
+        self.indent_level -= 1;
+        Ok(format!(
+            "runtime.run_subshell(shell_runtime::stage(move |runtime| {{\n{}{}    Ok(())\n{}}}))?;",
+            code?, self.indent(), self.indent()
+        ))
+    }
+    
+    /// `&`: start a job without waiting for it. A command that runs as a
+    /// child process is spawned directly, so `$!` is its pid; anything else
+    /// runs on a thread with its own copy of the runtime, like a subshell.
+    fn generate_background(&mut self, node: &ASTNode) -> Result<String> {
+        let text = escape_string(&job_text(node));
+        if let ASTNode::Command { name, args, redirections, .. } = node {
+            if !INLINE_BUILTINS.contains(&name.as_str()) {
+                let spawn = |gen: &mut Self| -> Result<String> {
+                    let args = gen.generate_word_slice(args)?;
+                    Ok(format!("runtime.spawn_command(\"{}\", \"{}\", {})?;", text, name, args))
+                };
+                return if redirections.is_empty() {
+                    spawn(self)
+                } else {
+                    self.generate_with_redirects(redirections, spawn)
+                };
+            }
+        }
+        
+        let mut body = match node {
+            // The job is a subshell already
+            ASTNode::Subshell(body) => body.as_ref().clone(),
+            other => other.clone(),
+        };
+        if let ASTNode::Command { background, .. } = &mut body {
+            *background = false;
+        }
+        let statements: Vec<&ASTNode> = match &body {
+            ASTNode::Block(statements) => statements.iter().map(|s| s.as_ref()).collect(),
+            other => vec![other],
+        };
+        self.indent_level += 1;
+        self.redirect_depth += 1;
+        let code = self.generate_statements(statements);
+        self.redirect_depth -= 1;
+        self.indent_level -= 1;
+        Ok(format!(
+            "runtime.spawn_job(\"{}\", shell_runtime::stage(move |runtime| {{\n{}{}    Ok(())\n{}}}))?;",
+            text, code?, self.indent(), self.indent()
+        ))
+    }
+    
     fn generate_command(&mut self, name: &str, args: &[Box<ASTNode>]) -> Result<String> {
         match name {
             "echo" => {
                 // Check for -e flag (enable escape sequences)
                 let has_e_flag = args.first()
                     .and_then(|a| match a.as_ref() {
-                        ASTNode::String(s, _) => Some(s == "-e"),
+                        ASTNode::String(s, _, _) => Some(s == "-e"),
                         _ => None
                     })
                     .unwrap_or(false);
                 
                 let start_idx = if has_e_flag { 1 } else { 0 };
                 let mut arg_strs = Vec::new();
-                for arg in &args[start_idx..] {
-                    arg_strs.push(self.generate_node(arg)?);
+                if self.xtrace {
+                    // The words are expanded once, for the trace and the output
+                    let words = self.generate_arg_list(&args[start_idx..])?;
+                    let temp = format!("words_{}", self.temp_counter);
+                    self.temp_counter += 1;
+                    self.hoisted.push(format!("let {}: Vec<String> = {};", temp, words));
+                    let traced = if has_e_flag {
+                        format!("[vec![\"-e\".to_string()], {}.clone()].concat()", temp)
+                    } else {
+                        temp.clone()
+                    };
+                    self.hoisted.push(format!("runtime.trace(\"echo\", &{})?;", traced));
+                    arg_strs.push(format!("{}.join(\" \")", temp));
+                } else if args[start_idx..].iter().any(|arg| self.expands_to_list(arg)) {
+                    let words = self.generate_arg_list(&args[start_idx..])?;
+                    arg_strs.push(format!("{}.join(\" \")", words));
+                } else {
+                    for arg in &args[start_idx..] {
+                        arg_strs.push(self.generate_node(arg)?);
+                    }
                 }
                 
+                let format_str = vec!["{}"; arg_strs.len()].join(" ");
                 if has_e_flag {
-                    // Handle color codes automatically based on terminal
-                    Ok(format!("runtime.echo_with_colors(&[{}]);", arg_strs.join(", ")))
+                    // Escapes are replaced after expansion, as in `echo -e "${RED}x"`
+                    Ok(format!(
+                        "runtime.write_out(&shell_runtime::echo_escapes(&format!(\"{}\", {})))?;",
+                        format_str, arg_strs.join(", ")
+                    ))
+                } else if self.redirect_depth > 0 {
+                    // stdout may be a file or a $(...) buffer, so go through the runtime
+                    Ok(format!("runtime.write_out(&format!(\"{}\\n\", {}))?;", format_str, arg_strs.join(", ")))
                 } else {
-                    Ok(format!("println!(\"{{}}\", {});", arg_strs.join(", ")))
+                    Ok(format!(
+                        "println!(\"{}\", {});\n{}runtime.set_exit_status(0);",
+                        format_str, arg_strs.join(", "), self.indent()
+                    ))
                 }
             }
             
@@ -266,7 +841,7 @@ impl CodeGenerator {
             
             "export" => {
                 if let Some(arg) = args.first() {
-                    if let ASTNode::String(assignment, _) = arg.as_ref() {
+                    if let ASTNode::String(assignment, _, _) = arg.as_ref() {
                         if let Some((var, val)) = assignment.split_once('=') {
                             Ok(format!("runtime.export_var(\"{}\", \"{}\")?;", var, val))
                         } else {
@@ -282,24 +857,26 @@ impl CodeGenerator {
             
             "read" => {
                 // Handle read command with automatic terminal detection
-                let mut var_name = "REPLY";
+                let mut var_names = Vec::new();
                 let mut prompt = "";
                 let mut silent = false;
+                let mut raw = false;
                 
                 let mut i = 0;
                 while i < args.len() {
-                    if let ASTNode::String(s, _) = args[i].as_ref() {
+                    if let ASTNode::String(s, _, _) = args[i].as_ref() {
                         match s.as_str() {
                             "-s" => silent = true,
+                            "-r" => raw = true,
                             "-p" => {
                                 if i + 1 < args.len() {
-                                    if let ASTNode::String(p, _) = args[i + 1].as_ref() {
+                                    if let ASTNode::String(p, _, _) = args[i + 1].as_ref() {
                                         prompt = p;
                                         i += 1;
                                     }
                                 }
                             }
-                            s if !s.starts_with('-') => var_name = s,
+                            s if !s.starts_with('-') => var_names.push(format!("\"{}\"", s)),
                             _ => {}
                         }
                     }
@@ -307,23 +884,169 @@ impl CodeGenerator {
                 }
                 
                 if silent {
+                    let var_name = var_names.first().map(String::as_str).unwrap_or("\"REPLY\"");
                     Ok(format!(
-                        "runtime.set_var(\"{}\", runtime.read_password(\"{}\")?)?;",
-                        var_name, prompt
+                        "runtime.set_var({}, runtime.read_password(\"{}\")?)?;",
+                        var_name, escape_string(prompt)
                     ))
                 } else {
+                    // Reads honor redirections and pipes, so `while read` loops
+                    // work on files and command output
                     Ok(format!(
-                        "runtime.set_var(\"{}\", runtime.read_input(\"{}\")?)?;",
-                        var_name, prompt
+                        "runtime.read_line(\"{}\", &[{}], {})?;",
+                        escape_string(prompt), var_names.join(", "), raw
                     ))
                 }
             }
             
+            "pwd" => Ok(format!("runtime.pwd({})?;", self.generate_word_slice(args)?)),
+            
+            "true" | ":" => Ok("runtime.set_exit_status(0);".to_string()),
+            
+            "false" => Ok("runtime.set_exit_status(1);".to_string()),
+            
+            "set" => {
+                let set_args = parse_set_args(args);
+                let mut statements = Vec::new();
+                if !set_args.options.is_empty() {
+                    let options: Vec<String> = set_args.options.iter()
+                        .map(|(name, enabled)| format!("(\"{}\", {})", escape_string(name), enabled))
+                        .collect();
+                    statements.push(format!("runtime.set_options(&[{}])?;", options.join(", ")));
+                }
+                if let Some(words) = set_args.positional {
+                    statements.push(format!("runtime.set_positional_args({});", self.generate_field_list(words)?));
+                    if set_args.options.is_empty() {
+                        statements.push("runtime.set_exit_status(0);".to_string());
+                    }
+                }
+                if statements.is_empty() {
+                    // Without arguments `set` lists the variables, and `set -o` the options
+                    let words: Vec<&str> = args.iter()
+                        .filter_map(|a| match a.as_ref() {
+                            ASTNode::String(s, _, _) => Some(s.as_str()),
+                            _ => None
+                        })
+                        .collect();
+                    bail!("Unsupported `{}`", std::iter::once("set").chain(words).collect::<Vec<_>>().join(" "));
+                }
+                Ok(statements.join(&format!("\n{}", self.indent())))
+            }
+            
+            "shift" => {
+                let count = match args.first() {
+                    Some(count) => str_arg(self.generate_node(count)?),
+                    None => "\"1\"".to_string(),
+                };
+                Ok(format!("runtime.shift({})?;", count))
+            }
+            
+            "unset" => {
+                // A readonly variable makes the status 1
+                let mut statements = vec!["runtime.set_exit_status(0);".to_string()];
+                for arg in args {
+                    match arg.as_ref() {
+                        ASTNode::String(name, _, _) if name != "-v" => {
+                            statements.push(format!("runtime.unset_var(\"{}\")?;", name));
+                        }
+                        ASTNode::String(..) => {}
+                        other => statements.push(format!("runtime.unset_var(&{})?;", self.generate_node(other)?)),
+                    }
+                }
+                Ok(statements.join(&format!("\n{}", self.indent())))
+            }
+            
+            "shopt" => {
+                let words: Vec<&str> = args.iter()
+                    .filter_map(|a| match a.as_ref() {
+                        ASTNode::String(s, _, _) => Some(s.as_str()),
+                        _ => None
+                    })
+                    .collect();
+                
+                let names = |names: &[&str]| {
+                    names.iter().map(|name| format!("\"{}\"", escape_string(name))).collect::<Vec<_>>().join(", ")
+                };
+                match words.as_slice() {
+                    [flag @ ("-s" | "-u"), options @ ..] if !options.is_empty() => {
+                        Ok(format!("runtime.set_shopt(&[{}], {})?;", names(options), *flag == "-s"))
+                    }
+                    ["-q", options @ ..] if !options.is_empty() => {
+                        Ok(format!("runtime.query_shopt(&[{}]);", names(options)))
+                    }
+                    // Listing, and `-o` for the `set -o` options
+                    _ => bail!("Unsupported `{}`", std::iter::once("shopt").chain(words).collect::<Vec<_>>().join(" ")),
+                }
+            }
+            
+            "trap" => self.generate_trap(args),
+            
+            "count" if self.ast.metadata.dialect == ShellDialect::Fish => {
+                Ok(format!("runtime.count({})?;", self.generate_word_slice(args)?))
+            }
+            
+            // The parameters of a PowerShell `param()`, switches as `-name`
+            "param" if self.ast.metadata.dialect == ShellDialect::PowerShell => {
+                let mut names = Vec::new();
+                let mut switches = Vec::new();
+                for arg in args {
+                    match arg.as_ref() {
+                        ASTNode::String(name, _, _) => match name.strip_prefix('-') {
+                            Some(switch) => switches.push(format!("\"{}\"", switch)),
+                            None => names.push(format!("\"{}\"", name)),
+                        },
+                        other => bail!("Unexpected {:?} among the parameters of `param`", other),
+                    }
+                }
+                Ok(format!("runtime.bind_parameters(&[{}], &[{}])?;", names.join(", "), switches.join(", ")))
+            }
+            
+            "wait" => Ok(format!("runtime.wait({})?;", self.generate_word_slice(args)?)),
+            
+            "jobs" => Ok(format!("runtime.list_jobs({})?;", self.generate_word_slice(args)?)),
+            
+            "kill" => Ok(format!("runtime.kill({})?;", self.generate_word_slice(args)?)),
+            
+            // Scripts run without job control
+            "fg" | "bg" => Ok(format!(
+                "runtime.write_err(\"{}: no job control\\n\")?;\n{}runtime.set_exit_status(1);",
+                name, self.indent()
+            )),
+            
+            "setopt" | "unsetopt" => {
+                let words: Vec<&str> = args.iter()
+                    .filter_map(|a| match a.as_ref() {
+                        ASTNode::String(s, _, _) => Some(s.as_str()),
+                        _ => None
+                    })
+                    .collect();
+                
+                let mut statements = Vec::new();
+                for word in &words {
+                    let (option, enable) = zsh_option(name, word);
+                    statements.push(match option.as_str() {
+                        "ksharrays" => format!("runtime.set_ksh_arrays({});", enable),
+                        "kshglob" => format!("runtime.set_extglob({});", enable),
+                        "nullglob" => format!("runtime.set_nullglob({});", enable),
+                        "globdots" => format!("runtime.set_dotglob({});", enable),
+                        "pipefail" => format!("runtime.set_pipefail({});", enable),
+                        "errexit" | "xtrace" => format!("runtime.set_options(&[(\"{}\", {})])?;", option, enable),
+                        // zsh names these the other way around from sh
+                        "unset" | "glob" | "clobber" => {
+                            format!("runtime.set_options(&[(\"no{}\", {})])?;", option, !enable)
+                        }
+                        _ => bail!("Unsupported `{} {}`", name, word),
+                    });
+                }
+                statements.push("runtime.set_exit_status(0);".to_string());
+                Ok(statements.join(&format!("\n{}", self.indent())))
+            }
+            
             "select" => {
                 // Handle select command with automatic terminal detection
                 let var_name = args.first()
                     .and_then(|a| match a.as_ref() {
-                        ASTNode::String(s, _) => Some(s.as_str()),
+                        ASTNode::String(s, _, _) => Some(s.as_str()),
                         _ => None
                     })
                     .unwrap_or("REPLY");
@@ -334,15 +1057,22 @@ impl CodeGenerator {
                 ))
             }
             
+            _ if args.iter().any(|arg| self.expands_to_list(arg)) => {
+                let args = self.generate_arg_list(args)?;
+                Ok(format!("runtime.execute_command(\"{}\", &{})?;", name, args))
+            }
+            
             _ => {
-                // External command
+                // External command; borrowing every argument gives the
+                // slice one element type
                 let mut arg_strs = Vec::new();
                 for arg in args {
-                    arg_strs.push(self.generate_node(arg)?);
+                    arg_strs.push(str_arg(self.generate_node(arg)?));
                 }
                 
-                if is_builtin(name) {
-                    Ok(format!("commands::{}(&[{}])?;", name, arg_strs.join(", ")))
+                if arg_strs.is_empty() {
+                    // An empty slice needs its element type spelled out
+                    Ok(format!("runtime.execute_command(\"{}\", &[] as &[&str])?;", name))
                 } else {
                     Ok(format!(
                         "runtime.execute_command(\"{}\", &[{}])?;",
@@ -354,25 +1084,936 @@ impl CodeGenerator {
         }
     }
     
-    fn generate_assignment(&mut self, name: &str, value: &ASTNode, export: bool) -> Result<String> {
-        let value_str = self.generate_node(value)?;
-        if export {
-            Ok(format!("runtime.export_var(\"{}\", {})?;", name, value_str))
+    /// `exec`. Without a command its redirections stay for the rest of the
+    /// script; with one, the script ends with the command's status.
+    fn generate_exec(&mut self, args: &[Box<ASTNode>], redirections: &[Redirection]) -> Result<String> {
+        if args.is_empty() {
+            let mut redirects = Vec::new();
+            for redirection in redirections {
+                redirects.push(self.generate_redirect(redirection)?);
+            }
+            return Ok(format!("runtime.exec_redirects(vec![{}])?;", redirects.join(", ")));
+        }
+        
+        let run = |gen: &mut Self| -> Result<String> {
+            match args[0].as_ref() {
+                ASTNode::String(name, _, _) => gen.generate_command(name, &args[1..]),
+                _ => {
+                    let words = gen.generate_arg_list(args)?;
+                    Ok(format!(
+                        "if let Some((cmd, args)) = {}.split_first() {{ runtime.execute_command(cmd, args)?; }}",
+                        words
+                    ))
+                }
+            }
+        };
+        let command = if redirections.is_empty() {
+            run(self)?
         } else {
-            Ok(format!("runtime.set_var(\"{}\", {})?;", name, value_str))
+            self.generate_with_redirects(redirections, run)?
+        };
+        Ok(format!(
+            "{}\n{}return Err(shell_runtime::Exit(runtime.last_exit_status()).into());",
+            command, self.indent()
+        ))
+    }
+    
+    /// `trap`. The action's text is compiled like a function body, so it has
+    /// to be known here: a literal, or double quotes around variables, which
+    /// are then expanded when the trap runs rather than when it is set.
+    fn generate_trap(&mut self, args: &[Box<ASTNode>]) -> Result<String> {
+        let (action, conditions) = match parse_trap_args(args) {
+            TrapArgs::List(conditions) => {
+                return Ok(format!("runtime.print_traps({})?;", self.generate_word_slice(conditions)?));
+            }
+            TrapArgs::Reset(conditions) => {
+                return Ok(format!("runtime.reset_traps({})?;", self.generate_word_slice(conditions)?));
+            }
+            TrapArgs::Set(action, conditions) => (action, conditions),
+            TrapArgs::ListSignals => bail!("Unsupported `trap -l`"),
+        };
+        let conditions = self.generate_word_slice(conditions)?;
+        let Some(text) = trap_text(action) else {
+            bail!("Unsupported trap action `{}`: it is only known when the script runs", word_text(action));
+        };
+        if text.is_empty() {
+            return Ok(format!("runtime.set_trap(\"\", None, {})?;", conditions));
         }
+        let ast = ShellParser::new(text.clone(), self.ast.metadata.dialect)
+            .and_then(|mut parser| parser.parse())
+            .with_context(|| format!("in trap action `{}`", text))?;
+        
+        let fn_name = format!("shell_trap_{}", self.temp_counter);
+        self.temp_counter += 1;
+        // Locations in the action are lines of its text
+        let saved_source_name = std::mem::replace(&mut self.source_name, "trap".to_string());
+        let saved_source_line = std::mem::replace(&mut self.source_line, 0);
+        // `$LINENO` in the action is the line the trap went off on
+        let saved_track_lines = std::mem::replace(&mut self.track_lines, false);
+        let generated = self.generate_function_item(&fn_name, &fn_name, &ast.root);
+        self.source_name = saved_source_name;
+        self.source_line = saved_source_line;
+        self.track_lines = saved_track_lines;
+        generated?;
+        Ok(format!("runtime.set_trap(\"{}\", Some({}), {})?;", escape_string(&text), fn_name, conditions))
     }
     
-    fn generate_if(
-        &mut self,
-        condition: &ASTNode,
-        then_block: &ASTNode,
-        elif_blocks: &[(Box<ASTNode>, Box<ASTNode>)],
-        else_block: &Option<Box<ASTNode>>,
-    ) -> Result<String> {
-        let mut code = String::new();
+    /// Words passed to the runtime as a slice of strings.
+    fn generate_word_slice(&mut self, words: &[Box<ASTNode>]) -> Result<String> {
+        if words.is_empty() {
+            return Ok("&[] as &[&str]".to_string());
+        }
+        if words.iter().any(|word| self.expands_to_list(word)) {
+            return Ok(format!("&{}", self.generate_arg_list(words)?));
+        }
+        let mut word_strs = Vec::new();
+        for word in words {
+            word_strs.push(match word.as_ref() {
+                ASTNode::Number(n) => format!("\"{}\"", n),
+                other => str_arg(self.generate_node(other)?),
+            });
+        }
+        Ok(format!("&[{}]", word_strs.join(", ")))
+    }
+    
+    fn generate_with_redirects<F>(&mut self, redirections: &[Redirection], generate: F) -> Result<String>
+    where
+        F: FnOnce(&mut Self) -> Result<String>,
+    {
+        let mut redirects = Vec::new();
+        for redirection in redirections {
+            redirects.push(self.generate_redirect(redirection)?);
+        }
         
-        // Generate condition evaluation
+        self.indent_level += 1;
+        self.redirect_depth += 1;
+        let body = generate(self);
+        self.redirect_depth -= 1;
+        self.indent_level -= 1;
+        let body = body?;
+        
+        let mut code = format!("runtime.with_redirects(vec![{}], |runtime| {{\n", redirects.join(", "));
+        // Blocks come back with every statement indented already
+        if !body.starts_with(' ') {
+            code.push_str(&self.indent());
+            code.push_str("    ");
+        }
+        code.push_str(&body);
+        if !code.ends_with('\n') {
+            code.push('\n');
+        }
+        code.push_str(&self.indent());
+        code.push_str("    Ok(())\n");
+        code.push_str(&self.indent());
+        code.push_str("})?;");
+        Ok(code)
+    }
+    
+    fn generate_redirect(&mut self, redirection: &Redirection) -> Result<String> {
+        let fd = redirection.fd.unwrap_or(match redirection.direction {
+            RedirectDirection::Input | RedirectDirection::ReadWrite => 0,
+            RedirectDirection::Output | RedirectDirection::Clobber => 1,
+        });
+        
+        Ok(match &redirection.target {
+            RedirectionTarget::File(word) => {
+                let path = self.generate_node(word)?;
+                match redirection.direction {
+                    RedirectDirection::Input => format!(
+                        "shell_runtime::Redirect::Read {{ fd: {}, path: {}.to_string() }}", fd, path
+                    ),
+                    RedirectDirection::Output => format!(
+                        "shell_runtime::Redirect::Write {{ fd: {}, path: {}.to_string(), append: {} }}",
+                        fd, path, redirection.append
+                    ),
+                    RedirectDirection::ReadWrite => format!(
+                        "shell_runtime::Redirect::ReadWrite {{ fd: {}, path: {}.to_string() }}", fd, path
+                    ),
+                    RedirectDirection::Clobber => format!(
+                        "shell_runtime::Redirect::Clobber {{ fd: {}, path: {}.to_string() }}", fd, path
+                    ),
+                }
+            }
+            RedirectionTarget::Fd(target) => {
+                format!("shell_runtime::Redirect::Dup {{ fd: {}, target: {} }}", fd, target)
+            }
+            RedirectionTarget::Close => format!("shell_runtime::Redirect::Close {{ fd: {} }}", fd),
+            RedirectionTarget::Heredoc { content, .. } => {
+                // A literal body for a quoted delimiter, else a template
+                // whose expansions are evaluated when the command runs
+                let data = self.generate_node(content)?;
+                format!("shell_runtime::Redirect::Input {{ fd: {}, data: {}.to_string() }}", fd, data)
+            }
+            RedirectionTarget::HereString(word) => {
+                // A here-string is fed to the command with a trailing newline
+                let data = self.generate_node(word)?;
+                format!("shell_runtime::Redirect::Input {{ fd: {}, data: format!(\"{{}}\\n\", {}) }}", fd, data)
+            }
+        })
+    }
+    
+    fn generate_command_substitution(&mut self, body: &ASTNode) -> Result<String> {
+        self.generate_capture(body, "subst", "capture_output")
+    }
+    
+    /// zsh's `=(...)`: the output goes to a temporary file whose path is
+    /// the value, and the file is removed after the statement.
+    fn generate_file_substitution(&mut self, body: &ASTNode) -> Result<String> {
+        let temp = self.generate_capture(body, "file", "capture_to_file")?;
+        self.temp_files.push(temp.clone());
+        Ok(format!("{}.as_str()", temp))
+    }
+    
+    /// Run `body` with its output captured by the runtime method `method`,
+    /// binding the result to a temporary named after `prefix`.
+    fn generate_capture(&mut self, body: &ASTNode, prefix: &str, method: &str) -> Result<String> {
+        // The body runs against the same runtime and needs it mutably, so the
+        // capture is bound to a temporary ahead of the statement using it
+        // and doesn't inherit errexit
+        self.indent_level += 1;
+        self.redirect_depth += 1;
+        self.errexit_exempt += 1;
+        let body_code = self.generate_node(body);
+        self.errexit_exempt -= 1;
+        self.redirect_depth -= 1;
+        self.indent_level -= 1;
+        
+        let temp = format!("{}_{}", prefix, self.temp_counter);
+        self.temp_counter += 1;
+        
+        let mut code = format!("let {} = runtime.{}(|runtime| {{\n", temp, method);
+        code.push_str(&body_code?);
+        code.push_str(&self.indent());
+        code.push_str("    Ok(())\n");
+        code.push_str(&self.indent());
+        code.push_str("})?;");
+        self.hoisted.push(code);
+        Ok(temp)
+    }
+    
+    /// Whether a command argument can expand to any number of words: a
+    /// glob, a list like `"$@"`, or an unquoted expansion that is split on
+    /// `IFS`. Arithmetic results are left alone, IFS rarely has digits.
+    fn expands_to_list(&self, word: &ASTNode) -> bool {
+        match word {
+            ASTNode::QualifiedGlob { .. } | ASTNode::Glob(_, _) => true,
+            ASTNode::String(s, StringType::Unquoted, _) => has_glob_chars(s) || s.contains('\\'),
+            ASTNode::Variable(_, _) | ASTNode::ParameterExpansion { .. } | ASTNode::CommandSubstitution(_, _) => true,
+            ASTNode::Concat(parts, _) => parts.iter().any(|part| self.expands_to_list(part)),
+            other => quoted_fields(other).is_some(),
+        }
+    }
+    
+    /// Rust `Vec<String>` expression for the arguments of a command when
+    /// some of them, like `"${list[@]}"`, `$files` or `*.log`, expand to
+    /// any number of words.
+    fn generate_arg_list(&mut self, args: &[Box<ASTNode>]) -> Result<String> {
+        let start = self.hoisted.len();
+        let mut field_lists = Vec::new();
+        let mut ends = Vec::new();
+        for arg in args {
+            field_lists.push(if self.expands_to_list(arg) {
+                self.generate_fields(arg)?
+            } else {
+                format!("vec![{}.to_string()]", self.generate_node(arg)?)
+            });
+            ends.push(self.hoisted.len());
+        }
+        self.hoist_in_order(&mut field_lists, start, &ends);
+        Ok(format!("[{}].concat()", field_lists.join(", ")))
+    }
+    
+    /// Rust `Vec<String>` expression for the fields a word expands to in a
+    /// word list: unquoted expansions are split on `IFS` and the results
+    /// go through pathname expansion, quoted text is kept as one field.
+    fn generate_fields(&mut self, word: &ASTNode) -> Result<String> {
+        if let Some(fields) = quoted_fields(word) {
+            return Ok(fields);
+        }
+        match word {
+            // Globbing also removes the backslashes of escaped characters
+            ASTNode::String(s, StringType::Unquoted, _) | ASTNode::Glob(s, _) if has_glob_chars(s) || s.contains('\\') => {
+                if split_tilde(s).is_none() {
+                    Ok(format!("runtime.expand_glob(\"{}\")?", escape_string(s)))
+                } else {
+                    let parts = self.generate_word_parts(word)?;
+                    Ok(format!("runtime.expand_word(vec![{}])?", parts.join(", ")))
+                }
+            }
+            ASTNode::String(..) | ASTNode::Glob(_, _) | ASTNode::Number(_) | ASTNode::Quoted(_, _) => {
+                Ok(format!("vec![{}.to_string()]", self.generate_node(word)?))
+            }
+            ASTNode::QualifiedGlob { pattern, qualifiers, .. } => {
+                let pattern = self.generate_match_operand(pattern, escape_glob, "shell_runtime::escape_glob")?;
+                let qualifiers: Vec<String> = qualifiers.iter().map(generate_glob_qualifier).collect();
+                Ok(format!("runtime.expand_qualified_glob({}, &[{}])", pattern, qualifiers.join(", ")))
+            }
+            ASTNode::Variable(name, _) if !self.splits_fields() && !matches!(name.as_str(), "@" | "*")
+                && !name.starts_with(|c: char| c == '_' || c.is_ascii_alphabetic()) => {
+                // `$1`, `$#` and `$?` are scalars, which give no word when empty
+                Ok(format!(
+                    "Some(runtime.get_var(\"{}\")?).filter(|value| !value.is_empty()).into_iter().collect::<Vec<String>>()",
+                    name
+                ))
+            }
+            ASTNode::Variable(name, _) if !self.splits_fields() => {
+                // An array gives its elements
+                Ok(format!("runtime.array_words(\"{}\")", name))
+            }
+            ASTNode::CommandSubstitution(_, _) if self.ast.metadata.dialect == ShellDialect::Fish => {
+                // Fish makes a word of every line of the output
+                let value = self.generate_node(word)?;
+                Ok(format!("{}.lines().map(String::from).collect::<Vec<String>>()", value))
+            }
+            ASTNode::Concat(_, _) => {
+                let parts = self.generate_word_parts(word)?;
+                Ok(format!("runtime.expand_word(vec![{}])?", parts.join(", ")))
+            }
+            other => {
+                let value = self.generate_node(other)?;
+                Ok(format!("runtime.expand_fields(&{}.to_string())?", value))
+            }
+        }
+    }
+    
+    /// Rust `shell_runtime::WordPart` expressions for the parts of a word,
+    /// for `expand_word`. A leading `~` becomes quoted text, so the home
+    /// directory isn't split or globbed.
+    fn generate_word_parts(&mut self, word: &ASTNode) -> Result<Vec<String>> {
+        let parts: Vec<&ASTNode> = match word {
+            ASTNode::Concat(parts, _) => parts.iter().map(|p| p.as_ref()).collect(),
+            other => vec![other],
+        };
+        
+        let start = self.hoisted.len();
+        let mut part_strs = Vec::new();
+        let mut ends = Vec::new();
+        for (i, part) in parts.into_iter().enumerate() {
+            match part {
+                ASTNode::String(s, StringType::Unquoted, _) | ASTNode::Glob(s, _) => {
+                    let rest = match split_tilde(s) {
+                        Some((user, rest)) if i == 0 => {
+                            part_strs.push(format!(
+                                "shell_runtime::WordPart::Quoted(runtime.tilde(\"{}\"))",
+                                escape_string(user)
+                            ));
+                            rest
+                        }
+                        _ => s.as_str(),
+                    };
+                    if !rest.is_empty() {
+                        part_strs.push(format!("shell_runtime::WordPart::Literal(\"{}\".to_string())", escape_string(rest)));
+                    }
+                }
+                ASTNode::String(s, _, _) => {
+                    part_strs.push(format!("shell_runtime::WordPart::Quoted(\"{}\".to_string())", escape_string(s)));
+                }
+                ASTNode::Number(_) => {
+                    let value = self.generate_node(part)?;
+                    part_strs.push(format!("shell_runtime::WordPart::Literal({}.to_string())", value));
+                }
+                ASTNode::Quoted(_, _) => {
+                    let value = self.generate_node(part)?;
+                    part_strs.push(format!("shell_runtime::WordPart::Quoted({}.to_string())", value));
+                }
+                ASTNode::Variable(_, _) | ASTNode::ParameterExpansion { .. } if !self.splits_fields() => {
+                    let value = self.generate_node(part)?;
+                    part_strs.push(format!("shell_runtime::WordPart::Quoted({}.to_string())", value));
+                }
+                other => {
+                    let value = self.generate_node(other)?;
+                    part_strs.push(format!("shell_runtime::WordPart::Expanded({}.to_string())", value));
+                }
+            }
+            ends.resize(part_strs.len(), self.hoisted.len());
+        }
+        self.hoist_in_order(&mut part_strs, start, &ends);
+        Ok(part_strs)
+    }
+    
+    /// Rust `Vec<String>` expression for the fields of a list of words.
+    fn generate_field_list(&mut self, words: &[Box<ASTNode>]) -> Result<String> {
+        let start = self.hoisted.len();
+        let mut field_lists = Vec::new();
+        let mut ends = Vec::new();
+        for word in words {
+            field_lists.push(self.generate_fields(word)?);
+            ends.push(self.hoisted.len());
+        }
+        self.hoist_in_order(&mut field_lists, start, &ends);
+        Ok(match field_lists.len() {
+            0 => "Vec::<String>::new()".to_string(),
+            1 => field_lists.remove(0),
+            _ => format!("[{}].concat()", field_lists.join(", ")),
+        })
+    }
+    
+    /// Arithmetic expression as a block that evaluates its own substitutions,
+    /// for expressions that are re-evaluated on every loop iteration.
+    fn generate_arithmetic_block(&mut self, expr: &ASTNode) -> Result<String> {
+        let outer_hoisted = std::mem::take(&mut self.hoisted);
+        let value = self.generate_arithmetic(expr);
+        let hoisted = std::mem::replace(&mut self.hoisted, outer_hoisted);
+        let value = value?;
+        
+        if hoisted.is_empty() {
+            Ok(value)
+        } else {
+            Ok(format!("{{ {} {} }}", hoisted.join(" "), value))
+        }
+    }
+    
+    /// Keep the words of a sequence in evaluation order. Values read inside
+    /// the statement, like `$x`, would otherwise see the side effects of a
+    /// later word evaluated ahead of it, like `$((x++))`, so they move to a
+    /// temporary among the hoisted statements. `ends` holds the length of
+    /// `self.hoisted` after each value was generated.
+    fn hoist_in_order(&mut self, values: &mut [String], start: usize, ends: &[usize]) {
+        let mut later_hoists = false;
+        // From the back, so inserting keeps the earlier positions
+        for i in (0..values.len()).rev() {
+            if later_hoists && values[i].contains("runtime.") {
+                let temp = format!("word_{}", self.temp_counter);
+                self.temp_counter += 1;
+                let value = std::mem::replace(&mut values[i], temp.clone());
+                self.hoisted.insert(ends[i], format!("let {} = {};", temp, value));
+            }
+            let begin = if i == 0 { start } else { ends[i - 1] };
+            later_hoists |= ends[i] > begin;
+        }
+    }
+    
+    /// Evaluate an arithmetic expression into a temporary ahead of the
+    /// statement, since assignments inside it need the runtime mutably.
+    fn hoist_arithmetic(&mut self, expr: &ASTNode) -> Result<String> {
+        let value = self.generate_arithmetic(expr)?;
+        let temp = format!("arith_{}", self.temp_counter);
+        self.temp_counter += 1;
+        self.hoisted.push(format!("let {}: i64 = {};", temp, value));
+        Ok(temp)
+    }
+    
+    /// Rust `i64` expression for an arithmetic expression tree.
+    ///
+    /// Operators map to wrapping integer operations like bash's 64-bit
+    /// arithmetic; division, remainder and `**` go through runtime helpers
+    /// that report division by zero and negative exponents. Anything that is
+    /// not an expression node is a word whose expansion is evaluated at
+    /// runtime, the way bash handles `$(( $a * 2 ))`.
+    fn generate_arithmetic(&mut self, expr: &ASTNode) -> Result<String> {
+        match expr {
+            ASTNode::Integer(n) if *n < 0 => Ok(format!("({}i64)", n)),
+            ASTNode::Integer(n) => Ok(format!("{}i64", n)),
+            ASTNode::Variable(name, _) => Ok(format!("runtime.arith_var(\"{}\")?", name)),
+            ASTNode::BinaryOp { left, op, right } => {
+                let left_str = self.generate_arithmetic(left)?;
+                let right_str = self.generate_arithmetic(right)?;
+                Ok(match op {
+                    BinaryOperator::Add => format!("i64::wrapping_add({}, {})", left_str, right_str),
+                    BinaryOperator::Subtract => format!("i64::wrapping_sub({}, {})", left_str, right_str),
+                    BinaryOperator::Multiply => format!("i64::wrapping_mul({}, {})", left_str, right_str),
+                    BinaryOperator::Divide => format!("shell_runtime::arith_div({}, {})?", left_str, right_str),
+                    BinaryOperator::Modulo => format!("shell_runtime::arith_rem({}, {})?", left_str, right_str),
+                    BinaryOperator::Power => format!("shell_runtime::arith_pow({}, {})?", left_str, right_str),
+                    BinaryOperator::ShiftLeft => format!("i64::wrapping_shl({}, {} as u32)", left_str, operand(right_str)),
+                    BinaryOperator::ShiftRight => format!("i64::wrapping_shr({}, {} as u32)", left_str, operand(right_str)),
+                    BinaryOperator::BitAnd => format!("{} & {}", operand(left_str), operand(right_str)),
+                    BinaryOperator::BitOr => format!("{} | {}", operand(left_str), operand(right_str)),
+                    BinaryOperator::BitXor => format!("{} ^ {}", operand(left_str), operand(right_str)),
+                    BinaryOperator::Equal => format!("i64::from({} == {})", operand(left_str), operand(right_str)),
+                    BinaryOperator::NotEqual => format!("i64::from({} != {})", operand(left_str), operand(right_str)),
+                    BinaryOperator::Less => format!("i64::from({} < {})", operand(left_str), operand(right_str)),
+                    BinaryOperator::Greater => format!("i64::from({} > {})", operand(left_str), operand(right_str)),
+                    BinaryOperator::LessEqual => format!("i64::from({} <= {})", operand(left_str), operand(right_str)),
+                    BinaryOperator::GreaterEqual => format!("i64::from({} >= {})", operand(left_str), operand(right_str)),
+                    // && and || only evaluate the right side when needed
+                    BinaryOperator::And => format!("i64::from({} != 0 && {} != 0)", operand(left_str), operand(right_str)),
+                    BinaryOperator::Or => format!("i64::from({} != 0 || {} != 0)", operand(left_str), operand(right_str)),
+                    BinaryOperator::Comma => format!("{{ let _ = {}; {} }}", left_str, right_str),
+                    _ => bail!("Operator {:?} is not valid in arithmetic", op),
+                })
+            }
+            ASTNode::UnaryOp { op, operand: value } => {
+                let var_name = match value.as_ref() {
+                    ASTNode::Variable(name, _) => Some(name.as_str()),
+                    _ => None,
+                };
+                match (op, var_name) {
+                    (UnaryOperator::PreIncrement, Some(name)) | (UnaryOperator::PreDecrement, Some(name)) => {
+                        let step = if *op == UnaryOperator::PreIncrement { "wrapping_add" } else { "wrapping_sub" };
+                        Ok(format!(
+                            "{{ let value = i64::{}(runtime.arith_var(\"{}\")?, 1); runtime.arith_assign(\"{}\", value)? }}",
+                            step, name, name
+                        ))
+                    }
+                    (UnaryOperator::PostIncrement, Some(name)) | (UnaryOperator::PostDecrement, Some(name)) => {
+                        let step = if *op == UnaryOperator::PostIncrement { "wrapping_add" } else { "wrapping_sub" };
+                        Ok(format!(
+                            "{{ let value = runtime.arith_var(\"{}\")?; runtime.arith_assign(\"{}\", i64::{}(value, 1))?; value }}",
+                            name, name, step
+                        ))
+                    }
+                    _ => {
+                        let value_str = self.generate_arithmetic(value)?;
+                        match op {
+                            UnaryOperator::Negate => Ok(format!("i64::wrapping_neg({})", value_str)),
+                            UnaryOperator::Plus => Ok(value_str),
+                            UnaryOperator::Not => Ok(format!("i64::from({} == 0)", operand(value_str))),
+                            UnaryOperator::BitNot => Ok(format!("!{}", operand(value_str))),
+                            _ => bail!("Operator {:?} needs a variable operand", op),
+                        }
+                    }
+                }
+            }
+            ASTNode::Conditional { condition, then_expr, else_expr } => {
+                let condition_str = self.generate_arithmetic(condition)?;
+                let then_str = self.generate_arithmetic(then_expr)?;
+                let else_str = self.generate_arithmetic(else_expr)?;
+                Ok(format!("if {} != 0 {{ {} }} else {{ {} }}", operand(condition_str), then_str, else_str))
+            }
+            ASTNode::ArithmeticAssignment { name, op, value } => {
+                let new_value = match op {
+                    None => self.generate_arithmetic(value)?,
+                    Some(op) => {
+                        // Reuse the binary operator code with the variable on the left
+                        let current = ASTNode::Variable(name.clone(), Span::default());
+                        let combined = ASTNode::BinaryOp {
+                            left: Box::new(current),
+                            op: op.clone(),
+                            right: value.clone(),
+                        };
+                        self.generate_arithmetic(&combined)?
+                    }
+                };
+                if let (None, ASTNode::Integer(_)) = (op, value.as_ref()) {
+                    Ok(format!("runtime.arith_assign(\"{}\", {})?", name, new_value))
+                } else {
+                    Ok(format!("{{ let value = {}; runtime.arith_assign(\"{}\", value)? }}", new_value, name))
+                }
+            }
+            word => {
+                let text = self.generate_node(word)?;
+                Ok(format!("runtime.eval_arith(&{})?", text))
+            }
+        }
+    }
+    
+    /// Rust `bool` expression for a `test`, `[ ]` or `[[ ]]` expression.
+    ///
+    /// Inside `[[ ]]` the right side of `==` and `!=` is a glob pattern, the
+    /// right side of `=~` a regular expression and integer operands are
+    /// arithmetic expressions; `[ ]` compares plain strings and decimal
+    /// integers.
+    fn generate_test(&mut self, expr: &ASTNode, extended: bool) -> Result<String> {
+        match expr {
+            ASTNode::UnaryOp { op: UnaryOperator::Not, operand: inner } => {
+                Ok(format!("!{}", operand(self.generate_test(inner, extended)?)))
+            }
+            ASTNode::UnaryOp { op: UnaryOperator::Terminal, operand: fd } => {
+                Ok(format!("shell_runtime::is_terminal({})", self.generate_test_integer(fd, extended)?))
+            }
+            ASTNode::UnaryOp { op, operand: word } => {
+                let value = self.generate_node(word)?;
+                let flag = match op {
+                    UnaryOperator::FileExists => return Ok(format!("std::path::Path::new({}).exists()", str_arg(value))),
+                    UnaryOperator::FileRegular => return Ok(format!("std::path::Path::new({}).is_file()", str_arg(value))),
+                    UnaryOperator::FileDirectory => return Ok(format!("std::path::Path::new({}).is_dir()", str_arg(value))),
+                    UnaryOperator::FileSymlink => return Ok(format!("std::path::Path::new({}).is_symlink()", str_arg(value))),
+                    UnaryOperator::StringNotEmpty => return Ok(format!("!{}.is_empty()", value)),
+                    UnaryOperator::StringEmpty => return Ok(format!("{}.is_empty()", value)),
+                    UnaryOperator::VariableSet => return Ok(format!("runtime.is_var_set({})", str_arg(value))),
+                    UnaryOperator::FileReadable => 'r',
+                    UnaryOperator::FileWritable => 'w',
+                    UnaryOperator::FileExecutable => 'x',
+                    UnaryOperator::FileNotEmpty => 's',
+                    UnaryOperator::FileBlock => 'b',
+                    UnaryOperator::FileCharacter => 'c',
+                    UnaryOperator::FilePipe => 'p',
+                    UnaryOperator::FileSocket => 'S',
+                    UnaryOperator::FileSetuid => 'u',
+                    UnaryOperator::FileSetgid => 'g',
+                    UnaryOperator::FileSticky => 'k',
+                    _ => bail!("Operator {:?} is not valid in a test", op),
+                };
+                Ok(format!("shell_runtime::file_test('{}', {})", flag, str_arg(value)))
+            }
+            ASTNode::BinaryOp { left, op, right } => {
+                match op {
+                    BinaryOperator::And | BinaryOperator::Or => {
+                        let left_str = self.generate_test(left, extended)?;
+                        let right_str = self.generate_test(right, extended)?;
+                        let joiner = if *op == BinaryOperator::And { "&&" } else { "||" };
+                        return Ok(format!("{} {} {}", operand(left_str), joiner, operand(right_str)));
+                    }
+                    BinaryOperator::Equal | BinaryOperator::NotEqual | BinaryOperator::Less |
+                    BinaryOperator::Greater | BinaryOperator::LessEqual | BinaryOperator::GreaterEqual => {
+                        let left_str = self.generate_test_integer(left, extended)?;
+                        let right_str = self.generate_test_integer(right, extended)?;
+                        let symbol = match op {
+                            BinaryOperator::Equal => "==",
+                            BinaryOperator::NotEqual => "!=",
+                            BinaryOperator::Less => "<",
+                            BinaryOperator::Greater => ">",
+                            BinaryOperator::LessEqual => "<=",
+                            _ => ">=",
+                        };
+                        return Ok(format!("{} {} {}", operand(left_str), symbol, operand(right_str)));
+                    }
+                    _ => {}
+                }
+                
+                let left_str = self.generate_node(left)?;
+                match op {
+                    BinaryOperator::StringEqual | BinaryOperator::StringNotEqual if extended => {
+                        let pattern = self.generate_match_operand(right, escape_glob, "shell_runtime::escape_glob")?;
+                        let negate = if *op == BinaryOperator::StringNotEqual { "!" } else { "" };
+                        Ok(format!("{}runtime.glob_match({}, {})", negate, pattern, str_arg(left_str)))
+                    }
+                    BinaryOperator::Match => {
+                        let regex = self.generate_match_operand(right, escape_regex, "regex::escape")?;
+                        Ok(format!("runtime.regex_match({}, {})?", str_arg(left_str), regex))
+                    }
+                    _ => {
+                        let right_str = self.generate_node(right)?;
+                        Ok(match op {
+                            BinaryOperator::StringEqual => format!("{} == {}", str_arg(left_str), str_arg(right_str)),
+                            BinaryOperator::StringNotEqual => format!("{} != {}", str_arg(left_str), str_arg(right_str)),
+                            BinaryOperator::StringLess => format!("*{} < *{}", left_str, right_str),
+                            BinaryOperator::StringGreater => format!("*{} > *{}", left_str, right_str),
+                            BinaryOperator::FileNewer => format!("shell_runtime::file_newer({}, {})", str_arg(left_str), str_arg(right_str)),
+                            BinaryOperator::FileOlder => format!("shell_runtime::file_newer({}, {})", str_arg(right_str), str_arg(left_str)),
+                            BinaryOperator::FileSame => format!("shell_runtime::same_file({}, {})", str_arg(left_str), str_arg(right_str)),
+                            _ => bail!("Operator {:?} is not valid in a test", op),
+                        })
+                    }
+                }
+            }
+            other => bail!("Unexpected {:?} in a test expression", other),
+        }
+    }
+    
+    /// Rust `i64` expression for an integer operand of a test.
+    fn generate_test_integer(&mut self, word: &ASTNode, extended: bool) -> Result<String> {
+        match word {
+            ASTNode::Number(n) if n.fract() == 0.0 => Ok(format!("{}i64", *n as i64)),
+            ASTNode::Variable(name, _) if extended => Ok(format!("runtime.arith_var(\"{}\")?", name)),
+            _ => {
+                let value = self.generate_node(word)?;
+                if extended {
+                    Ok(format!("runtime.eval_arith({})?", str_arg(value)))
+                } else {
+                    Ok(format!("shell_runtime::test_integer({})?", str_arg(value)))
+                }
+            }
+        }
+    }
+    
+    /// `&str` expression for the pattern or regular expression operand of a
+    /// `[[ ]]` match. Quoted parts are escaped so they match literally, the
+    /// values of quoted expansions by the runtime function `escape_value`;
+    /// unquoted text and expansions keep their special characters.
+    fn generate_match_operand(&mut self, word: &ASTNode, escape: fn(&str) -> String, escape_value: &str) -> Result<String> {
+        let parts: Vec<&ASTNode> = match word {
+            ASTNode::Concat(parts, _) => parts.iter().map(|p| p.as_ref()).collect(),
+            other => vec![other],
+        };
+        
+        let mut part_strs = Vec::new();
+        for part in parts {
+            match part {
+                ASTNode::String(s, StringType::Unquoted, _) | ASTNode::Glob(s, _) => {
+                    part_strs.push(format!("\"{}\"", escape_string(s)))
+                }
+                ASTNode::String(s, _, _) => part_strs.push(format!("\"{}\"", escape_string(&escape(s)))),
+                ASTNode::Quoted(quoted, _) => {
+                    for part in quoted {
+                        match part.as_ref() {
+                            ASTNode::String(s, _, _) => part_strs.push(format!("\"{}\"", escape_string(&escape(s)))),
+                            other => {
+                                let value = self.generate_node(other)?;
+                                part_strs.push(format!("{}(&{})", escape_value, value));
+                            }
+                        }
+                    }
+                }
+                other => part_strs.push(self.generate_node(other)?),
+            }
+        }
+        
+        if part_strs.len() == 1 {
+            Ok(str_arg(part_strs.pop().unwrap()))
+        } else {
+            Ok(format!("&format!(\"{}\", {})", "{}".repeat(part_strs.len()), part_strs.join(", ")))
+        }
+    }
+    
+    fn generate_assignment(
+        &mut self,
+        name: &str,
+        index: Option<&ASTNode>,
+        value: &ASTNode,
+        export: bool,
+        append: bool,
+    ) -> Result<String> {
+        if let ASTNode::Array(elements) = value {
+            return self.generate_array_assignment(name, elements, append);
+        }
+        
+        let value_str = self.generate_node(value)?;
+        if let Some(index) = index {
+            let subscript = self.generate_node(index)?;
+            let method = if append { "append_element" } else { "set_element" };
+            return Ok(format!("runtime.{}(\"{}\", {}, {})?;", method, name, str_arg(subscript), value_str));
+        }
+        if append {
+            Ok(format!("runtime.append_var(\"{}\", {})?;", name, value_str))
+        } else if export {
+            Ok(format!("runtime.export_var(\"{}\", {})?;", name, value_str))
+        } else {
+            Ok(format!("runtime.set_var(\"{}\", {})?;", name, value_str))
+        }
+    }
+    
+    /// `name=(...)` or `name+=(...)`. Keyed elements, `[key]=value`, are
+    /// set in order with the words around them.
+    fn generate_array_assignment(&mut self, name: &str, elements: &[Box<ASTNode>], append: bool) -> Result<String> {
+        let method = if append { "append_array" } else { "set_array" };
+        if !elements.iter().any(|element| matches!(element.as_ref(), ASTNode::Assignment { .. })) {
+            let fields = self.generate_field_list(elements)?;
+            return Ok(format!("runtime.{}(\"{}\", {})?;", method, name, fields));
+        }
+        
+        let mut statements = Vec::new();
+        if !append {
+            statements.push(format!("runtime.set_array(\"{}\", Vec::new())?;", name));
+        }
+        let mut words = Vec::new();
+        for element in elements {
+            match element.as_ref() {
+                ASTNode::Assignment { index, value, append, .. } => {
+                    if !words.is_empty() {
+                        let fields = self.generate_field_list(&std::mem::take(&mut words))?;
+                        statements.push(format!("runtime.append_array(\"{}\", {})?;", name, fields));
+                    }
+                    statements.push(self.generate_assignment(name, index.as_deref(), value, false, *append)?);
+                }
+                _ => words.push(element.clone()),
+            }
+        }
+        if !words.is_empty() {
+            let fields = self.generate_field_list(&words)?;
+            statements.push(format!("runtime.append_array(\"{}\", {})?;", name, fields));
+        }
+        Ok(statements.join(&format!("\n{}", self.indent())))
+    }
+    
+    /// `declare`, `typeset`, `local`, `export` or `readonly` with names and
+    /// assignments. Attributes come before the value so `-i` and `-u` apply
+    /// to it, except `-r`, which would refuse it.
+    fn generate_declaration(
+        &mut self,
+        builtin: &str,
+        attributes: &VariableAttributes,
+        removed: &VariableAttributes,
+        local: bool,
+        options: &str,
+        variables: &[Box<ASTNode>],
+    ) -> Result<String> {
+        if !options.is_empty() || variables.is_empty() {
+            // Listing variables and functions, as `declare -p` does, isn't supported
+            return Ok(format!("// TODO: {} -{}
+{}runtime.set_exit_status(0);", builtin, options, self.indent()));
+        }
+        
+        let before = VariableAttributes { readonly: false, array: false, associative: false, ..*attributes };
+        let mut statements = Vec::new();
+        for variable in variables {
+            let name = match variable.as_ref() {
+                ASTNode::String(name, _, _) | ASTNode::Assignment { name, .. } => name,
+                other => {
+                    statements.push(format!("// TODO: {} {:?}", builtin, other));
+                    continue;
+                }
+            };
+            if local {
+                statements.push(format!("runtime.declare_local(\"{}\")?;", name));
+            }
+            if attributes.array || attributes.associative {
+                statements.push(format!("runtime.declare_array(\"{}\", {})?;", name, attributes.associative));
+            }
+            if !before.is_empty() {
+                statements.push(format!("runtime.declare(\"{}\", {})?;", name, var_attributes(&before)));
+            }
+            if !removed.is_empty() {
+                statements.push(format!("runtime.remove_attributes(\"{}\", {})?;", name, var_attributes(removed)));
+            }
+            if let ASTNode::Assignment { .. } = variable.as_ref() {
+                statements.push(self.generate_node(variable)?);
+            }
+            if attributes.readonly {
+                let readonly = VariableAttributes { readonly: true, ..Default::default() };
+                statements.push(format!("runtime.declare(\"{}\", {})?;", name, var_attributes(&readonly)));
+            }
+        }
+        statements.push("runtime.set_exit_status(0);".to_string());
+        Ok(statements.join(&format!("\n{}", self.indent())))
+    }
+    
+    /// Rust `i64` expression for the offset or length of `${name:offset:length}`.
+    /// `${name...}` forms that test or transform the value of `name`. The
+    /// result is computed ahead of the statement; a default word is only
+    /// evaluated when it is used.
+    fn generate_parameter_expansion(&mut self, name: &str, expansion_type: &ExpansionType) -> Result<String> {
+        let param = self.generate_parameter_name(name)?;
+        let value = match expansion_type {
+            ExpansionType::Default { word, colon } => {
+                let word = self.generate_lazy_word(word)?;
+                format!("match runtime.lookup({}, {})? {{ Some(value) => value, None => {} }}", param, colon, word)
+            }
+            ExpansionType::Assign { word, colon } => {
+                let word = self.generate_lazy_word(word)?;
+                format!(
+                    "match runtime.lookup({}, {})? {{ Some(value) => value, None => {{ let value = {}; runtime.assign_parameter({}, &value)?; value }} }}",
+                    param, colon, word, param
+                )
+            }
+            ExpansionType::Error { message, colon } => {
+                let message = match message.as_str() {
+                    "" if *colon => "\"parameter null or not set\"".to_string(),
+                    "" => "\"parameter not set\"".to_string(),
+                    text if text.contains(['$', '`']) => {
+                        let word = ShellParser::parse_word_text(text, self.ast.metadata.dialect, Span::default())?;
+                        str_arg(self.generate_node(&word)?)
+                    }
+                    text => format!("\"{}\"", escape_string(text)),
+                };
+                format!(
+                    "match runtime.lookup({}, {})? {{ Some(value) => value, None => return Err(runtime.parameter_error({}, {})) }}",
+                    param, colon, param, message
+                )
+            }
+            ExpansionType::Alternative { word, colon } => {
+                let word = self.generate_lazy_word(word)?;
+                format!("match runtime.lookup({}, {})? {{ Some(_) => {}, None => String::new() }}", param, colon, word)
+            }
+            ExpansionType::Substring { offset, length } => {
+                let value = self.generate_parameter_value(name)?;
+                let offset = self.generate_offset(offset)?;
+                let length = match length {
+                    Some(length) => format!("Some({})", self.generate_offset(length)?),
+                    None => "None".to_string(),
+                };
+                format!("shell_runtime::substring(&{}, {}, {})?", value, offset, length)
+            }
+            ExpansionType::RemovePrefix(pattern)
+            | ExpansionType::RemovePrefixLong(pattern)
+            | ExpansionType::RemoveSuffix(pattern)
+            | ExpansionType::RemoveSuffixLong(pattern) => {
+                let suffix = matches!(expansion_type, ExpansionType::RemoveSuffix(_) | ExpansionType::RemoveSuffixLong(_));
+                let longest = matches!(expansion_type, ExpansionType::RemovePrefixLong(_) | ExpansionType::RemoveSuffixLong(_));
+                let pattern = self.generate_pattern(pattern)?;
+                let trim = format!("runtime.trim_pattern({{}}, {}, {}, {})", pattern, suffix, longest);
+                self.generate_per_value(name, &trim)?
+            }
+            ExpansionType::Replace { pattern, replacement, global } => {
+                let pattern = self.generate_pattern(pattern)?;
+                let word = ShellParser::parse_word_text(replacement, self.ast.metadata.dialect, Span::default())?;
+                let replacement = str_arg(self.generate_node(&word)?);
+                let replace = format!("runtime.replace_pattern({{}}, {}, {}, {})", pattern, replacement, global);
+                self.generate_per_value(name, &replace)?
+            }
+            ExpansionType::Indirect => format!("runtime.indirect({})?", param),
+            ExpansionType::Length
+            | ExpansionType::Keys
+            | ExpansionType::Element(_)
+            | ExpansionType::Subscript(_)
+            | ExpansionType::Flags(_) => bail!("Unsupported parameter expansion of `{}`", name),
+        };
+        
+        let temp = format!("param_{}", self.temp_counter);
+        self.temp_counter += 1;
+        self.hoisted.push(format!("let {} = {};", temp, value));
+        Ok(temp)
+    }
+    
+    /// `&str` expression for a parameter name as the runtime looks it up,
+    /// with the subscript of `name[subscript]` expanded.
+    fn generate_parameter_name(&mut self, name: &str) -> Result<String> {
+        match split_subscript(name) {
+            Some((array, subscript)) if subscript.contains(['$', '`', '"', '\'']) => {
+                let word = ShellParser::parse_word_text(subscript, self.ast.metadata.dialect, Span::default())?;
+                let subscript = self.generate_node(&word)?;
+                Ok(format!("&format!(\"{}[{{}}]\", {})", array, subscript))
+            }
+            _ => Ok(format!("\"{}\"", escape_string(name))),
+        }
+    }
+    
+    /// `String` expression for the value of a parameter, which may be an
+    /// element of an array.
+    fn generate_parameter_value(&mut self, name: &str) -> Result<String> {
+        let Some((array, subscript)) = split_subscript(name) else {
+            return Ok(format!("runtime.get_var(\"{}\")?", name));
+        };
+        let word = ShellParser::parse_word_text(subscript, self.ast.metadata.dialect, Span::default())?;
+        let subscript = self.generate_node(&word)?;
+        let temp = format!("element_{}", self.temp_counter);
+        self.temp_counter += 1;
+        self.hoisted.push(format!("let {} = runtime.element(\"{}\", {})?;", temp, array, str_arg(subscript)));
+        Ok(temp)
+    }
+    
+    /// Apply `op`, a format string taking a `&str` value, to the value of a
+    /// parameter, or to each element of `$@` and `name[@]`.
+    fn generate_per_value(&mut self, name: &str, op: &str) -> Result<String> {
+        if let Some(array) = array_name(name) {
+            let op = op.replacen("{}", "value", 1);
+            return Ok(format!(
+                "runtime.array_values(\"{}\").iter().map(|value| {}).collect::<Vec<_>>().join(\" \")",
+                array, op
+            ));
+        }
+        let value = self.generate_parameter_value(name)?;
+        Ok(op.replacen("{}", &str_arg(value), 1))
+    }
+    
+    /// `&str` expression for the pattern of a `${name%pattern}` or
+    /// `${name/pattern/...}`, with quoted parts matching literally.
+    fn generate_pattern(&mut self, pattern: &str) -> Result<String> {
+        let word = ShellParser::parse_word_text(pattern, self.ast.metadata.dialect, Span::default())?;
+        self.generate_match_operand(&word, escape_glob, "shell_runtime::escape_glob")
+    }
+    
+    /// `String` expression for a word that is only evaluated when it is
+    /// used: a block that runs the word's own substitutions first.
+    fn generate_lazy_word(&mut self, word: &ASTNode) -> Result<String> {
+        let outer = std::mem::take(&mut self.hoisted);
+        let value = self.generate_node(word);
+        let hoisted = std::mem::replace(&mut self.hoisted, outer);
+        let value = format!("String::from({})", str_arg(value?));
+        if hoisted.is_empty() {
+            return Ok(value);
+        }
+        Ok(format!("{{ {} {} }}", hoisted.join(" "), value))
+    }
+    
+    fn generate_offset(&mut self, offset: &ASTNode) -> Result<String> {
+        match offset {
+            ASTNode::Number(n) => Ok(format!("{}i64", *n as i64)),
+            other => self.hoist_arithmetic(other),
+        }
+    }
+    
+    fn generate_if(
+        &mut self,
+        condition: &ASTNode,
+        then_block: &ASTNode,
+        elif_blocks: &[(Box<ASTNode>, Box<ASTNode>)],
+        else_block: &Option<Box<ASTNode>>,
+    ) -> Result<String> {
+        let mut code = String::new();
+        
+        // Generate condition evaluation
         let cond_str = self.generate_condition(condition)?;
         code.push_str(&format!("if {} {{\n", cond_str));
         
@@ -404,86 +2045,224 @@ impl CodeGenerator {
     fn generate_while(&mut self, condition: &ASTNode, body: &ASTNode) -> Result<String> {
         let mut code = String::new();
         let cond_str = self.generate_condition(condition)?;
+        let label = self.next_loop_label();
         
-        code.push_str(&format!("while {} {{\n", cond_str));
+        code.push_str(&format!("{}: while {} {{\n", label, cond_str));
         self.indent_level += 1;
-        code.push_str(&self.generate_node(body)?);
+        self.loop_labels.push(label);
+        let body_code = self.generate_node(body);
+        self.loop_labels.pop();
+        code.push_str(&body_code?);
         self.indent_level -= 1;
         code.push_str(&format!("{}}}", self.indent()));
         
         Ok(code)
     }
     
+    /// zsh's `repeat N`: the count is evaluated once, before the loop.
+    fn generate_repeat(&mut self, count: &ASTNode, body: &ASTNode) -> Result<String> {
+        let count = self.hoist_arithmetic(count)?;
+        let label = self.next_loop_label();
+        
+        let mut code = format!("{}: for _ in 0..{} {{\n", label, count);
+        self.indent_level += 1;
+        code.push_str(&self.generate_loop_body(label, body)?);
+        self.indent_level -= 1;
+        code.push_str(&format!("{}}}", self.indent()));
+        Ok(code)
+    }
+    
+    /// The label of the loop `count` levels out, or the outermost one when
+    /// there are fewer, like bash.
+    fn loop_label(&self, count: usize) -> Option<&String> {
+        self.loop_labels.get(self.loop_labels.len().saturating_sub(count))
+    }
+    
+    fn next_loop_label(&mut self) -> String {
+        let label = format!("'loop_{}", self.temp_counter);
+        self.temp_counter += 1;
+        label
+    }
+    
+    /// Generate a loop body with `label` as the target of `break` and
+    /// `continue`.
+    fn generate_loop_body(&mut self, label: String, body: &ASTNode) -> Result<String> {
+        self.loop_labels.push(label);
+        let body_code = self.generate_node(body);
+        self.loop_labels.pop();
+        body_code
+    }
+    
     fn generate_for(&mut self, variable: &str, items: &ForItems, body: &ASTNode) -> Result<String> {
         let mut code = String::new();
         
+        let label = self.next_loop_label();
+        
         match items {
-            ForItems::List(list) => {
-                code.push_str(&format!("for {} in &[", variable));
-                let mut item_strs = Vec::new();
-                for item in list {
-                    item_strs.push(self.generate_node(item)?);
-                }
-                code.push_str(&item_strs.join(", "));
-                code.push_str("] {\n");
+            ForItems::List(_) | ForItems::Command(_) => {
+                // The word list is expanded once, before the first iteration
+                let fields = match items {
+                    ForItems::List(list) => self.generate_field_list(list)?,
+                    ForItems::Command(cmd) => self.generate_fields(cmd)?,
+                    ForItems::CStyle { .. } => unreachable!(),
+                };
                 
+                code.push_str(&format!("{}: for item in {} {{\n", label, fields));
                 self.indent_level += 1;
-                code.push_str(&format!("{}runtime.set_var(\"{}\", {})?;\n", self.indent(), variable, variable));
-                code.push_str(&self.generate_node(body)?);
+                code.push_str(&format!("{}runtime.set_var(\"{}\", item)?;\n", self.indent(), variable));
+                code.push_str(&self.generate_loop_body(label, body)?);
                 self.indent_level -= 1;
-                
                 code.push_str(&format!("{}}}", self.indent()));
             }
-            _ => {
-                code.push_str("// TODO: Complex for loop");
+            ForItems::CStyle { init, condition, update } => {
+                // The update runs at the top of every iteration but the first,
+                // so `continue` still reaches it
+                let init_str = self.generate_arithmetic_block(init)?;
+                code.push_str(&format!("let _ = {};\n", init_str));
+                code.push_str(&format!("{}let mut first_iteration = true;\n", self.indent()));
+                code.push_str(&format!("{}{}: loop {{\n", self.indent(), label));
+                self.indent_level += 1;
+                let update_str = self.generate_arithmetic_block(update)?;
+                let condition_str = self.generate_arithmetic_block(condition)?;
+                code.push_str(&format!("{}if !first_iteration {{\n", self.indent()));
+                code.push_str(&format!("{}    let _ = {};\n", self.indent(), update_str));
+                code.push_str(&format!("{}}}\n", self.indent()));
+                code.push_str(&format!("{}first_iteration = false;\n", self.indent()));
+                code.push_str(&format!("{}if {} == 0 {{\n", self.indent(), operand(condition_str)));
+                code.push_str(&format!("{}    break;\n", self.indent()));
+                code.push_str(&format!("{}}}\n", self.indent()));
+                code.push_str(&self.generate_loop_body(label, body)?);
+                self.indent_level -= 1;
+                code.push_str(&format!("{}}}", self.indent()));
             }
         }
         
         Ok(code)
     }
     
-    fn generate_function(&mut self, name: &str, body: &ASTNode) -> Result<String> {
-        let mut func_code = format!("fn shell_func_{}(runtime: &mut shell_runtime::ShellRuntime, args: &[String]) -> Result<()> {{\n", name);
+    fn generate_case(&mut self, expr: &ASTNode, cases: &[CaseItem]) -> Result<String> {
+        let label = format!("'case_{}", self.temp_counter);
+        self.temp_counter += 1;
+        let needs_fall_through = cases.iter().any(|c| c.terminator == CaseTerminator::FallThrough);
         
-        self.indent_level = 1;
-        func_code.push_str(&self.generate_node(body)?);
-        self.indent_level = 0;
+        let mut code = format!("{}: {{\n", label);
+        self.indent_level += 1;
+        code.push_str(&format!("{}let case_value = {}.to_string();\n", self.indent(), self.generate_node(expr)?));
+        if needs_fall_through {
+            code.push_str(&format!("{}let mut fall_through = false;\n", self.indent()));
+        }
         
-        func_code.push_str("    Ok(())\n");
-        func_code.push_str("}\n");
+        for case in cases {
+            // A pattern's substitutions only run when the patterns before it
+            // did not match
+            let mut tests = Vec::new();
+            for pattern in &case.patterns {
+                let outer = std::mem::take(&mut self.hoisted);
+                let pattern = self.generate_match_operand(pattern, escape_glob, "shell_runtime::escape_glob");
+                let hoisted = std::mem::replace(&mut self.hoisted, outer);
+                let test = format!("runtime.glob_match({}, &case_value)", pattern?);
+                if hoisted.is_empty() {
+                    tests.push(test);
+                } else {
+                    tests.push(format!("{{ {} {} }}", hoisted.join(" "), test));
+                }
+            }
+            if needs_fall_through {
+                tests.insert(0, "fall_through".to_string());
+            }
+            
+            code.push_str(&format!("{}if {} {{\n", self.indent(), tests.join(" || ")));
+            self.indent_level += 1;
+            code.push_str(&self.generate_node(&case.body)?);
+            match case.terminator {
+                CaseTerminator::Break => {
+                    code.push_str(&format!("{}break {};\n", self.indent(), label));
+                }
+                CaseTerminator::FallThrough => {
+                    code.push_str(&format!("{}fall_through = true;\n", self.indent()));
+                }
+                CaseTerminator::Continue if needs_fall_through => {
+                    code.push_str(&format!("{}fall_through = false;\n", self.indent()));
+                }
+                CaseTerminator::Continue => {}
+            }
+            self.indent_level -= 1;
+            code.push_str(&format!("{}}}\n", self.indent()));
+        }
         
-        self.functions.insert(name.to_string(), func_code);
+        self.indent_level -= 1;
+        code.push_str(&format!("{}}}", self.indent()));
+        Ok(code)
+    }
+    
+    fn generate_function(&mut self, name: &str, body: &ASTNode) -> Result<String> {
+        let fn_name = function_ident(name);
+        self.generate_function_item(&fn_name, name, body)?;
         
         // Register the function in runtime
-        Ok(format!("runtime.register_function(\"{}\", shell_func_{});", name, name))
+        Ok(format!("runtime.register_function(\"{}\", {});", escape_string(name), fn_name))
     }
     
-    fn generate_condition(&mut self, condition: &ASTNode) -> Result<String> {
-        // For now, generate a simple command execution check
-        match condition {
-            ASTNode::Command { name, args, .. } if name == "test" || name == "[" => {
-                // Handle test command
-                if args.len() >= 3 {
-                    // Simple file test
-                    if let (Some(flag), Some(path)) = (args.get(0), args.get(1)) {
-                        if let (ASTNode::String(flag_str, _), ASTNode::String(path_str, _)) = 
-                            (flag.as_ref(), path.as_ref()) {
-                            match flag_str.as_str() {
-                                "-f" => return Ok(format!("std::path::Path::new(\"{}\").is_file()", path_str)),
-                                "-d" => return Ok(format!("std::path::Path::new(\"{}\").is_dir()", path_str)),
-                                "-e" => return Ok(format!("std::path::Path::new(\"{}\").exists()", path_str)),
-                                _ => {}
-                            }
-                        }
-                    }
-                }
-                Ok("true /* TODO: Complex test */".to_string())
-            }
-            _ => {
-                // Execute command and check exit status
-                Ok("runtime.last_exit_status() == 0".to_string())
-            }
+    /// zsh's `() { ... } args`, a function that is called right away.
+    fn generate_anonymous_function(&mut self, body: &ASTNode, args: &[Box<ASTNode>]) -> Result<String> {
+        let fn_name = format!("shell_anon_{}", self.temp_counter);
+        self.temp_counter += 1;
+        self.generate_function_item(&fn_name, &fn_name, body)?;
+        
+        let mut arg_strs = Vec::new();
+        for arg in args {
+            arg_strs.push(format!("{}.to_string()", self.generate_node(arg)?));
+        }
+        Ok(format!("runtime.call_function(\"(anon)\", {}, &[{}])?;", fn_name, arg_strs.join(", ")))
+    }
+    
+    /// Generate the Rust function `fn_name` for a shell function body, kept
+    /// under `key` until it is written out after `script_main`.
+    fn generate_function_item(&mut self, fn_name: &str, key: &str, body: &ASTNode) -> Result<()> {
+        let mut func_code = format!("fn {}(runtime: &mut shell_runtime::ShellRuntime, args: &[String]) -> Result<()> {{\n", fn_name);
+        
+        // Any call site may redirect or capture the function's output, and
+        // loops around the definition are not loops of the body
+        let saved_redirect_depth = std::mem::replace(&mut self.redirect_depth, 1);
+        let saved_indent_level = std::mem::replace(&mut self.indent_level, 1);
+        let saved_loop_labels = std::mem::take(&mut self.loop_labels);
+        let saved_in_function = std::mem::replace(&mut self.in_function, true);
+        let body_code = match body {
+            ASTNode::Block(_) => self.generate_node(body),
+            // `name() ( ... )`, `name() if ...` and other compound commands
+            _ => self.generate_statements([body]),
+        };
+        self.in_function = saved_in_function;
+        self.loop_labels = saved_loop_labels;
+        self.indent_level = saved_indent_level;
+        self.redirect_depth = saved_redirect_depth;
+        func_code.push_str(&body_code?);
+        
+        func_code.push_str("    Ok(())\n");
+        func_code.push_str("}\n");
+        
+        self.functions.insert(key.to_string(), func_code);
+        Ok(())
+    }
+    
+    fn generate_condition(&mut self, condition: &ASTNode) -> Result<String> {
+        // Run the condition and check its exit status
+        self.indent_level += 1;
+        self.errexit_exempt += 1;
+        let statements = self.generate_statements([condition]);
+        self.errexit_exempt -= 1;
+        self.indent_level -= 1;
+        let mut statements = statements?;
+        if self.errexit {
+            statements = format!(
+                "{}    runtime.enter_condition();\n{}{}    runtime.leave_condition();\n",
+                self.indent(), statements, self.indent()
+            );
         }
+        Ok(format!(
+            "{{\n{}{}    runtime.last_exit_status() == 0\n{}}}",
+            statements, self.indent(), self.indent()
+        ))
     }
     
     fn indent(&self) -> String {
@@ -559,133 +2338,3794 @@ pub fn load_config() -> Result<Config> {
     }
 }
 
-fn config_path() -> Result<PathBuf> {
-    if let Ok(path) = std::env::var("CONFIG_PATH") {
-        Ok(PathBuf::from(path))
-    } else {
-        Ok(PathBuf::from("settings.toml"))
+fn config_path() -> Result<PathBuf> {
+    if let Ok(path) = std::env::var("CONFIG_PATH") {
+        Ok(PathBuf::from(path))
+    } else {
+        Ok(PathBuf::from("settings.toml"))
+    }
+}
+
+fn default_config() -> Config {
+    Config {
+        ui: UiConfig {
+            theme: "dracula".to_string(),
+        },
+        build: BuildConfig {
+            targets: vec!["linux_amd64".to_string()],
+            optimize: "size".to_string(),
+            strip: true,
+            compress: true,
+        },
+        notifications: NotificationConfig {
+            enabled: true,
+            desktop: true,
+        },
+        updates: UpdateConfig {
+            enabled: false,
+            check_on_start: false,
+            auto_download: false,
+        },
+    }
+}
+"#.to_string())
+    }
+    
+    fn generate_shell_runtime(&self) -> Result<String> {
+        Ok(r##"use anyhow::{Result, Context, bail};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{Cursor, PipeReader, PipeWriter, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// A redirection applied by `ShellRuntime::with_redirects`.
+pub enum Redirect {
+    Read { fd: i32, path: String },
+    Write { fd: i32, path: String, append: bool },
+    Clobber { fd: i32, path: String },  // >|, which ignores noclobber
+    ReadWrite { fd: i32, path: String },
+    Dup { fd: i32, target: i32 },
+    Close { fd: i32 },
+    Input { fd: i32, data: String },  // here-document or here-string
+}
+
+/// Ends the script with a status, or only the pipeline stage or `$(...)`
+/// it happens in. It is returned as an error so it unwinds through the
+/// generated functions.
+#[derive(Debug)]
+pub struct Exit(pub i32);
+
+impl std::fmt::Display for Exit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "exit {}", self.0)
+    }
+}
+
+impl std::error::Error for Exit {}
+
+/// The error of a pattern that matches nothing with failglob on, which
+/// abandons the top-level command it is in.
+#[derive(Debug)]
+pub struct NoMatch(pub String);
+
+impl std::fmt::Display for NoMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "no match: {}", self.0)
+    }
+}
+
+impl std::error::Error for NoMatch {}
+
+/// A `trap`: the text `trap -p` shows and the generated function for its
+/// commands, none when the text is empty and the condition is ignored.
+#[derive(Clone)]
+struct Trap {
+    text: String,
+    action: Option<fn(&mut ShellRuntime, &[String]) -> Result<()>>,
+    // Call frame that set it, for the traps functions don't inherit
+    frame: usize,
+}
+
+const SIGNALS: usize = 32;
+
+// Signals the handlers leave to `run_traps` rather than the default
+// action, and the ones that arrived since it last ran
+static DEFERRED: [AtomicBool; SIGNALS] = [const { AtomicBool::new(false) }; SIGNALS];
+static PENDING: [AtomicBool; SIGNALS] = [const { AtomicBool::new(false) }; SIGNALS];
+static HANDLED: [AtomicBool; SIGNALS] = [const { AtomicBool::new(false) }; SIGNALS];
+
+/// What a shell file descriptor currently refers to.
+#[derive(Clone)]
+enum OpenFd {
+    Std(i32),  // the process's own stdin, stdout or stderr
+    File(Arc<File>),
+    PipeIn(Arc<PipeReader>),
+    PipeOut(Arc<PipeWriter>),
+    Input(Arc<Mutex<Cursor<Vec<u8>>>>),
+    Capture,   // the innermost $(...) buffer
+    Closed,
+}
+
+/// Carries a child's output into the innermost `$(...)` buffer.
+type CapturePipe = (PipeReader, PipeWriter);
+
+/// The value of an array variable.
+#[derive(Clone)]
+enum Array {
+    // Sparse like bash's: `list[10]=x` leaves 1 to 9 unset
+    Indexed(BTreeMap<i64, String>),
+    // Keys in insertion order
+    Associative(Vec<(String, String)>),
+}
+
+impl Array {
+    fn from_values(values: Vec<String>) -> Self {
+        Array::Indexed((0..).zip(values).collect())
+    }
+    
+    fn values(&self) -> Vec<String> {
+        match self {
+            Array::Indexed(elements) => elements.values().cloned().collect(),
+            Array::Associative(entries) => entries.iter().map(|(_, value)| value.clone()).collect(),
+        }
+    }
+    
+    fn keys(&self) -> Vec<String> {
+        match self {
+            Array::Indexed(elements) => elements.keys().map(|index| index.to_string()).collect(),
+            Array::Associative(entries) => entries.iter().map(|(key, _)| key.clone()).collect(),
+        }
+    }
+    
+    fn len(&self) -> usize {
+        match self {
+            Array::Indexed(elements) => elements.len(),
+            Array::Associative(entries) => entries.len(),
+        }
+    }
+    
+    /// The index an indexed array stores `index` under: negative ones count
+    /// back from one past the last element.
+    fn position(elements: &BTreeMap<i64, String>, index: i64) -> i64 {
+        if index < 0 {
+            elements.keys().next_back().map_or(0, |last| last + 1) + index
+        } else {
+            index
+        }
+    }
+    
+    fn get(&self, key: &str) -> Option<&String> {
+        match self {
+            Array::Indexed(elements) => {
+                let index = key.trim().parse().ok()?;
+                elements.get(&Self::position(elements, index))
+            }
+            Array::Associative(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, value)| value),
+        }
+    }
+    
+    fn insert(&mut self, key: &str, value: String) {
+        if let Array::Associative(entries) = self {
+            match entries.iter_mut().find(|(k, _)| k == key) {
+                Some((_, old)) => *old = value,
+                None => entries.push((key.to_string(), value)),
+            }
+        }
+    }
+    
+    /// Add elements after the last one. An associative array takes them as
+    /// alternating keys and values.
+    fn extend(&mut self, values: Vec<String>) {
+        match self {
+            Array::Indexed(elements) => {
+                let next = elements.keys().next_back().map_or(0, |last| last + 1);
+                elements.extend((next..).zip(values));
+            }
+            Array::Associative(_) => {
+                let mut values = values.into_iter();
+                while let Some(key) = values.next() {
+                    self.insert(&key, values.next().unwrap_or_default());
+                }
+            }
+        }
+    }
+}
+
+/// What `declare -i`, `-r`, `-x`, `-l`, `-u` and `-n` say about a variable.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct VarAttributes {
+    pub integer: bool,
+    pub readonly: bool,
+    pub export: bool,
+    pub lowercase: bool,
+    pub uppercase: bool,
+    pub nameref: bool,
+}
+
+/// A variable as it was before a function made it local, put back when the
+/// function returns.
+#[derive(Clone)]
+struct SavedVar {
+    name: String,
+    value: Option<String>,
+    array: Option<Array>,
+    attributes: Option<VarAttributes>,
+}
+
+/// One command of a pipeline, run on its own thread with its own copy of
+/// the runtime, like the subshell bash forks for it.
+pub type PipelineStage = Box<dyn FnOnce(&mut ShellRuntime) -> Result<()> + Send>;
+
+pub fn stage<F>(f: F) -> PipelineStage
+where
+    F: FnOnce(&mut ShellRuntime) -> Result<()> + Send + 'static,
+{
+    Box::new(f)
+}
+
+/// A command started with `&`, until `wait` or `jobs` reports it.
+#[derive(Clone)]
+struct Job {
+    id: usize,
+    pid: u32,
+    command: String,
+    handle: Arc<Mutex<JobHandle>>,
+}
+
+enum JobHandle {
+    Process(std::process::Child),
+    // A job that isn't a single command runs on a thread, like a pipeline stage
+    Thread(Option<std::thread::JoinHandle<i32>>),
+    // The exit status, and the signal that ended the process
+    Done(i32, Option<i32>),
+}
+
+impl JobHandle {
+    /// The status once the job has finished, without blocking.
+    fn poll(&mut self) -> Result<Option<i32>> {
+        match self {
+            JobHandle::Process(child) => {
+                if let Some(status) = child.try_wait()? {
+                    #[cfg(unix)]
+                    let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+                    #[cfg(not(unix))]
+                    let signal = None;
+                    *self = JobHandle::Done(exit_code(status), signal);
+                }
+            }
+            JobHandle::Thread(handle) => {
+                if handle.as_ref().is_some_and(|handle| handle.is_finished()) {
+                    let status = handle.take().map_or(1, |handle| handle.join().unwrap_or(1));
+                    *self = JobHandle::Done(status, None);
+                }
+            }
+            JobHandle::Done(..) => {}
+        }
+        Ok(match self {
+            JobHandle::Done(status, _) => Some(*status),
+            _ => None,
+        })
+    }
+    
+    /// The state column of `jobs`.
+    fn state(&self) -> String {
+        match self {
+            JobHandle::Done(_, Some(signal)) => signal_description(*signal),
+            JobHandle::Done(0, None) => "Done".to_string(),
+            JobHandle::Done(status, None) => format!("Exit {}", status),
+            _ => "Running".to_string(),
+        }
+    }
+}
+
+// Thread jobs have no process, so `$!` gets a number above any real pid
+const FIRST_THREAD_PID: u32 = 1 << 22;
+
+#[derive(Clone)]
+pub struct ShellRuntime {
+    variables: HashMap<String, String>,
+    functions: HashMap<String, fn(&mut ShellRuntime, &[String]) -> Result<()>>,
+    args: Vec<String>,
+    last_exit_status: i32,
+    pipe_status: Vec<i32>,
+    pipefail: bool,
+    // Output of the `$(...)` being run, behind a cell so that reporting an
+    // unbound variable can write to a captured stderr
+    capture_stack: RefCell<Vec<String>>,
+    fds: HashMap<i32, OpenFd>,
+    extglob: bool,
+    arith_depth: usize,
+    rematch: Vec<String>,
+    arrays: HashMap<String, Array>,
+    ksh_arrays: bool,
+    nullglob: bool,
+    failglob: bool,
+    dotglob: bool,
+    nocaseglob: bool,
+    nocasematch: bool,
+    globstar: bool,
+    errexit: bool,
+    nounset: bool,
+    xtrace: bool,
+    noglob: bool,
+    noclobber: bool,
+    // Nonzero while running a condition, where errexit doesn't apply
+    condition_depth: usize,
+    // Where xtrace writes while a command's redirections are applied
+    trace_fd: Option<OpenFd>,
+    attributes: HashMap<String, VarAttributes>,
+    scopes: Vec<Vec<SavedVar>>,
+    // Names of the functions being called, innermost last
+    call_stack: Vec<String>,
+    // Call frame of the running function, 0 outside functions, and the
+    // number of calls so far
+    frame: usize,
+    frame_count: usize,
+    traps: HashMap<String, Trap>,
+    in_trap: bool,
+    errtrace: bool,
+    functrace: bool,
+    // A pipeline stage, which leaves signals to the main thread
+    in_subshell: bool,
+    jobs: Vec<Job>,
+    // Statuses of jobs that are gone from the table, for `wait $pid`
+    reaped: HashMap<u32, i32>,
+    next_thread_pid: u32,
+    // `$LINENO`, when the script reads it
+    lineno: usize,
+    current_dir: PathBuf,
+}
+
+impl ShellRuntime {
+    pub fn new(args: Vec<String>) -> Result<Self> {
+        let current_dir = env::current_dir()?;
+        
+        let mut runtime = Self {
+            variables: HashMap::new(),
+            functions: HashMap::new(),
+            args,
+            last_exit_status: 0,
+            pipe_status: Vec::new(),
+            pipefail: false,
+            capture_stack: RefCell::new(Vec::new()),
+            fds: HashMap::new(),
+            extglob: false,
+            arith_depth: 0,
+            rematch: Vec::new(),
+            arrays: HashMap::new(),
+            ksh_arrays: true,
+            nullglob: false,
+            failglob: false,
+            dotglob: false,
+            nocaseglob: false,
+            nocasematch: false,
+            globstar: false,
+            errexit: false,
+            nounset: false,
+            xtrace: false,
+            noglob: false,
+            noclobber: false,
+            condition_depth: 0,
+            trace_fd: None,
+            attributes: HashMap::new(),
+            scopes: Vec::new(),
+            call_stack: Vec::new(),
+            frame: 0,
+            frame_count: 0,
+            traps: HashMap::new(),
+            in_trap: false,
+            errtrace: false,
+            functrace: false,
+            in_subshell: false,
+            jobs: Vec::new(),
+            reaped: HashMap::new(),
+            next_thread_pid: FIRST_THREAD_PID,
+            lineno: 0,
+            current_dir,
+        };
+        
+        // Initialize environment variables
+        for (key, value) in env::vars() {
+            runtime.attributes.insert(key.clone(), VarAttributes { export: true, ..Default::default() });
+            runtime.variables.insert(key, value);
+        }
+        
+        let program = env::args().next().unwrap_or_default();
+        runtime.variables.insert("0".to_string(), program);
+        
+        Ok(runtime)
+    }
+    
+    pub fn get_var(&self, name: &str) -> Result<String> {
+        match name {
+            "?" => return Ok(self.last_exit_status.to_string()),
+            "@" => return Ok(self.args.join(" ")),
+            "*" => return Ok(self.join_star(&self.args)),
+            "-" => return Ok(self.option_letters()),
+            "$" => return Ok(std::process::id().to_string()),
+            "LINENO" => return Ok(self.lineno.to_string()),
+            _ => {}
+        }
+        if let Some(value) = self.positional(name) {
+            return match value {
+                Some(value) => Ok(value),
+                None => self.unbound(name),
+            };
+        }
+        if let Some(array) = self.arrays.get(name) {
+            // A ksh array name is its element 0, a zsh one all of them
+            return Ok(if self.ksh_arrays {
+                array.get("0").cloned().unwrap_or_default()
+            } else {
+                array.values().join(" ")
+            });
+        }
+        if let Some((array, index)) = name.strip_suffix(']').and_then(|name| name.split_once('[')) {
+            match (index, self.arrays.get(array)) {
+                ("@", _) => return Ok(self.array_values(array).join(" ")),
+                ("*", _) => return Ok(self.join_star(&self.array_values(array))),
+                (key, Some(values @ Array::Associative(_))) => {
+                    return Ok(values.get(key).cloned().unwrap_or_default());
+                }
+                (index, Some(_)) => if let Ok(index) = index.parse() {
+                    return Ok(self.array_element(array, index));
+                },
+                (_, None) => {}
+            }
+        }
+        let name = self.resolve_name(name);
+        match self.variables.get(name) {
+            Some(value) => Ok(value.clone()),
+            None => self.unbound(name),
+        }
+    }
+    
+    /// The value of an unset variable: empty, or with nounset a message
+    /// and exit status 1.
+    fn unbound(&self, name: &str) -> Result<String> {
+        if self.nounset {
+            self.write_fd(2, &format!("{}: unbound variable\n", name))?;
+            return Err(Exit(1).into());
+        }
+        Ok(String::new())
+    }
+    
+    /// `$#`, or `$1`, `$2`, ... of the function being called or else the
+    /// script, with `None` inside for one past the last. `None` for other
+    /// names.
+    fn positional(&self, name: &str) -> Option<Option<String>> {
+        if name == "#" {
+            return Some(Some(self.args.len().to_string()));
+        }
+        match name.parse::<usize>() {
+            Ok(n) if n > 0 && name.bytes().all(|b| b.is_ascii_digit()) => Some(self.args.get(n - 1).cloned()),
+            _ => None,
+        }
+    }
+    
+    /// The variable `name` stands for: the one a nameref points at, or
+    /// `name` itself.
+    fn resolve_name<'a>(&'a self, mut name: &'a str) -> &'a str {
+        // bash stops following a loop of namerefs too
+        for _ in 0..16 {
+            match (self.attributes.get(name), self.variables.get(name)) {
+                (Some(attributes), Some(target)) if attributes.nameref && !target.is_empty() => name = target,
+                _ => break,
+            }
+        }
+        name
+    }
+    
+    /// The attributes of a variable about to be assigned, or `None` after
+    /// reporting that it is readonly. Like bash outside POSIX mode, the
+    /// script goes on with status 1.
+    fn writable(&mut self, name: &str) -> Result<Option<VarAttributes>> {
+        let attributes = self.attributes.get(name).copied().unwrap_or_default();
+        if attributes.readonly {
+            self.report_readonly(&format!("{}: readonly variable", name))?;
+            return Ok(None);
+        }
+        Ok(Some(attributes))
+    }
+    
+    fn is_readonly(&self, name: &str) -> bool {
+        self.attributes.get(name).is_some_and(|attributes| attributes.readonly)
+    }
+    
+    fn report_readonly(&mut self, message: &str) -> Result<()> {
+        self.write_err(&format!("{}\n", message))?;
+        self.last_exit_status = 1;
+        Ok(())
+    }
+    
+    /// A value as stored in a variable with `attributes`: evaluated as
+    /// arithmetic for `-i`, then case folded for `-l` or `-u`.
+    fn apply_attributes(&mut self, attributes: VarAttributes, value: String) -> Result<String> {
+        let value = if attributes.integer {
+            self.eval_arith(&value)?.to_string()
+        } else {
+            value
+        };
+        Ok(if attributes.lowercase {
+            value.to_lowercase()
+        } else if attributes.uppercase {
+            value.to_uppercase()
+        } else {
+            value
+        })
+    }
+    
+    /// `name=value`. Assigning to an indexed array without a subscript sets
+    /// element 0 in bash and replaces the array in zsh.
+    pub fn set_var(&mut self, name: &str, value: impl Into<String>) -> Result<()> {
+        let name = self.resolve_name(name).to_string();
+        let Some(attributes) = self.writable(&name)? else {
+            return Ok(());
+        };
+        let value = self.apply_attributes(attributes, value.into())?;
+        if self.ksh_arrays {
+            if let Some(Array::Indexed(elements)) = self.arrays.get_mut(&name) {
+                elements.insert(0, value);
+                return Ok(());
+            }
+        }
+        self.arrays.remove(&name);
+        if attributes.export {
+            env::set_var(&name, &value);
+        }
+        self.variables.insert(name, value);
+        Ok(())
+    }
+    
+    pub fn export_var(&mut self, name: &str, value: impl Into<String>) -> Result<()> {
+        self.set_var(name, value)?;
+        self.declare(name, VarAttributes { export: true, ..Default::default() })
+    }
+    
+    /// `name+=value` for a scalar, or for element 0 of an indexed array.
+    /// An integer variable adds instead.
+    pub fn append_var(&mut self, name: &str, value: impl AsRef<str>) -> Result<()> {
+        let name = self.resolve_name(name).to_string();
+        if self.arrays.contains_key(&name) {
+            return self.append_element(&name, "0", value);
+        }
+        let current = self.variables.get(&name).cloned().unwrap_or_default();
+        if self.attributes.get(&name).is_some_and(|attributes| attributes.integer) {
+            let sum = self.eval_arith(&current)?.wrapping_add(self.eval_arith(value.as_ref())?);
+            return self.set_var(&name, sum.to_string());
+        }
+        self.set_var(&name, current + value.as_ref())
+    }
+    
+    /// The `unset` builtin, for scalars, arrays and array elements alike.
+    /// Unsetting a nameref unsets the variable it points at.
+    pub fn unset_var(&mut self, name: &str) -> Result<()> {
+        if let Some((array, key)) = name.strip_suffix(']').and_then(|name| name.split_once('[')) {
+            let array = self.resolve_name(array).to_string();
+            if self.is_readonly(&array) {
+                return self.report_readonly(&format!("unset: {}: cannot unset: readonly variable", array));
+            }
+            match self.arrays.get_mut(&array) {
+                Some(Array::Indexed(elements)) => {
+                    if let Ok(index) = key.trim().parse() {
+                        elements.remove(&Array::position(elements, index));
+                    }
+                }
+                Some(Array::Associative(entries)) => entries.retain(|(k, _)| k != key),
+                None => {}
+            }
+            return Ok(());
+        }
+        let name = self.resolve_name(name).to_string();
+        if self.is_readonly(&name) {
+            return self.report_readonly(&format!("unset: {}: cannot unset: readonly variable", name));
+        }
+        self.variables.remove(&name);
+        self.arrays.remove(&name);
+        self.attributes.remove(&name);
+        env::remove_var(&name);
+        Ok(())
+    }
+    
+    /// `declare -i name` and the like: give `name` more attributes. `-l`
+    /// and `-u` replace each other, and exporting puts the current value in
+    /// the environment.
+    pub fn declare(&mut self, name: &str, attributes: VarAttributes) -> Result<()> {
+        let current = self.attributes.entry(name.to_string()).or_default();
+        if attributes.lowercase {
+            current.uppercase = false;
+        }
+        if attributes.uppercase {
+            current.lowercase = false;
+        }
+        current.integer |= attributes.integer;
+        current.readonly |= attributes.readonly;
+        current.export |= attributes.export;
+        current.lowercase |= attributes.lowercase;
+        current.uppercase |= attributes.uppercase;
+        current.nameref |= attributes.nameref;
+        if attributes.export {
+            if let Some(value) = self.variables.get(name) {
+                env::set_var(name, value);
+            }
+        }
+        Ok(())
+    }
+    
+    /// `declare +i name` and the like, or `export -n name`: take attributes
+    /// away. Nothing takes away `-r`.
+    pub fn remove_attributes(&mut self, name: &str, attributes: VarAttributes) -> Result<()> {
+        if attributes.readonly && self.is_readonly(name) {
+            return self.report_readonly(&format!("{}: readonly variable", name));
+        }
+        let current = self.attributes.entry(name.to_string()).or_default();
+        current.integer &= !attributes.integer;
+        current.export &= !attributes.export;
+        current.lowercase &= !attributes.lowercase;
+        current.uppercase &= !attributes.uppercase;
+        current.nameref &= !attributes.nameref;
+        if attributes.export {
+            env::remove_var(name);
+        }
+        Ok(())
+    }
+    
+    /// `local name`: the running function gets its own, unset `name` until
+    /// it returns. Scoping is dynamic, so the functions it calls see the
+    /// local one too. Outside a function the variable stays global.
+    pub fn declare_local(&mut self, name: &str) -> Result<()> {
+        match self.scopes.last() {
+            Some(scope) if !scope.iter().any(|saved| saved.name == name) => {}
+            _ => return Ok(()),
+        }
+        if self.is_readonly(name) {
+            return self.report_readonly(&format!("local: {}: readonly variable", name));
+        }
+        let saved = SavedVar {
+            name: name.to_string(),
+            value: self.variables.remove(name),
+            array: self.arrays.remove(name),
+            attributes: self.attributes.remove(name),
+        };
+        if saved.attributes.is_some_and(|attributes| attributes.export) {
+            env::remove_var(name);
+        }
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(saved);
+        }
+        Ok(())
+    }
+    
+    /// Call a shell function with `args` as its positional parameters, in
+    /// a new scope for its `local` variables. The caller's parameters and
+    /// the variables the locals shadowed come back when it returns, and
+    /// `$?` is the status of its last command or `return`.
+    pub fn call_function(&mut self, name: &str, func: fn(&mut ShellRuntime, &[String]) -> Result<()>, args: &[String]) -> Result<()> {
+        let caller_args = std::mem::replace(&mut self.args, args.to_vec());
+        self.call_stack.push(name.to_string());
+        self.update_funcname();
+        self.scopes.push(Vec::new());
+        let caller_frame = self.frame;
+        self.frame_count += 1;
+        self.frame = self.frame_count;
+        let mut result = func(self, args);
+        if result.is_ok() {
+            if let Some(trap) = self.shell_trap("RETURN", self.functrace) {
+                result = self.run_trap(&trap);
+            }
+        }
+        self.frame = caller_frame;
+        self.args = caller_args;
+        self.call_stack.pop();
+        self.update_funcname();
+        for saved in self.scopes.pop().unwrap_or_default() {
+            let exported = saved.attributes.is_some_and(|attributes| attributes.export);
+            match (&saved.value, exported) {
+                (Some(value), true) => env::set_var(&saved.name, value),
+                _ => env::remove_var(&saved.name),
+            }
+            match saved.value {
+                Some(value) => self.variables.insert(saved.name.clone(), value),
+                None => self.variables.remove(&saved.name),
+            };
+            match saved.array {
+                Some(array) => self.arrays.insert(saved.name.clone(), array),
+                None => self.arrays.remove(&saved.name),
+            };
+            match saved.attributes {
+                Some(attributes) => self.attributes.insert(saved.name, attributes),
+                None => self.attributes.remove(&saved.name),
+            };
+        }
+        result
+    }
+    
+    /// `FUNCNAME`: the functions being called, innermost first, then `main`
+    /// for the script. Unset outside functions.
+    fn update_funcname(&mut self) {
+        if self.call_stack.is_empty() {
+            self.arrays.remove("FUNCNAME");
+            return;
+        }
+        let names = self.call_stack.iter().rev().cloned().chain(std::iter::once("main".to_string()));
+        self.arrays.insert("FUNCNAME".to_string(), Array::from_values(names.collect()));
+    }
+    
+    /// `shift n`: drop the first `n` positional parameters of the function
+    /// being called, or of the script. Shifting more than there are fails
+    /// without a message, as in bash.
+    pub fn shift(&mut self, count: &str) -> Result<()> {
+        let count = match count.trim().parse::<i64>() {
+            Ok(count) if count < 0 => {
+                self.write_err(&format!("shift: {}: shift count out of range\n", count))?;
+                self.last_exit_status = 1;
+                return Ok(());
+            }
+            Ok(count) => count as usize,
+            Err(_) => {
+                self.write_err(&format!("shift: {}: numeric argument required\n", count))?;
+                self.last_exit_status = 1;
+                return Ok(());
+            }
+        };
+        if count > self.args.len() {
+            self.last_exit_status = 1;
+            return Ok(());
+        }
+        self.args.drain(..count);
+        self.last_exit_status = 0;
+        Ok(())
+    }
+    
+    /// The status of `return n`, which is `n` modulo 256 like an exit
+    /// status, or 2 when `n` isn't a number.
+    pub fn set_return_status(&mut self, value: &str) -> Result<()> {
+        match value.trim().parse::<i64>() {
+            Ok(status) => self.last_exit_status = status.rem_euclid(256) as i32,
+            Err(_) => {
+                self.write_err(&format!("return: {}: numeric argument required\n", value))?;
+                self.last_exit_status = 2;
+            }
+        }
+        Ok(())
+    }
+    
+    /// `declare -a name` or `declare -A name`. An existing array keeps its
+    /// elements and a scalar becomes element 0 of an indexed array.
+    pub fn declare_array(&mut self, name: &str, associative: bool) -> Result<()> {
+        match (self.arrays.get(name), associative) {
+            (Some(Array::Indexed(_)), false) | (Some(Array::Associative(_)), true) => return Ok(()),
+            (Some(Array::Indexed(_)), true) => bail!("{}: cannot convert indexed to associative array", name),
+            (Some(Array::Associative(_)), false) => bail!("{}: cannot convert associative to indexed array", name),
+            (None, _) => {}
+        }
+        let array = match self.variables.remove(name) {
+            Some(value) if associative => Array::Associative(vec![("0".to_string(), value)]),
+            Some(value) => Array::from_values(vec![value]),
+            None if associative => Array::Associative(Vec::new()),
+            None => Array::Indexed(BTreeMap::new()),
+        };
+        self.arrays.insert(name.to_string(), array);
+        Ok(())
+    }
+    
+    /// `name=(a b c)`. An associative array takes the words as alternating
+    /// keys and values.
+    pub fn set_array(&mut self, name: &str, values: Vec<String>) -> Result<()> {
+        let name = self.resolve_name(name).to_string();
+        let Some(values) = self.array_attributes(&name, values)? else {
+            return Ok(());
+        };
+        self.variables.remove(&name);
+        let mut array = match self.arrays.get(&name) {
+            Some(Array::Associative(_)) => Array::Associative(Vec::new()),
+            _ => Array::Indexed(BTreeMap::new()),
+        };
+        array.extend(values);
+        self.arrays.insert(name, array);
+        Ok(())
+    }
+    
+    /// `name+=(d e)`: add elements after the last one. A scalar becomes
+    /// element 0.
+    pub fn append_array(&mut self, name: &str, values: Vec<String>) -> Result<()> {
+        let name = self.resolve_name(name).to_string();
+        let Some(values) = self.array_attributes(&name, values)? else {
+            return Ok(());
+        };
+        if !self.arrays.contains_key(&name) {
+            let current = self.variables.remove(&name).into_iter().collect();
+            self.arrays.insert(name.clone(), Array::from_values(current));
+        }
+        if let Some(array) = self.arrays.get_mut(&name) {
+            array.extend(values);
+        }
+        Ok(())
+    }
+    
+    /// The words of `name=(...)` as stored with the array's attributes, or
+    /// `None` for a readonly array. The keys of an associative array are
+    /// left alone.
+    fn array_attributes(&mut self, name: &str, values: Vec<String>) -> Result<Option<Vec<String>>> {
+        let Some(attributes) = self.writable(name)? else {
+            return Ok(None);
+        };
+        let associative = matches!(self.arrays.get(name), Some(Array::Associative(_)));
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| {
+                if associative && i % 2 == 0 {
+                    Ok(value)
+                } else {
+                    self.apply_attributes(attributes, value)
+                }
+            })
+            .collect::<Result<_>>()
+            .map(Some)
+    }
+    
+    /// `name[subscript]=value`. The subscript is the key of an associative
+    /// array and an arithmetic expression for any other variable, which
+    /// becomes an indexed array. zsh indexes count from 1.
+    pub fn set_element(&mut self, name: &str, subscript: &str, value: impl Into<String>) -> Result<()> {
+        let name = self.resolve_name(name).to_string();
+        let Some(attributes) = self.writable(&name)? else {
+            return Ok(());
+        };
+        let value = self.apply_attributes(attributes, value.into())?;
+        if let Some(array @ Array::Associative(_)) = self.arrays.get_mut(&name) {
+            array.insert(subscript, value);
+            return Ok(());
+        }
+        
+        let index = self.eval_arith(subscript)?;
+        if !self.arrays.contains_key(&name) {
+            let current = self.variables.remove(&name).into_iter().collect();
+            self.arrays.insert(name.clone(), Array::from_values(current));
+        }
+        let ksh_arrays = self.ksh_arrays;
+        if let Some(Array::Indexed(elements)) = self.arrays.get_mut(&name) {
+            let position = match index {
+                0 if !ksh_arrays => bail!("{}[0]: assignment to invalid subscript range", name),
+                index if index > 0 && !ksh_arrays => {
+                    // zsh arrays have no gaps
+                    for gap in elements.len() as i64..index - 1 {
+                        elements.insert(gap, String::new());
+                    }
+                    index - 1
+                }
+                index => Array::position(elements, index),
+            };
+            if position < 0 {
+                bail!("{}[{}]: bad array subscript", name, subscript);
+            }
+            elements.insert(position, value);
+        }
+        Ok(())
+    }
+    
+    /// `name[subscript]+=value`.
+    pub fn append_element(&mut self, name: &str, subscript: &str, value: impl AsRef<str>) -> Result<()> {
+        let current = self.element(name, subscript)?;
+        let name = self.resolve_name(name).to_string();
+        if self.attributes.get(&name).is_some_and(|attributes| attributes.integer) {
+            let sum = self.eval_arith(&current)?.wrapping_add(self.eval_arith(value.as_ref())?);
+            return self.set_element(&name, subscript, sum.to_string());
+        }
+        self.set_element(&name, subscript, current + value.as_ref())
+    }
+    
+    /// bash's `${name[subscript]}`: the key of an associative array, or an
+    /// arithmetic index into any other variable.
+    pub fn element(&mut self, name: &str, subscript: &str) -> Result<String> {
+        let name = self.resolve_name(name).to_string();
+        if let Some(array @ Array::Associative(_)) = self.arrays.get(&name) {
+            return Ok(array.get(subscript).cloned().unwrap_or_default());
+        }
+        let index = self.eval_arith(subscript)?;
+        Ok(self.array_element(&name, index))
+    }
+    
+    /// The elements of an array, or a set scalar as a single element. `@`
+    /// and `*` are the positional parameters.
+    pub fn array_values(&self, name: &str) -> Vec<String> {
+        if matches!(name, "@" | "*") {
+            return self.args.clone();
+        }
+        let name = self.resolve_name(name);
+        match self.arrays.get(name) {
+            Some(array) => array.values(),
+            None => self.variables.get(name).cloned().into_iter().collect(),
+        }
+    }
+    
+    /// `${!name[@]}`: the indexes or keys of an array.
+    pub fn array_keys(&self, name: &str) -> Vec<String> {
+        let name = self.resolve_name(name);
+        match self.arrays.get(name) {
+            Some(array) => array.keys(),
+            None if self.variables.contains_key(name) => vec!["0".to_string()],
+            None => Vec::new(),
+        }
+    }
+    
+    /// `${#name[@]}`: the number of elements.
+    pub fn array_len(&self, name: &str) -> usize {
+        let name = self.resolve_name(name);
+        match self.arrays.get(name) {
+            Some(array) => array.len(),
+            None => self.array_values(name).len(),
+        }
+    }
+    
+    /// zsh's `${#name}`: the number of elements of an array, or the length
+    /// of a scalar.
+    pub fn zsh_length(&self, name: &str) -> Result<usize> {
+        match self.arrays.get(self.resolve_name(name)) {
+            Some(array) => Ok(array.len()),
+            None => Ok(self.get_var(name)?.chars().count()),
+        }
+    }
+    
+    /// `${name[@]:offset:length}`: the elements from index `offset` on. A
+    /// negative offset counts back from the end, and for `@` index 0 is `$0`.
+    pub fn array_slice(&self, name: &str, offset: i64, length: Option<i64>) -> Result<Vec<String>> {
+        let elements: Vec<(i64, String)> = match self.arrays.get(name) {
+            Some(Array::Indexed(elements)) => elements.iter().map(|(index, value)| (*index, value.clone())).collect(),
+            _ if matches!(name, "@" | "*") => {
+                let program = self.variables.get("0").cloned().unwrap_or_default();
+                (0..).zip(std::iter::once(program).chain(self.args.iter().cloned())).collect()
+            }
+            _ => (0..).zip(self.array_values(name)).collect(),
+        };
+        let start = if offset < 0 {
+            elements.last().map_or(0, |(last, _)| last + 1) + offset
+        } else {
+            offset
+        };
+        let length = match length {
+            Some(length) if length < 0 => bail!("{}: substring expression < 0", length),
+            Some(length) => usize::try_from(length).unwrap_or(usize::MAX),
+            None => usize::MAX,
+        };
+        if start < 0 {
+            return Ok(Vec::new());
+        }
+        Ok(elements
+            .into_iter()
+            .filter(|(index, _)| *index >= start)
+            .take(length)
+            .map(|(_, value)| value)
+            .collect())
+    }
+    
+    /// The words of an unquoted zsh `$name`: the non-empty elements, without
+    /// field splitting.
+    pub fn array_words(&self, name: &str) -> Vec<String> {
+        let mut values = self.array_values(name);
+        values.retain(|value| !value.is_empty());
+        values
+    }
+    
+    /// `${name[index]}`. Indexes count from 0 with ksh arrays and from 1
+    /// otherwise; negative ones count back from the last element. ksh
+    /// arrays may have gaps, which are empty.
+    pub fn array_element(&self, name: &str, index: i64) -> String {
+        if self.ksh_arrays {
+            if let Some(Array::Indexed(elements)) = self.arrays.get(name) {
+                return elements.get(&Array::position(elements, index)).cloned().unwrap_or_default();
+            }
+        }
+        let values = self.array_values(name);
+        let position = if index < 0 {
+            values.len() as i64 + index
+        } else if self.ksh_arrays {
+            index
+        } else {
+            index - 1
+        };
+        usize::try_from(position)
+            .ok()
+            .and_then(|position| values.get(position).cloned())
+            .unwrap_or_default()
+    }
+    
+    pub fn set_ksh_arrays(&mut self, enabled: bool) {
+        self.ksh_arrays = enabled;
+    }
+    
+    /// zsh's `${(flags)name}`. Splitting comes first and drops empty
+    /// elements, joining comes last and defaults to a space.
+    pub fn expand_flags(&self, name: &str, flags: &[ParamFlag]) -> String {
+        let mut values = self.array_values(name);
+        for flag in flags {
+            if let ParamFlag::Split(separator) = flag {
+                values = values
+                    .iter()
+                    .flat_map(|value| value.split(separator))
+                    .filter(|value| !value.is_empty())
+                    .map(String::from)
+                    .collect();
+            }
+        }
+        
+        for flag in flags {
+            match flag {
+                ParamFlag::Upper => values = values.iter().map(|value| value.to_uppercase()).collect(),
+                ParamFlag::Lower => values = values.iter().map(|value| value.to_lowercase()).collect(),
+                ParamFlag::Capitalize => values = values.iter().map(|value| capitalize_words(value)).collect(),
+                ParamFlag::Unique => {
+                    let mut seen = std::collections::HashSet::new();
+                    values.retain(|value| seen.insert(value.clone()));
+                }
+                ParamFlag::Sort => values.sort(),
+                ParamFlag::SortDescending => {
+                    values.sort();
+                    values.reverse();
+                }
+                ParamFlag::Join(_) | ParamFlag::Split(_) => {}
+            }
+        }
+        
+        let separator = flags
+            .iter()
+            .rev()
+            .find_map(|flag| match flag {
+                ParamFlag::Join(separator) => Some(*separator),
+                _ => None,
+            })
+            .unwrap_or(" ");
+        values.join(separator)
+    }
+    
+    /// The `cd` builtin. A directory that can't be entered is reported and
+    /// sets status 1.
+    pub fn change_dir(&mut self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Err(e) = env::set_current_dir(path) {
+            self.write_err(&format!("cd: {}: {}\n", path.display(), io_error_message(&e)))?;
+            self.set_exit_status(1);
+            return Ok(());
+        }
+        self.current_dir = env::current_dir()?;
+        self.set_exit_status(0);
+        Ok(())
+    }
+    
+    pub fn change_dir_home(&mut self) -> Result<()> {
+        let home = dirs::home_dir()
+            .context("Failed to get home directory")?;
+        self.change_dir(home)
+    }
+    
+    pub fn execute_command(&mut self, cmd: &str, args: &[impl AsRef<str>]) -> Result<()> {
+        self.trace(cmd, args)?;
+        if let Some(&func) = self.functions.get(cmd) {
+            let args: Vec<String> = args.iter().map(|s| s.as_ref().to_string()).collect();
+            return self.call_function(cmd, func, &args);
+        }
+        
+        self.run_process(cmd, args)
+    }
+    
+    fn run_process(&mut self, cmd: &str, args: &[impl AsRef<str>]) -> Result<()> {
+        let mut command = Command::new(cmd);
+        command.args(args.iter().map(|s| s.as_ref()));
+        let (input, capture) = self.wire_stdio(&mut command)?;
+        
+        std::io::stdout().flush()?;
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                drop(command);
+                return self.report_spawn_error(cmd, &e);
+            }
+        };
+        // Our copies of the pipe ends must go away for the reader to see EOF
+        drop(command);
+        
+        let writer = match (input, child.stdin.take()) {
+            (Some(data), Some(mut stdin)) => Some(std::thread::spawn(move || {
+                let _ = stdin.write_all(&data);
+            })),
+            _ => None,
+        };
+        
+        if let Some((mut reader, writer)) = capture {
+            drop(writer);
+            let mut output = Vec::new();
+            reader.read_to_end(&mut output)?;
+            if let Some(buffer) = self.capture_stack.get_mut().last_mut() {
+                buffer.push_str(&String::from_utf8_lossy(&output));
+            }
+        }
+        
+        let status = child.wait()?;
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+        self.set_exit_status(exit_code(status));
+        
+        Ok(())
+    }
+    
+    /// Wire a child's stdin, stdout and stderr to our file descriptors.
+    /// Returns what to feed its stdin and the pipe for a `$(...)` capture.
+    fn wire_stdio(&mut self, command: &mut Command) -> Result<(Option<Vec<u8>>, Option<CapturePipe>)> {
+        let mut input = None;
+        let mut capture: Option<CapturePipe> = None;
+        for fd in 0..=2 {
+            let stdio = match self.fd(fd) {
+                OpenFd::Std(n) if n == fd => Stdio::inherit(),
+                OpenFd::Std(1) => std::io::stdout().into(),
+                OpenFd::Std(2) => std::io::stderr().into(),
+                OpenFd::Std(_) => Stdio::inherit(),
+                OpenFd::File(file) => file.try_clone()?.into(),
+                OpenFd::PipeIn(reader) => reader.try_clone()?.into(),
+                OpenFd::PipeOut(writer) => writer.try_clone()?.into(),
+                OpenFd::Input(data) if fd == 0 => {
+                    let mut rest = Vec::new();
+                    data.lock().unwrap().read_to_end(&mut rest)?;
+                    input = Some(rest);
+                    Stdio::piped()
+                }
+                OpenFd::Input(_) => bail!("{}: bad file descriptor", fd),
+                OpenFd::Capture => {
+                    if capture.is_none() {
+                        capture = Some(std::io::pipe()?);
+                    }
+                    let (_, writer) = capture.as_ref().unwrap();
+                    writer.try_clone()?.into()
+                }
+                OpenFd::Closed => Stdio::null(),
+            };
+            match fd {
+                0 => command.stdin(stdio),
+                1 => command.stdout(stdio),
+                _ => command.stderr(stdio),
+            };
+        }
+        Ok((input, capture))
+    }
+    
+    fn report_spawn_error(&mut self, cmd: &str, e: &std::io::Error) -> Result<()> {
+        // Like bash: 127 when the command doesn't exist, 126 when it can't run
+        let (message, status) = match e.kind() {
+            std::io::ErrorKind::NotFound => ("command not found".to_string(), 127),
+            _ => (io_error_message(e), 126),
+        };
+        self.write_err(&format!("{}: {}\n", cmd, message))?;
+        self.set_exit_status(status);
+        Ok(())
+    }
+    
+    /// Runs the stages of `a | b | c` concurrently, each with stdout connected
+    /// to the next stage's stdin through an OS pipe.
+    pub fn run_pipeline(&mut self, stages: Vec<PipelineStage>) -> Result<()> {
+        // Stages can't share our $(...) buffer, so they write into a pipe
+        // that is drained into it here
+        let captured = [0, 1, 2].iter().any(|&fd| matches!(self.fd(fd), OpenFd::Capture));
+        let (capture_reader, capture_writer) = if captured {
+            let (reader, writer) = std::io::pipe()?;
+            (Some(reader), Some(Arc::new(writer)))
+        } else {
+            (None, None)
+        };
+        
+        std::io::stdout().flush()?;
+        let count = stages.len();
+        let mut next_input = None;
+        let mut handles = Vec::new();
+        for (i, stage) in stages.into_iter().enumerate() {
+            let mut runtime = self.subshell();
+            if let Some(writer) = &capture_writer {
+                for open in runtime.fds.values_mut() {
+                    if matches!(open, OpenFd::Capture) {
+                        *open = OpenFd::PipeOut(writer.clone());
+                    }
+                }
+            }
+            if let Some(reader) = next_input.take() {
+                runtime.fds.insert(0, OpenFd::PipeIn(Arc::new(reader)));
+            }
+            if i + 1 < count {
+                let (reader, writer) = std::io::pipe()?;
+                runtime.fds.insert(1, OpenFd::PipeOut(Arc::new(writer)));
+                next_input = Some(reader);
+            }
+            
+            handles.push(std::thread::spawn(move || {
+                let result = stage(&mut runtime);
+                runtime.stage_status(result)
+            }));
+        }
+        drop(capture_writer);
+        
+        if let Some(mut reader) = capture_reader {
+            let mut output = Vec::new();
+            reader.read_to_end(&mut output)?;
+            if let Some(buffer) = self.capture_stack.get_mut().last_mut() {
+                buffer.push_str(&String::from_utf8_lossy(&output));
+            }
+        }
+        
+        let statuses: Vec<i32> = handles.into_iter()
+            .map(|handle| handle.join().unwrap_or(1))
+            .collect();
+        let status = if self.pipefail {
+            statuses.iter().rev().copied().find(|&s| s != 0).unwrap_or(0)
+        } else {
+            statuses.last().copied().unwrap_or(0)
+        };
+        self.last_exit_status = status;
+        self.set_pipe_status(statuses);
+        
+        Ok(())
+    }
+    
+    /// `( ... )`: run the commands with a copy of the runtime, so their
+    /// assignments and `cd` don't last.
+    pub fn run_subshell(&mut self, body: PipelineStage) -> Result<()> {
+        self.run_pipeline(vec![body])?;
+        // The working directory belongs to the process, not the copy
+        if env::current_dir()? != self.current_dir {
+            env::set_current_dir(&self.current_dir)?;
+        }
+        Ok(())
+    }
+    
+    /// A copy of the runtime for a subshell: a pipeline stage or a job.
+    fn subshell(&self) -> ShellRuntime {
+        let mut runtime = self.clone();
+        runtime.capture_stack.get_mut().clear();
+        // A subshell only keeps the traps that ignore signals
+        runtime.traps.retain(|_, trap| trap.action.is_none());
+        runtime.in_subshell = true;
+        runtime
+    }
+    
+    /// The exit status of a subshell that has run.
+    fn stage_status(&self, result: Result<()>) -> i32 {
+        match result {
+            Ok(()) => self.last_exit_status,
+            // A stage writing to a closed pipe ends quietly, like on SIGPIPE
+            Err(e) if is_broken_pipe(&e) => 141,
+            // errexit only ends the stage
+            Err(e) if e.is::<Exit>() => e.downcast_ref::<Exit>().map_or(1, |exit| exit.0),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                1
+            }
+        }
+    }
+    
+    /// `cmd &` for a command that runs as a child process: start it and
+    /// go on, with its pid in `$!`.
+    pub fn spawn_command(&mut self, text: &str, cmd: &str, args: &[impl AsRef<str>]) -> Result<()> {
+        let args: Vec<String> = args.iter().map(|s| s.as_ref().to_string()).collect();
+        let captured = (0..=2).any(|fd| matches!(self.fd(fd), OpenFd::Capture | OpenFd::Input(_)));
+        if captured || self.functions.contains_key(cmd) {
+            let cmd = cmd.to_string();
+            return self.spawn_job(text, stage(move |runtime| runtime.execute_command(&cmd, &args)));
+        }
+        
+        self.trace(cmd, &args)?;
+        let stdin = self.background_stdin()?;
+        let mut command = Command::new(cmd);
+        command.args(&args);
+        self.wire_stdio(&mut command)?;
+        if let Some(stdin) = stdin {
+            command.stdin(stdin);
+        }
+        std::io::stdout().flush()?;
+        let child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                // The job fails at once, as the forked shell would
+                drop(command);
+                self.report_spawn_error(cmd, &e)?;
+                let status = self.last_exit_status;
+                let pid = self.next_pid();
+                self.add_job(pid, text, JobHandle::Done(status, None));
+                return Ok(());
+            }
+        };
+        drop(command);
+        self.add_job(child.id(), text, JobHandle::Process(child));
+        Ok(())
+    }
+    
+    /// `{ ...; } &`, `a | b &` and other jobs: run them on a thread with a
+    /// copy of the runtime.
+    pub fn spawn_job(&mut self, text: &str, job: PipelineStage) -> Result<()> {
+        let pid = self.next_pid();
+        // Output for $(...) has to be in the buffer when it is read, so
+        // such a job runs to the end first
+        if (0..=2).any(|fd| matches!(self.fd(fd), OpenFd::Capture)) {
+            self.run_pipeline(vec![job])?;
+            let status = self.last_exit_status;
+            self.add_job(pid, text, JobHandle::Done(status, None));
+            return Ok(());
+        }
+        
+        let mut runtime = self.subshell();
+        // `jobs | ...` lists the script's jobs, but a job starts with none
+        runtime.jobs.clear();
+        runtime.reaped.clear();
+        if let Some(stdin) = self.background_stdin()? {
+            runtime.fds.insert(0, OpenFd::File(Arc::new(stdin)));
+        }
+        std::io::stdout().flush()?;
+        let handle = std::thread::spawn(move || {
+            let result = job(&mut runtime);
+            runtime.stage_status(result)
+        });
+        self.add_job(pid, text, JobHandle::Thread(Some(handle)));
+        Ok(())
+    }
+    
+    /// Without job control a job doesn't read the script's stdin, but
+    /// /dev/null, unless it was redirected.
+    fn background_stdin(&self) -> Result<Option<File>> {
+        match self.fd(0) {
+            OpenFd::Std(0) => Ok(Some(File::open("/dev/null")?)),
+            _ => Ok(None),
+        }
+    }
+    
+    fn next_pid(&mut self) -> u32 {
+        self.next_thread_pid += 1;
+        self.next_thread_pid
+    }
+    
+    fn add_job(&mut self, pid: u32, text: &str, handle: JobHandle) {
+        let id = self.jobs.last().map_or(1, |job| job.id + 1);
+        self.jobs.push(Job { id, pid, command: text.to_string(), handle: Arc::new(Mutex::new(handle)) });
+        self.variables.insert("!".to_string(), pid.to_string());
+        self.set_exit_status(0);
+    }
+    
+    /// The job a `%` job spec or a pid names.
+    fn job_index(&self, spec: &str) -> Option<usize> {
+        let Some(spec) = spec.strip_prefix('%') else {
+            let pid = spec.parse::<u32>().ok()?;
+            return self.jobs.iter().position(|job| job.pid == pid);
+        };
+        match spec {
+            "" | "%" | "+" => self.jobs.len().checked_sub(1),
+            "-" => self.jobs.len().checked_sub(2).or(self.jobs.len().checked_sub(1)),
+            _ => match spec.parse::<usize>() {
+                Ok(id) => self.jobs.iter().position(|job| job.id == id),
+                Err(_) => match spec.strip_prefix('?') {
+                    Some(text) => self.jobs.iter().position(|job| job.command.contains(text)),
+                    None => self.jobs.iter().position(|job| job.command.starts_with(spec)),
+                },
+            },
+        }
+    }
+    
+    fn remove_job(&mut self, index: usize, status: i32) {
+        let job = self.jobs.remove(index);
+        self.reaped.insert(job.pid, status);
+    }
+    
+    /// Block until a job finishes. A trapped signal interrupts the wait
+    /// with status 128 plus its number, and its trap runs next.
+    fn wait_job(&mut self, indices: Option<&[usize]>) -> Result<std::result::Result<(usize, i32), i32>> {
+        loop {
+            for (i, job) in self.jobs.iter().enumerate() {
+                if indices.is_some_and(|indices| !indices.contains(&i)) {
+                    continue;
+                }
+                if let Some(status) = job.handle.lock().unwrap().poll()? {
+                    return Ok(Ok((i, status)));
+                }
+            }
+            if let Some(signal) = (1..SIGNALS).find(|&signal| {
+                DEFERRED[signal].load(Ordering::SeqCst) && PENDING[signal].load(Ordering::SeqCst)
+            }) {
+                return Ok(Err(128 + signal as i32));
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+    
+    /// `wait`: all jobs, `wait -n`: the next one to finish, or `wait
+    /// %job pid ...`, with the status of the last one.
+    pub fn wait(&mut self, args: &[impl AsRef<str>]) -> Result<()> {
+        let mut any = false;
+        let mut specs = Vec::new();
+        for arg in args {
+            match arg.as_ref() {
+                "-n" => any = true,
+                "--" => {}
+                option if option.starts_with('-') && option.len() > 1 => {
+                    self.write_err(&format!("wait: {}: invalid option\nwait: usage: wait [-n] [id ...]\n", option))?;
+                    self.set_exit_status(2);
+                    return Ok(());
+                }
+                spec => specs.push(spec.to_string()),
+            }
+        }
+        
+        if any {
+            let mut indices = Vec::new();
+            for spec in &specs {
+                match self.job_index(spec) {
+                    Some(index) => indices.push(index),
+                    None => self.write_err(&format!("wait: {}: no such job\n", spec))?,
+                }
+            }
+            if self.jobs.is_empty() || (!specs.is_empty() && indices.is_empty()) {
+                self.set_exit_status(127);
+                return Ok(());
+            }
+            let indices = (!specs.is_empty()).then_some(indices.as_slice());
+            let status = match self.wait_job(indices)? {
+                Ok((index, status)) => {
+                    self.remove_job(index, status);
+                    status
+                }
+                Err(status) => status,
+            };
+            self.set_exit_status(status);
+            return Ok(());
+        }
+        
+        if specs.is_empty() {
+            while !self.jobs.is_empty() {
+                match self.wait_job(None)? {
+                    Ok((index, status)) => self.remove_job(index, status),
+                    Err(status) => {
+                        self.set_exit_status(status);
+                        return Ok(());
+                    }
+                }
+            }
+            self.set_exit_status(0);
+            return Ok(());
+        }
+        
+        let mut last = 0;
+        for spec in specs {
+            last = match self.job_index(&spec) {
+                Some(index) => match self.wait_job(Some(&[index]))? {
+                    Ok((index, status)) => {
+                        self.remove_job(index, status);
+                        status
+                    }
+                    Err(status) => {
+                        self.set_exit_status(status);
+                        return Ok(());
+                    }
+                },
+                None => match spec.parse::<u32>().ok().and_then(|pid| self.reaped.get(&pid)) {
+                    Some(&status) => status,
+                    None if spec.starts_with('%') => {
+                        self.write_err(&format!("wait: {}: no such job\n", spec))?;
+                        127
+                    }
+                    None if spec.parse::<u32>().is_ok() => {
+                        self.write_err(&format!("wait: pid {} is not a child of this shell\n", spec))?;
+                        127
+                    }
+                    None => {
+                        self.write_err(&format!("wait: `{}': not a pid or valid job spec\n", spec))?;
+                        2
+                    }
+                },
+            };
+        }
+        self.set_exit_status(last);
+        Ok(())
+    }
+    
+    /// `jobs`, `jobs -l` with pids, or `jobs -p` with only the pids.
+    /// Finished jobs are listed once, then forgotten.
+    pub fn list_jobs(&mut self, args: &[impl AsRef<str>]) -> Result<()> {
+        let mut long = false;
+        let mut pids = false;
+        let mut specs = Vec::new();
+        for arg in args {
+            match arg.as_ref() {
+                "-l" => long = true,
+                "-p" => pids = true,
+                option if option.starts_with('-') && option.len() > 1 => {
+                    self.write_err(&format!("jobs: {}: invalid option\njobs: usage: jobs [-lp] [jobspec ...]\n", option))?;
+                    self.set_exit_status(2);
+                    return Ok(());
+                }
+                spec => specs.push(spec.to_string()),
+            }
+        }
+        
+        let mut selected = Vec::new();
+        for spec in &specs {
+            match self.job_index(spec) {
+                Some(index) => selected.push(index),
+                None => {
+                    self.write_err(&format!("jobs: {}: no such job\n", spec))?;
+                    self.set_exit_status(1);
+                    return Ok(());
+                }
+            }
+        }
+        if specs.is_empty() {
+            selected = (0..self.jobs.len()).collect();
+        }
+        
+        let count = self.jobs.len();
+        let mut output = String::new();
+        let mut finished = Vec::new();
+        for index in selected {
+            let job = &self.jobs[index];
+            let mut handle = job.handle.lock().unwrap();
+            let status = handle.poll()?;
+            if pids {
+                output.push_str(&format!("{}\n", job.pid));
+                continue;
+            }
+            let mark = if index + 1 == count {
+                '+'
+            } else if index + 2 == count {
+                '-'
+            } else {
+                ' '
+            };
+            let command = match status {
+                Some(status) => {
+                    finished.push((index, status));
+                    job.command.clone()
+                }
+                None => format!("{} &", job.command),
+            };
+            if long {
+                output.push_str(&format!("[{}]{} {:>5} {:<24}{}\n", job.id, mark, job.pid, handle.state(), command));
+            } else {
+                output.push_str(&format!("[{}]{}  {:<24}{}\n", job.id, mark, handle.state(), command));
+            }
+        }
+        finished.sort();
+        for &(index, status) in finished.iter().rev() {
+            self.remove_job(index, status);
+        }
+        self.write_out(&output)
+    }
+    
+    /// `kill`, with `%` job specs turned into the pids of their processes.
+    pub fn kill(&mut self, args: &[impl AsRef<str>]) -> Result<()> {
+        let mut words = Vec::new();
+        for arg in args {
+            let arg = arg.as_ref();
+            let job = match arg.strip_prefix('%') {
+                Some(_) => match self.job_index(arg) {
+                    Some(index) => Some(&self.jobs[index]),
+                    None => {
+                        self.write_err(&format!("kill: {}: no such job\n", arg))?;
+                        self.set_exit_status(1);
+                        return Ok(());
+                    }
+                },
+                None => self.job_index(arg).map(|index| &self.jobs[index]),
+            };
+            match job {
+                Some(job) if job.pid > FIRST_THREAD_PID => {
+                    // The shell runs this job itself, so there is no process to signal
+                    self.write_err(&format!("kill: {}: job has no process of its own\n", arg))?;
+                    self.set_exit_status(1);
+                    return Ok(());
+                }
+                Some(job) => words.push(job.pid.to_string()),
+                None => words.push(arg.to_string()),
+            }
+        }
+        self.run_process("kill", &words)
+    }
+    
+    pub fn set_exit_status(&mut self, status: i32) {
+        self.last_exit_status = status;
+        self.set_pipe_status(vec![status]);
+    }
+    
+    /// `PIPESTATUS`, an array of the statuses of the last pipeline's stages.
+    fn set_pipe_status(&mut self, statuses: Vec<i32>) {
+        let mut array = Array::Indexed(BTreeMap::new());
+        array.extend(statuses.iter().map(|s| s.to_string()).collect());
+        self.variables.remove("PIPESTATUS");
+        self.arrays.insert("PIPESTATUS".to_string(), array);
+        self.pipe_status = statuses;
+    }
+    
+    pub fn pipe_status(&self) -> &[i32] {
+        &self.pipe_status
+    }
+    
+    pub fn set_pipefail(&mut self, enabled: bool) {
+        self.pipefail = enabled;
+    }
+    
+    /// `set -o name` and `set +o name`, or their letters. Options that don't
+    /// change what a script does, like `hashall`, are accepted and ignored;
+    /// an unknown name is reported with status 2.
+    pub fn set_options(&mut self, options: &[(&str, bool)]) -> Result<()> {
+        for &(name, enabled) in options {
+            match name {
+                "errexit" => self.errexit = enabled,
+                "nounset" => self.nounset = enabled,
+                "pipefail" => self.pipefail = enabled,
+                "xtrace" => self.xtrace = enabled,
+                "noglob" => self.noglob = enabled,
+                "noclobber" => self.noclobber = enabled,
+                "errtrace" => self.errtrace = enabled,
+                "functrace" => self.functrace = enabled,
+                "allexport" | "braceexpand" | "emacs" | "hashall" | "histexpand"
+                | "history" | "ignoreeof" | "interactive-comments" | "keyword" | "monitor" | "noexec"
+                | "notify" | "onecmd" | "physical" | "posix" | "privileged" | "verbose" | "vi" => {}
+                _ => {
+                    self.write_err(&format!("set: {}: invalid option name\n", name))?;
+                    self.set_exit_status(2);
+                    return Ok(());
+                }
+            }
+        }
+        self.set_exit_status(0);
+        Ok(())
+    }
+    
+    /// `$-`: the letters of the options that are on.
+    fn option_letters(&self) -> String {
+        [
+            (self.errexit, 'e'),
+            (self.noglob, 'f'),
+            (self.nounset, 'u'),
+            (self.xtrace, 'x'),
+            (self.noclobber, 'C'),
+            (self.errtrace, 'E'),
+            (self.functrace, 'T'),
+        ]
+            .iter()
+            .filter(|(enabled, _)| *enabled)
+            .map(|(_, letter)| *letter)
+            .collect()
+    }
+    
+    /// `shopt -s` and `shopt -u`. Options that only matter to interactive
+    /// shells are accepted and ignored; an unknown name is reported with
+    /// status 1.
+    pub fn set_shopt(&mut self, names: &[&str], enabled: bool) -> Result<()> {
+        let mut status = 0;
+        for &name in names {
+            match self.shopt_flag(name) {
+                Some(flag) => *flag = enabled,
+                None if IGNORED_SHOPTS.contains(&name) => {}
+                None => {
+                    self.write_err(&format!("shopt: {}: invalid shell option name\n", name))?;
+                    status = 1;
+                }
+            }
+        }
+        self.set_exit_status(status);
+        Ok(())
+    }
+    
+    /// `shopt -q`: status 0 when every option is on.
+    pub fn query_shopt(&mut self, names: &[&str]) {
+        let enabled = names.iter().all(|name| self.shopt_flag(name).is_some_and(|flag| *flag));
+        self.set_exit_status(if enabled { 0 } else { 1 });
+    }
+    
+    fn shopt_flag(&mut self, name: &str) -> Option<&mut bool> {
+        Some(match name {
+            "extglob" => &mut self.extglob,
+            "nullglob" => &mut self.nullglob,
+            "failglob" => &mut self.failglob,
+            "dotglob" => &mut self.dotglob,
+            "nocaseglob" => &mut self.nocaseglob,
+            "nocasematch" => &mut self.nocasematch,
+            "globstar" => &mut self.globstar,
+            _ => return None,
+        })
+    }
+    
+    /// After a command that failed, unless it ran as a condition or in a
+    /// function called from one: run the ERR trap, then with errexit end
+    /// the script.
+    pub fn check_errexit(&mut self) -> Result<()> {
+        if self.condition_depth > 0 || self.last_exit_status == 0 {
+            return Ok(());
+        }
+        if let Some(trap) = self.shell_trap("ERR", self.errtrace) {
+            self.run_trap(&trap)?;
+        }
+        if self.errexit {
+            return Err(Exit(self.last_exit_status).into());
+        }
+        Ok(())
+    }
+    
+    /// Start of a condition: an `if` or `while` test, the left side of `&&`
+    /// or `||`, or a negated pipeline.
+    pub fn enter_condition(&mut self) {
+        self.condition_depth += 1;
+    }
+    
+    pub fn leave_condition(&mut self) {
+        self.condition_depth = self.condition_depth.saturating_sub(1);
+    }
+    
+    /// End the script with the result of `script_main`, after the EXIT
+    /// trap: exit with the status of an `Exit`, or return the error.
+    pub fn finish(&mut self, result: Result<()>) -> Result<()> {
+        if let Some(exit) = result.as_ref().err().and_then(|e| e.downcast_ref::<Exit>()) {
+            self.last_exit_status = exit.0;
+        }
+        // `exit` in the trap changes the status
+        let result = match self.run_exit_trap() {
+            Ok(()) => result,
+            Err(e) => Err(e),
+        };
+        match result {
+            Err(e) if e.is::<Exit>() => {
+                std::io::stdout().flush()?;
+                std::process::exit(e.downcast_ref::<Exit>().map_or(1, |exit| exit.0))
+            }
+            result => result,
+        }
+    }
+    
+    /// `exit` with a status that isn't a literal number. The result is
+    /// always the `Exit` error.
+    pub fn exit(&mut self, value: &str) -> Result<()> {
+        let status = match value.trim().parse::<i64>() {
+            Ok(status) => status.rem_euclid(256) as i32,
+            Err(_) => {
+                self.write_err(&format!("exit: {}: numeric argument required\n", value))?;
+                2
+            }
+        };
+        Err(Exit(status).into())
+    }
+    
+    /// `trap text conditions...`, with `action` generated from the text.
+    /// Without an action the conditions are ignored.
+    pub fn set_trap(
+        &mut self,
+        text: &str,
+        action: Option<fn(&mut ShellRuntime, &[String]) -> Result<()>>,
+        conditions: &[impl AsRef<str>],
+    ) -> Result<()> {
+        let mut status = 0;
+        for condition in conditions {
+            match trap_condition(condition.as_ref()) {
+                Some(name) => {
+                    let trap = Trap { text: text.to_string(), action, frame: self.frame };
+                    self.traps.insert(name, trap);
+                }
+                None => {
+                    self.write_err(&format!("trap: {}: invalid signal specification\n", condition.as_ref()))?;
+                    status = 1;
+                }
+            }
+        }
+        self.defer_signals()?;
+        self.set_exit_status(status);
+        Ok(())
+    }
+    
+    /// `trap - conditions...`
+    pub fn reset_traps(&mut self, conditions: &[impl AsRef<str>]) -> Result<()> {
+        let mut status = 0;
+        for condition in conditions {
+            match trap_condition(condition.as_ref()) {
+                Some(name) => {
+                    self.traps.remove(&name);
+                }
+                None => {
+                    self.write_err(&format!("trap: {}: invalid signal specification\n", condition.as_ref()))?;
+                    status = 1;
+                }
+            }
+        }
+        self.defer_signals()?;
+        self.set_exit_status(status);
+        Ok(())
+    }
+    
+    /// `trap -p conditions...`, or every trap without conditions, in the
+    /// form that would set them again.
+    pub fn print_traps(&mut self, conditions: &[impl AsRef<str>]) -> Result<()> {
+        let mut names = Vec::new();
+        let mut status = 0;
+        for condition in conditions {
+            match trap_condition(condition.as_ref()) {
+                Some(name) => names.push(name),
+                None => {
+                    self.write_err(&format!("trap: {}: invalid signal specification\n", condition.as_ref()))?;
+                    status = 1;
+                }
+            }
+        }
+        if conditions.is_empty() {
+            names = self.traps.keys().cloned().collect();
+            names.sort_by_key(|name| trap_order(name));
+        }
+        let mut output = String::new();
+        for name in names {
+            if let Some(trap) = self.traps.get(&name) {
+                let shown = match signal_number(&name) {
+                    Some(_) => format!("SIG{}", name),
+                    None => name,
+                };
+                output.push_str(&format!("trap -- '{}' {}\n", trap.text.replace('\'', "'\\''"), shown));
+            }
+        }
+        self.write_out(&output)?;
+        self.set_exit_status(status);
+        Ok(())
+    }
+    
+    /// Run the traps of the signals that arrived since the last call. A
+    /// signal without a trap of its own was deferred for the EXIT trap:
+    /// run that, then die of the signal.
+    pub fn run_traps(&mut self) -> Result<()> {
+        if self.in_subshell {
+            return Ok(());
+        }
+        for (signal, pending) in PENDING.iter().enumerate() {
+            if !pending.swap(false, Ordering::SeqCst) {
+                continue;
+            }
+            let name = signal_hook::low_level::signal_name(signal as i32).map_or("", |name| &name[3..]);
+            match self.traps.get(name).cloned() {
+                Some(trap) => self.run_trap(&trap)?,
+                None => {
+                    self.last_exit_status = 128 + signal as i32;
+                    self.run_exit_trap()?;
+                    std::io::stdout().flush()?;
+                    signal_hook::low_level::emulate_default_handler(signal as i32)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    /// Record the script line about to run, for `$LINENO`.
+    pub fn set_line(&mut self, line: usize) {
+        self.lineno = line;
+    }
+    
+    /// The DEBUG trap, run before each simple command.
+    pub fn run_debug_trap(&mut self) -> Result<()> {
+        match self.shell_trap("DEBUG", self.functrace) {
+            Some(trap) => self.run_trap(&trap),
+            None => Ok(()),
+        }
+    }
+    
+    /// The EXIT trap runs once, when the script ends.
+    fn run_exit_trap(&mut self) -> Result<()> {
+        match self.traps.remove("EXIT") {
+            Some(trap) => self.run_trap(&trap),
+            None => Ok(()),
+        }
+    }
+    
+    /// The ERR, DEBUG or RETURN trap if it applies here. Functions only
+    /// inherit these with errtrace or functrace, and traps don't run them.
+    fn shell_trap(&self, condition: &str, inherited: bool) -> Option<Trap> {
+        let trap = self.traps.get(condition)?;
+        let applies = inherited || self.frame == 0 || trap.frame == self.frame;
+        (applies && !self.in_trap && trap.action.is_some()).then(|| trap.clone())
+    }
+    
+    /// Run a trap's commands. `$?` is kept unless they exit.
+    fn run_trap(&mut self, trap: &Trap) -> Result<()> {
+        let Some(action) = trap.action else {
+            return Ok(());
+        };
+        let status = self.last_exit_status;
+        let in_trap = std::mem::replace(&mut self.in_trap, true);
+        let result = action(self, &[]);
+        self.in_trap = in_trap;
+        self.last_exit_status = status;
+        result
+    }
+    
+    /// Point the signal handlers at the traps: a signal with a trap, or
+    /// one that would end the script while there is an EXIT trap, waits
+    /// for `run_traps`. Others get their default action again.
+    fn defer_signals(&mut self) -> Result<()> {
+        let exit_trap = self.traps.contains_key("EXIT");
+        for (signal, deferred) in DEFERRED.iter().enumerate() {
+            let Some(name) = signal_hook::low_level::signal_name(signal as i32) else {
+                continue;
+            };
+            let defer = self.traps.contains_key(&name[3..]) || (exit_trap && matches!(&name[3..], "HUP" | "INT" | "TERM"));
+            if defer && !signal_hook::consts::FORBIDDEN.contains(&(signal as i32)) && !HANDLED[signal].swap(true, Ordering::SeqCst) {
+                // SAFETY: the handler only touches atomics and re-raises
+                // the signal, which are async-signal-safe
+                unsafe {
+                    signal_hook::low_level::register(signal as i32, move || {
+                        if DEFERRED[signal].load(Ordering::SeqCst) {
+                            PENDING[signal].store(true, Ordering::SeqCst);
+                        } else {
+                            let _ = signal_hook::low_level::emulate_default_handler(signal as i32);
+                        }
+                    })?;
+                }
+            }
+            deferred.store(defer, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+    
+    /// xtrace: write a command about to run to stderr after `PS4`, quoted
+    /// so it could be run again.
+    pub fn trace(&mut self, name: &str, args: &[impl AsRef<str>]) -> Result<()> {
+        if !self.xtrace {
+            return Ok(());
+        }
+        let mut line = trace_quote(name);
+        for arg in args {
+            line.push(' ');
+            line.push_str(&trace_quote(arg.as_ref()));
+        }
+        self.write_trace(&line)
+    }
+    
+    /// xtrace for an assignment, as `name=value` with the new value.
+    pub fn trace_assignment(&mut self, name: &str) -> Result<()> {
+        if !self.xtrace {
+            return Ok(());
+        }
+        let value = self.variables.get(name).cloned().unwrap_or_default();
+        self.write_trace(&format!("{}={}", name, trace_quote(&value)))
+    }
+    
+    /// A trace goes to stderr as it was before the redirections of the
+    /// command it shows.
+    fn write_trace(&mut self, line: &str) -> Result<()> {
+        let line = format!("{}{}\n", self.variables.get("PS4").map_or("+ ", String::as_str), line);
+        match self.trace_fd.clone() {
+            Some(open) => {
+                let current = self.fd(2);
+                self.fds.insert(2, open);
+                let result = self.write_err(&line);
+                self.fds.insert(2, current);
+                result
+            }
+            None => self.write_err(&line),
+        }
+    }
+    
+    /// The `read` builtin: reads a line from stdin and splits it over `vars`,
+    /// the last one taking the rest of the line. Fails with status 1 at end
+    /// of input.
+    pub fn read_line(&mut self, prompt: &str, vars: &[&str], raw: bool) -> Result<()> {
+        if !prompt.is_empty() && matches!(self.fd(0), OpenFd::Std(_))
+            && std::io::IsTerminal::is_terminal(&std::io::stdin())
+        {
+            eprint!("{}", prompt);
+        }
+        
+        let (mut line, mut eof) = self.read_fd_line(0)?;
+        if !raw {
+            // A trailing backslash continues the line
+            while !eof && line.ends_with('\\') && !line.ends_with("\\\\") {
+                line.pop();
+                let (next, next_eof) = self.read_fd_line(0)?;
+                line.push_str(&next);
+                eof = next_eof;
+            }
+            line = remove_backslashes(&line);
+        }
+        
+        if vars.is_empty() {
+            self.set_var("REPLY", line)?;
+        } else {
+            let mut rest = line.trim_start();
+            for (i, var) in vars.iter().enumerate() {
+                if i + 1 == vars.len() {
+                    self.set_var(var, rest.trim_end())?;
+                } else {
+                    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                    self.set_var(var, &rest[..end])?;
+                    rest = rest[end..].trim_start();
+                }
+            }
+        }
+        
+        self.set_exit_status(if eof { 1 } else { 0 });
+        Ok(())
+    }
+    
+    /// Reads up to a newline without reading past it, so the rest of the
+    /// input is left for whatever reads the descriptor next.
+    fn read_fd_line(&mut self, fd: i32) -> Result<(String, bool)> {
+        let open = self.fd(fd);
+        if let OpenFd::Std(0) = open {
+            let mut line = String::new();
+            let eof = std::io::stdin().read_line(&mut line)? == 0 || !line.ends_with('\n');
+            return Ok((line.trim_end_matches('\n').to_string(), eof));
+        }
+        
+        let mut line = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            let n = match &open {
+                OpenFd::File(file) => (&**file).read(&mut byte)?,
+                OpenFd::PipeIn(reader) => (&**reader).read(&mut byte)?,
+                OpenFd::Input(data) => data.lock().unwrap().read(&mut byte)?,
+                _ => bail!("{}: bad file descriptor", fd),
+            };
+            if n == 0 {
+                return Ok((String::from_utf8_lossy(&line).into_owned(), true));
+            }
+            if byte[0] == b'\n' {
+                return Ok((String::from_utf8_lossy(&line).into_owned(), false));
+            }
+            line.push(byte[0]);
+        }
+    }
+    
+    pub fn last_exit_status(&self) -> i32 {
+        self.last_exit_status
+    }
+    
+    pub fn register_function(&mut self, name: &str, func: fn(&mut ShellRuntime, &[String]) -> Result<()>) {
+        self.functions.insert(name.to_string(), func);
+    }
+    
+    /// Runs `f` with stdout redirected into a buffer and returns what it
+    /// wrote, minus trailing newlines, like `$(...)`.
+    pub fn capture_output<F>(&mut self, f: F) -> Result<String>
+    where
+        F: FnOnce(&mut ShellRuntime) -> Result<()>,
+    {
+        Ok(self.capture_raw(f)?.trim_end_matches('\n').to_string())
+    }
+    
+    /// Runs `f` with stdout going to a new temporary file and returns the
+    /// file's path, like zsh's `=(...)`.
+    pub fn capture_to_file<F>(&mut self, f: F) -> Result<String>
+    where
+        F: FnOnce(&mut ShellRuntime) -> Result<()>,
+    {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let output = self.capture_raw(f)?;
+        let path = env::temp_dir().join(format!(
+            "shell-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, output)
+            .with_context(|| format!("{}: cannot create temporary file", path.display()))?;
+        Ok(path.to_string_lossy().into_owned())
+    }
+    
+    /// Removes a file made by `capture_to_file` once the command using it
+    /// has finished.
+    pub fn remove_temp_file(&self, path: &str) {
+        let _ = std::fs::remove_file(path);
+    }
+    
+    fn capture_raw<F>(&mut self, f: F) -> Result<String>
+    where
+        F: FnOnce(&mut ShellRuntime) -> Result<()>,
+    {
+        let saved_fds = self.fds.clone();
+        self.fds.insert(1, OpenFd::Capture);
+        self.capture_stack.get_mut().push(String::new());
+        // `$(...)` doesn't inherit errexit
+        let saved_errexit = std::mem::replace(&mut self.errexit, false);
+        let saved_depth = self.condition_depth;
+        let result = f(self);
+        self.errexit = saved_errexit;
+        self.condition_depth = saved_depth;
+        let output = self.capture_stack.get_mut().pop().unwrap_or_default();
+        self.fds = saved_fds;
+        match result {
+            // An errexit turned on inside only ends the substitution
+            Err(e) if e.is::<Exit>() => self.set_exit_status(e.downcast_ref::<Exit>().map_or(1, |exit| exit.0)),
+            Err(e) if e.is::<NoMatch>() => {
+                self.write_err(&format!("{}\n", e))?;
+                self.set_exit_status(1);
+            }
+            result => result?,
+        }
+        Ok(output)
+    }
+    
+    /// A top-level command of a script with failglob on: a pattern that
+    /// matches nothing is reported and the rest of the command skipped,
+    /// with status 1.
+    pub fn top_level<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut ShellRuntime) -> Result<()>,
+    {
+        match f(self) {
+            Err(e) if e.is::<NoMatch>() => {
+                self.write_err(&format!("{}\n", e))?;
+                self.set_exit_status(1);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+    
+    /// Runs `f` with `redirects` applied to the file descriptor table, like
+    /// `cmd >out 2>&1` or `while ...; done < file`.
+    pub fn with_redirects<F>(&mut self, redirects: Vec<Redirect>, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut ShellRuntime) -> Result<()>,
+    {
+        let saved_fds = self.fds.clone();
+        let saved_trace_fd = self.trace_fd.replace(self.fd(2));
+        let result = match self.apply_redirects(redirects) {
+            Ok(true) => f(self),
+            Ok(false) => Ok(()),
+            Err(e) => Err(e),
+        };
+        self.fds = saved_fds;
+        self.trace_fd = saved_trace_fd;
+        result
+    }
+    
+    /// `exec` without a command: the redirections stay, like `exec 3> log`
+    /// or `exec 2>&-`.
+    pub fn exec_redirects(&mut self, redirects: Vec<Redirect>) -> Result<()> {
+        if self.apply_redirects(redirects)? {
+            self.set_exit_status(0);
+        }
+        Ok(())
+    }
+    
+    /// Whether every redirection was made. One that can't be made, like a
+    /// file that doesn't open or one noclobber refuses, is reported and
+    /// makes the status 1.
+    fn apply_redirects(&mut self, redirects: Vec<Redirect>) -> Result<bool> {
+        for redirect in redirects {
+            let opened = match redirect {
+                Redirect::Read { fd, path } => File::open(&path)
+                    .map(|file| (fd, OpenFd::File(Arc::new(file))))
+                    .map_err(|e| format!("{}: {}", path, io_error_message(&e))),
+                Redirect::Write { path, append: false, .. }
+                    if self.noclobber && std::fs::metadata(&path).is_ok_and(|m| m.is_file()) =>
+                {
+                    Err(format!("{}: cannot overwrite existing file", path))
+                }
+                Redirect::Write { fd, path, append } => OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .append(append)
+                    .truncate(!append)
+                    .open(&path)
+                    .map(|file| (fd, OpenFd::File(Arc::new(file))))
+                    .map_err(|e| format!("{}: {}", path, io_error_message(&e))),
+                Redirect::Clobber { fd, path } => File::create(&path)
+                    .map(|file| (fd, OpenFd::File(Arc::new(file))))
+                    .map_err(|e| format!("{}: {}", path, io_error_message(&e))),
+                Redirect::ReadWrite { fd, path } => OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(&path)
+                    .map(|file| (fd, OpenFd::File(Arc::new(file))))
+                    .map_err(|e| format!("{}: {}", path, io_error_message(&e))),
+                Redirect::Dup { fd, target } => match self.fd(target) {
+                    OpenFd::Closed => Err(format!("{}: Bad file descriptor", target)),
+                    open => Ok((fd, open)),
+                },
+                Redirect::Close { fd } => Ok((fd, OpenFd::Closed)),
+                Redirect::Input { fd, data } => {
+                    Ok((fd, OpenFd::Input(Arc::new(Mutex::new(Cursor::new(data.into_bytes()))))))
+                }
+            };
+            match opened {
+                Ok((fd, target)) => {
+                    self.fds.insert(fd, target);
+                }
+                Err(message) => {
+                    self.write_err(&format!("{}\n", message))?;
+                    self.set_exit_status(1);
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+    
+    fn fd(&self, fd: i32) -> OpenFd {
+        match self.fds.get(&fd) {
+            Some(open) => open.clone(),
+            None if (0..=2).contains(&fd) => OpenFd::Std(fd),
+            None => OpenFd::Closed,
+        }
+    }
+    
+    fn write_fd(&self, fd: i32, text: &str) -> Result<()> {
+        match self.fd(fd) {
+            OpenFd::Std(2) => eprint!("{}", text),
+            OpenFd::Std(_) => print!("{}", text),
+            OpenFd::File(file) => (&*file).write_all(text.as_bytes())?,
+            OpenFd::PipeOut(writer) => (&*writer).write_all(text.as_bytes())?,
+            OpenFd::Capture => {
+                if let Some(buffer) = self.capture_stack.borrow_mut().last_mut() {
+                    buffer.push_str(text);
+                }
+            }
+            OpenFd::PipeIn(_) | OpenFd::Input(_) | OpenFd::Closed => {
+                bail!("{}: bad file descriptor", fd)
+            }
+        }
+        Ok(())
+    }
+    
+    /// The `pwd` builtin; `-P` resolves symbolic links.
+    pub fn pwd(&mut self, args: &[impl AsRef<str>]) -> Result<()> {
+        let dir = if args.iter().any(|arg| arg.as_ref() == "-P") {
+            self.current_dir.canonicalize()?
+        } else {
+            self.current_dir.clone()
+        };
+        self.write_out(&format!("{}\n", dir.display()))?;
+        self.set_exit_status(0);
+        Ok(())
+    }
+    
+    /// Fish's `count`: the number of arguments, and status 1 when there
+    /// are none.
+    pub fn count(&mut self, args: &[impl AsRef<str>]) -> Result<()> {
+        self.write_out(&format!("{}\n", args.len()))?;
+        self.set_exit_status(if args.is_empty() { 1 } else { 0 });
+        Ok(())
+    }
+    
+    /// PowerShell's `param()`: bind the arguments of the function being
+    /// called, or of the script, to the variables `names` and `switches`.
+    /// `-Name value`, `-Name:value` and unambiguous prefixes of a name bind
+    /// by name, a given switch is `True`, and the other arguments go to the
+    /// unbound `names` in order; after `--` all of them do. Parameters
+    /// without an argument are unset, and the arguments left over stay as
+    /// the positional parameters, for `$args`.
+    pub fn bind_parameters(&mut self, names: &[&str], switches: &[&str]) -> Result<()> {
+        let mut bound: HashMap<&str, String> = HashMap::new();
+        let mut positional = Vec::new();
+        let mut args = std::mem::take(&mut self.args).into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                positional.extend(args.by_ref());
+                break;
+            }
+            let Some(parameter) = arg.strip_prefix('-').filter(|p| p.starts_with(|c: char| c.is_ascii_alphabetic())) else {
+                positional.push(arg);
+                continue;
+            };
+            let (parameter, value) = match parameter.split_once(':') {
+                Some((parameter, value)) => (parameter.to_lowercase(), Some(value.to_string())),
+                None => (parameter.to_lowercase(), None),
+            };
+            let candidates: Vec<&str> = names.iter().chain(switches)
+                .copied()
+                .filter(|name| name.starts_with(&parameter))
+                .collect();
+            let name = match candidates.iter().find(|name| **name == parameter) {
+                Some(name) => *name,
+                None if candidates.len() == 1 => candidates[0],
+                None => {
+                    let problem = if candidates.is_empty() { "no parameter matches" } else { "ambiguous parameter" };
+                    self.write_err(&format!("{}: {}\n", arg, problem))?;
+                    return Err(Exit(1).into());
+                }
+            };
+            let value = if switches.contains(&name) {
+                match value {
+                    Some(value) if matches!(value.to_lowercase().as_str(), "" | "0" | "false" | "$false") => String::new(),
+                    _ => "True".to_string(),
+                }
+            } else {
+                match value.or_else(|| args.next()) {
+                    Some(value) => value,
+                    None => {
+                        self.write_err(&format!("{}: missing an argument\n", arg))?;
+                        return Err(Exit(1).into());
+                    }
+                }
+            };
+            bound.insert(name, value);
+        }
+        
+        let mut positional = positional.into_iter();
+        for &name in names {
+            if !bound.contains_key(name) {
+                if let Some(value) = positional.next() {
+                    bound.insert(name, value);
+                }
+            }
+        }
+        self.args = positional.collect();
+        for &name in names.iter().chain(switches) {
+            self.declare_local(name)?;
+            match bound.remove(name) {
+                Some(value) => self.set_var(name, value)?,
+                None => self.unset_var(name)?,
+            }
+        }
+        Ok(())
+    }
+    
+    /// Writes to stdout. Only a closed pipe is an error, which ends the
+    /// current pipeline stage the way SIGPIPE would.
+    pub fn write_out(&mut self, text: &str) -> Result<()> {
+        self.write_reporting_errors(1, text)
+    }
+    
+    pub fn write_err(&mut self, text: &str) -> Result<()> {
+        self.write_reporting_errors(2, text)
+    }
+    
+    fn write_reporting_errors(&mut self, fd: i32, text: &str) -> Result<()> {
+        match self.write_fd(fd, text) {
+            Err(e) if is_broken_pipe(&e) => Err(e),
+            Err(e) => {
+                eprintln!("write error: {}", e);
+                self.set_exit_status(1);
+                Ok(())
+            }
+            Ok(()) => {
+                self.set_exit_status(0);
+                Ok(())
+            }
+        }
+    }
+    
+    pub fn glob_match(&self, pattern: &str, text: &str) -> bool {
+        if self.nocasematch {
+            return glob_match(&pattern.to_lowercase(), &text.to_lowercase(), self.extglob);
+        }
+        glob_match(pattern, text, self.extglob)
+    }
+    
+    /// Join the values of `$*` or `${name[*]}` with the first character of
+    /// `IFS`, a space when it is unset and nothing when it is empty.
+    fn join_star(&self, values: &[String]) -> String {
+        match self.variables.get("IFS") {
+            Some(ifs) => values.join(&ifs.chars().next().map(String::from).unwrap_or_default()),
+            None => values.join(" "),
+        }
+    }
+    
+    /// The value of `name` for `${name-word}` and the like: `None` when it is
+    /// unset, or with `colon` also when it is empty. The subscript of
+    /// `name[subscript]` has been expanded.
+    pub fn lookup(&mut self, name: &str, colon: bool) -> Result<Option<String>> {
+        let value = match name.strip_suffix(']').and_then(|name| name.split_once('[')) {
+            Some((array, index @ ("@" | "*"))) => {
+                let values = self.array_values(array);
+                let joined = if index == "*" { self.join_star(&values) } else { values.join(" ") };
+                (!values.is_empty()).then_some(joined)
+            }
+            Some((array, subscript)) => {
+                let array = self.resolve_name(array).to_string();
+                let key = match self.arrays.get(&array) {
+                    Some(Array::Indexed(_)) => {
+                        let index = self.eval_arith(subscript)?;
+                        // zsh counts from 1
+                        if self.ksh_arrays || index <= 0 { index } else { index - 1 }.to_string()
+                    }
+                    _ => subscript.to_string(),
+                };
+                self.arrays.get(&array).and_then(|values| values.get(&key)).cloned()
+            }
+            None if matches!(name, "@" | "*") => (!self.args.is_empty()).then(|| self.get_var(name)).transpose()?,
+            None if matches!(name, "?" | "-" | "$") || self.is_var_set(name) => Some(self.get_var(name)?),
+            None => None,
+        };
+        Ok(value.filter(|value| !(colon && value.is_empty())))
+    }
+    
+    /// `${name:=word}`: assign to a variable or an array element.
+    pub fn assign_parameter(&mut self, name: &str, value: &str) -> Result<()> {
+        match name.strip_suffix(']').and_then(|name| name.split_once('[')) {
+            Some((array, subscript)) => self.set_element(array, subscript, value),
+            None if self.positional(name).is_some() || matches!(name, "?" | "-" | "$" | "!" | "@" | "*") => {
+                bail!("${}: cannot assign in this way", name)
+            }
+            None => self.set_var(name, value),
+        }
+    }
+    
+    /// `${name:?message}` for an unset name: report it and end the script,
+    /// or the subshell.
+    pub fn parameter_error(&mut self, name: &str, message: &str) -> anyhow::Error {
+        match self.write_err(&format!("{}: {}\n", name, message)) {
+            Ok(()) => Exit(1).into(),
+            Err(e) => e,
+        }
+    }
+    
+    /// `${!name}`: the value of the variable that `name` holds the name of.
+    pub fn indirect(&self, name: &str) -> Result<String> {
+        let target = self.get_var(name)?;
+        if target.is_empty() {
+            return Ok(String::new());
+        }
+        self.get_var(&target)
+    }
+    
+    /// `${name#pattern}` and `##`, or `%` and `%%` with `suffix`: remove the
+    /// shortest or the `longest` match of the pattern from the value.
+    pub fn trim_pattern(&self, value: &str, pattern: &str, suffix: bool, longest: bool) -> String {
+        let cuts: Vec<usize> = value.char_indices().map(|(i, _)| i).chain([value.len()]).collect();
+        let matches = |&cut: &usize| {
+            let part = if suffix { &value[cut..] } else { &value[..cut] };
+            glob_match(pattern, part, self.extglob)
+        };
+        // The shortest prefix or the longest suffix starts from the left
+        let cut = if longest == suffix {
+            cuts.iter().find(|cut| matches(cut))
+        } else {
+            cuts.iter().rev().find(|cut| matches(cut))
+        };
+        match cut {
+            Some(&cut) if suffix => value[..cut].to_string(),
+            Some(&cut) => value[cut..].to_string(),
+            None => value.to_string(),
+        }
+    }
+    
+    /// `${name/pattern/replacement}`: replace the longest match of the
+    /// pattern, the first one or with `global` all of them. A pattern that
+    /// starts with `#` or `%` only matches at the start or the end.
+    pub fn replace_pattern(&self, value: &str, pattern: &str, replacement: &str, global: bool) -> String {
+        let bounds: Vec<usize> = value.char_indices().map(|(i, _)| i).chain([value.len()]).collect();
+        if let Some(pattern) = pattern.strip_prefix('#') {
+            return match bounds.iter().rev().find(|&&end| glob_match(pattern, &value[..end], self.extglob)) {
+                Some(&end) => format!("{}{}", replacement, &value[end..]),
+                None => value.to_string(),
+            };
+        }
+        if let Some(pattern) = pattern.strip_prefix('%') {
+            return match bounds.iter().find(|&&start| glob_match(pattern, &value[start..], self.extglob)) {
+                Some(&start) => format!("{}{}", &value[..start], replacement),
+                None => value.to_string(),
+            };
+        }
+        if pattern.is_empty() {
+            return value.to_string();
+        }
+        
+        let mut result = String::new();
+        let mut i = 0;
+        while i + 1 < bounds.len() {
+            let start = bounds[i];
+            let end = (i + 1..bounds.len()).rev().find(|&j| glob_match(pattern, &value[start..bounds[j]], self.extglob));
+            match end {
+                Some(j) => {
+                    result.push_str(replacement);
+                    i = j;
+                    if !global {
+                        break;
+                    }
+                }
+                None => {
+                    result.push_str(&value[start..bounds[i + 1]]);
+                    i += 1;
+                }
+            }
+        }
+        result.push_str(&value[bounds[i]..]);
+        result
+    }
+    
+    /// `[[ text =~ regex ]]`. After a match `BASH_REMATCH` and `rematch`
+    /// hold the match followed by its capture groups; both are cleared when
+    /// nothing matches.
+    pub fn regex_match(&mut self, text: &str, regex: &str) -> Result<bool> {
+        let compiled = regex::RegexBuilder::new(regex)
+            .case_insensitive(self.nocasematch)
+            .build()
+            .with_context(|| format!("invalid regular expression `{}`", regex))?;
+        self.rematch = match compiled.captures(text) {
+            Some(captures) => captures
+                .iter()
+                .map(|group| group.map(|m| m.as_str().to_string()).unwrap_or_default())
+                .collect(),
+            None => Vec::new(),
+        };
+        let mut array = Array::Indexed(BTreeMap::new());
+        array.extend(self.rematch.clone());
+        self.variables.remove("BASH_REMATCH");
+        self.arrays.insert("BASH_REMATCH".to_string(), array);
+        Ok(!self.rematch.is_empty())
+    }
+    
+    pub fn rematch(&self) -> &[String] {
+        &self.rematch
+    }
+    
+    /// `test -v name`.
+    pub fn is_var_set(&self, name: &str) -> bool {
+        if let Some(value) = self.positional(name) {
+            return value.is_some();
+        }
+        let name = self.resolve_name(name);
+        self.variables.contains_key(name) || self.arrays.contains_key(name)
+    }
+    
+    /// The positional parameters, as `"$@"` expands them.
+    pub fn positional_args(&self) -> Vec<String> {
+        self.args.clone()
+    }
+    
+    /// `set -- words`: new positional parameters for the function being
+    /// called, or else the script.
+    pub fn set_positional_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+    
+    /// Split the result of an unquoted expansion into fields on `IFS`.
+    ///
+    /// Runs of IFS whitespace separate fields and are trimmed from both ends;
+    /// every other IFS character ends a field, so `a,,b` with `IFS=,` has an
+    /// empty middle field. An empty `IFS` disables splitting.
+    pub fn split_fields(&self, text: &str) -> Vec<String> {
+        let ifs = self.variables.get("IFS").map(String::as_str).unwrap_or(" \t\n");
+        if ifs.is_empty() {
+            return if text.is_empty() { Vec::new() } else { vec![text.to_string()] };
+        }
+        let is_ifs_space = |c: char| matches!(c, ' ' | '\t' | '\n') && ifs.contains(c);
+        
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut chars = text.trim_start_matches(is_ifs_space).chars().peekable();
+        while let Some(c) = chars.next() {
+            if !ifs.contains(c) {
+                field.push(c);
+                continue;
+            }
+            
+            // One delimiter is IFS whitespace around at most one other IFS character
+            let mut seen_other = !is_ifs_space(c);
+            while let Some(&next) = chars.peek() {
+                if is_ifs_space(next) {
+                    chars.next();
+                } else if ifs.contains(next) && !seen_other {
+                    seen_other = true;
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            fields.push(std::mem::take(&mut field));
+        }
+        if !field.is_empty() {
+            fields.push(field);
+        }
+        fields
+    }
+    
+    /// Pathname expansion: the sorted paths matching `pattern`, or the
+    /// pattern itself (with escapes removed) when nothing matches and
+    /// nullglob is off. Hidden files only match a pattern component that
+    /// starts with a dot, unless dotglob is on.
+    pub fn expand_glob(&self, pattern: &str) -> Result<Vec<String>> {
+        self.expand_word(vec![WordPart::Literal(pattern.to_string())])
+    }
+    
+    /// Field splitting and pathname expansion of a word, after its
+    /// expansions have been replaced by their values.
+    ///
+    /// Unquoted literal text keeps its glob characters and backslash escapes,
+    /// quoted text and the values of quoted expansions are kept as they are,
+    /// and the values of unquoted expansions are split on `IFS`. A word that
+    /// is nothing but unquoted expansions with empty values disappears, while
+    /// `""` stays as one empty field. With failglob a pattern that matches
+    /// nothing is a `NoMatch` error.
+    pub fn expand_word(&self, parts: Vec<WordPart>) -> Result<Vec<String>> {
+        let ifs = self.variables.get("IFS").map(String::as_str).unwrap_or(" \t\n");
+        let is_ifs_space = |c: char| matches!(c, ' ' | '\t' | '\n') && ifs.contains(c);
+        
+        let mut fields = Vec::new();
+        let mut field = Field::default();
+        for part in parts {
+            match part {
+                WordPart::Literal(text) => {
+                    field.globs |= has_glob_chars(&text);
+                    field.text.push_str(&remove_backslashes(&text));
+                    field.pattern.push_str(&text);
+                    field.kept = true;
+                }
+                WordPart::Quoted(text) => {
+                    field.pattern.push_str(&escape_glob(&text));
+                    field.text.push_str(&text);
+                    field.kept = true;
+                }
+                WordPart::Expanded(text) => {
+                    let pieces = self.split_fields(&text);
+                    // Leading IFS whitespace, or a value that is all IFS
+                    // whitespace, ends the text before it
+                    if text.starts_with(is_ifs_space) || (pieces.is_empty() && !text.is_empty()) {
+                        field.finish(&mut fields);
+                    }
+                    for (i, piece) in pieces.iter().enumerate() {
+                        if i > 0 {
+                            field.finish(&mut fields);
+                        }
+                        field.globs |= has_glob_chars(piece);
+                        field.text.push_str(piece);
+                        field.pattern.push_str(piece);
+                        field.kept = true;
+                    }
+                    if !pieces.is_empty() && text.ends_with(|c| ifs.contains(c)) {
+                        field.finish(&mut fields);
+                    }
+                }
+            }
+        }
+        field.finish(&mut fields);
+        
+        let mut words = Vec::new();
+        for field in fields {
+            if !field.globs || self.noglob {
+                words.push(field.text);
+                continue;
+            }
+            let paths = self.glob_paths(&field.pattern, self.dotglob);
+            if !paths.is_empty() {
+                words.extend(paths);
+            } else if self.failglob {
+                return Err(NoMatch(field.text).into());
+            } else if !self.nullglob {
+                words.push(field.text);
+            }
+        }
+        Ok(words)
+    }
+    
+    /// Tilde expansion of `~user`: `~` is `$HOME`, `~+` is `$PWD` and `~-`
+    /// is `$OLDPWD`. Any other user is left as it was written.
+    pub fn tilde(&self, user: &str) -> String {
+        let var = match user {
+            "" => "HOME",
+            "+" => "PWD",
+            "-" => "OLDPWD",
+            _ => return format!("~{}", user),
+        };
+        match self.variables.get(var) {
+            Some(value) => value.clone(),
+            None => format!("~{}", user),
+        }
+    }
+    
+    /// zsh's `pattern(qualifiers)`: the matches of `pattern` filtered by
+    /// file type, then sorted, then cut down to a range.
+    pub fn expand_qualified_glob(&self, pattern: &str, qualifiers: &[GlobQualifier]) -> Vec<String> {
+        let nullglob = self.nullglob || qualifiers.contains(&GlobQualifier::NullGlob);
+        let dotglob = self.dotglob || qualifiers.contains(&GlobQualifier::DotGlob);
+        let mut paths = self.glob_paths(pattern, dotglob);
+        
+        // `^` negates the file types after it
+        let mut negate = false;
+        for qualifier in qualifiers {
+            match *qualifier {
+                GlobQualifier::Not => negate = !negate,
+                GlobQualifier::FileType(kind) => paths.retain(|path| glob_file_type(kind, path) != negate),
+                _ => {}
+            }
+        }
+        
+        // Sorting is stable, so going backwards makes the first key the main one
+        for qualifier in qualifiers.iter().rev() {
+            if let GlobQualifier::Sort { key, descending } = *qualifier {
+                match key {
+                    'L' => paths.sort_by_key(|path| std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)),
+                    // By modification time is newest first
+                    'm' => paths.sort_by_key(|path| {
+                        std::cmp::Reverse(std::fs::metadata(path).and_then(|m| m.modified()).ok())
+                    }),
+                    _ => paths.sort(),
+                }
+                if descending {
+                    paths.reverse();
+                }
+            }
+        }
+        
+        for qualifier in qualifiers {
+            if let GlobQualifier::Range(first, last) = *qualifier {
+                let len = paths.len() as i64;
+                let position = |index: i64| if index < 0 { len + index } else { index - 1 };
+                let (start, end) = (position(first).max(0), position(last).min(len - 1));
+                paths = if start <= end {
+                    paths[start as usize..=end as usize].to_vec()
+                } else {
+                    Vec::new()
+                };
+            }
+        }
+        
+        if paths.is_empty() && !nullglob {
+            return vec![remove_backslashes(pattern)];
+        }
+        paths
+    }
+    
+    pub fn set_nullglob(&mut self, enabled: bool) {
+        self.nullglob = enabled;
+    }
+    
+    pub fn set_dotglob(&mut self, enabled: bool) {
+        self.dotglob = enabled;
+    }
+    
+    /// The sorted paths matching `pattern`.
+    fn glob_paths(&self, pattern: &str, dotglob: bool) -> Vec<String> {
+        let (mut paths, rest) = match pattern.strip_prefix('/') {
+            Some(rest) => (vec!["/".to_string()], rest),
+            None => (vec![String::new()], pattern),
+        };
+        let dirs_only = rest.ends_with('/');
+        let components: Vec<&str> = rest.split('/').filter(|c| !c.is_empty()).collect();
+        for (i, &component) in components.iter().enumerate() {
+            let mut matches = Vec::new();
+            for base in &paths {
+                let prefix = if base.is_empty() || base.ends_with('/') {
+                    base.clone()
+                } else {
+                    format!("{}/", base)
+                };
+                if !has_glob_chars(component) {
+                    matches.push(format!("{}{}", prefix, remove_backslashes(component)));
+                    continue;
+                }
+                if component == "**" && self.globstar {
+                    // Any depth of directories, and at the end of the pattern
+                    // files too; before a `/` it also matches no directory
+                    let last = i + 1 == components.len();
+                    let found = descendants(base, dotglob, last && !dirs_only);
+                    matches.extend(found.into_iter().map(|path| format!("{}{}", prefix, path)));
+                    if !last {
+                        matches.push(base.clone());
+                    }
+                    continue;
+                }
+                
+                let dir = if base.is_empty() { "." } else { base.as_str() };
+                let Ok(entries) = std::fs::read_dir(dir) else { continue };
+                let mut names: Vec<String> = entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .filter(|name| dotglob || !name.starts_with('.') || component.starts_with('.'))
+                    .filter(|name| if self.nocaseglob {
+                        glob_match(&component.to_lowercase(), &name.to_lowercase(), self.extglob)
+                    } else {
+                        glob_match(component, name, self.extglob)
+                    })
+                    .collect();
+                names.sort();
+                matches.extend(names.into_iter().map(|name| format!("{}{}", prefix, name)));
+            }
+            paths = matches;
+        }
+        
+        paths.retain(|path| {
+            let metadata = std::fs::metadata(path);
+            if dirs_only {
+                metadata.is_ok_and(|m| m.is_dir())
+            } else {
+                metadata.is_ok() || std::fs::symlink_metadata(path).is_ok()
+            }
+        });
+        if dirs_only {
+            for path in &mut paths {
+                path.push('/');
+            }
+        }
+        paths
+    }
+    
+    /// Field splitting followed by pathname expansion of each field, as
+    /// applied to an unquoted expansion in a word list.
+    pub fn expand_fields(&self, text: &str) -> Result<Vec<String>> {
+        self.expand_word(vec![WordPart::Expanded(text.to_string())])
+    }
+    
+    pub fn set_extglob(&mut self, enabled: bool) {
+        self.extglob = enabled;
+    }
+    
+    /// Value of a variable used in arithmetic. Unset and empty variables are
+    /// 0; any other value is evaluated as an expression itself, like bash.
+    pub fn arith_var(&mut self, name: &str) -> Result<i64> {
+        let value = self.get_var(name)?;
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        if let Ok(n) = value.parse::<i64>() {
+            return Ok(n);
+        }
+        
+        if self.arith_depth >= 1024 {
+            bail!("{}: expression recursion level exceeded", name);
+        }
+        self.arith_depth += 1;
+        let result = self.eval_arith(value);
+        self.arith_depth -= 1;
+        result
+    }
+    
+    pub fn arith_assign(&mut self, name: &str, value: i64) -> Result<i64> {
+        self.set_var(name, value.to_string())?;
+        Ok(value)
+    }
+    
+    /// Evaluate arithmetic text whose shape is only known at runtime, such
+    /// as `$(( $a $op 2 ))` after expansion.
+    pub fn eval_arith(&mut self, expr: &str) -> Result<i64> {
+        let tokens = match tokenize_arith(expr) {
+            Ok(tokens) => tokens,
+            Err(e) if self.arith_depth == 0 => bail!("{}: {}", expr.trim(), e),
+            Err(e) => return Err(e),
+        };
+        if tokens.is_empty() {
+            return Ok(0);
+        }
+        
+        let mut eval = ArithEval { runtime: self, tokens, pos: 0, skip: 0 };
+        let result = eval.comma().and_then(|value| match eval.tokens.get(eval.pos) {
+            Some(token) => bail!("syntax error in expression (error token is \"{}\")", token),
+            None => Ok(value),
+        });
+        match result {
+            // Variables holding expressions report errors at the outermost level
+            Err(e) if self.arith_depth == 0 => bail!("{}: {}", expr.trim(), e),
+            result => result,
+        }
+    }
+}
+
+fn exit_code(status: std::process::ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// An I/O error as the shell prints it, without Rust's `(os error N)`.
+/// A zsh `${(flags)name}` flag.
+#[derive(Debug, Clone, Copy)]
+pub enum ParamFlag {
+    Join(&'static str),
+    Split(&'static str),
+    Upper,
+    Lower,
+    Capitalize,
+    Sort,
+    SortDescending,
+    Unique,
+}
+
+/// Part of a word for `ShellRuntime::expand_word`: unquoted text as
+/// written, quoted text, or the value of an unquoted expansion.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    Literal(String),
+    Quoted(String),
+    Expanded(String),
+}
+
+/// A field being built by `ShellRuntime::expand_word`. `pattern` is the text
+/// with its quoted characters escaped, for pathname expansion.
+#[derive(Default)]
+struct Field {
+    text: String,
+    pattern: String,
+    globs: bool,
+    kept: bool,
+}
+
+impl Field {
+    fn finish(&mut self, fields: &mut Vec<Field>) {
+        let field = std::mem::take(self);
+        if field.kept {
+            fields.push(field);
+        }
+    }
+}
+
+/// A zsh glob qualifier, the `(.)` of `*(.)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GlobQualifier {
+    FileType(char),
+    Not,
+    NullGlob,
+    DotGlob,
+    Sort { key: char, descending: bool },
+    Range(i64, i64),
+}
+
+/// The paths under the directory `base` (the current one when empty)
+/// relative to it, sorted: the subdirectories, and with `files` the other
+/// entries too. Hidden ones need `dotglob`, and symlinked directories
+/// aren't followed.
+fn descendants(base: &str, dotglob: bool, files: bool) -> Vec<String> {
+    let mut found = Vec::new();
+    let mut pending = vec![String::new()];
+    while let Some(dir) = pending.pop() {
+        let path = match (base.is_empty(), dir.is_empty()) {
+            (true, true) => ".".to_string(),
+            (true, false) => dir.clone(),
+            (false, true) => base.to_string(),
+            (false, false) => format!("{}/{}", base.trim_end_matches('/'), dir),
+        };
+        let Ok(entries) = std::fs::read_dir(&path) else { continue };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') && !dotglob {
+                continue;
+            }
+            let relative = if dir.is_empty() { name } else { format!("{}/{}", dir, name) };
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                pending.push(relative.clone());
+                found.push(relative);
+            } else if files {
+                found.push(relative);
+            }
+        }
+    }
+    found.sort();
+    found
+}
+
+/// Whether `path` has the type of a glob qualifier: `.` plain file, `/`
+/// directory, `@` symlink, `=` socket, `p` named pipe or `*` executable
+/// plain file. Symlinks are not followed.
+fn glob_file_type(kind: char, path: &str) -> bool {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else { return false };
+    let file_type = metadata.file_type();
+    match kind {
+        '.' => file_type.is_file(),
+        '/' => file_type.is_dir(),
+        '@' => file_type.is_symlink(),
+        #[cfg(unix)]
+        '=' => std::os::unix::fs::FileTypeExt::is_socket(&file_type),
+        #[cfg(unix)]
+        'p' => std::os::unix::fs::FileTypeExt::is_fifo(&file_type),
+        #[cfg(unix)]
+        '*' => file_type.is_file() && std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o111 != 0,
+        _ => false,
+    }
+}
+
+/// zsh's `(C)` flag: each word with a capital first letter and the rest
+/// lower case.
+fn capitalize_words(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut in_word = false;
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            if in_word {
+                result.extend(c.to_lowercase());
+            } else {
+                result.extend(c.to_uppercase());
+            }
+            in_word = true;
+        } else {
+            result.push(c);
+            in_word = false;
+        }
+    }
+    result
+}
+
+fn io_error_message(error: &std::io::Error) -> String {
+    let message = error.to_string();
+    match message.find(" (os error") {
+        Some(end) => message[..end].to_string(),
+        None => message,
+    }
+}
+
+fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe)
+}
+
+/// `${name:offset:length}` of a value, in characters. A negative offset
+/// counts from the end, and a negative length stops that many characters
+/// before it.
+pub fn substring(value: &str, offset: i64, length: Option<i64>) -> Result<String> {
+    let chars: Vec<char> = value.chars().collect();
+    let len = chars.len() as i64;
+    let start = if offset < 0 { len + offset } else { offset.min(len) };
+    if start < 0 {
+        return Ok(String::new());
+    }
+    let end = match length {
+        None => len,
+        Some(length) if length < 0 => len + length,
+        Some(length) => (start + length).min(len),
+    };
+    if end < start {
+        bail!("{}: substring expression < 0", length.unwrap_or_default());
+    }
+    Ok(chars[start as usize..end as usize].iter().collect())
+}
+
+/// Integer operand of `test`: decimal digits with an optional sign and
+/// surrounding blanks.
+pub fn test_integer(text: &str) -> Result<i64> {
+    match text.trim_matches([' ', '\t', '\n']).parse::<i64>() {
+        Ok(value) => Ok(value),
+        Err(_) => bail!("{}: integer expression expected", text),
+    }
+}
+
+/// The file tests of `test` that look at metadata, by option letter.
+///
+/// `-r`, `-w` and `-x` check the permission bits for anyone, since the
+/// process's user and groups are not compared with the file's owner.
+pub fn file_test(op: char, path: &str) -> bool {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return false;
+    };
+    
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+        let mode = metadata.permissions().mode();
+        let file_type = metadata.file_type();
+        return match op {
+            'r' => mode & 0o444 != 0,
+            'w' => mode & 0o222 != 0,
+            'x' => mode & 0o111 != 0,
+            's' => metadata.len() > 0,
+            'b' => file_type.is_block_device(),
+            'c' => file_type.is_char_device(),
+            'p' => file_type.is_fifo(),
+            'S' => file_type.is_socket(),
+            'u' => mode & 0o4000 != 0,
+            'g' => mode & 0o2000 != 0,
+            'k' => mode & 0o1000 != 0,
+            _ => false,
+        };
+    }
+    #[cfg(not(unix))]
+    {
+        return match op {
+            'r' | 'x' => true,
+            'w' => !metadata.permissions().readonly(),
+            's' => metadata.len() > 0,
+            _ => false,
+        };
+    }
+}
+
+/// `a -nt b`: `a` was modified later than `b`, or only `a` exists.
+pub fn file_newer(a: &str, b: &str) -> bool {
+    let modified = |path: &str| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    match (modified(a), modified(b)) {
+        (Some(a), Some(b)) => a > b,
+        (Some(_), None) => true,
+        _ => false,
+    }
+}
+
+/// `a -ef b`: both paths name the same file.
+pub fn same_file(a: &str, b: &str) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// `test -t fd`.
+pub fn is_terminal(fd: i64) -> bool {
+    use std::io::IsTerminal;
+    match fd {
+        0 => std::io::stdin().is_terminal(),
+        1 => std::io::stdout().is_terminal(),
+        2 => std::io::stderr().is_terminal(),
+        _ => false,
+    }
+}
+
+/// Drops the backslashes `read` removes without `-r`.
+/// `shopt` options that only matter to an interactive shell, or that
+/// scripts turn on without depending on them.
+const IGNORED_SHOPTS: &[&str] = &[
+    "autocd", "cdspell", "checkhash", "checkjobs", "checkwinsize", "cmdhist", "direxpand", "dirspell",
+    "expand_aliases", "extquote", "globasciiranges", "histappend", "histreedit", "histverify",
+    "hostcomplete", "huponexit", "inherit_errexit", "interactive_comments", "lastpipe", "lithist",
+    "login_shell", "mailwarn", "progcomp", "promptvars", "shift_verbose", "sourcepath", "xpg_echo",
+];
+
+/// The name a trap is kept under, from a `trap` condition in any case:
+/// EXIT, ERR, DEBUG, RETURN, or a signal name without `SIG`. Signal 0 is
+/// EXIT.
+fn trap_condition(word: &str) -> Option<String> {
+    let word = word.to_uppercase();
+    if let Ok(number) = word.parse::<i32>() {
+        if number == 0 {
+            return Some("EXIT".to_string());
+        }
+        return signal_hook::low_level::signal_name(number).map(|name| name[3..].to_string());
+    }
+    match word.as_str() {
+        "EXIT" | "ERR" | "DEBUG" | "RETURN" => Some(word),
+        _ => {
+            let name = word.strip_prefix("SIG").unwrap_or(&word);
+            signal_number(name).map(|_| name.to_string())
+        }
+    }
+}
+
+/// How `jobs` describes a job that a signal ended.
+fn signal_description(signal: i32) -> String {
+    match signal {
+        1 => "Hangup",
+        2 => "Interrupt",
+        3 => "Quit",
+        6 => "Aborted",
+        9 => "Killed",
+        10 => "User defined signal 1",
+        12 => "User defined signal 2",
+        13 => "Broken pipe",
+        14 => "Alarm clock",
+        15 => "Terminated",
+        _ => return format!("Signal {}", signal),
+    }
+    .to_string()
+}
+
+fn signal_number(name: &str) -> Option<usize> {
+    (1..SIGNALS).find(|&signal| {
+        signal_hook::low_level::signal_name(signal as i32).is_some_and(|signal_name| signal_name[3..] == *name)
+    })
+}
+
+/// `trap -p` lists EXIT, the signals by number, then DEBUG, ERR and RETURN.
+fn trap_order(name: &str) -> usize {
+    match name {
+        "EXIT" => 0,
+        "DEBUG" => SIGNALS,
+        "ERR" => SIGNALS + 1,
+        "RETURN" => SIGNALS + 2,
+        _ => signal_number(name).unwrap_or(0),
+    }
+}
+
+/// A word as xtrace shows it: in single quotes when it has characters the
+/// shell would treat specially.
+fn trace_quote(word: &str) -> String {
+    if !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c)) {
+        return word.to_string();
+    }
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+fn remove_backslashes(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Whether a word contains unescaped pathname expansion characters.
+fn has_glob_chars(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Backslash-escape the value of a quoted expansion in a pattern, so it
+/// matches literally.
+pub fn escape_glob(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The output of `echo -e` for `text`: backslash escapes such as `\t`,
+/// `\0nnn` and `\xHH` are replaced, and the newline is left off after `\c`,
+/// which ends the output.
+pub fn echo_escapes(text: &str) -> String {
+    let mut result = String::with_capacity(text.len() + 1);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        let (radix, max_digits) = match chars.peek() {
+            Some('0') => (8, 3),
+            Some('x') => (16, 2),
+            Some('u') => (16, 4),
+            Some('U') => (16, 8),
+            _ => (0, 0),
+        };
+        if radix != 0 {
+            let prefix = chars.next().unwrap();
+            let mut digits = String::new();
+            while digits.len() < max_digits {
+                match chars.peek() {
+                    Some(&d) if d.is_digit(radix) => {
+                        digits.push(d);
+                        chars.next();
+                    }
+                    _ => break,
+                }
+            }
+            match u32::from_str_radix(&digits, radix).ok().and_then(char::from_u32) {
+                Some(code) => result.push(code),
+                // `\0` alone is a NUL, `\x` without digits stays as written
+                None if prefix == '0' => result.push('\0'),
+                None => {
+                    result.push('\\');
+                    result.push(prefix);
+                }
+            }
+            continue;
+        }
+        match chars.next() {
+            Some('a') => result.push('\x07'),
+            Some('b') => result.push('\x08'),
+            Some('c') => return result,
+            Some('e' | 'E') => result.push('\x1b'),
+            Some('f') => result.push('\x0c'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('t') => result.push('\t'),
+            Some('v') => result.push('\x0b'),
+            Some('\\') => result.push('\\'),
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result.push('\n');
+    result
+}
+
+/// Shell pattern matching as used by `case`: `*`, `?`, `[...]` brackets and,
+/// with `extglob`, the `?(...)`, `*(...)`, `+(...)`, `@(...)` and `!(...)`
+/// groups. A backslash makes the next character literal.
+pub fn glob_match(pattern: &str, text: &str, extglob: bool) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    glob_match_chars(&pattern, &text, extglob)
+}
+
+fn glob_match_chars(pattern: &[char], text: &[char], extglob: bool) -> bool {
+    let Some(&first) = pattern.first() else {
+        return text.is_empty();
+    };
+    
+    if extglob && matches!(first, '?' | '*' | '+' | '@' | '!') && pattern.get(1) == Some(&'(') {
+        if let Some(close) = find_group_end(pattern, 1) {
+            let alternatives = split_alternatives(&pattern[2..close]);
+            let rest = &pattern[close + 1..];
+            let matches_any = |part: &[char]| {
+                alternatives.iter().any(|alt| glob_match_chars(alt, part, extglob))
+            };
+            
+            return match first {
+                '?' => glob_match_chars(rest, text, extglob)
+                    || (1..=text.len()).any(|k| matches_any(&text[..k]) && glob_match_chars(rest, &text[k..], extglob)),
+                '@' => (0..=text.len()).any(|k| matches_any(&text[..k]) && glob_match_chars(rest, &text[k..], extglob)),
+                '!' => (0..=text.len()).any(|k| !matches_any(&text[..k]) && glob_match_chars(rest, &text[k..], extglob)),
+                _ => {
+                    // `*(...)` and `+(...)`: one or more repetitions, then the rest
+                    let star: Vec<char> = std::iter::once('*').chain(pattern[1..].iter().copied()).collect();
+                    (first == '*' && glob_match_chars(rest, text, extglob))
+                        || (1..=text.len()).any(|k| matches_any(&text[..k]) && glob_match_chars(&star, &text[k..], extglob))
+                }
+            };
+        }
+    }
+    
+    match first {
+        '*' => (0..=text.len()).any(|k| glob_match_chars(&pattern[1..], &text[k..], extglob)),
+        '?' => !text.is_empty() && glob_match_chars(&pattern[1..], &text[1..], extglob),
+        '[' => match (text.first(), match_bracket(pattern, text.first().copied())) {
+            (Some(_), Some((true, len))) => glob_match_chars(&pattern[len..], &text[1..], extglob),
+            (_, Some((false, _))) | (None, Some(_)) => false,
+            // An unclosed bracket is an ordinary character
+            (_, None) => text.first() == Some(&'[') && glob_match_chars(&pattern[1..], &text[1..], extglob),
+        },
+        '\\' if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && glob_match_chars(&pattern[2..], &text[1..], extglob)
+        }
+        c => text.first() == Some(&c) && glob_match_chars(&pattern[1..], &text[1..], extglob),
+    }
+}
+
+/// Match `c` against the bracket expression at the start of `pattern`,
+/// returning whether it matched and the length of the expression.
+fn match_bracket(pattern: &[char], c: Option<char>) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    
+    let mut matched = false;
+    let mut first = true;
+    while i < pattern.len() {
+        let start = pattern[i];
+        if start == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        
+        if start == '[' && pattern.get(i + 1) == Some(&':') {
+            let class_end = (i + 2..pattern.len().saturating_sub(1))
+                .find(|&j| pattern[j] == ':' && pattern[j + 1] == ']');
+            if let Some(end) = class_end {
+                let class: String = pattern[i + 2..end].iter().collect();
+                if let Some(c) = c {
+                    matched |= match class.as_str() {
+                        "alpha" => c.is_alphabetic(),
+                        "digit" => c.is_ascii_digit(),
+                        "alnum" => c.is_alphanumeric(),
+                        "upper" => c.is_uppercase(),
+                        "lower" => c.is_lowercase(),
+                        "space" => c.is_whitespace(),
+                        "punct" => c.is_ascii_punctuation(),
+                        "xdigit" => c.is_ascii_hexdigit(),
+                        _ => false,
+                    };
+                }
+                i = end + 2;
+                continue;
+            }
+        }
+        
+        let (start, next) = if start == '\\' && i + 1 < pattern.len() {
+            (pattern[i + 1], i + 2)
+        } else {
+            (start, i + 1)
+        };
+        
+        if pattern.get(next) == Some(&'-') && pattern.get(next + 1).is_some_and(|&e| e != ']') {
+            let end = pattern[next + 1];
+            if let Some(c) = c {
+                matched |= start <= c && c <= end;
+            }
+            i = next + 2;
+        } else {
+            matched |= c == Some(start);
+            i = next;
+        }
+    }
+    
+    None
+}
+
+/// Index of the `)` closing the group whose `(` is at `open`.
+fn find_group_end(pattern: &[char], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = open;
+    while i < pattern.len() {
+        match pattern[i] {
+            '\\' => i += 1,
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Split the inside of an extglob group on its top-level `|`.
+fn split_alternatives(group: &[char]) -> Vec<Vec<char>> {
+    let mut alternatives = vec![Vec::new()];
+    let mut depth = 0;
+    let mut i = 0;
+    while i < group.len() {
+        let c = group[i];
+        match c {
+            '\\' if i + 1 < group.len() => {
+                alternatives.last_mut().unwrap().extend([c, group[i + 1]]);
+                i += 2;
+                continue;
+            }
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '|' if depth == 0 => {
+                alternatives.push(Vec::new());
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+        alternatives.last_mut().unwrap().push(c);
+        i += 1;
+    }
+    alternatives
+}
+
+pub fn arith_div(a: i64, b: i64) -> Result<i64> {
+    if b == 0 {
+        bail!("division by 0");
+    }
+    Ok(a.wrapping_div(b))
+}
+
+pub fn arith_rem(a: i64, b: i64) -> Result<i64> {
+    if b == 0 {
+        bail!("division by 0");
     }
+    Ok(a.wrapping_rem(b))
 }
 
-fn default_config() -> Config {
-    Config {
-        ui: UiConfig {
-            theme: "dracula".to_string(),
-        },
-        build: BuildConfig {
-            targets: vec!["linux_amd64".to_string()],
-            optimize: "size".to_string(),
-            strip: true,
-            compress: true,
-        },
-        notifications: NotificationConfig {
-            enabled: true,
-            desktop: true,
-        },
-        updates: UpdateConfig {
-            enabled: false,
-            check_on_start: false,
-            auto_download: false,
-        },
+pub fn arith_pow(base: i64, exponent: i64) -> Result<i64> {
+    if exponent < 0 {
+        bail!("exponent less than 0");
+    }
+    let (mut result, mut base, mut exponent) = (1i64, base, exponent as u64);
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exponent >>= 1;
     }
+    Ok(result)
 }
-"#.to_string())
+
+#[derive(Debug, Clone, PartialEq)]
+enum ArithToken {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+impl std::fmt::Display for ArithToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArithToken::Number(n) => write!(f, "{}", n),
+            ArithToken::Name(name) => write!(f, "{}", name),
+            ArithToken::Op(op) => write!(f, "{}", op),
+        }
     }
-    
-    fn generate_shell_runtime(&self) -> Result<String> {
-        Ok(r#"use anyhow::{Result, Context};
-use std::collections::HashMap;
-use std::env;
-use std::path::PathBuf;
-use std::process::Command;
+}
 
-pub struct ShellRuntime {
-    variables: HashMap<String, String>,
-    functions: HashMap<String, fn(&mut ShellRuntime, &[String]) -> Result<()>>,
-    args: Vec<String>,
-    last_exit_status: i32,
-    current_dir: PathBuf,
+/// Arithmetic operators, longest first so tokenizing can take the first match.
+const ARITH_OPERATORS: &[&str] = &[
+    "<<=", ">>=",
+    "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+=", "-=", "*=", "/=", "%=", "&=", "^=", "|=",
+    "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "^", "|",
+    "?", ":", ",", "(", ")",
+];
+
+/// Left-associative binary operators from loosest to tightest binding.
+const ARITH_LEVELS: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", ">", "<=", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+fn tokenize_arith(expr: &str) -> Result<Vec<ArithToken>> {
+    let mut tokens = Vec::new();
+    let mut rest = expr;
+    
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '#' || c == '@' || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(ArithToken::Number(parse_arith_integer(&rest[..end])?));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(ArithToken::Name(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if let Some(op) = ARITH_OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(ArithToken::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            bail!("syntax error: invalid arithmetic operator (error token is \"{}\")", rest);
+        }
+    }
+    
+    Ok(tokens)
 }
 
-impl ShellRuntime {
-    pub fn new(args: Vec<String>) -> Result<Self> {
-        let current_dir = env::current_dir()?;
-        
-        let mut runtime = Self {
-            variables: HashMap::new(),
-            functions: HashMap::new(),
-            args,
-            last_exit_status: 0,
-            current_dir,
+/// Decimal, `0x` hex, leading-zero octal or `base#digits` integer constant.
+fn parse_arith_integer(text: &str) -> Result<i64> {
+    let (base, digits) = if let Some((base, digits)) = text.split_once('#') {
+        match base.parse::<u32>() {
+            Ok(base) if (2..=64).contains(&base) => (base, digits),
+            _ => bail!("{}: invalid arithmetic base", text),
+        }
+    } else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (16, hex)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    } else {
+        (10, text)
+    };
+    if digits.is_empty() {
+        bail!("{}: invalid integer constant", text);
+    }
+    
+    let mut value: u64 = 0;
+    for c in digits.chars() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base > 36 => c as u32 - 'A' as u32 + 36,
+            'A'..='Z' => c as u32 - 'A' as u32 + 10,
+            '@' => 62,
+            '_' => 63,
+            _ => bail!("{}: invalid number", text),
         };
-        
-        // Initialize environment variables
-        for (key, value) in env::vars() {
-            runtime.variables.insert(key, value);
+        if digit >= base {
+            bail!("{}: value too great for base", text);
         }
-        
-        // Set positional parameters
-        for (i, arg) in runtime.args.iter().enumerate() {
-            runtime.variables.insert(i.to_string(), arg.clone());
+        value = value.wrapping_mul(base as u64).wrapping_add(digit as u64);
+    }
+    Ok(value as i64)
+}
+
+/// Recursive-descent evaluator for arithmetic text. Operands on the side of
+/// `&&`, `||` and `?:` that is not taken are still parsed, with `skip` set
+/// so they have no effect.
+struct ArithEval<'a> {
+    runtime: &'a mut ShellRuntime,
+    tokens: Vec<ArithToken>,
+    pos: usize,
+    skip: usize,
+}
+
+impl ArithEval<'_> {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(ArithToken::Op(op)) => Some(op),
+            _ => None,
         }
-        runtime.variables.insert("#".to_string(), runtime.args.len().to_string());
-        
-        Ok(runtime)
     }
     
-    pub fn get_var(&self, name: &str) -> Result<String> {
-        Ok(self.variables.get(name).cloned().unwrap_or_default())
+    fn eat(&mut self, op: &str) -> bool {
+        let found = self.peek_op() == Some(op);
+        if found {
+            self.pos += 1;
+        }
+        found
     }
     
-    pub fn set_var(&mut self, name: &str, value: impl Into<String>) -> Result<()> {
-        self.variables.insert(name.to_string(), value.into());
+    fn expect(&mut self, op: &str) -> Result<()> {
+        if !self.eat(op) {
+            bail!("syntax error: `{}' expected", op);
+        }
         Ok(())
     }
     
-    pub fn export_var(&mut self, name: &str, value: impl Into<String>) -> Result<()> {
-        let value = value.into();
-        self.variables.insert(name.to_string(), value.clone());
-        env::set_var(name, value);
-        Ok(())
+    fn skipping(&mut self, skip: bool, f: impl FnOnce(&mut Self) -> Result<i64>) -> Result<i64> {
+        if skip {
+            self.skip += 1;
+        }
+        let result = f(self);
+        if skip {
+            self.skip -= 1;
+        }
+        result
     }
     
-    pub fn change_dir(&mut self, path: impl AsRef<std::path::Path>) -> Result<()> {
-        let path = path.as_ref();
-        env::set_current_dir(path)
-            .context("Failed to change directory")?;
-        self.current_dir = env::current_dir()?;
-        Ok(())
+    fn var(&mut self, name: &str) -> Result<i64> {
+        if self.skip > 0 {
+            return Ok(0);
+        }
+        self.runtime.arith_var(name)
     }
     
-    pub fn change_dir_home(&mut self) -> Result<()> {
-        let home = dirs::home_dir()
-            .context("Failed to get home directory")?;
-        self.change_dir(home)
+    fn assign(&mut self, name: &str, value: i64) -> Result<i64> {
+        if self.skip > 0 {
+            return Ok(value);
+        }
+        self.runtime.arith_assign(name, value)
     }
     
-    pub fn execute_command(&mut self, cmd: &str, args: &[impl AsRef<str>]) -> Result<()> {
-        let output = Command::new(cmd)
-            .args(args.iter().map(|s| s.as_ref()))
-            .output()
-            .context("Failed to execute command")?;
-        
-        self.last_exit_status = output.status.code().unwrap_or(-1);
-        
-        print!("{}", String::from_utf8_lossy(&output.stdout));
-        eprint!("{}", String::from_utf8_lossy(&output.stderr));
+    fn apply(&self, op: &str, a: i64, b: i64) -> Result<i64> {
+        if self.skip > 0 {
+            return Ok(0);
+        }
+        Ok(match op {
+            "+" => a.wrapping_add(b),
+            "-" => a.wrapping_sub(b),
+            "*" => a.wrapping_mul(b),
+            "/" => arith_div(a, b)?,
+            "%" => arith_rem(a, b)?,
+            "**" => arith_pow(a, b)?,
+            "<<" => a.wrapping_shl(b as u32),
+            ">>" => a.wrapping_shr(b as u32),
+            "&" => a & b,
+            "|" => a | b,
+            "^" => a ^ b,
+            "==" => i64::from(a == b),
+            "!=" => i64::from(a != b),
+            "<" => i64::from(a < b),
+            ">" => i64::from(a > b),
+            "<=" => i64::from(a <= b),
+            ">=" => i64::from(a >= b),
+            _ => bail!("syntax error: invalid arithmetic operator (error token is \"{}\")", op),
+        })
+    }
+    
+    fn comma(&mut self) -> Result<i64> {
+        let mut value = self.assignment()?;
+        while self.eat(",") {
+            value = self.assignment()?;
+        }
+        Ok(value)
+    }
+    
+    fn assignment(&mut self) -> Result<i64> {
+        if let (Some(ArithToken::Name(name)), Some(ArithToken::Op(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+        {
+            if matches!(*op, "=" | "+=" | "-=" | "*=" | "/=" | "%=" | "<<=" | ">>=" | "&=" | "^=" | "|=") {
+                let (name, op) = (name.clone(), *op);
+                self.pos += 2;
+                let mut value = self.assignment()?;
+                if op != "=" {
+                    let current = self.var(&name)?;
+                    value = self.apply(&op[..op.len() - 1], current, value)?;
+                }
+                return self.assign(&name, value);
+            }
+        }
+        self.conditional()
+    }
+    
+    fn conditional(&mut self) -> Result<i64> {
+        let condition = self.binary(0)?;
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+        let then_value = self.skipping(condition == 0, |eval| eval.comma())?;
+        self.expect(":")?;
+        let else_value = self.skipping(condition != 0, |eval| eval.conditional())?;
+        Ok(if condition != 0 { then_value } else { else_value })
+    }
+    
+    fn binary(&mut self, level: usize) -> Result<i64> {
+        if level == ARITH_LEVELS.len() {
+            return self.power();
+        }
         
-        Ok(())
+        let mut left = self.binary(level + 1)?;
+        while let Some(op) = self.peek_op().filter(|op| ARITH_LEVELS[level].contains(op)) {
+            self.pos += 1;
+            left = match op {
+                "&&" => {
+                    let right = self.skipping(left == 0, |eval| eval.binary(level + 1))?;
+                    i64::from(left != 0 && right != 0)
+                }
+                "||" => {
+                    let right = self.skipping(left != 0, |eval| eval.binary(level + 1))?;
+                    i64::from(left != 0 || right != 0)
+                }
+                _ => {
+                    let right = self.binary(level + 1)?;
+                    self.apply(op, left, right)?
+                }
+            };
+        }
+        Ok(left)
     }
     
-    pub fn last_exit_status(&self) -> i32 {
-        self.last_exit_status
+    fn power(&mut self) -> Result<i64> {
+        let base = self.unary()?;
+        if !self.eat("**") {
+            return Ok(base);
+        }
+        let exponent = self.power()?;
+        self.apply("**", base, exponent)
     }
     
-    pub fn register_function(&mut self, name: &str, func: fn(&mut ShellRuntime, &[String]) -> Result<()>) {
-        self.functions.insert(name.to_string(), func);
+    fn unary(&mut self) -> Result<i64> {
+        let op = match self.peek_op() {
+            Some(op @ ("!" | "~" | "-" | "+" | "++" | "--")) => op,
+            _ => return self.postfix(),
+        };
+        self.pos += 1;
+        
+        if let ("++" | "--", Some(ArithToken::Name(name))) = (op, self.tokens.get(self.pos)) {
+            let name = name.clone();
+            self.pos += 1;
+            let value = self.var(&name)?;
+            let value = if op == "++" { value.wrapping_add(1) } else { value.wrapping_sub(1) };
+            return self.assign(&name, value);
+        }
+        
+        let value = self.unary()?;
+        Ok(match op {
+            "!" => i64::from(value == 0),
+            "~" => !value,
+            "-" => value.wrapping_neg(),
+            // `--5` without a variable is two unary minuses
+            _ => value,
+        })
+    }
+    
+    fn postfix(&mut self) -> Result<i64> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(ArithToken::Number(n)) => Ok(n),
+            Some(ArithToken::Name(name)) => {
+                let value = self.var(&name)?;
+                if self.eat("++") {
+                    self.assign(&name, value.wrapping_add(1))?;
+                } else if self.eat("--") {
+                    self.assign(&name, value.wrapping_sub(1))?;
+                }
+                Ok(value)
+            }
+            Some(ArithToken::Op("(")) => {
+                let value = self.comma()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            Some(token) => bail!("syntax error: operand expected (error token is \"{}\")", token),
+            None => bail!("syntax error: operand expected"),
+        }
     }
 }
-"#.to_string())
+"##.to_string())
     }
     
     fn generate_embedded_files(&self) -> Result<String> {
@@ -713,11 +6153,470 @@ pub fn false_cmd(_args: &[&str]) -> Result<()> {
     }
     
     fn generate_ui(&self) -> Result<String> {
-        Ok(r#"pub mod theme;
+        // The generated program has no themes of its own yet
+        Ok("//! Terminal UI helpers.\n".to_string())
+    }
+}
 
-pub use theme::Theme;
-"#.to_string())
+/// Parenthesize a generated arithmetic expression unless it is a single
+/// literal, path or call, so it can be used as an operand.
+fn operand(code: String) -> String {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut prev = ' ';
+    let mut simple = !code.starts_with(['{', '!']) && !code.starts_with("if ");
+    for c in code.chars() {
+        match c {
+            '"' if prev != '\\' => in_string = !in_string,
+            '(' | '[' | '{' if !in_string => depth += 1,
+            ')' | ']' | '}' if !in_string => depth -= 1,
+            ' ' if !in_string && depth == 0 => simple = false,
+            _ => {}
+        }
+        prev = c;
+    }
+    
+    if simple {
+        code
+    } else {
+        format!("({})", code)
+    }
+}
+
+/// Rust `VarAttributes` expression with the runtime's counterparts of
+/// `attributes`. Arrays are declared separately.
+fn var_attributes(attributes: &VariableAttributes) -> String {
+    let fields: Vec<String> = [
+        ("integer", attributes.integer),
+        ("readonly", attributes.readonly),
+        ("export", attributes.export),
+        ("lowercase", attributes.lowercase),
+        ("uppercase", attributes.uppercase),
+        ("nameref", attributes.nameref),
+    ]
+    .iter()
+    .filter(|(_, set)| *set)
+    .map(|(field, _)| format!("{}: true, ", field))
+    .collect();
+    format!("shell_runtime::VarAttributes {{ {}..Default::default() }}", fields.concat())
+}
+
+/// The array and subscript of `name[subscript]`, other than `[@]` and `[*]`.
+fn split_subscript(name: &str) -> Option<(&str, &str)> {
+    let (array, subscript) = name.strip_suffix(']')?.split_once('[')?;
+    (subscript != "@" && subscript != "*").then_some((array, subscript))
+}
+
+/// The array an expansion like `${name[@]}` refers to, with `@` and `*`
+/// for the positional parameters.
+fn array_name(name: &str) -> Option<&str> {
+    match name {
+        "@" | "*" => Some(name),
+        _ => name.strip_suffix("[@]").or_else(|| name.strip_suffix("[*]")),
+    }
+}
+
+/// Rust `Vec<String>` expression for a double-quoted word that expands to
+/// one field per element: `"$@"`, `"${name[@]}"` or `"${!name[@]}"`.
+fn quoted_fields(word: &ASTNode) -> Option<String> {
+    let ASTNode::Quoted(parts, _) = word else {
+        return None;
+    };
+    match parts.as_slice() {
+        [part] => match part.as_ref() {
+            ASTNode::Variable(name, _) if name == "@" => Some("runtime.positional_args()".to_string()),
+            ASTNode::Variable(name, _) => {
+                let array = name.strip_suffix("[@]")?;
+                Some(format!("runtime.array_values(\"{}\")", array))
+            }
+            ASTNode::ParameterExpansion { name, expansion_type: ExpansionType::Keys, .. } => {
+                Some(format!("runtime.array_keys(\"{}\")", name))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The user and the rest of an unquoted word that starts with a tilde
+/// prefix, `~`, `~user`, `~+` or `~-` up to the first `/`.
+fn split_tilde(word: &str) -> Option<(&str, &str)> {
+    let prefix = word.strip_prefix('~')?;
+    let end = prefix.find('/').unwrap_or(prefix.len());
+    let user = &prefix[..end];
+    let valid = matches!(user, "+" | "-")
+        || user.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    valid.then(|| (user, &prefix[end..]))
+}
+
+/// `set` arguments: the options turned on or off, in order, and the new
+/// positional parameters when there are any.
+struct SetArgs<'a> {
+    options: Vec<(String, bool)>,
+    positional: Option<&'a [Box<ASTNode>]>,
+}
+
+fn parse_set_args(args: &[Box<ASTNode>]) -> SetArgs<'_> {
+    let mut options = Vec::new();
+    let mut i = 0;
+    while let Some(ASTNode::String(word, _, _)) = args.get(i).map(|arg| arg.as_ref()) {
+        let enabled = match word.chars().next() {
+            Some('-') => true,
+            Some('+') => false,
+            _ => break,
+        };
+        i += 1;
+        if word == "--" || word == "-" {
+            return SetArgs { options, positional: Some(&args[i..]) };
+        }
+        for letter in word[1..].chars() {
+            if letter != 'o' {
+                // An unknown letter is left for the runtime to report
+                let name = set_option_name(letter).map_or_else(|| letter.to_string(), str::to_string);
+                options.push((name, enabled));
+            } else if let Some(ASTNode::String(name, _, _)) = args.get(i).map(|arg| arg.as_ref()) {
+                // The name after `-o` can follow other letters, as in `-euo pipefail`
+                options.push((name.clone(), enabled));
+                i += 1;
+            }
+        }
+    }
+    SetArgs { options, positional: (i < args.len()).then(|| &args[i..]) }
+}
+
+/// The long name of a `set` option letter.
+fn set_option_name(letter: char) -> Option<&'static str> {
+    Some(match letter {
+        'a' => "allexport",
+        'b' => "notify",
+        'e' => "errexit",
+        'f' => "noglob",
+        'h' => "hashall",
+        'k' => "keyword",
+        'm' => "monitor",
+        'n' => "noexec",
+        'p' => "privileged",
+        't' => "onecmd",
+        'u' => "nounset",
+        'v' => "verbose",
+        'x' => "xtrace",
+        'B' => "braceexpand",
+        'C' => "noclobber",
+        'E' => "errtrace",
+        'H' => "histexpand",
+        'P' => "physical",
+        'T' => "functrace",
+        _ => return None,
+    })
+}
+
+/// A zsh option word of `setopt` or `unsetopt` as an option name and
+/// whether it is turned on. Names ignore case and underscores, and `no`
+/// inverts them.
+fn zsh_option(builtin: &str, word: &str) -> (String, bool) {
+    let option = word.to_lowercase().replace('_', "");
+    match option.strip_prefix("no") {
+        Some(rest) if rest != "tify" && rest != "match" => (rest.to_string(), builtin == "unsetopt"),
+        _ => (option, builtin == "setopt"),
+    }
+}
+
+/// The command of a job as `jobs` shows it.
+fn job_text(node: &ASTNode) -> String {
+    match node {
+        ASTNode::Command { name, args, .. } => {
+            let words: Vec<String> = args.iter().map(|arg| word_text(arg)).collect();
+            std::iter::once(name.clone()).chain(words).collect::<Vec<_>>().join(" ")
+        }
+        ASTNode::Pipeline(stages) => stages.iter().map(|stage| job_text(stage)).collect::<Vec<_>>().join(" | "),
+        ASTNode::AndList(left, right) => format!("{} && {}", job_text(left), job_text(right)),
+        ASTNode::OrList(left, right) => format!("{} || {}", job_text(left), job_text(right)),
+        ASTNode::Negated(body) => format!("! {}", job_text(body)),
+        ASTNode::Subshell(body) => format!("( {} )", statements_text(body)),
+        ASTNode::Block(_) => format!("{{ {}; }}", statements_text(node)),
+        ASTNode::Redirected { body, .. } => job_text(body),
+        ASTNode::Assignment { name, value, .. } => format!("{}={}", name, word_text(value)),
+        ASTNode::Exit(Some(status)) => format!("exit {}", word_text(status)),
+        ASTNode::Exit(None) => "exit".to_string(),
+        ASTNode::For { variable, .. } => format!("for {} in ...", variable),
+        ASTNode::While { .. } => "while ...".to_string(),
+        ASTNode::Until { .. } => "until ...".to_string(),
+        ASTNode::If { .. } => "if ...".to_string(),
+        ASTNode::Case { .. } => "case ...".to_string(),
+        _ => "...".to_string(),
+    }
+}
+
+fn statements_text(node: &ASTNode) -> String {
+    match node {
+        ASTNode::Block(statements) => statements.iter().map(|s| job_text(s)).collect::<Vec<_>>().join("; "),
+        other => job_text(other),
+    }
+}
+
+fn word_text(word: &ASTNode) -> String {
+    match word {
+        ASTNode::String(s, StringType::SingleQuoted, _) => format!("'{}'", s),
+        ASTNode::String(s, StringType::DoubleQuoted, _) => format!("\"{}\"", s),
+        ASTNode::String(s, _, _) | ASTNode::Glob(s, _) => s.clone(),
+        ASTNode::Number(n) => n.to_string(),
+        ASTNode::Variable(name, _) => format!("${}", name),
+        ASTNode::ParameterExpansion { name, .. } => format!("${{{}}}", name),
+        ASTNode::CommandSubstitution(body, _) => format!("$({})", statements_text(body)),
+        ASTNode::Concat(parts, _) => parts.iter().map(|part| word_text(part)).collect(),
+        ASTNode::Quoted(parts, _) => {
+            let inner: String = parts.iter()
+                .map(|part| match part.as_ref() {
+                    ASTNode::String(s, _, _) => s.clone(),
+                    other => word_text(other),
+                })
+                .collect();
+            format!("\"{}\"", inner)
+        }
+        _ => "...".to_string(),
+    }
+}
+
+/// Calls `f` with the name and words of each command in a script, other
+/// than in substitutions.
+fn visit_commands(node: &ASTNode, f: &mut dyn FnMut(&str, &[Box<ASTNode>])) {
+    match node {
+        ASTNode::Command { name, args, .. } => f(name, args),
+        ASTNode::Script(nodes) | ASTNode::Block(nodes) | ASTNode::Pipeline(nodes) => {
+            for node in nodes {
+                visit_commands(node, f);
+            }
+        }
+        ASTNode::AndList(left, right) | ASTNode::OrList(left, right) => {
+            visit_commands(left, f);
+            visit_commands(right, f);
+        }
+        ASTNode::Negated(body)
+        | ASTNode::Subshell(body)
+        | ASTNode::Background(body)
+        | ASTNode::Redirected { body, .. }
+        | ASTNode::Function { body, .. }
+        | ASTNode::AnonymousFunction { body, .. }
+        | ASTNode::For { body, .. }
+        | ASTNode::Repeat { body, .. } => visit_commands(body, f),
+        ASTNode::While { condition, body, .. } | ASTNode::Until { condition, body, .. } => {
+            visit_commands(condition, f);
+            visit_commands(body, f);
+        }
+        ASTNode::If { condition, then_block, elif_blocks, else_block, .. } => {
+            visit_commands(condition, f);
+            visit_commands(then_block, f);
+            for (cond, block) in elif_blocks {
+                visit_commands(cond, f);
+                visit_commands(block, f);
+            }
+            if let Some(block) = else_block {
+                visit_commands(block, f);
+            }
+        }
+        ASTNode::Case { cases, .. } => {
+            for case in cases {
+                visit_commands(&case.body, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether a script turns on a `set` option in any of its commands, other
+/// than in a substitution.
+fn turns_on_option(node: &ASTNode, option: &str) -> bool {
+    let mut found = false;
+    visit_commands(node, &mut |name, args| {
+        found |= match name {
+            "set" => parse_set_args(args).options.iter().any(|(name, enabled)| name == option && *enabled),
+            "setopt" | "unsetopt" => args.iter().any(|arg| {
+                matches!(arg.as_ref(), ASTNode::String(word, _, _) if zsh_option(name, word) == (option.to_string(), true))
+            }),
+            "shopt" => {
+                matches!(args.first().map(|arg| arg.as_ref()), Some(ASTNode::String(flag, _, _)) if flag == "-s")
+                    && args.iter().any(|arg| matches!(arg.as_ref(), ASTNode::String(word, _, _) if word == option))
+            }
+            _ => false,
+        };
+    });
+    found
+}
+
+/// Whether `$LINENO` appears anywhere in the script, including the text of
+/// trap actions and `eval`.
+fn mentions_lineno(node: &ASTNode) -> bool {
+    format!("{:?}", node).contains("LINENO")
+}
+
+/// The conditions a script sets traps on: EXIT, ERR, DEBUG, RETURN, or a
+/// signal name or number without `SIG`. `?` stands for one that is only
+/// known when the script runs.
+fn trapped_conditions(node: &ASTNode) -> Vec<String> {
+    let mut conditions = Vec::new();
+    visit_commands(node, &mut |name, args| {
+        if name != "trap" {
+            return;
+        }
+        if let TrapArgs::Set(_, words) = parse_trap_args(args) {
+            for word in words {
+                conditions.push(match trap_word(word) {
+                    Some(word) if word == "0" => "EXIT".to_string(),
+                    Some(word) => word.to_uppercase().trim_start_matches("SIG").to_string(),
+                    None => "?".to_string(),
+                });
+            }
+        }
+    });
+    conditions
+}
+
+/// What a `trap` command does, from its words.
+enum TrapArgs<'a> {
+    // No words or `-p`: list the traps, or those of the conditions
+    List(&'a [Box<ASTNode>]),
+    // `-`, or only conditions
+    Reset(&'a [Box<ASTNode>]),
+    Set(&'a ASTNode, &'a [Box<ASTNode>]),
+    // `-l`
+    ListSignals,
+}
+
+fn parse_trap_args(args: &[Box<ASTNode>]) -> TrapArgs<'_> {
+    let is = |node: &ASTNode, flag: &str| trap_word(node).is_some_and(|word| word == flag);
+    let args = match args.first() {
+        Some(first) if is(first, "--") => &args[1..],
+        _ => args,
+    };
+    match args {
+        [] => TrapArgs::List(args),
+        [flag, conditions @ ..] if is(flag, "-p") => TrapArgs::List(conditions),
+        [flag, ..] if is(flag, "-l") => TrapArgs::ListSignals,
+        [flag, conditions @ ..] if is(flag, "-") => TrapArgs::Reset(conditions),
+        [_] => TrapArgs::Reset(args),
+        // POSIX reads a number first as a condition too
+        [first, ..] if trap_word(first).is_some_and(|word| word.parse::<u32>().is_ok()) => TrapArgs::Reset(args),
+        [action, conditions @ ..] => TrapArgs::Set(action, conditions),
+    }
+}
+
+fn trap_word(node: &ASTNode) -> Option<String> {
+    match node {
+        ASTNode::String(word, _, _) => Some(word.clone()),
+        ASTNode::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// The text of a trap action, when it is known before the script runs.
+/// Variables in double quotes stay references, expanded when the trap
+/// runs.
+fn trap_text(action: &ASTNode) -> Option<String> {
+    match action {
+        ASTNode::String(text, _, _) => Some(text.clone()),
+        ASTNode::Quoted(parts, _) => parts.iter()
+            .map(|part| match part.as_ref() {
+                ASTNode::String(text, _, _) => Some(text.clone()),
+                ASTNode::Variable(name, _) => Some(format!("${{{}}}", name)),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Whether unquoted text contains pathname expansion characters.
+fn has_glob_chars(text: &str) -> bool {
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
     }
+    false
+}
+
+/// Backslash-escape the characters that are special in a glob pattern.
+fn escape_glob(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Backslash-escape the characters that are special in a regular expression.
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '.' | '+' | '*' | '?' | '(' | ')' | '|' | '[' | ']' | '{' | '}' | '^' | '$') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Pass generated string code to a `&str` parameter, borrowing it unless it
+/// is a string literal.
+/// Rust expression for a zsh parameter flag of the runtime.
+fn generate_parameter_flag(flag: &ParameterFlag) -> String {
+    match flag {
+        ParameterFlag::Join(separator) => format!("shell_runtime::ParamFlag::Join(\"{}\")", escape_string(separator)),
+        ParameterFlag::Split(separator) => format!("shell_runtime::ParamFlag::Split(\"{}\")", escape_string(separator)),
+        ParameterFlag::JoinLines => "shell_runtime::ParamFlag::Join(\"\\n\")".to_string(),
+        ParameterFlag::SplitLines => "shell_runtime::ParamFlag::Split(\"\\n\")".to_string(),
+        ParameterFlag::Upper => "shell_runtime::ParamFlag::Upper".to_string(),
+        ParameterFlag::Lower => "shell_runtime::ParamFlag::Lower".to_string(),
+        ParameterFlag::Capitalize => "shell_runtime::ParamFlag::Capitalize".to_string(),
+        ParameterFlag::Sort => "shell_runtime::ParamFlag::Sort".to_string(),
+        ParameterFlag::SortDescending => "shell_runtime::ParamFlag::SortDescending".to_string(),
+        ParameterFlag::Unique => "shell_runtime::ParamFlag::Unique".to_string(),
+    }
+}
+
+/// Rust expression for a zsh glob qualifier of the runtime.
+fn generate_glob_qualifier(qualifier: &GlobQualifier) -> String {
+    match qualifier {
+        GlobQualifier::FileType(kind) => format!("shell_runtime::GlobQualifier::FileType('{}')", kind),
+        GlobQualifier::Not => "shell_runtime::GlobQualifier::Not".to_string(),
+        GlobQualifier::NullGlob => "shell_runtime::GlobQualifier::NullGlob".to_string(),
+        GlobQualifier::DotGlob => "shell_runtime::GlobQualifier::DotGlob".to_string(),
+        GlobQualifier::Sort { key, descending } => format!(
+            "shell_runtime::GlobQualifier::Sort {{ key: '{}', descending: {} }}",
+            key, descending
+        ),
+        GlobQualifier::Range(first, last) => format!("shell_runtime::GlobQualifier::Range({}, {})", first, last),
+    }
+}
+
+fn str_arg(code: String) -> String {
+    if code.starts_with('"') {
+        code
+    } else {
+        format!("&{}", code)
+    }
+}
+
+/// Rust name of the function for a shell function, whose name may have
+/// characters like the `-` of PowerShell's `Verb-Noun` in it.
+fn function_ident(name: &str) -> String {
+    let mut ident = String::from("shell_func_");
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            ident.push(c);
+        } else {
+            ident.push_str(&format!("_{:x}_", c as u32));
+        }
+    }
+    ident
 }
 
 fn escape_string(s: &str) -> String {
@@ -733,9 +6632,12 @@ fn escape_string(s: &str) -> String {
         .collect()
 }
 
-fn is_builtin(cmd: &str) -> bool {
-    matches!(cmd, "pwd" | "true" | "false")
-}
+/// Builtins `generate_command` writes code for, rather than running them
+/// through the runtime.
+const INLINE_BUILTINS: &[&str] = &[
+    "echo", "cd", "export", "read", "true", ":", "false", "set", "shift", "unset", "shopt", "trap", "wait",
+    "jobs", "kill", "fg", "bg", "setopt", "unsetopt", "select", "count", "pwd", "exec", "param",
+];
 
 impl CodeGenerator {
     fn generate_main_with_terminal(&mut self, terminal_analysis: &crate::resolver::TerminalAnalysis) -> Result<String> {
@@ -781,7 +6683,7 @@ impl CodeGenerator {
         code.push_str("#[command(version, about, long_about = None)]\n");
         code.push_str("struct Args {\n");
         code.push_str("    /// Arguments passed to the script\n");
-        code.push_str("    #[arg(trailing_var_arg = true)]\n");
+        code.push_str("    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]\n");
         code.push_str("    args: Vec<String>,\n");
         code.push_str("}\n\n");
         
@@ -878,7 +6780,13 @@ impl CodeGenerator {
         }
         
         code.push_str("    // Initialize shell runtime\n");
-        code.push_str("    let mut runtime = shell_runtime::ShellRuntime::new(args.args)?;\n");
+        code.push_str("    // clap drops a leading `--`, which the script still sees as an argument\n");
+        code.push_str("    let mut script_args = args.args;\n");
+        code.push_str("    if std::env::args().nth(1).as_deref() == Some(\"--\") {\n");
+        code.push_str("        script_args.insert(0, \"--\".to_string());\n");
+        code.push_str("    }\n");
+        code.push_str("    let mut runtime = shell_runtime::ShellRuntime::new(script_args)?;\n");
+        code.push_str(&self.generate_dialect_setup());
         if terminal_analysis.needs_terminal() {
             code.push_str("    runtime.set_terminal_mode(is_terminal);\n");
         }
@@ -893,7 +6801,8 @@ impl CodeGenerator {
         
         // Generate the main script logic
         code.push_str("    // Execute main script\n");
-        code.push_str("    script_main(&mut runtime)?;\n");
+        code.push_str("    let result = script_main(&mut runtime);\n");
+        code.push_str("    runtime.finish(result)?;\n");
         code.push_str("    \n");
         code.push_str("    Ok(())\n");
         code.push_str("}\n\n");
@@ -1012,24 +6921,7 @@ impl ShellRuntime {
             println!("{}", text);
         }
     }
-    
-    pub fn echo_with_colors(&self, args: &[&str]) {
-        let text = args.join(" ");
-        if self.is_terminal {
-            // Process ANSI escape sequences
-            println!("{}", text);
-        } else {
-            // Strip ANSI codes for non-terminal output
-            let clean = strip_ansi_codes(&text);
-            println!("{}", clean);
-        }
-    }
-}
-
-fn strip_ansi_codes(text: &str) -> String {
-    // Simple ANSI code stripper
-    let re = regex::Regex::new(r"\x1b\[[0-9;]*m").unwrap();
-    re.replace_all(text, "").to_string()"#;
+}"#;
             
             // Insert the is_terminal field and methods into the runtime
             code = code.replace(
@@ -1045,8 +6937,8 @@ fn strip_ansi_codes(text: &str) -> String {
             
             // Add the use statement for IsTerminal
             code = code.replace(
-                "use anyhow::{Result, Context};\n",
-                "use anyhow::{Result, Context};\nuse std::io::IsTerminal;\n"
+                "use anyhow::{Result, Context, bail};\n",
+                "use anyhow::{Result, Context, bail};\nuse std::io::IsTerminal;\n"
             );
         }
         
@@ -38,6 +38,7 @@ impl RustGenerator {
                 enable_updates: args.enable_updates,
                 update: args.update,
                 command: args.command.clone(),
+                strict_dialect: args.strict_dialect,
             },
         }
     }
@@ -51,6 +52,9 @@ impl RustGenerator {
         
         // Create code generator
         let mut generator = CodeGenerator::new(self.ast.clone(), script_name);
+        if let Some(file_name) = self.script_path.file_name().and_then(|s| s.to_str()) {
+            generator.set_source_name(file_name);
+        }
         
         // Resolve dependencies
         let mut resolver = DependencyResolver::new(&self.script_path)?;
@@ -59,7 +63,12 @@ impl RustGenerator {
         if !self.args.quiet {
             println!("Found {} dependencies", dependencies.len());
             for dep in &dependencies {
-                println!("  - {:?}: {}", dep.dep_type, dep.path.display());
+                if dep.line_numbers.is_empty() {
+                    println!("  - {:?}: {}", dep.dep_type, dep.path.display());
+                } else {
+                    let lines: Vec<String> = dep.line_numbers.iter().map(|line| line.to_string()).collect();
+                    println!("  - {:?}: {} (line {})", dep.dep_type, dep.path.display(), lines.join(", "));
+                }
             }
         }
         
@@ -171,7 +171,7 @@ description = "{}"
         }
         
         // Add build configuration
-        cargo_toml.push_str(r#"
+        cargo_toml.push_str(&format!(r#"
 [profile.release]
 opt-level = "z"
 lto = true
@@ -182,7 +182,7 @@ panic = "abort"
 [[bin]]
 name = "{}"
 path = "src/main.rs"
-"#);
+"#, self.name));
         
         std::fs::write(output_dir.join("Cargo.toml"), cargo_toml)?;
         Ok(())
@@ -0,0 +1,346 @@
+use super::ast::{ASTNode, BinaryOperator, Span, UnaryOperator};
+use super::parser::advance_span;
+use anyhow::{Result, bail};
+
+/// Operators of shell arithmetic, longest first so that tokenizing can take
+/// the first match.
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=",
+    "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+=", "-=", "*=", "/=", "%=", "&=", "^=", "|=",
+    "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "^", "|",
+    "?", ":", ",", "(", ")",
+];
+
+/// Binary operators from loosest to tightest binding. `**` binds tighter
+/// than all of these and is handled separately because it is right
+/// associative.
+const BINARY_LEVELS: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", ">", "<=", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum ArithToken {
+    Number(i64),
+    Name(String, Span),
+    Op(&'static str),
+}
+
+/// Parse the text of an arithmetic expression (the inside of `$(( ))` or
+/// `(( ))`, or one `let` argument), which starts at `start`, into an
+/// expression tree.
+///
+/// The text must already be free of `$` expansions; bash substitutes those
+/// textually before parsing, so expressions containing them are evaluated
+/// at runtime instead. An empty expression evaluates to 0.
+pub fn parse_arithmetic(text: &str, start: Span) -> Result<ASTNode> {
+    let tokens = tokenize(text, start)?;
+    if tokens.is_empty() {
+        return Ok(ASTNode::Integer(0));
+    }
+
+    let mut parser = ArithParser { tokens, pos: 0 };
+    let expr = parser.parse_comma()?;
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        bail!("syntax error in expression (error token is \"{}\")", token_text(token));
+    }
+    Ok(expr)
+}
+
+/// Parse an integer literal: decimal, `0x` hex, leading-zero octal or
+/// `base#digits` with a base from 2 to 64. Values wrap around like bash's
+/// 64-bit arithmetic.
+pub fn parse_integer(text: &str) -> Result<i64> {
+    let (base, digits) = if let Some((base, digits)) = text.split_once('#') {
+        let base: u32 = match base.parse() {
+            Ok(base) if (2..=64).contains(&base) => base,
+            _ => bail!("{}: invalid arithmetic base", text),
+        };
+        (base, digits)
+    } else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (16, hex)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    } else {
+        (10, text)
+    };
+
+    if digits.is_empty() {
+        bail!("{}: invalid integer constant", text);
+    }
+
+    let mut value: u64 = 0;
+    for c in digits.chars() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            // Upper case letters only differ from lower case above base 36
+            'A'..='Z' if base > 36 => c as u32 - 'A' as u32 + 36,
+            'A'..='Z' => c as u32 - 'A' as u32 + 10,
+            '@' => 62,
+            '_' => 63,
+            _ => bail!("{}: invalid number", text),
+        };
+        if digit >= base {
+            bail!("{}: value too great for base (error token is \"{}\")", text, text);
+        }
+        value = value.wrapping_mul(base as u64).wrapping_add(digit as u64);
+    }
+
+    Ok(value as i64)
+}
+
+fn tokenize(text: &str, start: Span) -> Result<Vec<ArithToken>> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    let mut span = start;
+
+    while let Some(c) = rest.chars().next() {
+        let token = rest;
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '#' || c == '@' || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(ArithToken::Number(parse_integer(&rest[..end])?));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(ArithToken::Name(rest[..end].to_string(), span));
+            rest = &rest[end..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(ArithToken::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            bail!("syntax error: invalid arithmetic operator (error token is \"{}\")", rest);
+        }
+        span = advance_span(span, &token[..token.len() - rest.len()]);
+    }
+
+    Ok(tokens)
+}
+
+fn token_text(token: &ArithToken) -> String {
+    match token {
+        ArithToken::Number(n) => n.to_string(),
+        ArithToken::Name(name, _) => name.clone(),
+        ArithToken::Op(op) => op.to_string(),
+    }
+}
+
+fn binary_operator(op: &str) -> BinaryOperator {
+    match op {
+        "+" => BinaryOperator::Add,
+        "-" => BinaryOperator::Subtract,
+        "*" => BinaryOperator::Multiply,
+        "/" => BinaryOperator::Divide,
+        "%" => BinaryOperator::Modulo,
+        "**" => BinaryOperator::Power,
+        "<<" => BinaryOperator::ShiftLeft,
+        ">>" => BinaryOperator::ShiftRight,
+        "&" => BinaryOperator::BitAnd,
+        "|" => BinaryOperator::BitOr,
+        "^" => BinaryOperator::BitXor,
+        "==" => BinaryOperator::Equal,
+        "!=" => BinaryOperator::NotEqual,
+        "<" => BinaryOperator::Less,
+        ">" => BinaryOperator::Greater,
+        "<=" => BinaryOperator::LessEqual,
+        ">=" => BinaryOperator::GreaterEqual,
+        "&&" => BinaryOperator::And,
+        "||" => BinaryOperator::Or,
+        "," => BinaryOperator::Comma,
+        _ => unreachable!("not a binary operator: {}", op),
+    }
+}
+
+struct ArithParser {
+    tokens: Vec<ArithToken>,
+    pos: usize,
+}
+
+impl ArithParser {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(ArithToken::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<()> {
+        if !self.eat(op) {
+            match self.tokens.get(self.pos) {
+                Some(token) => bail!("syntax error: `{}' expected (error token is \"{}\")", op, token_text(token)),
+                None => bail!("syntax error: `{}' expected", op),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_comma(&mut self) -> Result<ASTNode> {
+        let mut left = self.parse_assignment()?;
+        while self.eat(",") {
+            let right = self.parse_assignment()?;
+            left = ASTNode::BinaryOp {
+                left: Box::new(left),
+                op: BinaryOperator::Comma,
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_assignment(&mut self) -> Result<ASTNode> {
+        if let (Some(ArithToken::Name(name, _)), Some(ArithToken::Op(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+        {
+            let op = match *op {
+                "=" => Some(None),
+                "+=" | "-=" | "*=" | "/=" | "%=" | "<<=" | ">>=" | "&=" | "^=" | "|=" => {
+                    Some(Some(binary_operator(&op[..op.len() - 1])))
+                }
+                _ => None,
+            };
+            if let Some(op) = op {
+                let name = name.clone();
+                self.pos += 2;
+                let value = self.parse_assignment()?;
+                return Ok(ASTNode::ArithmeticAssignment {
+                    name,
+                    op,
+                    value: Box::new(value),
+                });
+            }
+        }
+
+        self.parse_conditional()
+    }
+
+    fn parse_conditional(&mut self) -> Result<ASTNode> {
+        let condition = self.parse_binary(0)?;
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+
+        let then_expr = self.parse_comma()?;
+        self.expect(":")?;
+        let else_expr = self.parse_conditional()?;
+        Ok(ASTNode::Conditional {
+            condition: Box::new(condition),
+            then_expr: Box::new(then_expr),
+            else_expr: Box::new(else_expr),
+        })
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<ASTNode> {
+        if level == BINARY_LEVELS.len() {
+            return self.parse_power();
+        }
+
+        let mut left = self.parse_binary(level + 1)?;
+        while let Some(op) = self.peek_op().filter(|op| BINARY_LEVELS[level].contains(op)) {
+            self.pos += 1;
+            let right = self.parse_binary(level + 1)?;
+            left = ASTNode::BinaryOp {
+                left: Box::new(left),
+                op: binary_operator(op),
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_power(&mut self) -> Result<ASTNode> {
+        let base = self.parse_unary()?;
+        if !self.eat("**") {
+            return Ok(base);
+        }
+
+        let exponent = self.parse_power()?;
+        Ok(ASTNode::BinaryOp {
+            left: Box::new(base),
+            op: BinaryOperator::Power,
+            right: Box::new(exponent),
+        })
+    }
+
+    fn parse_unary(&mut self) -> Result<ASTNode> {
+        let op = match self.peek_op() {
+            Some("!") => UnaryOperator::Not,
+            Some("~") => UnaryOperator::BitNot,
+            Some("-") => UnaryOperator::Negate,
+            Some("+") => UnaryOperator::Plus,
+            Some(op @ ("++" | "--")) => {
+                self.pos += 1;
+                if let Some(ArithToken::Name(name, span)) = self.tokens.get(self.pos) {
+                    let operand = ASTNode::Variable(name.clone(), *span);
+                    self.pos += 1;
+                    let op = if op == "++" { UnaryOperator::PreIncrement } else { UnaryOperator::PreDecrement };
+                    return Ok(ASTNode::UnaryOp { op, operand: Box::new(operand) });
+                }
+
+                // Without a variable after it, `--5` is two unary minuses
+                let sign = if op == "++" { UnaryOperator::Plus } else { UnaryOperator::Negate };
+                let operand = self.parse_unary()?;
+                return Ok(ASTNode::UnaryOp {
+                    op: sign.clone(),
+                    operand: Box::new(ASTNode::UnaryOp { op: sign, operand: Box::new(operand) }),
+                });
+            }
+            _ => return self.parse_postfix(),
+        };
+
+        self.pos += 1;
+        let operand = self.parse_unary()?;
+        Ok(ASTNode::UnaryOp { op, operand: Box::new(operand) })
+    }
+
+    fn parse_postfix(&mut self) -> Result<ASTNode> {
+        let token = match self.tokens.get(self.pos) {
+            Some(token) => token.clone(),
+            None => bail!("syntax error: operand expected"),
+        };
+        self.pos += 1;
+
+        match token {
+            ArithToken::Number(n) => Ok(ASTNode::Integer(n)),
+            ArithToken::Name(name, span) => {
+                let operand = Box::new(ASTNode::Variable(name, span));
+                if self.eat("++") {
+                    Ok(ASTNode::UnaryOp { op: UnaryOperator::PostIncrement, operand })
+                } else if self.eat("--") {
+                    Ok(ASTNode::UnaryOp { op: UnaryOperator::PostDecrement, operand })
+                } else {
+                    Ok(*operand)
+                }
+            }
+            ArithToken::Op("(") => {
+                let inner = self.parse_comma()?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            ArithToken::Op(op) => bail!("syntax error: operand expected (error token is \"{}\")", op),
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use super::shell_dialect::ShellDialect;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct AST {
@@ -14,8 +15,34 @@ pub struct ScriptMetadata {
     pub description: Option<String>,
     pub dependencies: Vec<String>,
     pub headers: HashMap<String, String>,
+    pub dialect: ShellDialect,
 }
 
+/// Position of a token or node in the script: 1-based line and column,
+/// counted in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Hash)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, column: usize) -> Self {
+        Span { line, column }
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// Commands, compound commands, functions, words and expansions record where
+// they start. Lists, pipelines and groups start where their first command
+// does; arithmetic and test expressions are located by the word or command
+// they belong to. Words made up by a parser for nothing written in the script
+// have `Span::default()`.
 #[derive(Debug, Clone, PartialEq)]
 pub enum ASTNode {
     // Top-level
@@ -27,8 +54,17 @@ pub enum ASTNode {
         args: Vec<Box<ASTNode>>,
         redirections: Vec<Redirection>,
         background: bool,
+        span: Span,
     },
     Pipeline(Vec<Box<ASTNode>>),
+    AndList(Box<ASTNode>, Box<ASTNode>),  // a && b
+    OrList(Box<ASTNode>, Box<ASTNode>),   // a || b
+    Negated(Box<ASTNode>),                // ! pipeline
+    Test {  // `[ ]` and `test`, or `[[ ]]` when extended
+        expr: Box<ASTNode>,
+        extended: bool,
+        span: Span,
+    },
     
     // Control structures
     If {
@@ -36,46 +72,77 @@ pub enum ASTNode {
         then_block: Box<ASTNode>,
         elif_blocks: Vec<(Box<ASTNode>, Box<ASTNode>)>,
         else_block: Option<Box<ASTNode>>,
+        span: Span,
     },
     While {
         condition: Box<ASTNode>,
         body: Box<ASTNode>,
+        span: Span,
     },
     Until {
         condition: Box<ASTNode>,
         body: Box<ASTNode>,
+        span: Span,
     },
     For {
         variable: String,
         items: ForItems,
         body: Box<ASTNode>,
+        span: Span,
     },
     Case {
         expr: Box<ASTNode>,
         cases: Vec<CaseItem>,
+        span: Span,
+    },
+    Repeat {  // zsh `repeat N do ... done`
+        count: Box<ASTNode>,  // arithmetic expression, evaluated once
+        body: Box<ASTNode>,
+        span: Span,
     },
     
     // Functions
     Function {
         name: String,
         body: Box<ASTNode>,
+        span: Span,
+    },
+    AnonymousFunction {  // zsh `() { ... } args`, called where it is defined
+        body: Box<ASTNode>,
+        args: Vec<Box<ASTNode>>,
+        span: Span,
     },
     
     // Variables and expansion
     Assignment {
         name: String,
+        index: Option<Box<ASTNode>>,  // arr[index]=v: a word, expanded when assigned
         value: Box<ASTNode>,
+        append: bool,  // +=
         export: bool,
         readonly: bool,
         local: bool,
+        span: Span,
+    },
+    Declaration {  // declare, typeset, local, export or readonly with names and assignments
+        builtin: String,
+        attributes: VariableAttributes,  // turned on with -i, -x, ...
+        removed: VariableAttributes,     // turned off with +i, +x, ... (and export -n)
+        local: bool,      // local, or declare in a function without -g
+        options: String,  // other flags, like the p of declare -p
+        variables: Vec<Box<ASTNode>>,  // names and Assignments
+        span: Span,
     },
-    Variable(String),
+    Variable(String, Span),
     ParameterExpansion {
         name: String,
         expansion_type: ExpansionType,
+        span: Span,
     },
-    CommandSubstitution(Box<ASTNode>),
-    ArithmeticExpansion(Box<ASTNode>),
+    CommandSubstitution(Box<ASTNode>, Span),
+    FileSubstitution(Box<ASTNode>, Span),  // zsh =(cmd) and <(cmd): a temporary file holding the output
+    ArithmeticExpansion(Box<ASTNode>, Span),
+    ArithmeticCommand(Box<ASTNode>, Span),  // (( expr )) and let; status 0 when non-zero
     
     // Expressions
     BinaryOp {
@@ -87,27 +154,54 @@ pub enum ASTNode {
         op: UnaryOperator,
         operand: Box<ASTNode>,
     },
+    Conditional {  // cond ? a : b
+        condition: Box<ASTNode>,
+        then_expr: Box<ASTNode>,
+        else_expr: Box<ASTNode>,
+    },
+    ArithmeticAssignment {  // x = v, or x += v with op Some(Add)
+        name: String,
+        op: Option<BinaryOperator>,
+        value: Box<ASTNode>,
+    },
     
     // Literals
-    String(String, StringType),
+    String(String, StringType, Span),
     Number(f64),
-    Array(Vec<Box<ASTNode>>),
+    Integer(i64),  // arithmetic literal, already converted from its base
+    Array(Vec<Box<ASTNode>>),  // (a b [key]=c): words, and nameless assignments for keyed elements
     
     // Special
-    Glob(String),
+    Concat(Vec<Box<ASTNode>>, Span),  // adjacent parts of one word, e.g. ${file%.*}.bak
+    Quoted(Vec<Box<ASTNode>>, Span),  // "text $var": DoubleQuoted text and expansions, which aren't split or globbed
+    Glob(String, Span),
+    QualifiedGlob {  // zsh *(.): pathname expansion filtered by qualifiers
+        pattern: Box<ASTNode>,
+        qualifiers: Vec<GlobQualifier>,
+        span: Span,
+    },
     Heredoc {
         delimiter: String,
         content: String,
         strip_tabs: bool,
     },
+    Error {  // a statement that failed to parse, kept in place of it
+        message: String,
+        span: Span,
+    },
     Return(Option<Box<ASTNode>>),
-    Break,
-    Continue,
+    Break(usize),     // the number of enclosing loops to leave
+    Continue(usize),  // the number of enclosing loops out, to go on with
     Exit(Option<Box<ASTNode>>),
     
     // Compound
     Block(Vec<Box<ASTNode>>),
     Subshell(Box<ASTNode>),
+    Background(Box<ASTNode>),  // pipeline or compound command ending in `&`
+    Redirected {  // compound command with redirections, e.g. `done < file`
+        body: Box<ASTNode>,
+        redirections: Vec<Redirection>,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -123,16 +217,26 @@ pub enum ForItems {
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct CaseItem {
-    pub patterns: Vec<String>,
+    pub patterns: Vec<ASTNode>,  // words; quoted parts match literally
     pub body: Box<ASTNode>,
+    pub terminator: CaseTerminator,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseTerminator {
+    Break,        // ;;
+    FallThrough,  // ;&  runs the next body unconditionally
+    Continue,     // ;;& tests the next patterns
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ExpansionType {
-    Default(Box<ASTNode>),      // ${var:-default}
-    Assign(Box<ASTNode>),       // ${var:=default}
-    Error(String),              // ${var:?error}
-    Alternative(Box<ASTNode>),  // ${var:+alt}
+    // ${var:-default}, ${var:=default}, ${var:?error} and ${var:+alt}; without
+    // the colon an empty variable counts as set
+    Default { word: Box<ASTNode>, colon: bool },
+    Assign { word: Box<ASTNode>, colon: bool },
+    Error { message: String, colon: bool },
+    Alternative { word: Box<ASTNode>, colon: bool },
     Substring {
         offset: Box<ASTNode>,
         length: Option<Box<ASTNode>>,
@@ -149,6 +253,55 @@ pub enum ExpansionType {
     Length,                     // ${#var}
     Indirect,                   // ${!var}
     Keys,                       // ${!var[@]}
+    Element(Box<ASTNode>),      // ${var[index]}, index an arithmetic expression
+    Subscript(Box<ASTNode>),    // bash ${var[subscript]}: a key of an associative array, else an arithmetic index
+    Flags(Vec<ParameterFlag>),  // zsh ${(j:,:)var}
+}
+
+/// Attributes of a variable, as set by `declare`/`typeset` flags and by
+/// `export` and `readonly`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VariableAttributes {
+    pub integer: bool,      // -i: assignments are evaluated as arithmetic
+    pub readonly: bool,     // -r
+    pub export: bool,       // -x
+    pub array: bool,        // -a
+    pub associative: bool,  // -A
+    pub lowercase: bool,    // -l
+    pub uppercase: bool,    // -u
+    pub nameref: bool,      // -n: the value names another variable
+}
+
+impl VariableAttributes {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Parameter expansion flags of zsh, the letters in `${(...)name}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParameterFlag {
+    Join(String),    // j:sep:
+    Split(String),   // s:sep:
+    JoinLines,       // F
+    SplitLines,      // f
+    Upper,           // U
+    Lower,           // L
+    Capitalize,      // C
+    Sort,            // o
+    SortDescending,  // O
+    Unique,          // u
+}
+
+/// Glob qualifiers of zsh, the letters in `*(...)` after a pattern.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GlobQualifier {
+    FileType(char),  // . / @ * = p: regular, directory, symlink, executable, socket, fifo
+    Not,             // ^ negates the file types after it
+    NullGlob,        // N: no words instead of the pattern when nothing matches
+    DotGlob,         // D: hidden files match too
+    Sort { key: char, descending: bool },  // on, Om, oL: by name, modification time or size
+    Range(i64, i64),  // [n] or [n,m]: 1-based, negative counts from the end
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -167,6 +320,14 @@ pub enum BinaryOperator {
     Multiply,
     Divide,
     Modulo,
+    Power,      // **
+    
+    // Bitwise
+    ShiftLeft,
+    ShiftRight,
+    BitAnd,
+    BitOr,
+    BitXor,
     
     // Comparison
     Equal,
@@ -177,23 +338,35 @@ pub enum BinaryOperator {
     GreaterEqual,
     
     // String comparison
-    StringEqual,
+    StringEqual,     // = and ==, a pattern match inside [[ ]]
     StringNotEqual,
+    StringLess,      // <
+    StringGreater,   // >
     Match,      // =~
     
     // Logical
     And,
     Or,
     
+    // Sequencing
+    Comma,      // evaluates both, yields the right
+    
     // File test
     FileNewer,  // -nt
     FileOlder,  // -ot
+    FileSame,   // -ef
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum UnaryOperator {
     Not,
     Negate,
+    Plus,
+    BitNot,             // ~
+    PreIncrement,       // ++x
+    PreDecrement,       // --x
+    PostIncrement,      // x++
+    PostDecrement,      // x--
     
     // File tests
     FileExists,         // -e
@@ -204,29 +377,48 @@ pub enum UnaryOperator {
     FileWritable,       // -w
     FileExecutable,     // -x
     FileNotEmpty,       // -s
+    FileBlock,          // -b
+    FileCharacter,      // -c
+    FilePipe,           // -p
+    FileSocket,         // -S
+    FileSetuid,         // -u
+    FileSetgid,         // -g
+    FileSticky,         // -k
+    Terminal,           // -t
     
     // String tests
     StringNotEmpty,     // -n
     StringEmpty,        // -z
+    VariableSet,        // -v
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Redirection {
-    pub fd: Option<i32>,
+    pub fd: Option<i32>,  // None means 0 for input and 1 for output
+    pub direction: RedirectDirection,
     pub target: RedirectionTarget,
     pub append: bool,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectDirection {
+    Input,      // <, <&, <<, <<<
+    Output,     // >, >>, >&
+    Clobber,    // >|, which overwrites files even with noclobber
+    ReadWrite,  // <>
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum RedirectionTarget {
-    File(String),
+    File(Box<ASTNode>),
     Fd(i32),
+    Close,  // n>&- and n<&-
     Heredoc {
         delimiter: String,
-        content: String,
+        content: Box<ASTNode>,  // literal text when the delimiter is quoted, else a word to expand
         strip_tabs: bool,
     },
-    HereString(String),
+    HereString(Box<ASTNode>),
 }
 
 impl ASTNode {
@@ -237,6 +429,47 @@ impl ASTNode {
         }
     }
     
+    /// Where this node starts in the script, if it or its first command
+    /// was located by the parser.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ASTNode::Command { span, .. }
+            | ASTNode::Test { span, .. }
+            | ASTNode::If { span, .. }
+            | ASTNode::While { span, .. }
+            | ASTNode::Until { span, .. }
+            | ASTNode::For { span, .. }
+            | ASTNode::Case { span, .. }
+            | ASTNode::Repeat { span, .. }
+            | ASTNode::Function { span, .. }
+            | ASTNode::AnonymousFunction { span, .. }
+            | ASTNode::Assignment { span, .. }
+            | ASTNode::Declaration { span, .. }
+            | ASTNode::String(_, _, span)
+            | ASTNode::Variable(_, span)
+            | ASTNode::ParameterExpansion { span, .. }
+            | ASTNode::CommandSubstitution(_, span)
+            | ASTNode::FileSubstitution(_, span)
+            | ASTNode::ArithmeticExpansion(_, span)
+            | ASTNode::ArithmeticCommand(_, span)
+            | ASTNode::Concat(_, span)
+            | ASTNode::Quoted(_, span)
+            | ASTNode::Glob(_, span)
+            | ASTNode::QualifiedGlob { span, .. }
+            | ASTNode::Error { span, .. } => Some(*span),
+            ASTNode::Pipeline(nodes) | ASTNode::Block(nodes) | ASTNode::Script(nodes) => {
+                nodes.first().and_then(|node| node.span())
+            }
+            ASTNode::AndList(first, _)
+            | ASTNode::OrList(first, _)
+            | ASTNode::Negated(first)
+            | ASTNode::Subshell(first)
+            | ASTNode::Background(first)
+            | ASTNode::Redirected { body: first, .. } => first.span(),
+            _ => None,
+        }
+    }
+    
     pub fn get_dependencies(&self) -> Vec<String> {
         let mut deps = Vec::new();
         self.collect_dependencies(&mut deps);
@@ -252,7 +485,7 @@ impl ASTNode {
                     node.collect_dependencies(deps);
                 }
             }
-            ASTNode::Command { name, args, .. } => {
+            ASTNode::Command { name, args, redirections, .. } => {
                 // Check if it's an external command
                 if !is_builtin(name) {
                     deps.push(name.clone());
@@ -260,13 +493,29 @@ impl ASTNode {
                 for arg in args {
                     arg.collect_dependencies(deps);
                 }
+                for redirection in redirections {
+                    redirection.collect_dependencies(deps);
+                }
+            }
+            ASTNode::Redirected { body, redirections } => {
+                body.collect_dependencies(deps);
+                for redirection in redirections {
+                    redirection.collect_dependencies(deps);
+                }
             }
             ASTNode::Pipeline(commands) => {
                 for cmd in commands {
                     cmd.collect_dependencies(deps);
                 }
             }
-            ASTNode::If { condition, then_block, elif_blocks, else_block } => {
+            ASTNode::AndList(left, right) | ASTNode::OrList(left, right) => {
+                left.collect_dependencies(deps);
+                right.collect_dependencies(deps);
+            }
+            ASTNode::Negated(cmd) => {
+                cmd.collect_dependencies(deps);
+            }
+            ASTNode::If { condition, then_block, elif_blocks, else_block, .. } => {
                 condition.collect_dependencies(deps);
                 then_block.collect_dependencies(deps);
                 for (cond, block) in elif_blocks {
@@ -277,7 +526,7 @@ impl ASTNode {
                     block.collect_dependencies(deps);
                 }
             }
-            ASTNode::While { condition, body } | ASTNode::Until { condition, body } => {
+            ASTNode::While { condition, body, .. } | ASTNode::Until { condition, body, .. } => {
                 condition.collect_dependencies(deps);
                 body.collect_dependencies(deps);
             }
@@ -297,21 +546,88 @@ impl ASTNode {
                 }
                 body.collect_dependencies(deps);
             }
-            ASTNode::Case { expr, cases } => {
+            ASTNode::Case { expr, cases, .. } => {
                 expr.collect_dependencies(deps);
                 for case in cases {
+                    for pattern in &case.patterns {
+                        pattern.collect_dependencies(deps);
+                    }
                     case.body.collect_dependencies(deps);
                 }
             }
+            ASTNode::Repeat { count, body, .. } => {
+                count.collect_dependencies(deps);
+                body.collect_dependencies(deps);
+            }
             ASTNode::Function { body, .. } => {
                 body.collect_dependencies(deps);
             }
-            ASTNode::CommandSubstitution(cmd) => {
+            ASTNode::AnonymousFunction { body, args, .. } => {
+                body.collect_dependencies(deps);
+                for arg in args {
+                    arg.collect_dependencies(deps);
+                }
+            }
+            ASTNode::Assignment { index, value, .. } => {
+                if let Some(index) = index {
+                    index.collect_dependencies(deps);
+                }
+                value.collect_dependencies(deps);
+            }
+            ASTNode::Declaration { variables, .. } => {
+                for variable in variables {
+                    variable.collect_dependencies(deps);
+                }
+            }
+            ASTNode::CommandSubstitution(cmd, _) | ASTNode::FileSubstitution(cmd, _) => {
                 cmd.collect_dependencies(deps);
             }
-            ASTNode::Subshell(cmd) => {
+            ASTNode::QualifiedGlob { pattern, .. } => {
+                pattern.collect_dependencies(deps);
+            }
+            ASTNode::ArithmeticExpansion(expr, _) | ASTNode::ArithmeticCommand(expr, _) => {
+                expr.collect_dependencies(deps);
+            }
+            ASTNode::Test { expr, .. } => {
+                expr.collect_dependencies(deps);
+            }
+            ASTNode::BinaryOp { left, right, .. } => {
+                left.collect_dependencies(deps);
+                right.collect_dependencies(deps);
+            }
+            ASTNode::UnaryOp { operand, .. } => {
+                operand.collect_dependencies(deps);
+            }
+            ASTNode::Subshell(cmd) | ASTNode::Background(cmd) => {
                 cmd.collect_dependencies(deps);
             }
+            ASTNode::Concat(parts, _) | ASTNode::Quoted(parts, _) | ASTNode::Array(parts) => {
+                for part in parts {
+                    part.collect_dependencies(deps);
+                }
+            }
+            ASTNode::ParameterExpansion {
+                expansion_type:
+                    ExpansionType::Default { word, .. }
+                    | ExpansionType::Assign { word, .. }
+                    | ExpansionType::Alternative { word, .. }
+                    | ExpansionType::Element(word)
+                    | ExpansionType::Subscript(word),
+                ..
+            } => word.collect_dependencies(deps),
+            _ => {}
+        }
+    }
+}
+
+impl Redirection {
+    fn collect_dependencies(&self, deps: &mut Vec<String>) {
+        match &self.target {
+            RedirectionTarget::File(word)
+            | RedirectionTarget::HereString(word)
+            | RedirectionTarget::Heredoc { content: word, .. } => {
+                word.collect_dependencies(deps);
+            }
             _ => {}
         }
     }
@@ -0,0 +1,1558 @@
+use super::ast::{
+    ASTNode, BinaryOperator, CaseItem, CaseTerminator, ExpansionType, ForItems, RedirectDirection,
+    Redirection, RedirectionTarget, Span, StringType, UnaryOperator,
+};
+use super::parser::{
+    ParseError, RecoveringParser, literal, merge_stderr, push_word_part, test_command,
+    unterminated_string,
+};
+use anyhow::{Result, bail};
+
+/// Words that are keywords when they start a command.
+const KEYWORDS: &[&str] = &[
+    "if", "then", "else", "endif", "while", "foreach", "end", "switch", "case", "default",
+    "breaksw", "endsw", "repeat", "exit", "goto", "onintr",
+];
+
+/// Operators of `if`, `while` and `@` expressions by precedence, lowest
+/// first.
+const PRECEDENCE: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!=", "=~", "!~"],
+    &["<=", ">=", "<", ">"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// Parser for csh and tcsh scripts.
+///
+/// Blocks close with `endif`, `end` and `endsw`, `if`, `while` and `@`
+/// take C-like expressions whose operands are words, and variables are
+/// word lists counted from 1. Scripts are lowered into the same AST as the
+/// POSIX shells so the generator handles them alike.
+pub struct CshParser {
+    chars: Vec<char>,
+    pos: usize,
+    // Position of `chars[pos]` in the script
+    span: Span,
+    // Errors recovered from so far, in script order
+    errors: Vec<ParseError>,
+    // Set once a bad statement read the rest of the script, after which
+    // errors about the missing rest would only repeat the first one
+    skipped_to_end: bool,
+    // Here-document bodies after the current line, skipped once its newline
+    // is reached: where they start and end, and the lines they span
+    heredoc_skip: Option<(usize, usize, usize)>,
+    // Nesting of `( ... )` subshells, which `)` closes
+    subshell_depth: usize,
+    // Inside an expression, where `==` and the like end words
+    in_expression: bool,
+    // Inside a `{ command }` of an expression, which `}` ends
+    in_brace_command: bool,
+    // Reading a `case` pattern, which `:` ends
+    in_pattern: bool,
+}
+
+/// A csh expression, kept until the place it is used decides whether it
+/// becomes a condition, arithmetic or a word.
+#[derive(Debug, Clone)]
+enum Expr {
+    Word(ASTNode),
+    IsSet(String, Span),                 // $?name
+    FileTest(UnaryOperator, ASTNode),    // -e file
+    Command(ASTNode),                    // { command }, true when it succeeds
+    Not(Box<Expr>),
+    Unary(UnaryOperator, Box<Expr>),     // -x and ~x
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+impl CshParser {
+    pub fn new(input: &str) -> Self {
+        Self::starting_at(input, Span::new(1, 1))
+    }
+
+    /// A parser for text embedded in a script at `start`, such as the body
+    /// of a command substitution.
+    pub fn starting_at(input: &str, start: Span) -> Self {
+        CshParser {
+            chars: input.chars().collect(),
+            pos: 0,
+            span: start,
+            errors: Vec::new(),
+            skipped_to_end: false,
+            heredoc_skip: None,
+            subshell_depth: 0,
+            in_expression: false,
+            in_brace_command: false,
+            in_pattern: false,
+        }
+    }
+
+    /// Parse the script, recovering from syntax errors the way
+    /// `ShellParser::parse_with_diagnostics` does: a statement that fails
+    /// becomes an `ASTNode::Error` and parsing resumes after it.
+    pub fn parse_script(&mut self) -> (ASTNode, Vec<ParseError>) {
+        let statements = self.parse_block_until(&[]);
+        (ASTNode::Script(statements.into_iter().map(Box::new).collect()), std::mem::take(&mut self.errors))
+    }
+
+    /// Statements up to one of the keywords in `terminators`, which is left
+    /// for the caller, or the end of the input. Inside a subshell a `)`
+    /// ends them too.
+    fn parse_block_until(&mut self, terminators: &[&str]) -> Vec<ASTNode> {
+        let mut statements = Vec::new();
+        loop {
+            self.skip_separators();
+            if self.at_end()
+                || self.keyword().is_some_and(|keyword| terminators.contains(&keyword))
+                || (self.subshell_depth > 0 && self.peek() == Some(')'))
+            {
+                return statements;
+            }
+
+            let span = self.span;
+            let statement = self.parse_job_list().and_then(|statement| {
+                self.end_statement()?;
+                Ok(statement)
+            });
+            let statement = statement.unwrap_or_else(|error| {
+                self.recover(span, error, |parser| {
+                    while !matches!(parser.peek(), None | Some(';' | '\n')) {
+                        parser.advance();
+                    }
+                })
+            });
+            statements.push(statement);
+        }
+    }
+
+    /// A statement ends at `;`, a newline, the end of the input or the `)`
+    /// of a subshell.
+    fn end_statement(&mut self) -> Result<()> {
+        self.skip_blanks();
+        match self.peek() {
+            None | Some(';' | '\n') => Ok(()),
+            Some(')') if self.subshell_depth > 0 => Ok(()),
+            Some(c) => bail!("Expected end of command, found `{}'", c),
+        }
+    }
+
+    /// Pipelines joined by `&&` and `||`, grouping from the left.
+    fn parse_job_list(&mut self) -> Result<ASTNode> {
+        let mut left = self.parse_pipeline()?;
+        loop {
+            self.skip_blanks();
+            let is_and = if self.starts_with("&&") {
+                true
+            } else if self.starts_with("||") {
+                false
+            } else {
+                return Ok(left);
+            };
+            self.advance_by(2);
+            self.skip_separators();
+            let right = Box::new(self.parse_pipeline()?);
+            left = if is_and {
+                ASTNode::AndList(Box::new(left), right)
+            } else {
+                ASTNode::OrList(Box::new(left), right)
+            };
+        }
+    }
+
+    fn parse_pipeline(&mut self) -> Result<ASTNode> {
+        let mut stages = vec![self.parse_stage()?];
+        loop {
+            self.skip_blanks();
+            if self.peek() != Some('|') || self.starts_with("||") {
+                break;
+            }
+            // `a |& b` pipes stderr along with stdout
+            let merge = self.starts_with("|&");
+            self.advance_by(if merge { 2 } else { 1 });
+            if merge {
+                let stage = stages.pop().unwrap();
+                stages.push(merge_stderr(stage));
+            }
+            self.skip_separators();
+            stages.push(self.parse_stage()?);
+        }
+
+        if stages.len() == 1 {
+            Ok(stages.pop().unwrap())
+        } else {
+            Ok(ASTNode::Pipeline(stages.into_iter().map(Box::new).collect()))
+        }
+    }
+
+    fn parse_stage(&mut self) -> Result<ASTNode> {
+        self.skip_blanks();
+        let span = self.span;
+        let node = match self.keyword() {
+            Some("if") => self.parse_if()?,
+            Some("while") => self.parse_while()?,
+            Some("foreach") => self.parse_foreach()?,
+            Some("switch") => self.parse_switch()?,
+            Some("repeat") => return self.parse_repeat(),
+            Some("exit") => return self.parse_exit(),
+            Some(keyword @ ("goto" | "onintr")) => {
+                let hint = match keyword {
+                    "goto" => "restructure the script with `while` and `if`",
+                    _ => "interrupts stop the converted program",
+                };
+                return Err(ParseError {
+                    span,
+                    message: format!("`{}' is not supported", keyword),
+                    hint: Some(hint.to_string()),
+                }.into());
+            }
+            Some(keyword) => {
+                let hint = match keyword {
+                    "then" => "`then` ends the line of an `if (...)`",
+                    "else" | "endif" => "this keyword is only valid in an `if` block",
+                    "end" => "there is no `while` or `foreach` open here",
+                    "breaksw" => "`breaksw` is only supported at the end of a `case`",
+                    _ => "this keyword is only valid in a `switch` block",
+                };
+                return Err(ParseError {
+                    span,
+                    message: format!("Unexpected `{}'", keyword),
+                    hint: Some(hint.to_string()),
+                }.into());
+            }
+            None if self.peek() == Some('(') => {
+                self.advance();
+                self.subshell_depth += 1;
+                let body = self.parse_block_until(&[]);
+                self.subshell_depth -= 1;
+                self.skip_blanks();
+                if self.peek() != Some(')') {
+                    return Err(ParseError {
+                        span: self.span,
+                        message: "Expected `)'".to_string(),
+                        hint: Some(format!("the `(` at {} is not closed", span)),
+                    }.into());
+                }
+                self.advance();
+                ASTNode::Subshell(Box::new(ASTNode::Block(body.into_iter().map(Box::new).collect())))
+            }
+            None if self.at_builtin("set") => return self.parse_set(),
+            None if self.at_builtin("setenv") => return self.parse_setenv(),
+            None if self.at_builtin("@") => return self.parse_at(),
+            None if self.at_label() => {
+                return Err(ParseError {
+                    span,
+                    message: "Labels are not supported".to_string(),
+                    hint: Some("restructure the script with `while` and `if`".to_string()),
+                }.into());
+            }
+            None => return self.parse_command(),
+        };
+
+        // Redirections after `end` apply to the whole block
+        let mut redirections = Vec::new();
+        loop {
+            self.skip_blanks();
+            if !self.at_redirection() {
+                break;
+            }
+            redirections.extend(self.parse_redirection()?);
+        }
+        if redirections.is_empty() {
+            Ok(node)
+        } else {
+            Ok(ASTNode::Redirected { body: Box::new(node), redirections })
+        }
+    }
+
+    /// `if (expr) then ... else if (expr) then ... else ... endif`, or
+    /// `if (expr) command` on one line.
+    fn parse_if(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        self.advance_by("if".len());
+        let condition = self.parse_condition("if")?;
+        self.skip_blanks();
+        if self.keyword() != Some("then") {
+            let command = self.parse_job_list()?;
+            return Ok(ASTNode::If {
+                condition: Box::new(condition),
+                then_block: Box::new(ASTNode::Block(vec![Box::new(command)])),
+                elif_blocks: Vec::new(),
+                else_block: None,
+                span,
+            });
+        }
+        self.advance_by("then".len());
+        self.end_statement()?;
+        let then_block = self.parse_body(&["else", "endif"]);
+
+        let mut elif_blocks = Vec::new();
+        let mut else_block = None;
+        while self.keyword() == Some("else") {
+            self.advance_by("else".len());
+            self.skip_blanks();
+            if self.keyword() == Some("if") {
+                self.advance_by("if".len());
+                let condition = self.parse_condition("else if")?;
+                self.skip_blanks();
+                if self.keyword() != Some("then") {
+                    bail!("Expected `then' after `else if (...)'");
+                }
+                self.advance_by("then".len());
+                self.end_statement()?;
+                let block = self.parse_body(&["else", "endif"]);
+                elif_blocks.push((Box::new(condition), Box::new(block)));
+            } else {
+                else_block = Some(Box::new(self.parse_body(&["endif"])));
+                break;
+            }
+        }
+        self.expect_close("endif", "if", span)?;
+
+        Ok(ASTNode::If {
+            condition: Box::new(condition),
+            then_block: Box::new(then_block),
+            elif_blocks,
+            else_block,
+            span,
+        })
+    }
+
+    fn parse_while(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        self.advance_by("while".len());
+        let condition = self.parse_condition("while")?;
+        self.end_statement()?;
+        let body = self.parse_body(&["end"]);
+        self.expect_close("end", "while", span)?;
+
+        Ok(ASTNode::While {
+            condition: Box::new(condition),
+            body: Box::new(body),
+            span,
+        })
+    }
+
+    /// `foreach name (words) ... end`
+    fn parse_foreach(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        self.advance_by("foreach".len());
+        self.skip_blanks();
+        let variable = self.read_name();
+        if variable.is_empty() {
+            bail!("Expected a variable name after `foreach'");
+        }
+        self.skip_blanks();
+        if self.peek() != Some('(') {
+            bail!("Expected `(' after `foreach {}'", variable);
+        }
+        let items = self.parse_word_list()?;
+        self.end_statement()?;
+        let body = self.parse_body(&["end"]);
+        self.expect_close("end", "foreach", span)?;
+
+        Ok(ASTNode::For {
+            variable,
+            items: ForItems::List(items.into_iter().map(Box::new).collect()),
+            body: Box::new(body),
+            span,
+        })
+    }
+
+    /// `switch (word)` with `case pattern:` and `default:` labels. A case
+    /// runs on into the next one unless it ends with `breaksw`.
+    fn parse_switch(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        self.advance_by("switch".len());
+        self.skip_blanks();
+        if self.peek() != Some('(') {
+            bail!("Expected `(' after `switch'");
+        }
+        let mut words = self.parse_word_list()?;
+        if words.len() != 1 {
+            bail!("`switch' expects exactly one word, found {}", words.len());
+        }
+        let expr = words.pop().unwrap();
+        self.end_statement()?;
+
+        let mut cases: Vec<CaseItem> = Vec::new();
+        loop {
+            self.skip_separators();
+            let mut patterns = Vec::new();
+            loop {
+                match self.keyword() {
+                    Some("case") => {
+                        self.advance_by("case".len());
+                        self.skip_blanks();
+                        self.in_pattern = true;
+                        let pattern = self.parse_word();
+                        self.in_pattern = false;
+                        patterns.push(pattern?);
+                    }
+                    Some("default") => {
+                        let span = self.span;
+                        self.advance_by("default".len());
+                        self.skip_blanks();
+                        patterns.push(ASTNode::String("*".to_string(), StringType::Unquoted, span));
+                    }
+                    _ => break,
+                }
+                if self.peek() != Some(':') {
+                    bail!("Expected `:' after the `case' pattern");
+                }
+                self.advance();
+                // Labels with nothing between them share a body
+                self.skip_separators();
+            }
+            if patterns.is_empty() {
+                break;
+            }
+
+            let body = self.parse_body(&["case", "default", "breaksw", "endsw"]);
+            let terminator = if self.keyword() == Some("breaksw") {
+                self.advance_by("breaksw".len());
+                self.end_statement()?;
+                CaseTerminator::Break
+            } else if self.keyword() == Some("endsw") {
+                CaseTerminator::Break
+            } else {
+                CaseTerminator::FallThrough
+            };
+            cases.push(CaseItem { patterns, body: Box::new(body), terminator });
+        }
+        self.expect_close("endsw", "switch", span)?;
+
+        Ok(ASTNode::Case { expr: Box::new(expr), cases, span })
+    }
+
+    /// `repeat count command`
+    fn parse_repeat(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        self.advance_by("repeat".len());
+        self.skip_blanks();
+        if matches!(self.peek(), None | Some(';' | '\n')) {
+            bail!("Expected a count after `repeat'");
+        }
+        let count = to_arith(Expr::Word(self.parse_word()?))?;
+        let command = self.parse_pipeline()?;
+        Ok(ASTNode::Repeat {
+            count: Box::new(count),
+            body: Box::new(ASTNode::Block(vec![Box::new(command)])),
+            span,
+        })
+    }
+
+    /// `exit`, `exit status` or `exit (expr)`.
+    fn parse_exit(&mut self) -> Result<ASTNode> {
+        self.advance_by("exit".len());
+        self.skip_blanks();
+        if matches!(self.peek(), None | Some(';' | '\n' | ')')) {
+            return Ok(ASTNode::Exit(None));
+        }
+        let span = self.span;
+        let status = lower_value(self.parse_expression()?, span)?;
+        Ok(ASTNode::Exit(Some(Box::new(status))))
+    }
+
+    /// The parenthesized expression after `if` and `while`, as a condition.
+    fn parse_condition(&mut self, keyword: &str) -> Result<ASTNode> {
+        self.skip_blanks();
+        let span = self.span;
+        if self.peek() != Some('(') {
+            bail!("Expected `(' after `{}'", keyword);
+        }
+        let expr = self.parse_expression()?;
+        lower_condition(expr, span)
+    }
+
+    /// An expression up to the end of the statement or of the parentheses
+    /// it is in.
+    fn parse_expression(&mut self) -> Result<Expr> {
+        let outer = self.in_expression;
+        self.in_expression = true;
+        let expr = self.parse_binary(0);
+        self.in_expression = outer;
+        expr
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr> {
+        if level == PRECEDENCE.len() {
+            return self.parse_unary();
+        }
+        let mut left = self.parse_binary(level + 1)?;
+        loop {
+            self.skip_blanks();
+            let Some(op) = self.operator().filter(|op| PRECEDENCE[level].contains(op)) else {
+                return Ok(left);
+            };
+            self.advance_by(op.len());
+            let right = self.parse_binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        self.skip_blanks();
+        let next = self.chars.get(self.pos + 1).copied();
+        let operand_follows = next.is_none_or(|c| c.is_whitespace() || matches!(c, '(' | '$' | '!' | '~' | '-'));
+        match self.peek() {
+            Some('!') if !matches!(next, Some('=' | '~')) => {
+                self.advance();
+                Ok(Expr::Not(Box::new(self.parse_unary()?)))
+            }
+            Some('~') if operand_follows => {
+                self.advance();
+                Ok(Expr::Unary(UnaryOperator::BitNot, Box::new(self.parse_unary()?)))
+            }
+            Some('-') if next.is_some_and(|c| c.is_ascii_alphabetic())
+                && self.chars.get(self.pos + 2).is_none_or(|c| c.is_whitespace()) =>
+            {
+                let test = next.unwrap();
+                self.advance_by(2);
+                self.skip_blanks();
+                let file = self.parse_word()?;
+                let op = match test {
+                    'e' => UnaryOperator::FileExists,
+                    'f' => UnaryOperator::FileRegular,
+                    'd' => UnaryOperator::FileDirectory,
+                    'l' => UnaryOperator::FileSymlink,
+                    'r' => UnaryOperator::FileReadable,
+                    'w' => UnaryOperator::FileWritable,
+                    'x' => UnaryOperator::FileExecutable,
+                    's' => UnaryOperator::FileNotEmpty,
+                    'b' => UnaryOperator::FileBlock,
+                    'c' => UnaryOperator::FileCharacter,
+                    'p' => UnaryOperator::FilePipe,
+                    'S' => UnaryOperator::FileSocket,
+                    // Zero size
+                    'z' => return Ok(Expr::Not(Box::new(Expr::FileTest(UnaryOperator::FileNotEmpty, file)))),
+                    _ => bail!("The file test `-{}' is not supported", test),
+                };
+                Ok(Expr::FileTest(op, file))
+            }
+            Some('-') if operand_follows => {
+                self.advance();
+                Ok(Expr::Unary(UnaryOperator::Negate, Box::new(self.parse_unary()?)))
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        let span = self.span;
+        match self.peek() {
+            Some('(') => {
+                self.advance();
+                let expr = self.parse_binary(0)?;
+                self.skip_blanks();
+                if self.peek() != Some(')') {
+                    let found = self.peek().map_or("end of line".to_string(), |c| format!("`{}'", c));
+                    return Err(ParseError {
+                        span: self.span,
+                        message: format!("Expected `)', found {}", found),
+                        hint: Some(format!("the `(` at {} is not closed", span)),
+                    }.into());
+                }
+                self.advance();
+                Ok(expr)
+            }
+            // `{ command }` is true when the command succeeds
+            Some('{') if self.chars.get(self.pos + 1).is_some_and(|c| c.is_whitespace()) => {
+                self.advance();
+                let (outer_expression, outer_brace) = (self.in_expression, self.in_brace_command);
+                self.in_expression = false;
+                self.in_brace_command = true;
+                let command = self.parse_job_list();
+                (self.in_expression, self.in_brace_command) = (outer_expression, outer_brace);
+                let command = command?;
+                self.skip_blanks();
+                if self.peek() != Some('}') {
+                    return Err(ParseError {
+                        span: self.span,
+                        message: "Expected `}'".to_string(),
+                        hint: Some(format!("the `{{` at {} is not closed", span)),
+                    }.into());
+                }
+                self.advance();
+                Ok(Expr::Command(command))
+            }
+            Some('$') if self.chars.get(self.pos + 1) == Some(&'?')
+                && self.chars.get(self.pos + 2).is_some_and(|&c| is_name_char(c)) =>
+            {
+                let span = self.span;
+                self.advance_by(2);
+                let name = self.read_name();
+                Ok(Expr::IsSet(variable_name(&name)?, span))
+            }
+            Some(c) if !self.is_word_end(c) => Ok(Expr::Word(self.parse_word()?)),
+            Some(c) => bail!("Expected an operand, found `{}'", c),
+            None => bail!("Expected an operand"),
+        }
+    }
+
+    /// The operator at the current position, longest first.
+    fn operator(&self) -> Option<&'static str> {
+        PRECEDENCE
+            .iter()
+            .flat_map(|level| level.iter().copied())
+            .filter(|op| self.starts_with(op))
+            .max_by_key(|op| op.len())
+    }
+
+    /// `set name`, `set name = word`, `set name = (words)`, several of them
+    /// in one command. tcsh's `set -r` makes them read-only.
+    fn parse_set(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        self.advance_by("set".len());
+        self.skip_blanks();
+        let readonly = self.starts_with("-r") && self.chars.get(self.pos + 2).is_none_or(|&c| self.is_word_end(c));
+        if readonly {
+            self.advance_by(2);
+        }
+
+        let mut assignments = Vec::new();
+        loop {
+            self.skip_blanks();
+            if matches!(self.peek(), None | Some(';' | '\n' | '|' | '&' | ')')) {
+                break;
+            }
+            let name = self.read_name();
+            if name.is_empty() {
+                bail!("set: expected a variable name, found `{}'", self.peek().unwrap());
+            }
+            if name == "path" {
+                bail!("set: the `path' list is not supported, use `setenv PATH ...' instead");
+            }
+            if self.peek() == Some('[') {
+                bail!("set: assigning to elements of `{}' is not supported", name);
+            }
+
+            self.skip_blanks();
+            let value = if self.peek() == Some('=') {
+                self.advance();
+                self.skip_blanks();
+                match self.peek() {
+                    Some('(') => ASTNode::Array(self.parse_word_list()?.into_iter().map(Box::new).collect()),
+                    // `set x = \`cmd\`` keeps the words of the output
+                    Some('`') => match self.parse_word()? {
+                        word @ ASTNode::CommandSubstitution(_, _) => ASTNode::Array(vec![Box::new(word)]),
+                        word => word,
+                    },
+                    Some(c) if !self.is_word_end(c) => self.parse_word()?,
+                    _ => ASTNode::String(String::new(), StringType::SingleQuoted, self.span),
+                }
+            } else {
+                ASTNode::String(String::new(), StringType::SingleQuoted, self.span)
+            };
+            assignments.push(Box::new(ASTNode::Assignment {
+                name: variable_name(&name)?,
+                index: None,
+                value: Box::new(value),
+                append: false,
+                export: false,
+                readonly,
+                local: false,
+                span,
+            }));
+        }
+
+        match assignments.len() {
+            0 => bail!("set: listing variables is not supported"),
+            1 => Ok(*assignments.pop().unwrap()),
+            _ => Ok(ASTNode::Block(assignments)),
+        }
+    }
+
+    /// `setenv NAME [value]`
+    fn parse_setenv(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        self.advance_by("setenv".len());
+        self.skip_blanks();
+        let name = self.read_name();
+        if name.is_empty() {
+            bail!("setenv: listing the environment is not supported");
+        }
+        let mut values = self.parse_words()?;
+        if values.len() > 1 {
+            bail!("setenv: too many arguments");
+        }
+        let value = values.pop().unwrap_or(ASTNode::String(String::new(), StringType::SingleQuoted, self.span));
+        Ok(ASTNode::Assignment { name, index: None, value: Box::new(value), append: false, export: true, readonly: false, local: false, span })
+    }
+
+    /// `@ name = expr`, `@ name += expr` and the like, or `@ name++`.
+    fn parse_at(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        self.advance();
+        self.skip_blanks();
+        let name_span = self.span;
+        let name = self.read_name();
+        if name.is_empty() {
+            bail!("@: listing variables is not supported");
+        }
+        if self.peek() == Some('[') {
+            bail!("@: assigning to elements of `{}' is not supported", name);
+        }
+        let name = variable_name(&name)?;
+        self.skip_blanks();
+
+        for (text, op) in [("++", UnaryOperator::PostIncrement), ("--", UnaryOperator::PostDecrement)] {
+            if self.starts_with(text) {
+                self.advance_by(2);
+                let operand = Box::new(ASTNode::Variable(name, name_span));
+                return Ok(ASTNode::ArithmeticCommand(Box::new(ASTNode::UnaryOp { op, operand }), span));
+            }
+        }
+
+        let assignments = [
+            ("<<=", Some(BinaryOperator::ShiftLeft)),
+            (">>=", Some(BinaryOperator::ShiftRight)),
+            ("+=", Some(BinaryOperator::Add)),
+            ("-=", Some(BinaryOperator::Subtract)),
+            ("*=", Some(BinaryOperator::Multiply)),
+            ("/=", Some(BinaryOperator::Divide)),
+            ("%=", Some(BinaryOperator::Modulo)),
+            ("&=", Some(BinaryOperator::BitAnd)),
+            ("|=", Some(BinaryOperator::BitOr)),
+            ("^=", Some(BinaryOperator::BitXor)),
+            ("=", None),
+        ];
+        let Some((text, op)) = assignments.into_iter().find(|(text, _)| self.starts_with(text)) else {
+            bail!("@: expected an assignment to `{}'", name);
+        };
+        self.advance_by(text.len());
+        let value = to_arith(self.parse_expression()?)?;
+        Ok(ASTNode::ArithmeticCommand(Box::new(ASTNode::ArithmeticAssignment {
+            name,
+            op,
+            value: Box::new(value),
+        }), span))
+    }
+
+    fn parse_body(&mut self, terminators: &[&str]) -> ASTNode {
+        ASTNode::Block(self.parse_block_until(terminators).into_iter().map(Box::new).collect())
+    }
+
+    /// Consume the `closer` of the block opened by `opener` at `span`.
+    fn expect_close(&mut self, closer: &str, opener: &str, span: Span) -> Result<()> {
+        if self.keyword() != Some(closer) {
+            let found = match self.peek() {
+                Some(_) if self.keyword().is_some() => format!("`{}'", self.keyword().unwrap()),
+                Some(c) => format!("`{}'", c),
+                None => "end of script".to_string(),
+            };
+            return Err(ParseError {
+                span: self.span,
+                message: format!("Expected `{}', found {}", closer, found),
+                hint: Some(format!("the `{}` at {} is not closed", opener, span)),
+            }.into());
+        }
+        self.advance_by(closer.len());
+        Ok(())
+    }
+
+    /// `(words)` after `foreach` and `switch` and in `set`.
+    fn parse_word_list(&mut self) -> Result<Vec<ASTNode>> {
+        let open = self.span;
+        self.advance();
+        let mut words = Vec::new();
+        loop {
+            self.skip_blanks();
+            match self.peek() {
+                Some(')') => {
+                    self.advance();
+                    return Ok(words);
+                }
+                Some(c) if !self.is_word_end(c) => words.push(self.parse_word()?),
+                found => {
+                    let found = found.map_or("end of script".to_string(), |c| match c {
+                        '\n' => "end of line".to_string(),
+                        _ => format!("`{}'", c),
+                    });
+                    return Err(ParseError {
+                        span: self.span,
+                        message: format!("Expected `)', found {}", found),
+                        hint: Some(format!("the `(` at {} is not closed", open)),
+                    }.into());
+                }
+            }
+        }
+    }
+
+    /// The words up to the end of the statement.
+    fn parse_words(&mut self) -> Result<Vec<ASTNode>> {
+        let mut words = Vec::new();
+        loop {
+            self.skip_blanks();
+            match self.peek() {
+                Some(c) if !self.is_word_end(c) => words.push(self.parse_word()?),
+                _ => return Ok(words),
+            }
+        }
+    }
+
+    fn parse_command(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        let mut words = Vec::new();
+        let mut redirections = Vec::new();
+        let mut background = false;
+
+        loop {
+            self.skip_blanks();
+            match self.peek() {
+                None | Some(';' | '\n' | '|' | ')') => break,
+                Some('}') if self.in_brace_command => break,
+                Some('&') if self.starts_with("&&") => break,
+                Some('&') => {
+                    self.advance();
+                    background = true;
+                    break;
+                }
+                Some('(') => bail!("Unexpected `(' in a command"),
+                _ if self.at_redirection() => redirections.extend(self.parse_redirection()?),
+                _ => words.push(Box::new(self.parse_word()?)),
+            }
+        }
+
+        if words.is_empty() {
+            match self.peek() {
+                Some(c) => bail!("Expected a command, found `{}'", c),
+                None => bail!("Expected a command"),
+            }
+        }
+        let name = match literal(&words[0]) {
+            Some(name) => name.to_string(),
+            None => bail!("Dynamic command names are not supported"),
+        };
+        let args: Vec<Box<ASTNode>> = words.drain(1..).collect();
+
+        let plain = redirections.is_empty() && !background;
+        match name.as_str() {
+            "break" if plain => return Ok(ASTNode::Break(1)),
+            "continue" if plain => return Ok(ASTNode::Continue(1)),
+            "unset" | "unsetenv" if plain => {
+                return Ok(ASTNode::Command { name: "unset".to_string(), args, redirections, background, span });
+            }
+            "shift" if !args.is_empty() => bail!("shift: shifting lists other than `argv' is not supported"),
+            "alias" | "unalias" => bail!("`{}' is not supported, write the command out instead", name),
+            "test" | "[" if plain => {
+                return test_command(&name, args.into_iter().map(|arg| *arg).collect(), span);
+            }
+            _ => {}
+        }
+
+        Ok(ASTNode::Command { name, args, redirections, background, span })
+    }
+
+    /// Whether a redirection operator starts here. csh has no file
+    /// descriptor numbers: `>&` sends both stdout and stderr.
+    fn at_redirection(&self) -> bool {
+        matches!(self.peek(), Some('<' | '>'))
+    }
+
+    fn parse_redirection(&mut self) -> Result<Vec<Redirection>> {
+        if self.starts_with("<<") {
+            self.advance_by(2);
+            return Ok(vec![self.parse_heredoc()?]);
+        }
+        if self.peek() == Some('<') {
+            self.advance();
+            let file = self.parse_redirection_word()?;
+            return Ok(vec![Redirection {
+                fd: None,
+                direction: RedirectDirection::Input,
+                target: RedirectionTarget::File(Box::new(file)),
+                append: false,
+            }]);
+        }
+
+        let append = self.starts_with(">>");
+        self.advance_by(if append { 2 } else { 1 });
+        let with_errors = self.peek() == Some('&');
+        if with_errors {
+            self.advance();
+        }
+        // `>!` overwrites even with noclobber set
+        if self.peek() == Some('!') {
+            self.advance();
+        }
+
+        let file = self.parse_redirection_word()?;
+        let mut redirections = vec![Redirection {
+            fd: None,
+            direction: RedirectDirection::Output,
+            target: RedirectionTarget::File(Box::new(file)),
+            append,
+        }];
+        if with_errors {
+            redirections.push(Redirection {
+                fd: Some(2),
+                direction: RedirectDirection::Output,
+                target: RedirectionTarget::Fd(1),
+                append: false,
+            });
+        }
+        Ok(redirections)
+    }
+
+    fn parse_redirection_word(&mut self) -> Result<ASTNode> {
+        self.skip_blanks();
+        match self.peek() {
+            Some(c) if !self.is_word_end(c) => self.parse_word(),
+            Some(c) => bail!("Expected redirection target, found `{}'", c),
+            None => bail!("Expected redirection target"),
+        }
+    }
+
+    /// `<< word`. The body is the lines after the current one up to a line
+    /// holding only the word; it is skipped over once the current line
+    /// ends. Quoting any part of the word keeps the body literal.
+    fn parse_heredoc(&mut self) -> Result<Redirection> {
+        self.skip_blanks();
+        let mut delimiter = String::new();
+        let mut quoted = false;
+        while let Some(c) = self.peek().filter(|&c| !self.is_word_end(c)) {
+            self.advance();
+            match c {
+                '\'' | '"' | '\\' => quoted = true,
+                _ => delimiter.push(c),
+            }
+        }
+        if delimiter.is_empty() {
+            bail!("Expected a here-document delimiter after `<<'");
+        }
+
+        // A second here-document on the line starts after the first one
+        let (start, lines_before) = match self.heredoc_skip {
+            Some((_, end, lines)) => (end, lines),
+            None => match self.chars[self.pos..].iter().position(|&c| c == '\n') {
+                Some(offset) => (self.pos + offset + 1, 0),
+                None => bail!("Missing here-document body after `<< {}'", delimiter),
+            },
+        };
+        let body_span = Span::new(self.span.line + 1 + lines_before, 1);
+
+        let mut body = String::new();
+        let mut line_start = start;
+        let mut lines = lines_before;
+        let end = loop {
+            if line_start >= self.chars.len() {
+                return Err(ParseError {
+                    span: self.span,
+                    message: format!("Unterminated here-document `{}'", delimiter),
+                    hint: Some(format!("end it with a line holding only `{}`", delimiter)),
+                }.into());
+            }
+            let line_end = self.chars[line_start..]
+                .iter()
+                .position(|&c| c == '\n')
+                .map_or(self.chars.len(), |offset| line_start + offset);
+            let line: String = self.chars[line_start..line_end].iter().collect();
+            lines += 1;
+            let next = (line_end + 1).min(self.chars.len());
+            if line == delimiter {
+                break next;
+            }
+            body.push_str(&line);
+            body.push('\n');
+            line_start = next;
+        };
+        let from = self.heredoc_skip.map_or(start, |(from, _, _)| from);
+        self.heredoc_skip = Some((from, end, lines));
+
+        let content = if quoted {
+            ASTNode::String(body, StringType::SingleQuoted, body_span)
+        } else {
+            let mut parser = CshParser::starting_at(&body, body_span);
+            let mut parts = Vec::new();
+            let content = parser.parse_expanding_text(&mut parts, None, true);
+            self.errors.append(&mut parser.errors);
+            content?;
+            match parts.len() {
+                0 => ASTNode::String(String::new(), StringType::SingleQuoted, body_span),
+                1 => parts.pop().unwrap(),
+                _ => ASTNode::Concat(parts.into_iter().map(Box::new).collect(), body_span),
+            }
+        };
+
+        Ok(Redirection {
+            fd: None,
+            direction: RedirectDirection::Input,
+            target: RedirectionTarget::Heredoc { delimiter, content: Box::new(content), strip_tabs: false },
+            append: false,
+        })
+    }
+
+    /// A word: literal text, quotes, variables and backquote substitutions
+    /// up to the next blank or operator. Unquoted glob characters stay
+    /// special; quoted and escaped text becomes single-quoted parts.
+    fn parse_word(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        let mut parts = Vec::new();
+        while let Some(c) = self.peek() {
+            let start = self.span;
+            match c {
+                _ if self.is_word_end(c) => break,
+                '\'' => {
+                    let text = self.read_single_quoted()?;
+                    parts.push(ASTNode::String(text, StringType::SingleQuoted, start));
+                }
+                '"' => {
+                    self.advance();
+                    let before = parts.len();
+                    self.parse_expanding_text(&mut parts, Some(start), false)?;
+                    // `""` is still a word
+                    if parts.len() == before {
+                        parts.push(ASTNode::String(String::new(), StringType::SingleQuoted, start));
+                    }
+                }
+                '$' => {
+                    let node = self.parse_variable()?;
+                    push_word_part(&mut parts, node);
+                }
+                '`' => {
+                    let node = self.parse_backquote()?;
+                    parts.push(node);
+                }
+                '\\' => {
+                    self.advance();
+                    match self.advance() {
+                        // A line continuation inside a word
+                        Some('\n') => {}
+                        Some(c) => parts.push(ASTNode::String(c.to_string(), StringType::SingleQuoted, start)),
+                        None => parts.push(ASTNode::String("\\".to_string(), StringType::SingleQuoted, start)),
+                    }
+                }
+                _ => {
+                    self.advance();
+                    push_word_part(&mut parts, ASTNode::String(c.to_string(), StringType::Unquoted, start));
+                }
+            }
+        }
+
+        match parts.len() {
+            0 => bail!("Expected a word"),
+            1 => Ok(match parts.pop().unwrap() {
+                ASTNode::String(text, StringType::Unquoted, _) if !text.is_empty() && text.chars().all(|c| c.is_ascii_digit()) => {
+                    ASTNode::Number(text.parse()?)
+                }
+                part => part,
+            }),
+            _ => Ok(ASTNode::Concat(parts.into_iter().map(Box::new).collect(), span)),
+        }
+    }
+
+    /// `'...'`, where nothing is special.
+    fn read_single_quoted(&mut self) -> Result<String> {
+        let start = self.span;
+        self.advance();
+        let mut text = String::new();
+        loop {
+            match self.advance() {
+                Some('\'') => return Ok(text),
+                Some(c) => text.push(c),
+                None => return Err(unterminated_string(start)),
+            }
+        }
+    }
+
+    /// Text where variables and backquotes expand: the inside of `"..."`
+    /// opened at `quote`, or an unquoted here-document body up to the end
+    /// of the input, where a backslash also escapes `$`, `` ` `` and `\`.
+    fn parse_expanding_text(&mut self, parts: &mut Vec<ASTNode>, quote: Option<Span>, escapes: bool) -> Result<()> {
+        let mut text = String::new();
+        let mut text_start = self.span;
+        loop {
+            if text.is_empty() {
+                text_start = self.span;
+            }
+            match self.peek() {
+                Some('"') if quote.is_some() => {
+                    self.advance();
+                    break;
+                }
+                None => match quote {
+                    Some(start) => return Err(unterminated_string(start)),
+                    None => break,
+                },
+                Some('\\') if escapes && matches!(self.chars.get(self.pos + 1), Some('$' | '`' | '\\')) => {
+                    self.advance();
+                    text.extend(self.advance());
+                }
+                Some('$') if self.chars.get(self.pos + 1).is_some_and(|&c| is_variable_start(c)) => {
+                    if !text.is_empty() {
+                        parts.push(ASTNode::String(std::mem::take(&mut text), StringType::SingleQuoted, text_start));
+                    }
+                    parts.push(self.parse_variable()?);
+                }
+                Some('`') => {
+                    if !text.is_empty() {
+                        parts.push(ASTNode::String(std::mem::take(&mut text), StringType::SingleQuoted, text_start));
+                    }
+                    parts.push(self.parse_backquote()?);
+                }
+                Some(c) => {
+                    self.advance();
+                    text.push(c);
+                }
+            }
+        }
+
+        if !text.is_empty() {
+            parts.push(ASTNode::String(text, StringType::SingleQuoted, text_start));
+        }
+        Ok(())
+    }
+
+    /// A variable reference: `$name`, `${name}`, `$name[index]`, `$#name`
+    /// for the number of words, `$?name` for whether it is set, `$<` for a
+    /// line of input, and the `:r`, `:e`, `:h` and `:t` modifiers.
+    fn parse_variable(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        self.advance();
+        let braced = self.peek() == Some('{');
+        if braced {
+            self.advance();
+        }
+
+        let node = match self.peek() {
+            Some('<') if !braced => {
+                self.advance();
+                let head = ASTNode::Command {
+                    name: "head".to_string(),
+                    args: vec![
+                        Box::new(ASTNode::String("-n".to_string(), StringType::Unquoted, span)),
+                        Box::new(ASTNode::Number(1.0)),
+                    ],
+                    redirections: Vec::new(),
+                    background: false,
+                    span,
+                };
+                return Ok(ASTNode::CommandSubstitution(Box::new(head), span));
+            }
+            Some('#') if self.chars.get(self.pos + 1).is_some_and(|&c| is_name_char(c)) => {
+                self.advance();
+                let name = variable_name(&self.read_name())?;
+                match name.as_str() {
+                    "@" => ASTNode::Variable("#".to_string(), span),
+                    _ => ASTNode::ParameterExpansion { name: format!("{}[@]", name), expansion_type: ExpansionType::Length, span },
+                }
+            }
+            Some('?') if self.chars.get(self.pos + 1).is_some_and(|&c| is_name_char(c)) => {
+                self.advance();
+                let name = variable_name(&self.read_name())?;
+                is_set_word(&name, span)
+            }
+            Some(c @ ('?' | '$' | '!')) => {
+                self.advance();
+                ASTNode::Variable(c.to_string(), span)
+            }
+            Some(c) if c.is_ascii_digit() => {
+                self.advance();
+                ASTNode::Variable(c.to_string(), span)
+            }
+            Some(c) if is_name_char(c) => {
+                let name = variable_name(&self.read_name())?;
+                let node = if self.peek() == Some('[') {
+                    self.parse_index(name, span)?
+                } else {
+                    ASTNode::Variable(name, span)
+                };
+                self.parse_modifiers(node)?
+            }
+            // A lone `$` is literal
+            _ if !braced => return Ok(ASTNode::String("$".to_string(), StringType::Unquoted, span)),
+            _ => bail!("Bad variable reference `${{'"),
+        };
+
+        if braced {
+            if self.peek() != Some('}') {
+                bail!("Expected `}}' to end the variable reference");
+            }
+            self.advance();
+        }
+        Ok(node)
+    }
+
+    /// `[index]` after a variable name referenced at `span`. Ranges such as
+    /// `[2-3]` are not supported.
+    fn parse_index(&mut self, name: String, span: Span) -> Result<ASTNode> {
+        self.advance();
+        let start = self.span;
+        let mut index = String::new();
+        loop {
+            match self.advance() {
+                Some(']') => break,
+                Some(c) => index.push(c),
+                None => bail!("Unterminated index of `${}'", name),
+            }
+        }
+        let index = index.trim();
+
+        if index.contains('-') || index == "*" {
+            bail!("`${}[{}]': index ranges are not supported", name, index);
+        }
+        if name == "@" {
+            if let Ok(n @ 1..) = index.parse::<usize>() {
+                return Ok(ASTNode::Variable(n.to_string(), span));
+            }
+        }
+        let mut parser = CshParser::starting_at(index, start);
+        let index = to_arith(parser.parse_expression()?)?;
+        Ok(ASTNode::ParameterExpansion { name, expansion_type: ExpansionType::Element(Box::new(index)), span })
+    }
+
+    /// Modifiers after a variable: `:r` drops the extension, `:e` keeps
+    /// only it, `:h` drops the last path component and `:t` keeps only it.
+    /// A `:` followed by anything but a modifier is literal text.
+    fn parse_modifiers(&mut self, mut node: ASTNode) -> Result<ASTNode> {
+        while self.peek() == Some(':') {
+            let Some(&modifier) = self.chars.get(self.pos + 1) else {
+                break;
+            };
+            // Other letters are modifiers only where the word ends after them
+            let ends = self.chars.get(self.pos + 2).is_none_or(|&c| self.is_word_end(c) || matches!(c, ':' | '}' | '"'));
+            let known = matches!(modifier, 'r' | 'e' | 'h' | 't' | 'q' | 'x');
+            if !(known || modifier.is_ascii_alphabetic() && ends) {
+                break;
+            }
+            self.advance_by(2);
+            let expansion_type = match modifier {
+                'r' => ExpansionType::RemoveSuffix(".*".to_string()),
+                'e' => ExpansionType::RemovePrefixLong("*.".to_string()),
+                'h' => ExpansionType::RemoveSuffix("/*".to_string()),
+                't' => ExpansionType::RemovePrefixLong("*/".to_string()),
+                // Quoting is already how the words are kept
+                'q' | 'x' => continue,
+                _ => bail!("The `:{}' modifier is not supported", modifier),
+            };
+            node = match node {
+                ASTNode::Variable(name, span) => ASTNode::ParameterExpansion { name, expansion_type, span },
+                _ => bail!("Only one modifier per variable is supported, and none on elements"),
+            };
+        }
+        Ok(node)
+    }
+
+    /// `` `cmd` ``, parsed as a script of its own but located in this one.
+    fn parse_backquote(&mut self) -> Result<ASTNode> {
+        let open = self.span;
+        self.advance();
+        let start = self.span;
+        let mut body = String::new();
+        loop {
+            match self.advance() {
+                Some('`') => break,
+                Some('\\') if self.peek() == Some('`') => body.push(self.advance().unwrap()),
+                Some(c) => body.push(c),
+                None => {
+                    return Err(ParseError {
+                        span: open,
+                        message: "Unterminated command substitution".to_string(),
+                        hint: Some("add the missing `` ` ``".to_string()),
+                    }.into());
+                }
+            }
+        }
+
+        let mut parser = CshParser::starting_at(&body, start);
+        let (root, errors) = parser.parse_script();
+        for mut error in errors {
+            error.message = format!("in command substitution `{}`: {}", body.trim(), error.message);
+            self.errors.push(error);
+        }
+        let statements = match root {
+            ASTNode::Script(statements) => statements,
+            other => vec![Box::new(other)],
+        };
+        Ok(ASTNode::CommandSubstitution(Box::new(ASTNode::Block(statements)), open))
+    }
+
+    /// The keyword starting at the current position, if any. `case` and
+    /// `default` labels may be followed by `:` directly.
+    fn keyword(&self) -> Option<&'static str> {
+        KEYWORDS.iter().copied().find(|keyword| {
+            self.starts_with(keyword)
+                && self.chars.get(self.pos + keyword.len()).is_none_or(|&c| is_word_end(c) || c == ':')
+        })
+    }
+
+    /// Whether the builtin `name` starts here, which takes its arguments in
+    /// a syntax of its own.
+    fn at_builtin(&self, name: &str) -> bool {
+        self.starts_with(name) && self.chars.get(self.pos + name.len()).is_none_or(|&c| c.is_whitespace() || c == ';')
+    }
+
+    /// Whether the line is a `goto` label such as `retry:`.
+    fn at_label(&self) -> bool {
+        let name = self.chars[self.pos..].iter().take_while(|&&c| is_name_char(c)).count();
+        name > 0
+            && self.chars.get(self.pos + name) == Some(&':')
+            && self.chars.get(self.pos + name + 1).is_none_or(|&c| c.is_whitespace())
+    }
+
+    /// Whether `c` ends an unquoted word here. In expressions, operators
+    /// starting with `=` or `!` do too, and in `case` labels the `:`.
+    fn is_word_end(&self, c: char) -> bool {
+        if is_word_end(c) {
+            return true;
+        }
+        let next = self.chars.get(self.pos + 1).copied();
+        match c {
+            '=' | '!' if self.in_expression => matches!(next, Some('=' | '~')),
+            ':' if self.in_pattern => next.is_none_or(|c| c.is_whitespace()),
+            '}' if self.in_brace_command => true,
+            _ => false,
+        }
+    }
+
+    fn read_name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek().filter(|&c| is_name_char(c)) {
+            self.advance();
+            name.push(c);
+        }
+        name
+    }
+
+    /// Skip blanks, line continuations and comments, but not newlines.
+    fn skip_blanks(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ' | '\t' | '\r') => {
+                    self.advance();
+                }
+                Some('\\') if self.chars.get(self.pos + 1) == Some(&'\n') => self.advance_by(2),
+                Some('#') => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.advance();
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Skip blanks and statement separators.
+    fn skip_separators(&mut self) {
+        loop {
+            self.skip_blanks();
+            if !matches!(self.peek(), Some(';' | '\n')) {
+                return;
+            }
+            self.advance();
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, text: &str) -> bool {
+        text.chars().enumerate().all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        self.span = match c {
+            '\n' => Span::new(self.span.line + 1, 1),
+            _ => Span::new(self.span.line, self.span.column + 1),
+        };
+        // Here-document bodies were read with the line that started them
+        if let Some((from, to, lines)) = self.heredoc_skip {
+            if self.pos == from {
+                self.pos = to;
+                self.span = Span::new(self.span.line + lines, 1);
+                self.heredoc_skip = None;
+            }
+        }
+        Some(c)
+    }
+
+    fn advance_by(&mut self, count: usize) {
+        for _ in 0..count {
+            self.advance();
+        }
+    }
+}
+
+impl RecoveringParser for CshParser {
+    fn error_span(&self) -> Span {
+        self.span
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.chars.len()
+    }
+
+    fn errors_mut(&mut self) -> &mut Vec<ParseError> {
+        &mut self.errors
+    }
+
+    fn skipped_to_end_mut(&mut self) -> &mut bool {
+        &mut self.skipped_to_end
+    }
+}
+
+/// Whether `c` ends an unquoted word.
+fn is_word_end(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ';' | '|' | '&' | '<' | '>' | '(' | ')')
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Whether `c` after a `$` starts a variable reference.
+fn is_variable_start(c: char) -> bool {
+    is_name_char(c) || matches!(c, '{' | '#' | '?' | '$' | '!' | '<')
+}
+
+/// The name a csh variable has in the shared AST. `argv` is the positional
+/// parameters, `status` the exit status, and the variables csh keeps in
+/// step with the environment are the environment variables.
+fn variable_name(name: &str) -> Result<String> {
+    Ok(match name {
+        "argv" => "@".to_string(),
+        "status" => "?".to_string(),
+        "cwd" => "PWD".to_string(),
+        "home" => "HOME".to_string(),
+        "user" => "USER".to_string(),
+        "term" => "TERM".to_string(),
+        "shell" => "SHELL".to_string(),
+        "path" => bail!("The `path' list is not supported, use `$PATH' instead"),
+        _ => name.to_string(),
+    })
+}
+
+/// `$?name`, written at `span`, as a word: 1 when the variable is set and
+/// 0 otherwise.
+fn is_set_word(name: &str, span: Span) -> ASTNode {
+    let echo = |text: &str| ASTNode::Command {
+        name: "echo".to_string(),
+        args: vec![Box::new(ASTNode::Number(text.parse().unwrap()))],
+        redirections: Vec::new(),
+        background: false,
+        span,
+    };
+    let test = ASTNode::Test {
+        expr: Box::new(ASTNode::UnaryOp {
+            op: UnaryOperator::VariableSet,
+            operand: Box::new(ASTNode::String(name.to_string(), StringType::Unquoted, Span::new(span.line, span.column + 2))),
+        }),
+        extended: false,
+        span,
+    };
+    let choice = ASTNode::OrList(Box::new(ASTNode::AndList(Box::new(test), Box::new(echo("1")))), Box::new(echo("0")));
+    ASTNode::CommandSubstitution(Box::new(choice), span)
+}
+
+/// Lower an expression used as a condition into a command whose status is
+/// its truth. `==` and `!=` compare text, `=~` and `!~` match a glob
+/// pattern, and anything else is arithmetic that is true when non-zero.
+fn lower_condition(expr: Expr, span: Span) -> Result<ASTNode> {
+    let test = |expr: ASTNode, extended: bool| ASTNode::Test { expr: Box::new(expr), extended, span };
+    Ok(match expr {
+        Expr::Binary("||", left, right) => {
+            ASTNode::OrList(Box::new(lower_condition(*left, span)?), Box::new(lower_condition(*right, span)?))
+        }
+        Expr::Binary("&&", left, right) => {
+            ASTNode::AndList(Box::new(lower_condition(*left, span)?), Box::new(lower_condition(*right, span)?))
+        }
+        Expr::Not(operand) => ASTNode::Negated(Box::new(lower_condition(*operand, span)?)),
+        Expr::IsSet(name, at) => test(
+            ASTNode::UnaryOp {
+                op: UnaryOperator::VariableSet,
+                operand: Box::new(ASTNode::String(name, StringType::Unquoted, Span::new(at.line, at.column + 2))),
+            },
+            false,
+        ),
+        Expr::FileTest(op, file) => test(ASTNode::UnaryOp { op, operand: Box::new(file) }, false),
+        Expr::Command(command) => command,
+        Expr::Binary(op @ ("==" | "!="), left, right) => {
+            let op = if op == "==" { BinaryOperator::StringEqual } else { BinaryOperator::StringNotEqual };
+            test(ASTNode::BinaryOp { left: Box::new(lower_value(*left, span)?), op, right: Box::new(lower_value(*right, span)?) }, false)
+        }
+        Expr::Binary(op @ ("=~" | "!~"), left, right) => {
+            let op = if op == "=~" { BinaryOperator::StringEqual } else { BinaryOperator::StringNotEqual };
+            let right = Box::new(pattern_word(lower_value(*right, span)?));
+            test(ASTNode::BinaryOp { left: Box::new(lower_value(*left, span)?), op, right }, true)
+        }
+        Expr::Binary(op @ ("<" | ">" | "<=" | ">="), left, right)
+            if matches!((left.as_ref(), right.as_ref()), (Expr::Word(_), Expr::Word(_))) =>
+        {
+            let op = arithmetic_operator(op)?;
+            test(ASTNode::BinaryOp { left: Box::new(lower_value(*left, span)?), op, right: Box::new(lower_value(*right, span)?) }, false)
+        }
+        expr => ASTNode::ArithmeticCommand(Box::new(to_arith(expr)?), span),
+    })
+}
+
+/// Lower an expression at `span` used as a word, such as the status of
+/// `exit`.
+fn lower_value(expr: Expr, span: Span) -> Result<ASTNode> {
+    Ok(match expr {
+        Expr::Word(word) => word,
+        Expr::IsSet(name, at) => is_set_word(&name, at),
+        expr => ASTNode::ArithmeticExpansion(Box::new(to_arith(expr)?), span),
+    })
+}
+
+/// Lower an expression used as arithmetic.
+fn to_arith(expr: Expr) -> Result<ASTNode> {
+    Ok(match expr {
+        Expr::Word(ASTNode::Number(n)) => ASTNode::Integer(n as i64),
+        Expr::Word(ASTNode::String(text, _, _)) if text.parse::<i64>().is_ok() => ASTNode::Integer(text.parse()?),
+        // Other words are evaluated at runtime, like `$(( $x ))`
+        Expr::Word(word) => word,
+        Expr::IsSet(name, at) => is_set_word(&name, at),
+        Expr::FileTest(..) | Expr::Command(_) => bail!("File tests and commands can't be used in arithmetic"),
+        Expr::Not(operand) => ASTNode::UnaryOp { op: UnaryOperator::Not, operand: Box::new(to_arith(*operand)?) },
+        Expr::Unary(op, operand) => ASTNode::UnaryOp { op, operand: Box::new(to_arith(*operand)?) },
+        Expr::Binary(op, left, right) => ASTNode::BinaryOp {
+            left: Box::new(to_arith(*left)?),
+            op: arithmetic_operator(op)?,
+            right: Box::new(to_arith(*right)?),
+        },
+    })
+}
+
+fn arithmetic_operator(op: &str) -> Result<BinaryOperator> {
+    Ok(match op {
+        "||" => BinaryOperator::Or,
+        "&&" => BinaryOperator::And,
+        "|" => BinaryOperator::BitOr,
+        "^" => BinaryOperator::BitXor,
+        "&" => BinaryOperator::BitAnd,
+        "==" => BinaryOperator::Equal,
+        "!=" => BinaryOperator::NotEqual,
+        "<=" => BinaryOperator::LessEqual,
+        ">=" => BinaryOperator::GreaterEqual,
+        "<" => BinaryOperator::Less,
+        ">" => BinaryOperator::Greater,
+        "<<" => BinaryOperator::ShiftLeft,
+        ">>" => BinaryOperator::ShiftRight,
+        "+" => BinaryOperator::Add,
+        "-" => BinaryOperator::Subtract,
+        "*" => BinaryOperator::Multiply,
+        "/" => BinaryOperator::Divide,
+        "%" => BinaryOperator::Modulo,
+        _ => bail!("`{}' can't be used in arithmetic", op),
+    })
+}
+
+/// The right side of `=~`, a pattern even where it is quoted.
+fn pattern_word(word: ASTNode) -> ASTNode {
+    match word {
+        ASTNode::String(text, _, span) => ASTNode::String(text, StringType::Unquoted, span),
+        ASTNode::Concat(parts, span) => ASTNode::Concat(parts.into_iter().map(|part| Box::new(pattern_word(*part))).collect(), span),
+        other => other,
+    }
+}
+
@@ -0,0 +1,1052 @@
+use super::arithmetic::parse_arithmetic;
+use super::ast::{
+    ASTNode, CaseItem, CaseTerminator, ExpansionType, ForItems, RedirectDirection, Redirection,
+    RedirectionTarget, Span, StringType, UnaryOperator, VariableAttributes,
+};
+use super::parser::{
+    ParseError, RecoveringParser, escape_glob, literal, merge_stderr, push_word_part, test_command,
+    unterminated_string,
+};
+use anyhow::{Result, bail};
+
+/// Words that are keywords when they start a command.
+const KEYWORDS: &[&str] = &[
+    "if", "else", "end", "while", "for", "switch", "case", "begin", "function",
+    "and", "or", "not", "!",
+];
+
+/// Parser for fish scripts.
+///
+/// Fish has its own grammar: blocks close with `end`, `(cmd)` is command
+/// substitution, `and`/`or` are commands that test the status of the one
+/// before them and variables are lists counted from 1. Scripts are lowered
+/// into the same AST as the POSIX shells so the generator handles them
+/// alike.
+pub struct FishParser {
+    chars: Vec<char>,
+    pos: usize,
+    // Position of `chars[pos]` in the script
+    span: Span,
+    // Errors recovered from so far, in script order
+    errors: Vec<ParseError>,
+    // Set once a bad statement read the rest of the script, after which
+    // errors about the missing rest would only repeat the first one
+    skipped_to_end: bool,
+    // Functions being parsed, in which an unscoped `set` makes a local
+    function_depth: usize,
+}
+
+impl FishParser {
+    pub fn new(input: &str) -> Self {
+        Self::starting_at(input, Span::new(1, 1))
+    }
+
+    /// A parser for text embedded in a script at `start`, such as the body
+    /// of a command substitution.
+    pub fn starting_at(input: &str, start: Span) -> Self {
+        FishParser {
+            chars: input.chars().collect(),
+            pos: 0,
+            span: start,
+            errors: Vec::new(),
+            skipped_to_end: false,
+            function_depth: 0,
+        }
+    }
+
+    /// Parse the script, recovering from syntax errors the way
+    /// `ShellParser::parse_with_diagnostics` does: a statement that fails
+    /// becomes an `ASTNode::Error` and parsing resumes after it.
+    pub fn parse_script(&mut self) -> (ASTNode, Vec<ParseError>) {
+        let statements = self.parse_block_until(&[]);
+        (ASTNode::Script(statements.into_iter().map(Box::new).collect()), std::mem::take(&mut self.errors))
+    }
+
+    /// Statements up to one of the keywords in `terminators`, which is left
+    /// for the caller, or the end of the input.
+    fn parse_block_until(&mut self, terminators: &[&str]) -> Vec<ASTNode> {
+        let mut statements = Vec::new();
+        loop {
+            self.skip_separators();
+            if self.at_end() || self.keyword().is_some_and(|keyword| terminators.contains(&keyword)) {
+                return statements;
+            }
+
+            let span = self.span;
+            if let Err(error) = self.parse_statement(&mut statements) {
+                let node = self.recover(span, error, |parser| {
+                    while !matches!(parser.peek(), None | Some(';' | '\n')) {
+                        parser.advance();
+                    }
+                });
+                statements.push(node);
+            }
+        }
+    }
+
+    /// Parse a statement onto `statements`. `and` and `or` run depending on
+    /// the status of the statement before them, so they take it over.
+    fn parse_statement(&mut self, statements: &mut Vec<ASTNode>) -> Result<()> {
+        match self.keyword() {
+            Some(keyword @ ("and" | "or")) => {
+                let Some(previous) = statements.pop() else {
+                    bail!("`{}' needs a command before it", keyword);
+                };
+                self.advance_by(keyword.len());
+                let (previous, next) = (Box::new(previous), Box::new(self.parse_job_list()?));
+                statements.push(if keyword == "and" {
+                    ASTNode::AndList(previous, next)
+                } else {
+                    ASTNode::OrList(previous, next)
+                });
+            }
+            _ => statements.push(self.parse_job_list()?),
+        }
+        self.end_statement()
+    }
+
+    /// A statement ends at `;`, a newline or the end of the input.
+    fn end_statement(&mut self) -> Result<()> {
+        self.skip_blanks();
+        match self.peek() {
+            None | Some(';' | '\n') => Ok(()),
+            Some(c) => bail!("Expected end of command, found `{}'", c),
+        }
+    }
+
+    /// Jobs joined by `&&` and `||`, grouping from the left.
+    fn parse_job_list(&mut self) -> Result<ASTNode> {
+        let mut left = self.parse_negated_job()?;
+        loop {
+            self.skip_blanks();
+            let is_and = if self.starts_with("&&") {
+                true
+            } else if self.starts_with("||") {
+                false
+            } else {
+                return Ok(left);
+            };
+            self.advance_by(2);
+            self.skip_separators();
+            let right = Box::new(self.parse_negated_job()?);
+            left = if is_and {
+                ASTNode::AndList(Box::new(left), right)
+            } else {
+                ASTNode::OrList(Box::new(left), right)
+            };
+        }
+    }
+
+    /// A pipeline, optionally preceded by `not` or `!`.
+    fn parse_negated_job(&mut self) -> Result<ASTNode> {
+        self.skip_blanks();
+        if let Some(keyword @ ("not" | "!")) = self.keyword() {
+            self.advance_by(keyword.len());
+            return Ok(ASTNode::Negated(Box::new(self.parse_negated_job()?)));
+        }
+        self.parse_pipeline()
+    }
+
+    fn parse_pipeline(&mut self) -> Result<ASTNode> {
+        let mut stages = vec![self.parse_stage()?];
+        loop {
+            self.skip_blanks();
+            // `a &| b` pipes stderr along with stdout
+            let merge = if self.starts_with("&|") {
+                true
+            } else if self.peek() == Some('|') && !self.starts_with("||") {
+                false
+            } else {
+                break;
+            };
+            self.advance_by(if merge { 2 } else { 1 });
+            if merge {
+                let stage = stages.pop().unwrap();
+                stages.push(merge_stderr(stage));
+            }
+            self.skip_separators();
+            stages.push(self.parse_stage()?);
+        }
+
+        if stages.len() == 1 {
+            Ok(stages.pop().unwrap())
+        } else {
+            Ok(ASTNode::Pipeline(stages.into_iter().map(Box::new).collect()))
+        }
+    }
+
+    fn parse_stage(&mut self) -> Result<ASTNode> {
+        self.skip_blanks();
+        let span = self.span;
+        let node = match self.keyword() {
+            Some("if") => self.parse_if()?,
+            Some("while") => self.parse_while()?,
+            Some("for") => self.parse_for()?,
+            Some("switch") => self.parse_switch()?,
+            Some("begin") => self.parse_begin()?,
+            Some("function") => self.parse_function()?,
+            Some(keyword @ ("end" | "else" | "case")) => {
+                let hint = match keyword {
+                    "end" => "there is no block open here",
+                    "else" => "this keyword is only valid in an `if` block",
+                    _ => "this keyword is only valid in a `switch` block",
+                };
+                return Err(ParseError {
+                    span,
+                    message: format!("Unexpected `{}'", keyword),
+                    hint: Some(hint.to_string()),
+                }.into());
+            }
+            _ => return self.parse_command(),
+        };
+
+        // Redirections after `end` apply to the whole block
+        let mut redirections = Vec::new();
+        loop {
+            self.skip_blanks();
+            if !self.at_redirection() {
+                break;
+            }
+            redirections.extend(self.parse_redirection()?);
+        }
+        if redirections.is_empty() {
+            Ok(node)
+        } else {
+            Ok(ASTNode::Redirected { body: Box::new(node), redirections })
+        }
+    }
+
+    fn parse_if(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        self.advance_by("if".len());
+        let condition = self.parse_condition()?;
+        let then_block = self.parse_body(&["else", "end"]);
+
+        let mut elif_blocks = Vec::new();
+        let mut else_block = None;
+        while self.keyword() == Some("else") {
+            self.advance_by("else".len());
+            self.skip_blanks();
+            if self.keyword() == Some("if") {
+                self.advance_by("if".len());
+                let condition = self.parse_condition()?;
+                let block = self.parse_body(&["else", "end"]);
+                elif_blocks.push((Box::new(condition), Box::new(block)));
+            } else {
+                else_block = Some(Box::new(self.parse_body(&["end"])));
+                break;
+            }
+        }
+        self.expect_end("if", span)?;
+
+        Ok(ASTNode::If {
+            condition: Box::new(condition),
+            then_block: Box::new(then_block),
+            elif_blocks,
+            else_block,
+            span,
+        })
+    }
+
+    fn parse_while(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        self.advance_by("while".len());
+        let condition = self.parse_condition()?;
+        let body = self.parse_body(&["end"]);
+        self.expect_end("while", span)?;
+
+        Ok(ASTNode::While {
+            condition: Box::new(condition),
+            body: Box::new(body),
+            span,
+        })
+    }
+
+    /// The condition of `if` and `while`. Lines starting with `and` or `or`
+    /// right after it still belong to the condition.
+    fn parse_condition(&mut self) -> Result<ASTNode> {
+        let mut condition = self.parse_job_list()?;
+        self.end_statement()?;
+        loop {
+            self.skip_separators();
+            let is_and = match self.keyword() {
+                Some("and") => true,
+                Some("or") => false,
+                _ => return Ok(condition),
+            };
+            self.advance_by(if is_and { 3 } else { 2 });
+            let next = Box::new(self.parse_job_list()?);
+            condition = if is_and {
+                ASTNode::AndList(Box::new(condition), next)
+            } else {
+                ASTNode::OrList(Box::new(condition), next)
+            };
+            self.end_statement()?;
+        }
+    }
+
+    fn parse_for(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        self.advance_by("for".len());
+        self.skip_blanks();
+        let variable = self.read_name();
+        if variable.is_empty() {
+            bail!("Expected a variable name after `for'");
+        }
+        self.skip_blanks();
+        if !self.starts_with("in") || self.chars.get(self.pos + 2).is_some_and(|&c| !is_word_end(c)) {
+            bail!("Expected `in' after `for {}'", variable);
+        }
+        self.advance_by("in".len());
+        let items = self.parse_words()?;
+        let body = self.parse_body(&["end"]);
+        self.expect_end("for", span)?;
+
+        Ok(ASTNode::For {
+            variable,
+            items: ForItems::List(items.into_iter().map(Box::new).collect()),
+            body: Box::new(body),
+            span,
+        })
+    }
+
+    fn parse_switch(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        self.advance_by("switch".len());
+        let mut words = self.parse_words()?;
+        if words.len() != 1 {
+            bail!("`switch' expects exactly one argument, found {}", words.len());
+        }
+        let expr = words.pop().unwrap();
+
+        let mut cases = Vec::new();
+        loop {
+            self.skip_separators();
+            if self.keyword() != Some("case") {
+                break;
+            }
+            self.advance_by("case".len());
+            let patterns = self.parse_words()?.iter().map(case_pattern).collect();
+            let body = self.parse_body(&["case", "end"]);
+            // Fish never falls through to the next case
+            cases.push(CaseItem { patterns, body: Box::new(body), terminator: CaseTerminator::Break });
+        }
+        self.expect_end("switch", span)?;
+
+        Ok(ASTNode::Case { expr: Box::new(expr), cases, span })
+    }
+
+    fn parse_begin(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        self.advance_by("begin".len());
+        let body = self.parse_body(&["end"]);
+        self.expect_end("begin", span)?;
+        Ok(body)
+    }
+
+    /// `function name [options]; ...; end`. Names given with
+    /// `--argument-names` become locals holding the positional parameters.
+    fn parse_function(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        self.advance_by("function".len());
+        let mut words = self.parse_words()?.into_iter();
+        let name = match words.next().as_ref().and_then(literal) {
+            Some(name) => name.to_string(),
+            None => bail!("Expected a function name after `function'"),
+        };
+
+        let mut argument_names = Vec::new();
+        let mut in_argument_names = false;
+        for word in words {
+            let Some(text) = literal(&word) else {
+                bail!("function {}: dynamic options are not supported", name);
+            };
+            match text {
+                "-a" | "--argument-names" => in_argument_names = true,
+                _ if text.starts_with('-') => in_argument_names = false,
+                _ if in_argument_names => argument_names.push(text.to_string()),
+                // The values of options such as --description
+                _ => {}
+            }
+        }
+
+        self.function_depth += 1;
+        let body = self.parse_body(&["end"]);
+        self.function_depth -= 1;
+        let mut body = match body {
+            ASTNode::Block(statements) => statements,
+            other => vec![Box::new(other)],
+        };
+        self.expect_end("function", span)?;
+
+        for (i, argument) in argument_names.into_iter().enumerate().rev() {
+            body.insert(0, Box::new(ASTNode::Assignment {
+                name: argument,
+                index: None,
+                value: Box::new(ASTNode::Variable((i + 1).to_string(), span)),
+                append: false,
+                export: false,
+                readonly: false,
+                local: true,
+                span,
+            }));
+        }
+
+        Ok(ASTNode::Function { name, body: Box::new(ASTNode::Block(body)), span })
+    }
+
+    fn parse_body(&mut self, terminators: &[&str]) -> ASTNode {
+        ASTNode::Block(self.parse_block_until(terminators).into_iter().map(Box::new).collect())
+    }
+
+    /// Consume the `end` of the block opened by `opener` at `span`.
+    fn expect_end(&mut self, opener: &str, span: Span) -> Result<()> {
+        if self.keyword() != Some("end") {
+            let found = match self.peek() {
+                Some(c) => format!("`{}'", c),
+                None => "end of script".to_string(),
+            };
+            return Err(ParseError {
+                span: self.span,
+                message: format!("Expected `end', found {}", found),
+                hint: Some(format!("the `{}` at {} is not closed", opener, span)),
+            }.into());
+        }
+        self.advance_by("end".len());
+        Ok(())
+    }
+
+    /// The words up to the end of the statement, for block headers.
+    fn parse_words(&mut self) -> Result<Vec<ASTNode>> {
+        let mut words = Vec::new();
+        loop {
+            self.skip_blanks();
+            if matches!(self.peek(), None | Some(';' | '\n')) {
+                return Ok(words);
+            }
+            words.push(self.parse_word()?);
+        }
+    }
+
+    fn parse_command(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        let mut words = Vec::new();
+        let mut redirections = Vec::new();
+        let mut background = false;
+
+        loop {
+            self.skip_blanks();
+            match self.peek() {
+                None | Some(';' | '\n' | '|' | ')') => break,
+                Some('&') if self.starts_with("&&") || self.starts_with("&|") => break,
+                Some('&') if !self.starts_with("&>") => {
+                    self.advance();
+                    background = true;
+                    break;
+                }
+                _ if self.at_redirection() => redirections.extend(self.parse_redirection()?),
+                _ => words.push(Box::new(self.parse_word()?)),
+            }
+        }
+
+        if words.is_empty() {
+            match self.peek() {
+                Some(c) => bail!("Expected a command, found `{}'", c),
+                None => bail!("Expected a command"),
+            }
+        }
+        let name = match literal(&words[0]) {
+            Some(name) => name.to_string(),
+            None => bail!("Dynamic command names are not supported"),
+        };
+        let args: Vec<Box<ASTNode>> = words.drain(1..).collect();
+
+        let plain = redirections.is_empty() && !background;
+        match name.as_str() {
+            "set" if plain => return lower_set(args.into_iter().map(|arg| *arg).collect(), self.function_depth > 0, span),
+            "return" if plain => return Ok(ASTNode::Return(args.into_iter().next())),
+            "exit" if plain => return Ok(ASTNode::Exit(args.into_iter().next())),
+            "break" if plain => return Ok(ASTNode::Break(1)),
+            "continue" if plain => return Ok(ASTNode::Continue(1)),
+            "test" | "[" if plain => {
+                return test_command(&name, args.into_iter().map(|arg| *arg).collect(), span);
+            }
+            _ => {}
+        }
+
+        Ok(ASTNode::Command { name, args, redirections, background, span })
+    }
+
+    /// Whether a redirection operator starts here: `<`, `>`, `>>`, `&>`, with
+    /// an optional file descriptor in front.
+    fn at_redirection(&self) -> bool {
+        let digits = self.chars[self.pos..].iter().take_while(|c| c.is_ascii_digit()).count();
+        match self.chars.get(self.pos + digits) {
+            Some('<' | '>') => true,
+            Some('&') => digits == 0 && self.chars.get(self.pos + 1) == Some(&'>'),
+            _ => false,
+        }
+    }
+
+    fn parse_redirection(&mut self) -> Result<Vec<Redirection>> {
+        let digits: String = self.chars[self.pos..].iter().take_while(|c| c.is_ascii_digit()).collect();
+        self.advance_by(digits.len());
+        let fd = if digits.is_empty() { None } else { Some(digits.parse::<i32>()?) };
+
+        if self.starts_with("&>") {
+            let append = self.starts_with("&>>");
+            self.advance_by(if append { 3 } else { 2 });
+            return Ok(vec![
+                Redirection {
+                    fd: Some(1),
+                    direction: RedirectDirection::Output,
+                    target: RedirectionTarget::File(Box::new(self.parse_redirection_word()?)),
+                    append,
+                },
+                Redirection {
+                    fd: Some(2),
+                    direction: RedirectDirection::Output,
+                    target: RedirectionTarget::Fd(1),
+                    append: false,
+                },
+            ]);
+        }
+
+        let (direction, append) = if self.starts_with(">>") {
+            self.advance_by(2);
+            (RedirectDirection::Output, true)
+        } else if self.starts_with(">") {
+            self.advance();
+            (RedirectDirection::Output, false)
+        } else {
+            self.advance();
+            (RedirectDirection::Input, false)
+        };
+
+        // `>?file` only refuses to overwrite an existing file
+        if direction == RedirectDirection::Output && self.peek() == Some('?') {
+            self.advance();
+        }
+
+        let target = if self.peek() == Some('&') {
+            self.advance();
+            let target: String = self.chars[self.pos..].iter().take_while(|c| c.is_ascii_digit()).collect();
+            if !target.is_empty() {
+                self.advance_by(target.len());
+                RedirectionTarget::Fd(target.parse()?)
+            } else if self.peek() == Some('-') {
+                self.advance();
+                RedirectionTarget::Close
+            } else {
+                bail!("Expected file descriptor after redirection");
+            }
+        } else {
+            RedirectionTarget::File(Box::new(self.parse_redirection_word()?))
+        };
+
+        Ok(vec![Redirection { fd, direction, target, append }])
+    }
+
+    fn parse_redirection_word(&mut self) -> Result<ASTNode> {
+        self.skip_blanks();
+        match self.peek() {
+            Some(c) if !is_word_end(c) => self.parse_word(),
+            Some(c) => bail!("Expected redirection target, found `{}'", c),
+            None => bail!("Expected redirection target"),
+        }
+    }
+
+    /// A word: literal text, quotes, variables and `(cmd)` substitutions
+    /// up to the next blank or operator.
+    ///
+    /// Unquoted `*` and `?` stay glob characters. Everything fish takes
+    /// literally but other shells would not (quoted and escaped text, `[`
+    /// and `]`) becomes single-quoted parts.
+    fn parse_word(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        let mut parts = Vec::new();
+        while let Some(c) = self.peek() {
+            let start = self.span;
+            match c {
+                _ if is_word_end(c) => break,
+                '\'' => {
+                    let text = self.read_single_quoted()?;
+                    parts.push(ASTNode::String(text, StringType::SingleQuoted, start));
+                }
+                '"' => self.parse_double_quoted(&mut parts)?,
+                '$' => {
+                    let node = self.parse_variable()?;
+                    push_word_part(&mut parts, node);
+                }
+                '(' => {
+                    let node = self.parse_substitution()?;
+                    parts.push(node);
+                }
+                '\\' => {
+                    self.advance();
+                    match self.advance() {
+                        // A line continuation inside a word
+                        Some('\n') => {}
+                        Some(c) => parts.push(ASTNode::String(unescape(c, self).to_string(), StringType::SingleQuoted, start)),
+                        None => parts.push(ASTNode::String("\\".to_string(), StringType::SingleQuoted, start)),
+                    }
+                }
+                '[' | ']' => {
+                    self.advance();
+                    parts.push(ASTNode::String(c.to_string(), StringType::SingleQuoted, start));
+                }
+                _ => {
+                    self.advance();
+                    push_word_part(&mut parts, ASTNode::String(c.to_string(), StringType::Unquoted, start));
+                }
+            }
+        }
+
+        match parts.len() {
+            0 => bail!("Expected a word"),
+            1 => Ok(match parts.pop().unwrap() {
+                ASTNode::String(text, StringType::Unquoted, _) if !text.is_empty() && text.chars().all(|c| c.is_ascii_digit()) => {
+                    ASTNode::Number(text.parse()?)
+                }
+                part => part,
+            }),
+            _ => Ok(ASTNode::Concat(parts.into_iter().map(Box::new).collect(), span)),
+        }
+    }
+
+    /// `'...'`, where only `\'` and `\\` are escapes.
+    fn read_single_quoted(&mut self) -> Result<String> {
+        let start = self.span;
+        self.advance();
+        let mut text = String::new();
+        loop {
+            match self.advance() {
+                Some('\'') => return Ok(text),
+                Some('\\') if matches!(self.peek(), Some('\'' | '\\')) => text.push(self.advance().unwrap()),
+                Some(c) => text.push(c),
+                None => return Err(unterminated_string(start)),
+            }
+        }
+    }
+
+    /// `"..."`, where variables and `$(cmd)` expand and `\"`, `\$` and `\\`
+    /// are escapes.
+    fn parse_double_quoted(&mut self, parts: &mut Vec<ASTNode>) -> Result<()> {
+        let start = self.span;
+        self.advance();
+        let mut text = String::new();
+        let mut text_start = self.span;
+        let mut empty = true;
+        loop {
+            if text.is_empty() {
+                text_start = self.span;
+            }
+            match self.peek() {
+                Some('"') => {
+                    self.advance();
+                    break;
+                }
+                Some('\\') => {
+                    self.advance();
+                    match self.peek() {
+                        Some(c @ ('"' | '$' | '\\')) => {
+                            self.advance();
+                            text.push(c);
+                        }
+                        Some('\n') => {
+                            self.advance();
+                        }
+                        _ => text.push('\\'),
+                    }
+                }
+                Some('$') if self.chars.get(self.pos + 1).is_some_and(|&c| c == '(' || is_name_char(c)) => {
+                    if !text.is_empty() {
+                        parts.push(ASTNode::String(std::mem::take(&mut text), StringType::SingleQuoted, text_start));
+                    }
+                    parts.push(self.parse_variable()?);
+                    empty = false;
+                }
+                Some(c) => {
+                    self.advance();
+                    text.push(c);
+                }
+                None => return Err(unterminated_string(start)),
+            }
+        }
+
+        if !text.is_empty() {
+            parts.push(ASTNode::String(text, StringType::SingleQuoted, text_start));
+        } else if empty {
+            parts.push(ASTNode::String(text, StringType::SingleQuoted, start));
+        }
+        Ok(())
+    }
+
+    /// `$name`, `$name[index]` or `$(cmd)`. `$argv` is the positional
+    /// parameters and `$status` the exit status.
+    fn parse_variable(&mut self) -> Result<ASTNode> {
+        let span = self.span;
+        self.advance();
+        if self.peek() == Some('(') {
+            return self.parse_substitution();
+        }
+
+        let name = self.read_name();
+        if name.is_empty() {
+            if self.peek() == Some('$') {
+                bail!("Indirect variables (`$$name') are not supported");
+            }
+            // A lone `$` is literal
+            return Ok(ASTNode::String("$".to_string(), StringType::Unquoted, span));
+        }
+        let name = match name.as_str() {
+            "argv" => "@".to_string(),
+            "status" => "?".to_string(),
+            _ => name,
+        };
+
+        if self.peek() != Some('[') {
+            return Ok(ASTNode::Variable(name, span));
+        }
+        self.advance();
+        let index_start = self.span;
+        let mut index = String::new();
+        loop {
+            match self.advance() {
+                Some(']') => break,
+                Some(c) => index.push(c),
+                None => bail!("Unterminated index of `${}'", name),
+            }
+        }
+
+        if index.contains("..") {
+            bail!("`{}[{}]': index ranges are not supported", name, index);
+        }
+        if name == "@" {
+            if let Ok(n @ 1..) = index.trim().parse::<usize>() {
+                return Ok(ASTNode::Variable(n.to_string(), span));
+            }
+        }
+        // Indexes are arithmetic on plain names; `$i` is just `i` there
+        let index = parse_arithmetic(&index.replace('$', ""), index_start)?;
+        Ok(ASTNode::ParameterExpansion { name, expansion_type: ExpansionType::Element(Box::new(index)), span })
+    }
+
+    /// `(cmd)`, parsed as a script of its own but located in this one.
+    fn parse_substitution(&mut self) -> Result<ASTNode> {
+        let open = self.span;
+        self.advance();
+        let start = self.span;
+        let mut body = String::new();
+        let mut depth = 1;
+        loop {
+            let Some(c) = self.advance() else {
+                return Err(ParseError {
+                    span: open,
+                    message: "Unterminated command substitution".to_string(),
+                    hint: Some("add the missing `)`".to_string()),
+                }.into());
+            };
+            match c {
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                '\\' => {
+                    body.push(c);
+                    if let Some(next) = self.advance() {
+                        body.push(next);
+                    }
+                    continue;
+                }
+                '\'' | '"' => {
+                    // Parentheses in quotes don't count
+                    body.push(c);
+                    while let Some(next) = self.advance() {
+                        body.push(next);
+                        if next == '\\' {
+                            if let Some(escaped) = self.advance() {
+                                body.push(escaped);
+                            }
+                        } else if next == c {
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            body.push(c);
+        }
+
+        let mut parser = FishParser::starting_at(&body, start);
+        let (root, errors) = parser.parse_script();
+        for mut error in errors {
+            error.message = format!("in command substitution `{}`: {}", body.trim(), error.message);
+            self.errors.push(error);
+        }
+        let statements = match root {
+            ASTNode::Script(statements) => statements,
+            other => vec![Box::new(other)],
+        };
+        Ok(ASTNode::CommandSubstitution(Box::new(ASTNode::Block(statements)), open))
+    }
+
+    /// The keyword starting at the current position, if any.
+    fn keyword(&self) -> Option<&'static str> {
+        KEYWORDS.iter().copied().find(|keyword| {
+            self.starts_with(keyword) && self.chars.get(self.pos + keyword.len()).is_none_or(|&c| is_word_end(c))
+        })
+    }
+
+    fn read_name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek().filter(|&c| is_name_char(c)) {
+            self.advance();
+            name.push(c);
+        }
+        name
+    }
+
+    /// Skip blanks, line continuations and comments, but not newlines.
+    fn skip_blanks(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ' | '\t' | '\r') => {
+                    self.advance();
+                }
+                Some('\\') if self.chars.get(self.pos + 1) == Some(&'\n') => self.advance_by(2),
+                Some('#') => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.advance();
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Skip blanks and statement separators.
+    fn skip_separators(&mut self) {
+        loop {
+            self.skip_blanks();
+            if !matches!(self.peek(), Some(';' | '\n')) {
+                return;
+            }
+            self.advance();
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, text: &str) -> bool {
+        text.chars().enumerate().all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        self.span = match c {
+            '\n' => Span::new(self.span.line + 1, 1),
+            _ => Span::new(self.span.line, self.span.column + 1),
+        };
+        Some(c)
+    }
+
+    fn advance_by(&mut self, count: usize) {
+        for _ in 0..count {
+            self.advance();
+        }
+    }
+}
+
+impl RecoveringParser for FishParser {
+    fn error_span(&self) -> Span {
+        self.span
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.chars.len()
+    }
+
+    fn errors_mut(&mut self) -> &mut Vec<ParseError> {
+        &mut self.errors
+    }
+
+    fn skipped_to_end_mut(&mut self) -> &mut bool {
+        &mut self.skipped_to_end
+    }
+}
+
+/// Whether `c` ends an unquoted word.
+fn is_word_end(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ';' | '|' | '&' | '<' | '>' | ')')
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// The character a backslash escape outside quotes stands for. `\xHH`
+/// reads its hex digits from `parser`.
+fn unescape(c: char, parser: &mut FishParser) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        'a' => '\x07',
+        'b' => '\x08',
+        'e' => '\x1b',
+        'x' | 'X' => {
+            let mut value = 0;
+            for _ in 0..2 {
+                match parser.peek().and_then(|c| c.to_digit(16)) {
+                    Some(digit) => {
+                        parser.advance();
+                        value = value * 16 + digit;
+                    }
+                    None => break,
+                }
+            }
+            char::from_u32(value).unwrap_or(c)
+        }
+        _ => c,
+    }
+}
+
+/// Word of a `case` pattern. Fish matches `*` and `?` in a pattern even when
+/// they are quoted or escaped, and everything else literally.
+fn case_pattern(word: &ASTNode) -> ASTNode {
+    match word {
+        ASTNode::String(text, _, span) => {
+            let glob = text.chars()
+                .map(|c| match c {
+                    '*' | '?' => c.to_string(),
+                    _ => escape_glob(&c.to_string()),
+                })
+                .collect();
+            ASTNode::String(glob, StringType::Unquoted, *span)
+        }
+        ASTNode::Concat(parts, span) => ASTNode::Concat(parts.iter().map(|part| Box::new(case_pattern(part))).collect(), *span),
+        other => other.clone(),
+    }
+}
+
+/// Lower fish's `set` into an assignment. `-e` becomes `unset` and `-q` a
+/// `test -v`; list values become arrays.
+fn lower_set(args: Vec<ASTNode>, in_function: bool, span: Span) -> Result<ASTNode> {
+    let mut local = false;
+    let mut global = false;
+    let mut export = false;
+    let mut erase = false;
+    let mut query = false;
+    let mut append = false;
+    let mut prepend = false;
+
+    let mut args = args.into_iter().peekable();
+    while let Some(option) = args.peek().and_then(|arg| literal(arg)).filter(|arg| arg.starts_with('-')) {
+        let option = option.to_string();
+        args.next();
+        let flags: Vec<char> = match option.as_str() {
+            "--" => break,
+            "--local" => vec!['l'],
+            "--function" => vec!['f'],
+            "--global" => vec!['g'],
+            "--universal" => vec!['U'],
+            "--unexport" => vec![],
+            "--export" => vec!['x'],
+            "--erase" => vec!['e'],
+            "--query" => vec!['q'],
+            "--append" => vec!['a'],
+            "--prepend" => vec!['p'],
+            _ if option.starts_with("--") => bail!("set: unsupported option `{}'", option),
+            _ => option[1..].chars().collect(),
+        };
+        for flag in flags {
+            match flag {
+                'l' | 'f' => local = true,
+                'g' | 'U' => global = true,
+                'x' => export = true,
+                'e' => erase = true,
+                'q' => query = true,
+                'a' => append = true,
+                'p' => prepend = true,
+                'u' => {}
+                _ => bail!("set: unsupported option `-{}'", flag),
+            }
+        }
+    }
+
+    let (name, name_span) = match args.next() {
+        Some(word) => match literal(&word) {
+            Some(name) if !name.is_empty() && name.chars().all(is_name_char) => (name.to_string(), word.span().unwrap_or(span)),
+            Some(name) => bail!("set: `{}' is not a variable name this converter supports", name),
+            None => bail!("set: dynamic variable names are not supported"),
+        },
+        None => bail!("set: listing variables is not supported"),
+    };
+    let name_word = || Box::new(ASTNode::String(name.clone(), StringType::Unquoted, name_span));
+
+    if erase {
+        return Ok(ASTNode::Command {
+            name: "unset".to_string(),
+            args: vec![name_word()],
+            redirections: Vec::new(),
+            background: false,
+            span,
+        });
+    }
+    if query {
+        let expr = ASTNode::UnaryOp { op: UnaryOperator::VariableSet, operand: name_word() };
+        return Ok(ASTNode::Test { expr: Box::new(expr), extended: false, span });
+    }
+
+    let mut values: Vec<Box<ASTNode>> = args.map(Box::new).collect();
+    if append {
+        values.insert(0, Box::new(ASTNode::Variable(name.clone(), name_span)));
+    }
+    if prepend {
+        values.push(Box::new(ASTNode::Variable(name.clone(), name_span)));
+    }
+    // `(cmd)` is a list of the lines of the output
+    let value = if values.len() == 1 && !append && !prepend && !matches!(values[0].as_ref(), ASTNode::CommandSubstitution(_, _)) {
+        values.pop().unwrap()
+    } else {
+        Box::new(ASTNode::Array(values))
+    };
+
+    let assignment = ASTNode::Assignment { name: name.clone(), index: None, value, append: false, export, readonly: false, local, span };
+    if !in_function || local || global {
+        return Ok(assignment);
+    }
+    // Without a scope a function sets the variable it can see, or else
+    // makes a local one
+    let unset = ASTNode::UnaryOp {
+        op: UnaryOperator::Not,
+        operand: Box::new(ASTNode::UnaryOp { op: UnaryOperator::VariableSet, operand: name_word() }),
+    };
+    let declare = ASTNode::Declaration {
+        builtin: "local".to_string(),
+        attributes: VariableAttributes::default(),
+        removed: VariableAttributes::default(),
+        local: true,
+        options: String::new(),
+        variables: vec![name_word()],
+        span,
+    };
+    Ok(ASTNode::Block(vec![
+        Box::new(ASTNode::If {
+            condition: Box::new(ASTNode::Test { expr: Box::new(unset), extended: false, span }),
+            then_block: Box::new(declare),
+            elif_blocks: Vec::new(),
+            else_block: None,
+            span,
+        }),
+        Box::new(assignment),
+    ]))
+}
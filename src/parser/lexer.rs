@@ -1,7 +1,9 @@
+use super::ast::Span;
 use super::shell_dialect::ShellDialect;
 use anyhow::{Result, bail};
 use std::str::Chars;
 use std::iter::Peekable;
+use std::collections::VecDeque;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
@@ -16,6 +18,9 @@ pub enum Token {
     Redirect(RedirectOp),
     Background,            // &
     Semicolon,             // ;
+    DoubleSemicolon,       // ;;
+    SemicolonAnd,          // ;&
+    DoubleSemicolonAnd,    // ;;&
     Newline,
     
     // Logical operators
@@ -43,6 +48,7 @@ pub enum Token {
     
     // Grouping
     LeftParen,             // (
+    DoubleLeftParen,       // ((
     RightParen,            // )
     LeftBrace,             // {
     RightBrace,            // }
@@ -56,6 +62,9 @@ pub enum Token {
     DollarBrace,           // ${
     DollarParen,           // $(
     DollarDoubleParen,     // $((
+    EqualParen,            // =( in zsh
+    LessParen,             // <( process substitution
+    GreaterParen,          // >( process substitution
     Backtick,              // `
     AtSign,                // @
     Hash,                  // #
@@ -76,6 +85,8 @@ pub enum Token {
     Until,
     Function,
     Return,
+    Break,
+    Continue,
     Export,
     Local,
     Readonly,
@@ -84,6 +95,7 @@ pub enum Token {
     Let,
     Select,
     Time,
+    Repeat,                // zsh only
     
     // Built-ins
     Echo,
@@ -102,6 +114,7 @@ pub enum Token {
     Question,              // ?
     Tilde,                 // ~
     Heredoc(String),       // <<EOF
+    GlobQualifiers(String),  // (.om) after a glob in zsh
     HereString,            // <<<
     
     // End of input
@@ -119,50 +132,219 @@ pub enum QuoteType {
 #[derive(Debug, Clone, PartialEq)]
 pub enum RedirectOp {
     Out,           // >
+    Clobber,       // >|
     OutAppend,     // >>
     In,            // <
     InOut,         // <>
     OutErr,        // >&
     ErrOut,        // 2>&1
+    InDup,         // <&
+    AllOut,        // &>
+    AllAppend,     // &>>
     HereDoc,       // <<
     HereString,    // <<<
 }
 
+impl RedirectOp {
+    /// The operator as it is written.
+    pub fn text(&self) -> &'static str {
+        match self {
+            RedirectOp::Out => ">",
+            RedirectOp::Clobber => ">|",
+            RedirectOp::OutAppend => ">>",
+            RedirectOp::In => "<",
+            RedirectOp::InOut => "<>",
+            RedirectOp::OutErr => ">&",
+            RedirectOp::ErrOut => "2>&1",
+            RedirectOp::InDup => "<&",
+            RedirectOp::AllOut => "&>",
+            RedirectOp::AllAppend => "&>>",
+            RedirectOp::HereDoc => "<<",
+            RedirectOp::HereString => "<<<",
+        }
+    }
+}
+
+/// Tokens in messages, as they are written in the script.
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Token::Eof => return f.write_str("end of file"),
+            Token::Newline => return f.write_str("end of line"),
+            Token::String(text, QuoteType::Single) => return write!(f, "'{}'", text),
+            Token::String(text, QuoteType::Double) => return write!(f, "\"{}\"", text),
+            Token::String(text, QuoteType::Backtick) => return write!(f, "`{}`", text),
+            Token::String(text, QuoteType::Ansi) => return write!(f, "$'{}'", text),
+            Token::Heredoc(delimiter) => return write!(f, "`<<{}'", delimiter),
+            Token::GlobQualifiers(qualifiers) => return write!(f, "`({})'", qualifiers),
+            Token::Word(text) | Token::Number(text) => text.as_str(),
+            Token::Redirect(op) => op.text(),
+            Token::Pipe => "|",
+            Token::PipeErr => "|&",
+            Token::Background => "&",
+            Token::Semicolon => ";",
+            Token::DoubleSemicolon => ";;",
+            Token::SemicolonAnd => ";&",
+            Token::DoubleSemicolonAnd => ";;&",
+            Token::And => "&&",
+            Token::Or => "||",
+            Token::Assign => "=",
+            Token::PlusAssign => "+=",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::Equal => "==",
+            Token::NotEqual => "!=",
+            Token::Less => "<",
+            Token::Greater => ">",
+            Token::LessEqual => "<=",
+            Token::GreaterEqual => ">=",
+            Token::LeftParen => "(",
+            Token::DoubleLeftParen => "((",
+            Token::RightParen => ")",
+            Token::LeftBrace => "{",
+            Token::RightBrace => "}",
+            Token::LeftBracket => "[",
+            Token::RightBracket => "]",
+            Token::DoubleLeftBracket => "[[",
+            Token::DoubleRightBracket => "]]",
+            Token::Dollar => "$",
+            Token::DollarBrace => "${",
+            Token::DollarParen => "$(",
+            Token::DollarDoubleParen => "$((",
+            Token::EqualParen => "=(",
+            Token::LessParen => "<(",
+            Token::GreaterParen => ">(",
+            Token::Backtick => "`",
+            Token::AtSign => "@",
+            Token::Hash => "#",
+            Token::If => "if",
+            Token::Then => "then",
+            Token::Else => "else",
+            Token::Elif => "elif",
+            Token::Fi => "fi",
+            Token::Case => "case",
+            Token::Esac => "esac",
+            Token::For => "for",
+            Token::In => "in",
+            Token::Do => "do",
+            Token::Done => "done",
+            Token::While => "while",
+            Token::Until => "until",
+            Token::Function => "function",
+            Token::Return => "return",
+            Token::Break => "break",
+            Token::Continue => "continue",
+            Token::Export => "export",
+            Token::Local => "local",
+            Token::Readonly => "readonly",
+            Token::Declare => "declare",
+            Token::Typeset => "typeset",
+            Token::Let => "let",
+            Token::Select => "select",
+            Token::Time => "time",
+            Token::Repeat => "repeat",
+            Token::Echo => "echo",
+            Token::Printf => "printf",
+            Token::Read => "read",
+            Token::Cd => "cd",
+            Token::Pwd => "pwd",
+            Token::Exit => "exit",
+            Token::Source => "source",
+            Token::Dot => ".",
+            Token::Exec => "exec",
+            Token::Eval => "eval",
+            Token::Bang => "!",
+            Token::Question => "?",
+            Token::Tilde => "~",
+            Token::HereString => "<<<",
+        };
+        write!(f, "`{}'", text)
+    }
+}
+
+/// A here-document: the body read for a `<<` redirection from the lines
+/// after the one it is on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heredoc {
+    pub delimiter: String,
+    pub content: String,   // with leading tabs removed for `<<-`
+    pub strip_tabs: bool,
+    pub quoted: bool,      // part of the delimiter was quoted, so no expansion
+    pub span: Span,        // where the body starts
+}
+
+// Where the script continues after the here-document bodies that follow
+// the newline at offset `from`
+#[derive(Debug, Clone, Copy)]
+struct HeredocSkip {
+    from: usize,
+    to: usize,
+    line: usize,
+}
+
 pub struct Lexer<'a> {
+    source: &'a str,
     input: Peekable<Chars<'a>>,
     current_char: Option<char>,
     position: usize,
+    offset: usize,
     line: usize,
     column: usize,
     dialect: ShellDialect,
+    token_start: usize,
+    token_end: usize,
+    token_span: Span,
+    space_before: bool,
+    // Bodies of the `<<` redirections lexed so far that the parser has not
+    // taken yet, and the text they occupy
+    heredocs: VecDeque<Heredoc>,
+    heredoc_skip: Option<HeredocSkip>,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str, dialect: ShellDialect) -> Self {
+        Self::starting_at(input, dialect, Span::new(1, 1))
+    }
+    
+    /// Lex `input` as text that starts at `start` in the script, so that spans
+    /// of an embedded body like `$(...)` point into the enclosing script.
+    pub fn starting_at(input: &'a str, dialect: ShellDialect, start: Span) -> Self {
         let mut lexer = Lexer {
+            source: input,
             input: input.chars().peekable(),
             current_char: None,
-            position: 0,
-            line: 1,
-            column: 0,
+            position: 1,
+            offset: 0,
+            line: start.line,
+            column: start.column,
             dialect,
+            token_start: 0,
+            token_end: 0,
+            token_span: start,
+            space_before: true,
+            heredocs: VecDeque::new(),
+            heredoc_skip: None,
         };
-        lexer.advance();
+        lexer.current_char = lexer.input.next();
         lexer
     }
     
+    // `line` and `column` are the position of `current_char`
     fn advance(&mut self) {
-        self.current_char = self.input.next();
-        self.position += 1;
-        
         if let Some(ch) = self.current_char {
+            self.offset += ch.len_utf8();
             if ch == '\n' {
                 self.line += 1;
-                self.column = 0;
+                self.column = 1;
             } else {
                 self.column += 1;
             }
         }
+        self.current_char = self.input.next();
+        self.position += 1;
     }
     
     fn peek(&mut self) -> Option<&char> {
@@ -181,6 +363,10 @@ impl<'a> Lexer<'a> {
         while let Some(ch) = self.current_char {
             if ch.is_whitespace() && ch != '\n' {
                 self.advance();
+            } else if ch == '\\' && self.peek() == Some(&'\n') {
+                // A line continuation
+                self.advance();
+                self.advance();
             } else {
                 break;
             }
@@ -197,6 +383,154 @@ impl<'a> Lexer<'a> {
         }
     }
     
+    /// Whether the current character would begin a new word: it follows
+    /// whitespace, an operator character or the start of input.
+    fn at_word_start(&self) -> bool {
+        self.source[..self.offset]
+            .chars()
+            .next_back()
+            .is_none_or(|c| c.is_whitespace() || matches!(c, ';' | '|' | '&' | '(' | ')' | '<' | '>'))
+    }
+    
+    /// The qualifiers of a zsh glob like `*.log(.om)` when the current `(`
+    /// starts them: a group without `|` that ends a word containing `*`, `?`
+    /// or `[`.
+    fn glob_qualifiers(&self) -> Option<String> {
+        let before = &self.source[..self.offset];
+        let word_start = before
+            .char_indices()
+            .rfind(|&(_, c)| c.is_whitespace() || matches!(c, ';' | '|' | '&' | '(' | ')' | '<' | '>'))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        if !before[word_start..].contains(['*', '?', '[']) {
+            return None;
+        }
+        
+        let rest = &self.source[self.offset + 1..];
+        let close = rest.find(')')?;
+        let qualifiers = &rest[..close];
+        let word_ends = rest[close + 1..]
+            .chars()
+            .next()
+            .is_none_or(|c| c.is_whitespace() || matches!(c, ';' | '|' | '&' | ')' | '<' | '>'));
+        if !word_ends || qualifiers.contains(['(', '|']) || qualifiers.contains(char::is_whitespace) {
+            return None;
+        }
+        Some(qualifiers.to_string())
+    }
+    
+    /// Read the delimiter of a here-document after `<<` and its body.
+    ///
+    /// The body starts on the line after the current one, or after the
+    /// bodies of earlier `<<` on the same line, and ends at a line that is
+    /// exactly the delimiter. The lexer skips over it when it reaches the end
+    /// of the current line.
+    fn read_heredoc(&mut self) -> Heredoc {
+        let strip_tabs = self.current_char == Some('-');
+        if strip_tabs {
+            self.advance();
+        }
+        self.skip_whitespace();
+        
+        // Quoting any part of the delimiter turns off expansion in the body
+        let mut delimiter = String::new();
+        let mut quoted = false;
+        while let Some(ch) = self.current_char {
+            match ch {
+                '\'' | '"' => {
+                    quoted = true;
+                    self.advance();
+                    while let Some(c) = self.current_char {
+                        self.advance();
+                        if c == ch {
+                            break;
+                        }
+                        delimiter.push(c);
+                    }
+                }
+                '\\' => {
+                    quoted = true;
+                    self.advance();
+                    if let Some(c) = self.current_char {
+                        delimiter.push(c);
+                        self.advance();
+                    }
+                }
+                c if c.is_whitespace() || matches!(c, ';' | '|' | '&' | '<' | '>' | '(' | ')') => break,
+                c => {
+                    delimiter.push(c);
+                    self.advance();
+                }
+            }
+        }
+        
+        let (start, line, from) = match self.heredoc_skip {
+            Some(skip) => (skip.to, skip.line, skip.from),
+            None => match self.line_end() {
+                Some((newline, line)) => (newline + 1, line + 1, newline),
+                // Nothing follows the current line
+                None => (self.source.len(), self.line, self.source.len()),
+            },
+        };
+        
+        let mut content = String::new();
+        let mut end = start;
+        let mut end_line = line;
+        for text in self.source[start..].split_inclusive('\n') {
+            end += text.len();
+            end_line += 1;
+            let text = text.strip_suffix('\n').unwrap_or(text);
+            let text = if strip_tabs { text.trim_start_matches('\t') } else { text };
+            if text == delimiter {
+                break;
+            }
+            content.push_str(text);
+            content.push('\n');
+        }
+        
+        self.heredoc_skip = Some(HeredocSkip { from, to: end, line: end_line });
+        Heredoc { delimiter, content, strip_tabs, quoted, span: Span::new(line, 1) }
+    }
+    
+    /// Offset of the newline that ends the current line and the line number
+    /// it is on, which is later than the current one when a quoted string
+    /// spans lines.
+    fn line_end(&self) -> Option<(usize, usize)> {
+        let mut line = self.line;
+        let mut quote = None;
+        let mut prev = ' ';
+        let mut chars = self.source[self.offset..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\n' if quote.is_none() => return Some((self.offset + i, line)),
+                '\n' => line += 1,
+                '\\' if quote != Some('\'') => {
+                    if let Some((_, '\n')) = chars.next() {
+                        line += 1;
+                    }
+                }
+                '\'' | '"' if quote.is_none() => quote = Some(c),
+                _ if quote == Some(c) => quote = None,
+                // A comment runs to the end of the line, quotes and all
+                '#' if quote.is_none() && prev.is_whitespace() => {
+                    let rest = &self.source[self.offset + i..];
+                    return rest.find('\n').map(|end| (self.offset + i + end, line));
+                }
+                _ => {}
+            }
+            prev = c;
+        }
+        None
+    }
+    
+    /// Continue lexing at byte `offset`, which is on `line`.
+    fn jump_to(&mut self, offset: usize, line: usize) {
+        self.input = self.source[offset..].chars().peekable();
+        self.current_char = self.input.next();
+        self.offset = offset;
+        self.line = line;
+        self.column = 1;
+    }
+    
     fn read_word(&mut self) -> String {
         let mut word = String::new();
         
@@ -235,18 +569,36 @@ impl<'a> Lexer<'a> {
                     string.push(escaped);
                     self.advance();
                 }
+            } else if ch == '$' && quote_type == QuoteType::Double && matches!(self.peek(), Some('(' | '{')) {
+                // Quotes inside `"$(...)"` and `"${...}"` don't end the string
+                let open = *self.peek().unwrap();
+                self.advance();
+                self.advance();
+                string.push('$');
+                string.push(open);
+                if open == '(' {
+                    string.push_str(&self.read_paren_body()?);
+                    string.push(')');
+                } else {
+                    string.push_str(&self.read_brace_body()?);
+                    string.push('}');
+                }
+            } else if ch == '`' && quote_type == QuoteType::Double {
+                let (body, _) = self.read_string('`')?;
+                string.push('`');
+                string.push_str(&body);
+                string.push('`');
             } else {
                 string.push(ch);
                 self.advance();
             }
         }
         
-        bail!("Unterminated string at line {}, column {}", self.line, self.column)
+        bail!("Unterminated string")
     }
     
     fn read_ansi_string(&mut self) -> Result<(String, QuoteType)> {
-        self.advance(); // Skip $
-        self.advance(); // Skip '
+        self.advance(); // Skip ' (the $ was already consumed)
         
         let mut string = String::new();
         
@@ -280,7 +632,243 @@ impl<'a> Lexer<'a> {
             }
         }
         
-        bail!("Unterminated ANSI string at line {}, column {}", self.line, self.column)
+        bail!("Unterminated ANSI string")
+    }
+    
+    /// Read the raw body of a `${...}` expansion up to the matching `}`.
+    ///
+    /// Must be called right after `Token::DollarBrace` has been returned. Nested
+    /// `${...}`, quotes and backslash escapes are skipped over so that forms like
+    /// `${a:-${b:-"}"}}` are captured whole. The closing brace is consumed.
+    pub fn read_brace_body(&mut self) -> Result<String> {
+        let mut body = String::new();
+        let mut depth = 0;
+        
+        while let Some(ch) = self.current_char {
+            match ch {
+                '}' if depth == 0 => {
+                    self.advance();
+                    return Ok(body);
+                }
+                '}' => {
+                    depth -= 1;
+                    body.push(ch);
+                    self.advance();
+                }
+                '$' if self.peek() == Some(&'{') => {
+                    depth += 1;
+                    body.push_str("${");
+                    self.advance();
+                    self.advance();
+                }
+                '\\' => {
+                    body.push(ch);
+                    self.advance();
+                    if let Some(escaped) = self.current_char {
+                        body.push(escaped);
+                        self.advance();
+                    }
+                }
+                '\'' | '"' => {
+                    let (string, _) = self.read_string(ch)?;
+                    body.push(ch);
+                    body.push_str(&string);
+                    body.push(ch);
+                }
+                _ => {
+                    body.push(ch);
+                    self.advance();
+                }
+            }
+        }
+        
+        bail!("Unterminated parameter expansion")
+    }
+    
+    /// Read the raw text of an array subscript up to the matching `]`.
+    ///
+    /// Must be called right after `Token::LeftBracket` has been returned.
+    /// Nested brackets are balanced and quoted strings are kept with their
+    /// quotes. The closing bracket is consumed.
+    pub fn read_subscript_body(&mut self) -> Result<String> {
+        let mut body = String::new();
+        let mut depth = 0;
+        
+        while let Some(ch) = self.current_char {
+            match ch {
+                ']' if depth == 0 => {
+                    self.advance();
+                    return Ok(body);
+                }
+                '[' | ']' => {
+                    depth += if ch == '[' { 1 } else { -1 };
+                    body.push(ch);
+                    self.advance();
+                }
+                '\\' => {
+                    body.push(ch);
+                    self.advance();
+                    if let Some(escaped) = self.current_char {
+                        body.push(escaped);
+                        self.advance();
+                    }
+                }
+                '\'' | '"' => {
+                    let (string, _) = self.read_string(ch)?;
+                    body.push(ch);
+                    body.push_str(&string);
+                    body.push(ch);
+                }
+                _ => {
+                    body.push(ch);
+                    self.advance();
+                }
+            }
+        }
+        
+        bail!("Unterminated array subscript")
+    }
+    
+    /// Whether an array element assignment like `name[key]=value` or
+    /// `name[key]+=value` continues at the current character, which is the
+    /// `[` after the name or, in `([key]=value)`, just after it.
+    pub fn at_subscript_assignment(&self) -> bool {
+        let rest = &self.source[self.offset..];
+        let rest = rest.strip_prefix('[').unwrap_or(rest);
+        let mut depth = 0;
+        let mut quote = None;
+        let mut chars = rest.char_indices();
+        
+        while let Some((i, c)) = chars.next() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some('"'), '\\') | (None, '\\') => {
+                    chars.next();
+                }
+                (Some(_), _) => {}
+                (None, '\'' | '"') => quote = Some(c),
+                (None, '[') => depth += 1,
+                (None, ']') if depth > 0 => depth -= 1,
+                (None, ']') => {
+                    let after = &rest[i + 1..];
+                    return after.starts_with('=') || after.starts_with("+=");
+                }
+                // The subscript is part of a single word
+                (None, c) if c.is_whitespace() || matches!(c, ';' | '|' | '&' | '<' | '>') => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+    
+    /// Read the raw body of a `$(...)` command substitution up to the matching `)`.
+    ///
+    /// Must be called right after `Token::DollarParen` has been returned. Nested
+    /// parentheses, quoted strings, backslash escapes and comments are skipped
+    /// over. The closing parenthesis is consumed.
+    pub fn read_paren_body(&mut self) -> Result<String> {
+        let mut body = String::new();
+        let mut depth = 0;
+        let mut word_start = true;
+        
+        while let Some(ch) = self.current_char {
+            match ch {
+                ')' if depth == 0 => {
+                    self.advance();
+                    return Ok(body);
+                }
+                '(' | ')' => {
+                    depth += if ch == '(' { 1 } else { -1 };
+                    body.push(ch);
+                    self.advance();
+                }
+                '\\' => {
+                    body.push(ch);
+                    self.advance();
+                    if let Some(escaped) = self.current_char {
+                        body.push(escaped);
+                        self.advance();
+                    }
+                }
+                '\'' | '"' | '`' => {
+                    let (string, _) = self.read_string(ch)?;
+                    body.push(ch);
+                    body.push_str(&string);
+                    body.push(ch);
+                }
+                '#' if word_start => {
+                    while let Some(c) = self.current_char {
+                        if c == '\n' {
+                            break;
+                        }
+                        self.advance();
+                    }
+                }
+                _ => {
+                    body.push(ch);
+                    self.advance();
+                }
+            }
+            word_start = body.ends_with(|c: char| c.is_whitespace() || c == ';' || c == '(');
+        }
+        
+        bail!("Unterminated command substitution")
+    }
+    
+    /// Read the raw body of an arithmetic expression up to the closing `))`.
+    ///
+    /// Must be called right after `Token::DollarDoubleParen` or
+    /// `Token::DoubleLeftParen` has been returned. Parentheses inside the
+    /// expression are balanced and quoted strings are kept with their quotes.
+    /// Both closing parentheses are consumed.
+    pub fn read_arith_body(&mut self) -> Result<String> {
+        let mut body = String::new();
+        let mut depth = 0;
+        
+        while let Some(ch) = self.current_char {
+            match ch {
+                ')' if depth == 0 && self.peek() == Some(&')') => {
+                    self.advance();
+                    self.advance();
+                    return Ok(body);
+                }
+                '(' | ')' => {
+                    depth += if ch == '(' { 1 } else { -1 };
+                    body.push(ch);
+                    self.advance();
+                }
+                '\\' => {
+                    body.push(ch);
+                    self.advance();
+                    if let Some(escaped) = self.current_char {
+                        body.push(escaped);
+                        self.advance();
+                    }
+                }
+                '\'' | '"' | '`' => {
+                    let (string, _) = self.read_string(ch)?;
+                    body.push(ch);
+                    body.push_str(&string);
+                    body.push(ch);
+                }
+                _ => {
+                    body.push(ch);
+                    self.advance();
+                }
+            }
+        }
+        
+        bail!("Unterminated arithmetic expression")
+    }
+    
+    /// Look at the next `n` unconsumed characters without advancing.
+    pub fn lookahead(&self, n: usize) -> String {
+        let mut result = String::new();
+        if let Some(ch) = self.current_char {
+            result.push(ch);
+            result.extend(self.input.clone().take(n.saturating_sub(1)));
+        }
+        result
     }
     
     fn read_number(&mut self) -> String {
@@ -315,6 +903,8 @@ impl<'a> Lexer<'a> {
             "until" => Some(Token::Until),
             "function" => Some(Token::Function),
             "return" => Some(Token::Return),
+            "break" => Some(Token::Break),
+            "continue" => Some(Token::Continue),
             "export" => Some(Token::Export),
             "local" => Some(Token::Local),
             "readonly" => Some(Token::Readonly),
@@ -332,23 +922,84 @@ impl<'a> Lexer<'a> {
             "source" => Some(Token::Source),
             "exec" => Some(Token::Exec),
             "eval" => Some(Token::Eval),
+            "repeat" if self.dialect == ShellDialect::Zsh => Some(Token::Repeat),
             _ => None,
         }
     }
     
     pub fn next_token(&mut self) -> Result<Token> {
+        let start = self.offset;
         self.skip_whitespace();
+        self.space_before = start == 0 || self.offset != start;
+        self.token_start = self.offset;
+        self.token_span = self.current_span();
         
+        let token = self.lex_token()?;
+        self.token_end = self.offset;
+        Ok(token)
+    }
+    
+    /// Source text of the token most recently returned by `next_token`.
+    pub fn token_text(&self) -> &'a str {
+        &self.source[self.token_start..self.token_end]
+    }
+    
+    /// Take the body of the oldest `Token::Heredoc` returned so far.
+    pub fn take_heredoc(&mut self) -> Option<Heredoc> {
+        self.heredocs.pop_front()
+    }
+    
+    /// Where the token most recently returned by `next_token` starts.
+    pub fn token_span(&self) -> Span {
+        self.token_span
+    }
+    
+    /// Where the next character to be read is, e.g. the first character of
+    /// a raw body right after its opening token.
+    pub fn current_span(&self) -> Span {
+        Span::new(self.line, self.column)
+    }
+    
+    /// Byte offset in the input of the next character to lex.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    
+    /// Whether whitespace (or the start of input) precedes the most recent token.
+    ///
+    /// Tokens that touch each other, like `-` and `f` in `-f`, belong to the same
+    /// shell word.
+    pub fn space_before(&self) -> bool {
+        self.space_before
+    }
+    
+    /// Whether whitespace (or the end of input) follows the most recent token.
+    pub fn space_after(&self) -> bool {
+        self.current_char.is_none_or(char::is_whitespace)
+    }
+    
+    fn lex_token(&mut self) -> Result<Token> {
         match self.current_char {
             None => Ok(Token::Eof),
             Some('\n') => {
+                let newline = self.offset;
                 self.advance();
+                // Here-document bodies begun on this line follow it
+                if let Some(skip) = self.heredoc_skip.filter(|skip| skip.from == newline) {
+                    self.heredoc_skip = None;
+                    self.jump_to(skip.to, skip.line);
+                }
                 Ok(Token::Newline)
             }
-            Some('#') => {
+            // `#` only starts a comment at the beginning of a word
+            Some('#') if self.at_word_start() => {
                 self.skip_comment();
                 self.next_token()
             }
+            Some('#') => {
+                self.advance();
+                Ok(Token::Hash)
+            }
             Some('\'') | Some('"') | Some('`') => {
                 let (string, quote_type) = self.read_string(self.current_char.unwrap())?;
                 Ok(Token::String(string, quote_type))
@@ -397,16 +1048,59 @@ impl<'a> Lexer<'a> {
                         self.advance();
                         Ok(Token::And)
                     }
+                    Some('>') => {
+                        self.advance();
+                        if self.current_char == Some('>') {
+                            self.advance();
+                            Ok(Token::Redirect(RedirectOp::AllAppend))
+                        } else {
+                            Ok(Token::Redirect(RedirectOp::AllOut))
+                        }
+                    }
                     _ => Ok(Token::Background),
                 }
             }
             Some(';') => {
                 self.advance();
-                Ok(Token::Semicolon)
+                match self.current_char {
+                    Some(';') => {
+                        self.advance();
+                        if self.current_char == Some('&') {
+                            self.advance();
+                            Ok(Token::DoubleSemicolonAnd)
+                        } else {
+                            Ok(Token::DoubleSemicolon)
+                        }
+                    }
+                    Some('&') => {
+                        self.advance();
+                        Ok(Token::SemicolonAnd)
+                    }
+                    _ => Ok(Token::Semicolon),
+                }
+            }
+            Some('(') if self.dialect == ShellDialect::Zsh && !self.at_word_start() => {
+                match self.glob_qualifiers() {
+                    Some(qualifiers) => {
+                        for _ in 0..qualifiers.chars().count() + 2 {
+                            self.advance();
+                        }
+                        Ok(Token::GlobQualifiers(qualifiers))
+                    }
+                    None => {
+                        self.advance();
+                        Ok(Token::LeftParen)
+                    }
+                }
             }
             Some('(') => {
                 self.advance();
-                Ok(Token::LeftParen)
+                if self.current_char == Some('(') {
+                    self.advance();
+                    Ok(Token::DoubleLeftParen)
+                } else {
+                    Ok(Token::LeftParen)
+                }
             }
             Some(')') => {
                 self.advance();
@@ -438,6 +1132,13 @@ impl<'a> Lexer<'a> {
                     Ok(Token::RightBracket)
                 }
             }
+            // `<(cmd)` and `>(cmd)` start a word, where `<` and `>` would
+            // otherwise be redirections
+            Some(ch @ ('<' | '>')) if self.at_word_start() && self.lookahead(2).ends_with('(') => {
+                self.advance();
+                self.advance();
+                Ok(if ch == '<' { Token::LessParen } else { Token::GreaterParen })
+            }
             Some('>') => {
                 self.advance();
                 match self.current_char {
@@ -449,6 +1150,10 @@ impl<'a> Lexer<'a> {
                         self.advance();
                         Ok(Token::Redirect(RedirectOp::OutErr))
                     }
+                    Some('|') => {
+                        self.advance();
+                        Ok(Token::Redirect(RedirectOp::Clobber))
+                    }
                     Some('=') => {
                         self.advance();
                         Ok(Token::GreaterEqual)
@@ -465,9 +1170,9 @@ impl<'a> Lexer<'a> {
                             self.advance();
                             Ok(Token::HereString)
                         } else {
-                            // Read heredoc delimiter
-                            self.skip_whitespace();
-                            let delimiter = self.read_word();
+                            let heredoc = self.read_heredoc();
+                            let delimiter = heredoc.delimiter.clone();
+                            self.heredocs.push_back(heredoc);
                             Ok(Token::Heredoc(delimiter))
                         }
                     }
@@ -475,6 +1180,10 @@ impl<'a> Lexer<'a> {
                         self.advance();
                         Ok(Token::Redirect(RedirectOp::InOut))
                     }
+                    Some('&') => {
+                        self.advance();
+                        Ok(Token::Redirect(RedirectOp::InDup))
+                    }
                     Some('=') => {
                         self.advance();
                         Ok(Token::LessEqual)
@@ -482,6 +1191,12 @@ impl<'a> Lexer<'a> {
                     _ => Ok(Token::Redirect(RedirectOp::In)),
                 }
             }
+            // zsh's `=(cmd)` substitutes a temporary file with the output
+            Some('=') if self.dialect == ShellDialect::Zsh && self.at_word_start() && self.lookahead(2) == "=(" => {
+                self.advance();
+                self.advance();
+                Ok(Token::EqualParen)
+            }
             Some('=') => {
                 self.advance();
                 if self.current_char == Some('=') {
@@ -559,6 +1274,14 @@ impl<'a> Lexer<'a> {
                     Ok(Token::Word(word))
                 }
             }
+            // An escaped character is text, even `\<` or `\;`; the backslash
+            // stays for globbing to remove
+            Some('\\') if self.input.peek().is_some() => {
+                self.advance();
+                let escaped = self.current_char.unwrap();
+                self.advance();
+                Ok(Token::Word(format!("\\{}", escaped)))
+            }
             Some(ch) => {
                 self.advance();
                 Ok(Token::Word(ch.to_string()))
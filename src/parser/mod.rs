@@ -1,9 +1,16 @@
 pub mod lexer;
 pub mod ast;
 pub mod parser;
+pub mod arithmetic;
+pub mod test_expression;
+pub mod fish;
+pub mod csh;
+pub mod powershell_lexer;
+pub mod powershell;
 pub mod shell_dialect;
+pub mod validator;
 
 pub use lexer::{Lexer, Token};
 pub use ast::{AST, ASTNode};
-pub use parser::ShellParser;
+pub use parser::{ShellParser, ParseError};
 pub use shell_dialect::ShellDialect;
\ No newline at end of file
@@ -1,40 +1,203 @@
-use super::{Lexer, Token, AST, ASTNode, ScriptMetadata};
-use super::shell_dialect::ShellDialect;
+use super::{Lexer, Token, AST, ASTNode};
+use super::ast::{ScriptMetadata, Span};
+use super::csh::CshParser;
+use super::fish::FishParser;
+use super::powershell::PowerShellParser;
+use super::shell_dialect::{ShellDialect, ShellFeature};
+use super::validator::{unsupported, validate};
 use anyhow::{Result, Context, bail};
-use std::path::Path;
+
+/// A syntax error, the position of the token the parser stopped at and,
+/// when the likely cause is known, a hint on how to fix it.
+#[derive(Debug)]
+pub struct ParseError {
+    pub span: Span,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
 
 pub struct ShellParser {
     lexer: Lexer<'static>,
     current_token: Token,
     dialect: ShellDialect,
     input: String,
+    // Errors recovered from so far, in script order
+    errors: Vec<ParseError>,
+    // Set once a bad statement read the rest of the script, after which
+    // errors about the missing rest would only repeat the first one
+    skipped_to_end: bool,
+    // Uses of features the dialect lacks, in script order
+    warnings: Vec<ParseError>,
+    // Report those as errors rather than warnings
+    strict_dialect: bool,
 }
 
 impl ShellParser {
     pub fn new(input: String, dialect: ShellDialect) -> Result<Self> {
+        Self::starting_at(input, dialect, Span::new(1, 1))
+    }
+    
+    /// A parser for text embedded in a script at `start`, so that spans and
+    /// error locations refer to the script rather than to the text.
+    pub fn starting_at(input: String, dialect: ShellDialect, start: Span) -> Result<Self> {
         // We need to leak the string to get a 'static lifetime for the lexer
         // This is safe because we're storing the String in the parser
         let input_ref = unsafe { std::mem::transmute::<&str, &'static str>(input.as_str()) };
-        let mut lexer = Lexer::new(input_ref, dialect);
-        let current_token = lexer.next_token()?;
+        let mut lexer = Lexer::starting_at(input_ref, dialect, start);
+        let mut errors = Vec::new();
+        let current_token = if matches!(dialect, ShellDialect::Fish | ShellDialect::PowerShell | ShellDialect::Csh | ShellDialect::Tcsh) {
+            // Fish, PowerShell and csh scripts have parsers of their own and
+            // never reach the lexer
+            Token::Eof
+        } else {
+            match lexer.next_token() {
+                Ok(token) => token,
+                Err(e) => {
+                    let message = format!("{:#}", e);
+                    errors.push(ParseError { span: lexer.token_span(), hint: Some(hint(&Token::Eof, &message)), message });
+                    Token::Eof
+                }
+            }
+        };
         
         Ok(ShellParser {
             lexer,
             current_token,
             dialect,
             input,
+            errors,
+            skipped_to_end: false,
+            warnings: Vec::new(),
+            strict_dialect: false,
         })
     }
     
+    /// Treat features the dialect lacks, such as arrays in a `#!/bin/sh`
+    /// script, as errors instead of warnings.
+    pub fn set_strict_dialect(&mut self, strict: bool) {
+        self.strict_dialect = strict;
+    }
+    
+    /// Uses of features the dialect lacks, found by
+    /// `parse_with_diagnostics` unless the dialect is strict.
+    pub fn warnings(&self) -> &[ParseError] {
+        &self.warnings
+    }
+    
+    /// Parse the script, failing with the first syntax error.
     pub fn parse(&mut self) -> Result<AST> {
-        let metadata = self.extract_metadata()?;
-        let root = self.parse_script()?;
+        let (ast, errors) = self.parse_with_diagnostics();
+        match errors.into_iter().next() {
+            Some(error) => Err(error.into()),
+            None => Ok(ast),
+        }
+    }
+    
+    /// Parse the script, recovering from syntax errors.
+    ///
+    /// A statement that fails to parse becomes an `ASTNode::Error` and parsing
+    /// resumes after the `;` or newline that ends it, also inside blocks, so
+    /// the AST keeps everything that did parse and all errors are reported.
+    pub fn parse_with_diagnostics(&mut self) -> (AST, Vec<ParseError>) {
+        let metadata = self.extract_metadata().unwrap_or_default();
+        let root = match self.dialect {
+            ShellDialect::Fish => {
+                let (root, errors) = FishParser::new(&self.input).parse_script();
+                self.errors.extend(errors);
+                root
+            }
+            ShellDialect::PowerShell => {
+                let (root, errors) = PowerShellParser::new(&self.input).parse_script();
+                self.errors.extend(errors);
+                root
+            }
+            ShellDialect::Csh | ShellDialect::Tcsh => {
+                let (root, errors) = CshParser::new(&self.input).parse_script();
+                self.errors.extend(errors);
+                root
+            }
+            _ => self.parse_script(),
+        };
+        let ast = AST { root, metadata };
+        
+        // The parser reports the features it sees in the syntax, the
+        // validator those the AST shows
+        self.warnings.extend(validate(&ast, self.dialect));
+        self.warnings.sort_by_key(|warning| warning.span);
+        if self.strict_dialect {
+            self.errors.append(&mut self.warnings);
+            self.errors.sort_by_key(|error| error.span);
+        }
         
-        Ok(AST { root, metadata })
+        (ast, std::mem::take(&mut self.errors))
+    }
+    
+    /// Turn an error into a `ParseError` at the current token, unless a
+    /// nested parser already located it.
+    fn diagnostic(&self, error: anyhow::Error) -> ParseError {
+        match error.downcast::<ParseError>() {
+            Ok(error) => error,
+            Err(error) => {
+                let message = format!("{:#}", error);
+                ParseError {
+                    span: self.lexer.token_span(),
+                    hint: Some(hint(&self.current_token, &message)),
+                    message,
+                }
+            }
+        }
+    }
+    
+    /// Parse a statement. On a syntax error, record it, skip the rest of the
+    /// statement and stand in an error node for it.
+    fn parse_statement_or_recover(&mut self) -> ASTNode {
+        let span = self.lexer.token_span();
+        match self.parse_statement() {
+            Ok(statement) => statement,
+            Err(error) => {
+                let error = self.diagnostic(error);
+                let node = ASTNode::Error { message: error.message.clone(), span };
+                let mut read_to_end = unterminated(&error.message);
+                if !(self.skipped_to_end && self.current_token == Token::Eof) {
+                    self.errors.push(error);
+                }
+                
+                while !matches!(self.current_token, Token::Semicolon | Token::Newline | Token::Eof) {
+                    // Errors in the skipped text are not reported
+                    read_to_end |= self.advance().is_err();
+                }
+                self.skipped_to_end |= read_to_end && self.current_token == Token::Eof;
+                node
+            }
+        }
+    }
+    
+    /// Consume the keyword that closes a compound command opened by `opener`
+    /// at `span`.
+    fn expect_closing(&mut self, closer: Token, opener: &str, span: Span) -> Result<()> {
+        if self.current_token != closer {
+            return Err(ParseError {
+                span: self.lexer.token_span(),
+                message: format!("Expected {}, found {}", closer, self.current_token),
+                hint: Some(format!("the `{}` at {} is not closed", opener, span)),
+            }.into());
+        }
+        self.advance()
     }
     
     fn extract_metadata(&self) -> Result<ScriptMetadata> {
-        let mut metadata = ScriptMetadata::default();
+        let mut metadata = ScriptMetadata {
+            dialect: self.dialect,
+            ..ScriptMetadata::default()
+        };
         
         // Extract shebang
         if self.input.starts_with("#!") {
@@ -75,58 +238,147 @@ impl ShellParser {
         Ok(metadata)
     }
     
-    fn parse_script(&mut self) -> Result<ASTNode> {
+    fn parse_script(&mut self) -> ASTNode {
         let mut statements = Vec::new();
         
         while self.current_token != Token::Eof {
             // Skip newlines at top level
             if self.current_token == Token::Newline {
-                self.advance()?;
+                self.skip_newlines();
                 continue;
             }
             
-            let stmt = self.parse_statement()?;
+            let stmt = self.parse_statement_or_recover();
             statements.push(Box::new(stmt));
             
             // Consume optional terminators
-            while matches!(self.current_token, Token::Semicolon | Token::Newline) {
-                self.advance()?;
-            }
+            self.skip_terminators();
         }
         
-        Ok(ASTNode::Script(statements))
+        ASTNode::Script(statements)
     }
     
+    /// Parse an and-or list: pipelines joined by `&&` and `||`, which have
+    /// equal precedence and group from the left.
     fn parse_statement(&mut self) -> Result<ASTNode> {
+        let mut left = self.parse_negated_pipeline()?;
+        
+        loop {
+            let is_and = match self.current_token {
+                Token::And => true,
+                Token::Or => false,
+                _ => return Ok(left),
+            };
+            self.advance()?;
+            self.skip_newlines();
+            // `a && b &` puts the whole list in the background
+            let (mut right, mut background) = match self.parse_negated_pipeline()? {
+                ASTNode::Background(body) => (*body, true),
+                node => (node, false),
+            };
+            if let ASTNode::Command { background: last, .. } = &mut right {
+                background |= std::mem::take(last);
+            }
+            left = if is_and {
+                ASTNode::AndList(Box::new(left), Box::new(right))
+            } else {
+                ASTNode::OrList(Box::new(left), Box::new(right))
+            };
+            if background {
+                return Ok(ASTNode::Background(Box::new(left)));
+            }
+        }
+    }
+    
+    /// A pipeline, optionally preceded by `!` to negate its exit status.
+    fn parse_negated_pipeline(&mut self) -> Result<ASTNode> {
+        if self.current_token == Token::Bang && self.lexer.space_after() {
+            self.advance()?;
+            let pipeline = self.parse_negated_pipeline()?;
+            return Ok(ASTNode::Negated(Box::new(pipeline)));
+        }
+        self.parse_pipeline_statement()
+    }
+    
+    fn parse_pipeline_statement(&mut self) -> Result<ASTNode> {
         match &self.current_token {
+            Token::Function => self.parse_function(),
+            Token::Word(_) if self.at_function_definition() => self.parse_posix_function(),
+            Token::Export | Token::Local | Token::Readonly | Token::Declare | Token::Typeset => {
+                self.parse_declaration()
+            }
+            Token::Let => self.parse_let(),
+            _ => self.parse_command_or_assignment(),
+        }
+    }
+    
+    fn is_compound_start(&self) -> bool {
+        matches!(
+            self.current_token,
+            Token::If | Token::While | Token::Until | Token::For | Token::Case |
+            Token::Repeat | Token::LeftBrace | Token::LeftParen | Token::DoubleLeftParen |
+            Token::DoubleLeftBracket
+        )
+    }
+    
+    /// Whether a POSIX function definition, `name() ...`, starts here.
+    fn at_function_definition(&self) -> bool {
+        let Token::Word(name) = &self.current_token else {
+            return false;
+        };
+        if name.contains(['=', '$']) {
+            return false;
+        }
+        let rest = self.lexer.lookahead(64);
+        rest.trim_start_matches([' ', '\t'])
+            .strip_prefix('(')
+            .is_some_and(|rest| rest.trim_start_matches([' ', '\t']).starts_with(')'))
+    }
+    
+    /// Whether a zsh anonymous function `() { ... }` starts here rather than
+    /// a subshell.
+    fn at_anonymous_function(&self) -> bool {
+        self.dialect == ShellDialect::Zsh
+            && self.current_token == Token::LeftParen
+            && self.lexer.lookahead(1) == ")"
+    }
+    
+    fn parse_compound_command(&mut self) -> Result<ASTNode> {
+        let node = match &self.current_token {
             Token::If => self.parse_if(),
             Token::While => self.parse_while(),
             Token::Until => self.parse_until(),
             Token::For => self.parse_for(),
             Token::Case => self.parse_case(),
-            Token::Function => self.parse_function(),
-            Token::Export => self.parse_export(),
-            Token::Local => self.parse_local(),
-            Token::Readonly => self.parse_readonly(),
-            Token::Return => self.parse_return(),
-            Token::Break => {
-                self.advance()?;
-                Ok(ASTNode::Break)
-            }
-            Token::Continue => {
-                self.advance()?;
-                Ok(ASTNode::Continue)
-            }
-            Token::Exit => self.parse_exit(),
+            Token::Repeat => self.parse_repeat(),
             Token::LeftBrace => self.parse_block(),
+            Token::LeftParen if self.at_anonymous_function() => self.parse_anonymous_function(),
             Token::LeftParen => self.parse_subshell(),
-            _ => self.parse_command_or_assignment(),
+            Token::DoubleLeftParen => self.parse_arithmetic_command(),
+            Token::DoubleLeftBracket => self.parse_extended_test(),
+            _ => bail!("Expected a compound command, found {}", self.current_token),
+        }?;
+        
+        // Redirections after a compound command apply to all of it
+        if self.at_redirection() {
+            let mut redirections = Vec::new();
+            while self.at_redirection() {
+                redirections.extend(self.parse_redirection()?);
+            }
+            return Ok(ASTNode::Redirected {
+                body: Box::new(node),
+                redirections,
+            });
         }
+        
+        Ok(node)
     }
     
     fn parse_if(&mut self) -> Result<ASTNode> {
+        let span = self.lexer.token_span();
         self.expect(Token::If)?;
         let condition = self.parse_condition()?;
+        self.skip_terminators();
         self.expect(Token::Then)?;
         self.skip_newlines();
         
@@ -136,6 +388,7 @@ impl ShellParser {
         while self.current_token == Token::Elif {
             self.advance()?;
             let elif_condition = self.parse_condition()?;
+            self.skip_terminators();
             self.expect(Token::Then)?;
             self.skip_newlines();
             let elif_block = self.parse_block_until(&[Token::Elif, Token::Else, Token::Fi])?;
@@ -150,47 +403,60 @@ impl ShellParser {
             None
         };
         
-        self.expect(Token::Fi)?;
+        self.expect_closing(Token::Fi, "if", span)?;
         
         Ok(ASTNode::If {
             condition: Box::new(condition),
             then_block: Box::new(then_block),
             elif_blocks,
             else_block,
+            span,
         })
     }
     
     fn parse_while(&mut self) -> Result<ASTNode> {
+        let span = self.lexer.token_span();
         self.expect(Token::While)?;
         let condition = self.parse_condition()?;
+        self.skip_terminators();
         self.expect(Token::Do)?;
         self.skip_newlines();
         let body = self.parse_block_until(&[Token::Done])?;
-        self.expect(Token::Done)?;
+        self.expect_closing(Token::Done, "while", span)?;
         
         Ok(ASTNode::While {
             condition: Box::new(condition),
             body: Box::new(body),
+            span,
         })
     }
     
     fn parse_until(&mut self) -> Result<ASTNode> {
+        let span = self.lexer.token_span();
         self.expect(Token::Until)?;
         let condition = self.parse_condition()?;
+        self.skip_terminators();
         self.expect(Token::Do)?;
         self.skip_newlines();
         let body = self.parse_block_until(&[Token::Done])?;
-        self.expect(Token::Done)?;
+        self.expect_closing(Token::Done, "until", span)?;
         
         Ok(ASTNode::Until {
             condition: Box::new(condition),
             body: Box::new(body),
+            span,
         })
     }
     
     fn parse_for(&mut self) -> Result<ASTNode> {
+        let span = self.lexer.token_span();
         self.expect(Token::For)?;
         
+        if self.current_token == Token::DoubleLeftParen {
+            self.use_feature(ShellFeature::ArithmeticCommand, span);
+            return self.parse_c_style_for(span);
+        }
+        
         let variable = match &self.current_token {
             Token::Word(name) => {
                 let var = name.clone();
@@ -199,36 +465,211 @@ impl ShellParser {
             }
             _ => bail!("Expected variable name after 'for'"),
         };
+        self.skip_newlines();
         
-        // TODO: Implement full for loop parsing including C-style for loops
-        // For now, just handle basic for..in loops
-        self.expect(Token::In)?;
+        let items = if self.current_token == Token::In {
+            self.advance()?;
+            let mut items = Vec::new();
+            while !matches!(self.current_token, Token::Do | Token::Semicolon | Token::Newline | Token::Eof) {
+                items.push(Box::new(glob_word(self.parse_word()?)));
+            }
+            
+            if items.len() == 1 && matches!(items[0].as_ref(), ASTNode::CommandSubstitution(_, _)) {
+                ForItems::Command(items.remove(0))
+            } else {
+                ForItems::List(items)
+            }
+        } else {
+            // `for x; do` iterates over the positional parameters
+            ForItems::List(vec![Box::new(ASTNode::Quoted(vec![Box::new(ASTNode::Variable("@".to_string(), span))], span))])
+        };
         
-        let mut items = Vec::new();
-        while !matches!(self.current_token, Token::Do | Token::Semicolon | Token::Newline) {
-            items.push(Box::new(self.parse_word()?));
-        }
+        self.parse_for_body(variable, items, span)
+    }
+    
+    fn parse_c_style_for(&mut self, span: Span) -> Result<ASTNode> {
+        // The lexer is positioned just past `((`
+        let start = self.lexer.current_span();
+        let header = self.lexer.read_arith_body()?;
+        self.advance()?;
         
-        self.skip_newlines();
+        let (init, rest) = split_unnested(&header, ';')
+            .with_context(|| format!("Expected `;` in for (({}))", header))?;
+        let (condition, update) = split_unnested(rest, ';')
+            .with_context(|| format!("Expected two `;` in for (({}))", header))?;
+        let at = |text: &str| advance_span(start, &header[..header.len() - text.len()]);
+        
+        // An empty condition is always true
+        let condition = if condition.trim().is_empty() {
+            ASTNode::Integer(1)
+        } else {
+            self.parse_arithmetic_text(condition, at(rest))?
+        };
+        let items = ForItems::CStyle {
+            init: Box::new(self.parse_arithmetic_text(init, start)?),
+            condition: Box::new(condition),
+            update: Box::new(self.parse_arithmetic_text(update, at(update))?),
+        };
+        
+        self.parse_for_body(String::new(), items, span)
+    }
+    
+    fn parse_for_body(&mut self, variable: String, items: ForItems, span: Span) -> Result<ASTNode> {
+        self.skip_terminators();
         self.expect(Token::Do)?;
         self.skip_newlines();
         
         let body = self.parse_block_until(&[Token::Done])?;
-        self.expect(Token::Done)?;
+        self.expect_closing(Token::Done, "for", span)?;
         
         Ok(ASTNode::For {
             variable,
-            items: ForItems::List(items),
+            items,
+            body: Box::new(body),
+            span,
+        })
+    }
+    
+    /// zsh's `repeat N do ... done`, or the short form `repeat N command`.
+    fn parse_repeat(&mut self) -> Result<ASTNode> {
+        let span = self.lexer.token_span();
+        self.expect(Token::Repeat)?;
+        let start = self.lexer.token_span();
+        let word = self.parse_word()?;
+        let count = self.arithmetic_from_word(word, start)?;
+        
+        let body = if matches!(self.current_token, Token::Do | Token::Semicolon | Token::Newline) {
+            self.skip_terminators();
+            self.expect(Token::Do)?;
+            self.skip_newlines();
+            let body = self.parse_block_until(&[Token::Done])?;
+            self.expect_closing(Token::Done, "repeat", span)?;
+            body
+        } else {
+            ASTNode::Block(vec![Box::new(self.parse_statement()?)])
+        };
+        
+        Ok(ASTNode::Repeat {
+            count: Box::new(count),
             body: Box::new(body),
+            span,
         })
     }
     
     fn parse_case(&mut self) -> Result<ASTNode> {
-        // TODO: Implement case statement parsing
-        bail!("Case statements not yet implemented")
+        let span = self.lexer.token_span();
+        self.expect(Token::Case)?;
+        let expr = self.parse_word()?;
+        self.skip_newlines();
+        self.expect(Token::In)?;
+        self.skip_terminators();
+        
+        let mut cases = Vec::new();
+        while self.current_token != Token::Esac {
+            if self.current_token == Token::Eof {
+                break;
+            }
+            
+            // The opening paren of a pattern list is optional
+            if self.current_token == Token::LeftParen {
+                self.advance()?;
+            }
+            let patterns = self.parse_case_patterns()?;
+            
+            let body = self.parse_block_until(&[
+                Token::DoubleSemicolon,
+                Token::SemicolonAnd,
+                Token::DoubleSemicolonAnd,
+                Token::Esac,
+            ])?;
+            
+            // The last item may leave out its terminator
+            let terminator = match self.current_token {
+                Token::SemicolonAnd => CaseTerminator::FallThrough,
+                Token::DoubleSemicolonAnd => CaseTerminator::Continue,
+                _ => CaseTerminator::Break,
+            };
+            if self.current_token != Token::Esac {
+                self.advance()?;
+            }
+            self.skip_terminators();
+            
+            cases.push(CaseItem {
+                patterns,
+                body: Box::new(body),
+                terminator,
+            });
+        }
+        
+        self.expect_closing(Token::Esac, "case", span)?;
+        
+        Ok(ASTNode::Case {
+            expr: Box::new(expr),
+            cases,
+            span,
+        })
+    }
+    
+    /// Parse `pat1|pat2)` of a case item, consuming the closing paren.
+    ///
+    /// Each pattern becomes a word whose quoted parts match literally, like
+    /// the right side of `[[ == ]]`. Extglob groups such as `@(a|b)` keep
+    /// their inner `|`.
+    fn parse_case_patterns(&mut self) -> Result<Vec<ASTNode>> {
+        let mut patterns = Vec::new();
+        let mut pattern = String::new();
+        let mut start = self.lexer.token_span();
+        let mut depth = 0;
+        
+        loop {
+            if pattern.is_empty() {
+                start = self.lexer.token_span();
+            }
+            match &self.current_token {
+                Token::Pipe if depth == 0 => {
+                    patterns.push(ShellParser::parse_word_text(&std::mem::take(&mut pattern), self.dialect, start)?);
+                }
+                Token::RightParen if depth == 0 => {
+                    patterns.push(ShellParser::parse_word_text(&pattern, self.dialect, start)?);
+                    self.advance()?;
+                    return Ok(patterns);
+                }
+                Token::LeftParen => {
+                    // Only valid as an extglob group like `+(...)`
+                    if !pattern.ends_with(['?', '*', '+', '@', '!']) || self.lexer.space_before() {
+                        bail!("Unexpected '(' in case pattern");
+                    }
+                    depth += 1;
+                    pattern.push('(');
+                }
+                Token::RightParen => {
+                    depth -= 1;
+                    pattern.push(')');
+                }
+                Token::DollarBrace => {
+                    let body = self.lexer.read_brace_body()?;
+                    pattern.push_str("${");
+                    pattern.push_str(&body);
+                    pattern.push('}');
+                }
+                Token::DollarParen => {
+                    let body = self.lexer.read_paren_body()?;
+                    pattern.push_str("$(");
+                    pattern.push_str(&body);
+                    pattern.push(')');
+                }
+                Token::Newline | Token::Semicolon | Token::Eof => {
+                    bail!("Expected `)' after case pattern, found {}", self.current_token);
+                }
+                _ => pattern.push_str(self.lexer.token_text()),
+            }
+            self.advance()?;
+        }
     }
     
     fn parse_function(&mut self) -> Result<ASTNode> {
+        let span = self.lexer.token_span();
+        self.use_feature(ShellFeature::FunctionKeyword, span);
         self.expect(Token::Function)?;
         
         let name = match &self.current_token {
@@ -257,181 +698,461 @@ impl ShellParser {
         Ok(ASTNode::Function {
             name,
             body: Box::new(body),
+            span,
         })
     }
     
-    fn parse_export(&mut self) -> Result<ASTNode> {
-        self.expect(Token::Export)?;
-        let mut node = self.parse_assignment_or_word()?;
+    /// A POSIX function definition: `name()` and a compound command.
+    fn parse_posix_function(&mut self) -> Result<ASTNode> {
+        let span = self.lexer.token_span();
+        let Token::Word(name) = self.current_token.clone() else {
+            bail!("Expected function name, found {}", self.current_token);
+        };
+        self.advance()?;
+        self.expect(Token::LeftParen)?;
+        self.expect(Token::RightParen)?;
+        self.skip_newlines();
         
-        if let ASTNode::Assignment { export, .. } = &mut node {
-            *export = true;
+        if !self.is_compound_start() {
+            return Err(ParseError {
+                span: self.lexer.token_span(),
+                message: format!("Expected the body of function `{}', found {}", name, self.current_token),
+                hint: Some("the body is a compound command, like `{ ...; }`".to_string()),
+            }.into());
         }
+        let body = self.parse_compound_command()?;
         
-        Ok(node)
+        Ok(ASTNode::Function {
+            name,
+            body: Box::new(body),
+            span,
+        })
     }
     
-    fn parse_local(&mut self) -> Result<ASTNode> {
-        self.expect(Token::Local)?;
-        let mut node = self.parse_assignment_or_word()?;
+    /// A zsh anonymous function, `() { ... }` followed by its arguments.
+    fn parse_anonymous_function(&mut self) -> Result<ASTNode> {
+        let span = self.lexer.token_span();
+        self.expect(Token::LeftParen)?;
+        self.expect(Token::RightParen)?;
+        self.skip_newlines();
         
-        if let ASTNode::Assignment { local, .. } = &mut node {
-            *local = true;
+        if self.current_token != Token::LeftBrace {
+            bail!("Expected `{{' after `()', found {}", self.current_token);
         }
+        let body = self.parse_block()?;
         
-        Ok(node)
+        let mut args = Vec::new();
+        while self.is_word_token() {
+            args.push(Box::new(self.parse_word()?));
+        }
+        
+        Ok(ASTNode::AnonymousFunction {
+            body: Box::new(body),
+            args,
+            span,
+        })
     }
     
-    fn parse_readonly(&mut self) -> Result<ASTNode> {
-        self.expect(Token::Readonly)?;
-        let mut node = self.parse_assignment_or_word()?;
+    /// `declare`, `typeset`, `local`, `export` and `readonly`: attribute
+    /// flags, then the names and assignments they apply to.
+    ///
+    /// A lone assignment without flags, like `export NAME=value`, stays an
+    /// `Assignment` with its flag set, the way the other dialects' parsers
+    /// write it.
+    fn parse_declaration(&mut self) -> Result<ASTNode> {
+        let span = self.lexer.token_span();
+        let builtin = self.lexer.token_text().to_string();
+        if builtin == "local" {
+            self.use_feature(ShellFeature::LocalKeyword, span);
+        }
+        self.advance()?;
         
-        if let ASTNode::Assignment { readonly, .. } = &mut node {
-            *readonly = true;
+        let implied = VariableAttributes {
+            export: builtin == "export",
+            readonly: builtin == "readonly",
+            ..Default::default()
+        };
+        let mut attributes = implied;
+        let mut removed = VariableAttributes::default();
+        let mut global = false;
+        let mut options = String::new();
+        let mut variables = Vec::new();
+        let mut redirections = Vec::new();
+        loop {
+            if self.at_redirection() {
+                redirections.extend(self.parse_redirection()?);
+            } else if self.at_assignment() {
+                variables.push(Box::new(self.parse_assignment()?));
+            } else if self.is_word_token() {
+                let word = self.parse_word()?;
+                let flags = match &word {
+                    ASTNode::String(text, StringType::Unquoted, _) if variables.is_empty() && text.len() > 1 => {
+                        text.strip_prefix('-').map(|flags| (flags, true))
+                            .or_else(|| text.strip_prefix('+').map(|flags| (flags, false)))
+                    }
+                    _ => None,
+                };
+                let Some((flags, on)) = flags else {
+                    variables.push(Box::new(word));
+                    continue;
+                };
+                for flag in flags.chars() {
+                    let target = if on { &mut attributes } else { &mut removed };
+                    match flag {
+                        // `export -n` takes the export attribute away
+                        'n' if builtin == "export" => {
+                            attributes.export = false;
+                            removed.export = true;
+                        }
+                        'i' => target.integer = true,
+                        'r' => target.readonly = true,
+                        'x' => target.export = true,
+                        'a' => target.array = true,
+                        'A' => target.associative = true,
+                        'l' => target.lowercase = true,
+                        'u' => target.uppercase = true,
+                        'n' => target.nameref = true,
+                        'g' => global = true,
+                        other => options.push(other),
+                    }
+                }
+            } else {
+                break;
+            }
         }
         
-        Ok(node)
+        let local = builtin == "local" || (matches!(builtin.as_str(), "declare" | "typeset") && !global);
+        let lone_assignment = matches!(builtin.as_str(), "local" | "export" | "readonly")
+            && attributes == implied
+            && removed.is_empty()
+            && options.is_empty()
+            && variables.len() == 1
+            && matches!(variables[0].as_ref(), ASTNode::Assignment { .. });
+        let declaration = if lone_assignment {
+            let mut node = *variables.pop().unwrap();
+            if let ASTNode::Assignment { export, readonly, local: is_local, .. } = &mut node {
+                *export = attributes.export;
+                *readonly = attributes.readonly;
+                *is_local = local;
+            }
+            node
+        } else {
+            ASTNode::Declaration { builtin, attributes, removed, local, options, variables, span }
+        };
+        
+        if redirections.is_empty() {
+            Ok(declaration)
+        } else {
+            Ok(ASTNode::Redirected { body: Box::new(declaration), redirections })
+        }
     }
     
     fn parse_return(&mut self) -> Result<ASTNode> {
         self.expect(Token::Return)?;
         
-        let value = if matches!(self.current_token, Token::Newline | Token::Semicolon | Token::Eof) {
-            None
-        } else {
+        let value = if self.is_word_token() {
             Some(Box::new(self.parse_word()?))
+        } else {
+            None
         };
         
         Ok(ASTNode::Return(value))
     }
     
+    /// `break` or `continue`, with an optional count of enclosing loops.
+    fn parse_loop_control(&mut self) -> Result<ASTNode> {
+        let is_break = self.current_token == Token::Break;
+        let keyword = if is_break { "break" } else { "continue" };
+        self.advance()?;
+        
+        let count = if self.is_word_token() {
+            match self.parse_word()? {
+                ASTNode::Number(n) if n >= 1.0 && n.fract() == 0.0 => n as usize,
+                ASTNode::Number(n) => bail!("{}: {}: loop count out of range", keyword, n),
+                _ => bail!("{}: only a literal loop count is supported", keyword),
+            }
+        } else {
+            1
+        };
+        
+        Ok(if is_break { ASTNode::Break(count) } else { ASTNode::Continue(count) })
+    }
+    
     fn parse_exit(&mut self) -> Result<ASTNode> {
         self.expect(Token::Exit)?;
         
-        let code = if matches!(self.current_token, Token::Newline | Token::Semicolon | Token::Eof) {
-            None
-        } else {
+        let code = if self.is_word_token() {
             Some(Box::new(self.parse_word()?))
+        } else {
+            None
         };
         
         Ok(ASTNode::Exit(code))
     }
     
+    fn parse_let(&mut self) -> Result<ASTNode> {
+        let span = self.lexer.token_span();
+        self.use_feature(ShellFeature::ArithmeticCommand, span);
+        self.expect(Token::Let)?;
+        
+        // Each argument is a separate expression; the last one sets the status
+        let mut expr: Option<ASTNode> = None;
+        while self.is_word_token() {
+            let start = self.lexer.token_span();
+            let word = self.parse_word()?;
+            let arg = self.arithmetic_from_word(word, start)?;
+            expr = Some(match expr {
+                Some(left) => ASTNode::BinaryOp {
+                    left: Box::new(left),
+                    op: BinaryOperator::Comma,
+                    right: Box::new(arg),
+                },
+                None => arg,
+            });
+        }
+        
+        match expr {
+            Some(expr) => Ok(ASTNode::ArithmeticCommand(Box::new(expr), span)),
+            None => bail!("let: expression expected"),
+        }
+    }
+    
+    fn parse_arithmetic_command(&mut self) -> Result<ASTNode> {
+        // The lexer is positioned just past `((`
+        let span = self.lexer.token_span();
+        self.use_feature(ShellFeature::ArithmeticCommand, span);
+        let start = self.lexer.current_span();
+        let body = self.lexer.read_arith_body()?;
+        self.advance()?;
+        Ok(ASTNode::ArithmeticCommand(Box::new(self.parse_arithmetic_text(&body, start)?), span))
+    }
+    
     fn parse_block(&mut self) -> Result<ASTNode> {
+        let span = self.lexer.token_span();
         self.expect(Token::LeftBrace)?;
         self.skip_newlines();
         
         let block = self.parse_block_until(&[Token::RightBrace])?;
-        self.expect(Token::RightBrace)?;
+        self.expect_closing(Token::RightBrace, "{", span)?;
         
         Ok(block)
     }
     
     fn parse_subshell(&mut self) -> Result<ASTNode> {
+        let span = self.lexer.token_span();
         self.expect(Token::LeftParen)?;
         self.skip_newlines();
         
         let mut statements = Vec::new();
         while self.current_token != Token::RightParen && self.current_token != Token::Eof {
-            statements.push(Box::new(self.parse_statement()?));
+            statements.push(Box::new(self.parse_statement_or_recover()));
             self.skip_terminators();
         }
         
-        self.expect(Token::RightParen)?;
+        self.expect_closing(Token::RightParen, "(", span)?;
+        if statements.is_empty() {
+            return Err(ParseError {
+                span,
+                message: "Expected a command in `( )'".to_string(),
+                hint: Some("a function is defined as `name() { ...; }`".to_string()),
+            }.into());
+        }
         
         Ok(ASTNode::Subshell(Box::new(ASTNode::Block(statements))))
     }
     
     fn parse_command_or_assignment(&mut self) -> Result<ASTNode> {
-        // Check if this looks like an assignment
-        if let Token::Word(name) = &self.current_token {
-            let mut chars = self.input[self.lexer.position..].chars();
-            if chars.next() == Some('=') || (chars.next() == Some('+') && chars.next() == Some('=')) {
-                return self.parse_assignment();
+        if self.at_assignment() {
+            let assignment = self.parse_assignment()?;
+            if !self.at_redirection() {
+                return Ok(assignment);
+            }
+            // `v=$(cmd) 2>/dev/null` redirects a command without a name; with
+            // a command or assignment after them they belong to that
+            let mut redirections = Vec::new();
+            while self.at_redirection() {
+                redirections.extend(self.parse_redirection()?);
             }
+            if !self.is_word_token() {
+                return Ok(ASTNode::Redirected { body: Box::new(assignment), redirections });
+            }
+            let rest = self.parse_command_or_assignment()?;
+            return Ok(ASTNode::Block(vec![
+                Box::new(assignment),
+                Box::new(ASTNode::Redirected { body: Box::new(rest), redirections }),
+            ]));
         }
         
         self.parse_pipeline()
     }
     
+    /// Whether the current word starts an assignment: `name=value`,
+    /// `name+=value` or `name[key]=value`.
+    fn at_assignment(&self) -> bool {
+        if !matches!(self.current_token, Token::Word(_)) {
+            return false;
+        }
+        let next = self.lexer.lookahead(2);
+        next.starts_with('=') || next == "+=" || (next.starts_with('[') && self.lexer.at_subscript_assignment())
+    }
+    
     fn parse_assignment(&mut self) -> Result<ASTNode> {
         let name = match &self.current_token {
             Token::Word(n) => n.clone(),
             _ => bail!("Expected variable name"),
         };
+        let span = self.lexer.token_span();
         
         self.advance()?;
+        self.finish_assignment(name, span)
+    }
+    
+    /// The rest of an assignment after the name: an optional `[key]`
+    /// subscript, `=` or `+=` and the value.
+    fn finish_assignment(&mut self, name: String, span: Span) -> Result<ASTNode> {
+        let index = if self.current_token == Token::LeftBracket {
+            let start = self.lexer.current_span();
+            let subscript = self.lexer.read_subscript_body()?;
+            self.advance()?;
+            Some(Box::new(self.parse_subscript(&subscript, start)?))
+        } else {
+            None
+        };
         
-        let _op = match &self.current_token {
-            Token::Assign => {
-                self.advance()?;
-                "="
-            }
-            Token::PlusAssign => {
-                self.advance()?;
-                "+="
-            }
+        let append = match &self.current_token {
+            Token::Assign => false,
+            Token::PlusAssign => true,
             _ => bail!("Expected assignment operator"),
         };
+        let value_span = self.lexer.current_span();
+        self.advance()?;
         
-        let value = self.parse_word()?;
+        // `NAME=` with nothing attached assigns the empty string
+        let value = if self.current_token == Token::LeftParen && !self.lexer.space_before() {
+            self.parse_array()?
+        } else if self.is_word_token() && !self.lexer.space_before() {
+            self.parse_word()?
+        } else {
+            ASTNode::String(String::new(), StringType::Unquoted, value_span)
+        };
         
         Ok(ASTNode::Assignment {
             name,
+            index,
             value: Box::new(value),
+            append,
             export: false,
             readonly: false,
             local: false,
+            span,
         })
     }
     
-    fn parse_assignment_or_word(&mut self) -> Result<ASTNode> {
-        // This is used after export/local/readonly
-        // TODO: Implement proper parsing
-        self.parse_word()
+    /// The words of an array assignment, `(a b c)`, which may span lines.
+    /// Elements written `[key]=value` become assignments without a name.
+    fn parse_array(&mut self) -> Result<ASTNode> {
+        let span = self.lexer.token_span();
+        self.expect(Token::LeftParen)?;
+        
+        let mut elements = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.current_token == Token::LeftBracket && self.lexer.at_subscript_assignment() {
+                let element_span = self.lexer.token_span();
+                elements.push(Box::new(self.finish_assignment(String::new(), element_span)?));
+                continue;
+            }
+            if !self.is_word_token() {
+                break;
+            }
+            elements.push(Box::new(glob_word(self.parse_word()?)));
+        }
+        self.expect_closing(Token::RightParen, "(", span)?;
+        
+        Ok(ASTNode::Array(elements))
     }
     
     fn parse_pipeline(&mut self) -> Result<ASTNode> {
-        let mut commands = vec![Box::new(self.parse_command()?)];
+        let mut commands = vec![self.parse_pipeline_stage()?];
         
         while matches!(self.current_token, Token::Pipe | Token::PipeErr) {
+            // `a |& b` is shorthand for `a 2>&1 | b`
+            if self.current_token == Token::PipeErr {
+                self.use_feature(ShellFeature::PipeStderr, self.lexer.token_span());
+                let stage = commands.pop().unwrap();
+                commands.push(merge_stderr(stage));
+            }
             self.advance()?;
             self.skip_newlines();
-            commands.push(Box::new(self.parse_command()?));
+            commands.push(self.parse_pipeline_stage()?);
         }
         
-        if commands.len() == 1 {
-            Ok(*commands.into_iter().next().unwrap())
-        } else {
-            Ok(ASTNode::Pipeline(commands))
+        // `&` after a pipeline or a compound command puts all of it in the
+        // background; a simple command on its own keeps the flag
+        let mut background = self.current_token == Token::Background;
+        if background {
+            self.advance()?;
+        }
+        if commands.len() > 1 {
+            if let Some(ASTNode::Command { background: last, .. }) = commands.last_mut() {
+                background |= std::mem::take(last);
+            }
         }
+        
+        let node = if commands.len() == 1 {
+            commands.pop().unwrap()
+        } else {
+            ASTNode::Pipeline(commands.into_iter().map(Box::new).collect())
+        };
+        Ok(if background { ASTNode::Background(Box::new(node)) } else { node })
     }
     
-    fn parse_command(&mut self) -> Result<ASTNode> {
-        let name = match &self.current_token {
-            Token::Word(n) => n.clone(),
-            _ => bail!("Expected command name"),
+    fn parse_pipeline_stage(&mut self) -> Result<ASTNode> {
+        match self.current_token {
+            Token::Return => self.parse_return(),
+            Token::Break | Token::Continue => self.parse_loop_control(),
+            Token::Exit => self.parse_exit(),
+            _ if self.is_compound_start() => self.parse_compound_command(),
+            _ => self.parse_command(),
+        }
+    }
+    
+    fn parse_command(&mut self) -> Result<ASTNode> {
+        let span = self.lexer.token_span();
+        
+        // Redirections may also come before the command name
+        let mut redirections = Vec::new();
+        while self.at_redirection() {
+            redirections.extend(self.parse_redirection()?);
+        }
+        
+        let mut name = match self.command_name() {
+            Some(n) => n,
+            None => bail!("Expected command name, found {}", self.current_token),
         };
         
         self.advance()?;
         
-        let mut args = Vec::new();
-        let mut redirections = Vec::new();
-        
-        while !matches!(
-            self.current_token,
-            Token::Pipe | Token::PipeErr | Token::Semicolon | Token::Newline | 
-            Token::Background | Token::And | Token::Or | Token::Eof
-        ) {
-            match &self.current_token {
-                Token::Redirect(_) => {
-                    // TODO: Parse redirections properly
+        // Commands like `./run.sh` or `/usr/bin/env` span several tokens
+        while self.is_word_token() && !self.lexer.space_before() {
+            match self.literal_text() {
+                Some(text) => {
+                    name.push_str(text);
                     self.advance()?;
-                    self.advance()?; // Skip target for now
-                }
-                _ => {
-                    args.push(Box::new(self.parse_word()?));
                 }
+                None => bail!("Dynamic command names are not supported: {}", name),
+            }
+        }
+        
+        let mut args = Vec::new();
+        
+        loop {
+            if self.at_redirection() {
+                redirections.extend(self.parse_redirection()?);
+            } else if self.is_word_token() {
+                args.push(Box::new(glob_word(self.parse_word()?)));
+            } else {
+                break;
             }
         }
         
@@ -442,72 +1163,881 @@ impl ShellParser {
             false
         };
         
+        if (name == "[" || name == "test") && redirections.is_empty() && !background {
+            return test_command(&name, args.into_iter().map(|arg| *arg).collect(), span);
+        }
+        
         Ok(ASTNode::Command {
             name,
             args,
             redirections,
             background,
+            span,
         })
     }
     
     fn parse_condition(&mut self) -> Result<ASTNode> {
-        // For now, just parse as a command
-        // TODO: Implement proper condition parsing with test commands
-        self.parse_pipeline()
+        self.parse_statement()
+    }
+    
+    /// Parse `[[ expression ]]`.
+    ///
+    /// Unlike `[`, the operators are part of the shell grammar here: `&&`,
+    /// `||`, `<`, `>` and parentheses are not command syntax, operator words
+    /// are only recognized unquoted and the right side of `=~` is read as a
+    /// regular expression.
+    fn parse_extended_test(&mut self) -> Result<ASTNode> {
+        let span = self.lexer.token_span();
+        self.use_feature(ShellFeature::ExtendedTest, span);
+        self.expect(Token::DoubleLeftBracket)?;
+        self.skip_newlines();
+        let expr = self.parse_test_or()?;
+        if self.current_token != Token::DoubleRightBracket {
+            bail!("Expected `]]', found {}", self.current_token);
+        }
+        self.advance()?;
+        
+        Ok(ASTNode::Test { expr: Box::new(expr), extended: true, span })
+    }
+    
+    fn parse_test_or(&mut self) -> Result<ASTNode> {
+        let mut left = self.parse_test_and()?;
+        while self.current_token == Token::Or {
+            self.advance()?;
+            self.skip_newlines();
+            let right = self.parse_test_and()?;
+            left = ASTNode::BinaryOp { left: Box::new(left), op: BinaryOperator::Or, right: Box::new(right) };
+        }
+        Ok(left)
+    }
+    
+    fn parse_test_and(&mut self) -> Result<ASTNode> {
+        let mut left = self.parse_test_not()?;
+        while self.current_token == Token::And {
+            self.advance()?;
+            self.skip_newlines();
+            let right = self.parse_test_not()?;
+            left = ASTNode::BinaryOp { left: Box::new(left), op: BinaryOperator::And, right: Box::new(right) };
+        }
+        Ok(left)
+    }
+    
+    fn parse_test_not(&mut self) -> Result<ASTNode> {
+        if self.current_token == Token::Bang {
+            self.advance()?;
+            let operand = self.parse_test_not()?;
+            return Ok(ASTNode::UnaryOp { op: UnaryOperator::Not, operand: Box::new(operand) });
+        }
+        self.parse_test_primary()
+    }
+    
+    fn parse_test_primary(&mut self) -> Result<ASTNode> {
+        if self.current_token == Token::LeftParen {
+            self.advance()?;
+            self.skip_newlines();
+            let inner = self.parse_test_or()?;
+            self.skip_newlines();
+            self.expect(Token::RightParen)?;
+            return Ok(inner);
+        }
+        
+        let left = self.parse_test_word()?;
+        
+        if let Some(op) = unquoted_literal(&left).and_then(test_expression::unary_operator) {
+            if self.at_test_word() {
+                let operand = self.parse_test_word()?;
+                return Ok(ASTNode::UnaryOp { op, operand: Box::new(operand) });
+            }
+        }
+        
+        let op = match &self.current_token {
+            Token::Redirect(RedirectOp::In) => Some(BinaryOperator::StringLess),
+            Token::Redirect(RedirectOp::Out) => Some(BinaryOperator::StringGreater),
+            _ => None,
+        };
+        let op = match op {
+            Some(op) => {
+                self.advance()?;
+                op
+            }
+            None if self.at_test_word() => {
+                let word = self.parse_word()?;
+                match unquoted_literal(&word).and_then(test_expression::binary_operator) {
+                    Some(op) => op,
+                    None => match unquoted_literal(&word) {
+                        Some(text) => bail!("Conditional binary operator expected, found `{}'", text),
+                        None => bail!("Conditional binary operator expected"),
+                    },
+                }
+            }
+            None => {
+                return Ok(ASTNode::UnaryOp {
+                    op: UnaryOperator::StringNotEmpty,
+                    operand: Box::new(left),
+                });
+            }
+        };
+        
+        let right = if op == BinaryOperator::Match {
+            self.parse_test_regex()?
+        } else {
+            self.parse_test_word()?
+        };
+        Ok(ASTNode::BinaryOp { left: Box::new(left), op, right: Box::new(right) })
+    }
+    
+    /// Whether the current token starts an operand inside `[[ ]]`.
+    fn at_test_word(&self) -> bool {
+        self.is_word_token() && !matches!(self.current_token, Token::DoubleRightBracket | Token::Bang)
+    }
+    
+    fn parse_test_word(&mut self) -> Result<ASTNode> {
+        if !self.at_test_word() {
+            bail!("Unexpected {} in conditional expression", self.current_token);
+        }
+        self.parse_word()
     }
     
+    /// Parse the regular expression after `=~`. Parentheses and `|` are
+    /// part of the word, and whitespace is kept inside parentheses.
+    fn parse_test_regex(&mut self) -> Result<ASTNode> {
+        let span = self.lexer.token_span();
+        let mut parts = Vec::new();
+        let mut depth = 0;
+        
+        loop {
+            if !parts.is_empty() && self.lexer.space_before() {
+                if depth == 0 {
+                    break;
+                }
+                push_word_part(&mut parts, ASTNode::String(" ".to_string(), StringType::Unquoted, self.lexer.token_span()));
+            }
+            match self.current_token {
+                Token::DoubleRightBracket | Token::And | Token::Or if depth == 0 => break,
+                Token::Newline | Token::Eof => break,
+                Token::LeftParen | Token::DoubleLeftParen | Token::RightParen | Token::Pipe |
+                Token::PipeErr | Token::And | Token::Or | Token::Background | Token::Semicolon |
+                Token::Redirect(_) => {
+                    let text = self.lexer.token_text();
+                    depth += text.matches('(').count() as i32 - text.matches(')').count() as i32;
+                    push_word_part(&mut parts, ASTNode::String(text.to_string(), StringType::Unquoted, self.lexer.token_span()));
+                    self.advance()?;
+                }
+                _ => self.parse_word_part(&mut parts)?,
+            }
+        }
+        
+        match parts.len() {
+            0 => bail!("Expected regular expression after =~"),
+            1 => Ok(parts.pop().unwrap()),
+            _ => Ok(ASTNode::Concat(parts.into_iter().map(Box::new).collect(), span)),
+        }
+    }
+    
+    /// Parse one shell word.
+    ///
+    /// The lexer splits words like `-f`, `/usr/bin` or `${file%.*}.bak` into
+    /// several tokens; tokens that touch each other are joined back together
+    /// here. A word made of a single part is returned as that part, otherwise
+    /// the parts are wrapped in `ASTNode::Concat`.
     fn parse_word(&mut self) -> Result<ASTNode> {
-        match &self.current_token.clone() {
-            Token::Word(w) => {
-                let word = w.clone();
+        if !self.is_word_token() {
+            bail!("Unexpected {}", self.current_token);
+        }
+        
+        let span = self.lexer.token_span();
+        let single_number = match &self.current_token {
+            Token::Number(n) => Some(n.clone()),
+            _ => None,
+        };
+        
+        let mut parts = Vec::new();
+        let mut token_count = 0;
+        let mut qualifiers = None;
+        loop {
+            // zsh glob qualifiers always end the word
+            if let Token::GlobQualifiers(text) = &self.current_token {
+                qualifiers = Some(parse_glob_qualifiers(text)?);
                 self.advance()?;
-                Ok(ASTNode::String(word, StringType::Unquoted))
+                break;
             }
+            self.parse_word_part(&mut parts)?;
+            token_count += 1;
+            if !self.is_word_token() || self.lexer.space_before() {
+                break;
+            }
+        }
+        
+        if let (Some(n), 1) = (single_number, token_count) {
+            return Ok(ASTNode::Number(n.parse::<f64>().context("Invalid number")?));
+        }
+        
+        let word = if parts.len() == 1 {
+            parts.pop().unwrap()
+        } else {
+            ASTNode::Concat(parts.into_iter().map(Box::new).collect(), span)
+        };
+        
+        match qualifiers {
+            Some(qualifiers) => Ok(ASTNode::QualifiedGlob { pattern: Box::new(word), qualifiers, span }),
+            None => Ok(word),
+        }
+    }
+    
+    fn parse_word_part(&mut self, parts: &mut Vec<ASTNode>) -> Result<()> {
+        let span = self.lexer.token_span();
+        match self.current_token.clone() {
             Token::String(s, quote_type) => {
-                let string = s.clone();
                 let string_type = match quote_type {
                     super::lexer::QuoteType::Single => StringType::SingleQuoted,
-                    super::lexer::QuoteType::Double => StringType::DoubleQuoted,
+                    super::lexer::QuoteType::Double => {
+                        self.advance()?;
+                        let word = self.parse_double_quoted(&s, span)?;
+                        parts.push(word);
+                        return Ok(());
+                    }
                     super::lexer::QuoteType::Ansi => StringType::AnsiC,
                     super::lexer::QuoteType::Backtick => {
+                        let start = Span::new(span.line, span.column + 1);
                         self.advance()?;
-                        return Ok(ASTNode::CommandSubstitution(
-                            Box::new(ASTNode::String(string, StringType::Unquoted))
-                        ));
+                        let body = self.parse_substitution(&unescape_backticks(&s), start)?;
+                        parts.push(ASTNode::CommandSubstitution(Box::new(body), span));
+                        return Ok(());
                     }
                 };
                 self.advance()?;
-                Ok(ASTNode::String(string, string_type))
+                parts.push(ASTNode::String(s, string_type, span));
             }
-            Token::Number(n) => {
-                let num = n.parse::<f64>().context("Invalid number")?;
+            Token::Dollar => {
                 self.advance()?;
-                Ok(ASTNode::Number(num))
+                if self.is_word_token() && !self.lexer.space_before() {
+                    let node = self.parse_variable_or_expansion(span)?;
+                    push_word_part(parts, node);
+                } else {
+                    // A lone `$` is literal
+                    push_word_part(parts, ASTNode::String("$".to_string(), StringType::Unquoted, span));
+                }
             }
-            Token::Dollar => {
+            Token::DollarBrace | Token::DollarParen | Token::DollarDoubleParen => {
+                let node = self.parse_variable_or_expansion(span)?;
+                push_word_part(parts, node);
+            }
+            Token::EqualParen => {
+                let start = self.lexer.current_span();
+                let body = self.lexer.read_paren_body()?;
+                self.advance()?;
+                let body = self.parse_substitution(&body, start)?;
+                parts.push(ASTNode::FileSubstitution(Box::new(body), span));
+            }
+            Token::LessParen => {
+                // The command runs to completion and is read back from a
+                // temporary file, like zsh's `=(cmd)`
+                let start = self.lexer.current_span();
+                self.use_feature(ShellFeature::ProcessSubstitution, span);
+                let body = self.lexer.read_paren_body()?;
+                self.advance()?;
+                let body = self.parse_substitution(&body, start)?;
+                parts.push(ASTNode::FileSubstitution(Box::new(body), span));
+            }
+            Token::GreaterParen => {
+                bail!("Output process substitution `>(...)' is not supported; write to a temporary file and run the command on it");
+            }
+            _ => {
+                let text = self.lexer.token_text().to_string();
                 self.advance()?;
-                self.parse_variable_or_expansion()
+                push_word_part(parts, ASTNode::String(text, StringType::Unquoted, span));
             }
-            _ => bail!("Unexpected token: {:?}", self.current_token),
         }
+        
+        Ok(())
     }
     
-    fn parse_variable_or_expansion(&mut self) -> Result<ASTNode> {
+    /// Parse the expansion whose `$` is at `span`: the current token is the
+    /// name after a lone `$`, or the `${`, `$(` or `$((` itself.
+    fn parse_variable_or_expansion(&mut self, span: Span) -> Result<ASTNode> {
         match &self.current_token {
-            Token::Word(name) => {
-                let var = name.clone();
+            Token::Word(_) | Token::Number(_) => {
+                // `$name` takes the longest identifier, `$1` a single digit; the
+                // rest of the token is literal text (`$1st`, `$dir.bak`)
+                let text = self.lexer.token_text();
+                let end = if text.starts_with(|c: char| c.is_ascii_digit()) {
+                    1
+                } else {
+                    text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                        .unwrap_or(text.len())
+                };
+                let name = text[..end].to_string();
+                let rest = text[end..].to_string();
+                self.advance()?;
+                
+                // zsh subscripts `$name[index]` without braces
+                if rest.is_empty()
+                    && self.dialect == ShellDialect::Zsh
+                    && self.current_token == Token::LeftBracket
+                    && !self.lexer.space_before()
+                {
+                    let index = self.read_subscript()?;
+                    let name_start = Span::new(span.line, span.column + 1);
+                    return self.element_expansion(ASTNode::Variable(format!("{}[{}]", name, index), span), name_start);
+                }
+                
+                let rest_span = Span::new(span.line, span.column + 1 + name.len());
+                let var = ASTNode::Variable(name, span);
+                if rest.is_empty() {
+                    Ok(var)
+                } else {
+                    Ok(ASTNode::Concat(vec![
+                        Box::new(var),
+                        Box::new(ASTNode::String(rest, StringType::Unquoted, rest_span)),
+                    ], span))
+                }
+            }
+            Token::Question | Token::AtSign | Token::Star | Token::Bang | Token::Minus | Token::Hash | Token::Dollar => {
+                let var = self.lexer.token_text().to_string();
+                self.advance()?;
+                Ok(ASTNode::Variable(var, span))
+            }
+            Token::DollarBrace => {
+                // The lexer is positioned just past `${`, so take the raw body
+                // before advancing to the next token.
+                let start = self.lexer.current_span();
+                let body = self.lexer.read_brace_body()?;
+                self.advance()?;
+                if body.starts_with('(') && self.dialect == ShellDialect::Zsh {
+                    return parse_flagged_expansion(&body, span);
+                }
+                self.element_expansion(parse_parameter_expansion(&body, span)?, start)
+            }
+            Token::DollarParen => {
+                let start = self.lexer.current_span();
+                let body = self.lexer.read_paren_body()?;
+                self.advance()?;
+                Ok(ASTNode::CommandSubstitution(Box::new(self.parse_substitution(&body, start)?), span))
+            }
+            Token::DollarDoubleParen => {
+                let start = self.lexer.current_span();
+                let body = self.lexer.read_arith_body()?;
+                self.advance()?;
+                Ok(ASTNode::ArithmeticExpansion(Box::new(self.parse_arithmetic_text(&body, start)?), span))
+            }
+            _ => bail!("Unexpected {} after `$'", self.current_token),
+        }
+    }
+    
+    /// The text between the brackets of a zsh `$name[index]`, with the
+    /// current token at the `[`.
+    fn read_subscript(&mut self) -> Result<String> {
+        self.advance()?;
+        let mut index = String::new();
+        while self.current_token != Token::RightBracket {
+            if !self.is_word_token() || self.lexer.space_before() {
+                bail!("Expected `]' to close the subscript `[{}'", index);
+            }
+            index.push_str(self.lexer.token_text());
+            self.advance()?;
+        }
+        self.advance()?;
+        Ok(index)
+    }
+    
+    /// Turn a variable with an index subscript, `${name[index]}` with the
+    /// name at `name_start`, into an element expansion. `[@]` and `[*]` stay
+    /// part of the name.
+    ///
+    /// In zsh the index is an arithmetic expression so the runtime can count
+    /// from 1, and an index that is not one is kept as written. Elsewhere the
+    /// subscript is a word, expanded at runtime into the key of an
+    /// associative array or an arithmetic index.
+    fn element_expansion(&self, node: ASTNode, name_start: Span) -> Result<ASTNode> {
+        let ASTNode::Variable(name, span) = &node else {
+            return Ok(node);
+        };
+        let span = *span;
+        let Some((array, index)) = name.strip_suffix(']').and_then(|name| name.split_once('[')) else {
+            return Ok(node);
+        };
+        if index == "@" || index == "*" {
+            return Ok(node);
+        }
+        let index_start = advance_span(name_start, &name[..=array.len()]);
+        if self.dialect != ShellDialect::Zsh {
+            return Ok(ASTNode::ParameterExpansion {
+                name: array.to_string(),
+                expansion_type: ExpansionType::Subscript(Box::new(self.parse_subscript(index, index_start)?)),
+                span,
+            });
+        }
+        if split_unnested(index, ',').is_some() {
+            bail!("Array slices like `{}` are not supported", name);
+        }
+        
+        match self.parse_arithmetic_text(index, index_start) {
+            Ok(index) => Ok(ASTNode::ParameterExpansion {
+                name: array.to_string(),
+                expansion_type: ExpansionType::Element(Box::new(index)),
+                span,
+            }),
+            Err(_) => Ok(node),
+        }
+    }
+    
+    /// Parse the text of a bash subscript, `key` in `name[key]`, as a word:
+    /// quotes are removed and `$` expansions are kept to be expanded at
+    /// runtime, like the text of an arithmetic expression. The text starts
+    /// at `start`.
+    fn parse_subscript(&self, text: &str, start: Span) -> Result<ASTNode> {
+        if text.is_empty() {
+            bail!("Bad array subscript: `[]'");
+        }
+        match parse_expansion_word(text, start)? {
+            word @ (ASTNode::Variable(_, _) | ASTNode::ParameterExpansion { .. }) => Ok(word),
+            _ if text.contains(['$', '`']) && !text.contains(['"', '\'']) => self.parse_arithmetic_template(text, start),
+            word => Ok(word),
+        }
+    }
+    
+    /// Parse the body of `$(...)`, `=(...)` or a backtick string, which
+    /// begins at `start` in the script, into a block of statements.
+    ///
+    /// Syntax errors in the body are recovered from like those of the script.
+    fn parse_substitution(&mut self, body: &str, start: Span) -> Result<ASTNode> {
+        let mut parser = ShellParser::starting_at(body.to_string(), self.dialect, start)?;
+        let statements = match parser.parse_script() {
+            ASTNode::Script(statements) => statements,
+            other => vec![Box::new(other)],
+        };
+        for mut error in parser.errors {
+            error.message = format!("in command substitution `{}`: {}", body.trim(), error.message);
+            self.errors.push(error);
+        }
+        self.warnings.append(&mut parser.warnings);
+        
+        Ok(ASTNode::Block(statements))
+    }
+    
+    /// Parse the text of an arithmetic expression, which begins at `start`.
+    ///
+    /// Bash expands `$` and backtick substitutions in the text before parsing
+    /// it, so an expression containing them is kept as a word (text and
+    /// expansion nodes) that is expanded and evaluated at runtime.
+    fn parse_arithmetic_text(&self, text: &str, start: Span) -> Result<ASTNode> {
+        let result = if text.contains(['$', '`']) {
+            self.parse_arithmetic_template(text, start)
+        } else {
+            parse_arithmetic(text, start)
+        };
+        result.with_context(|| format!("in arithmetic expression `{}`", text.trim()))
+    }
+    
+    fn parse_arithmetic_template(&self, text: &str, start: Span) -> Result<ASTNode> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut literal_start = start;
+        let mut rest = text;
+        let mut position = start;
+        
+        while let Some(c) = rest.chars().next() {
+            let len = match c {
+                '$' | '`' => expansion_len(rest, self.dialect),
+                _ => 0,
+            };
+            if len == 0 {
+                // Quotes are removed before the expression is evaluated
+                if c != '"' {
+                    if literal.is_empty() {
+                        literal_start = position;
+                    }
+                    literal.push(c);
+                }
+                position = advance_span(position, &rest[..c.len_utf8()]);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            
+            if !literal.is_empty() {
+                push_word_part(&mut parts, ASTNode::String(std::mem::take(&mut literal), StringType::Unquoted, literal_start));
+            }
+            let mut parser = ShellParser::starting_at(rest[..len].to_string(), self.dialect, position)?;
+            push_word_part(&mut parts, parser.parse_word()?);
+            position = advance_span(position, &rest[..len]);
+            rest = &rest[len..];
+        }
+        if !literal.is_empty() {
+            push_word_part(&mut parts, ASTNode::String(literal, StringType::Unquoted, literal_start));
+        }
+        
+        Ok(ASTNode::Concat(parts.into_iter().map(Box::new).collect(), start))
+    }
+    
+    /// Parse a word that was written inside `${...}`, like the default of
+    /// `${name:-word}` or the pattern of `${name%pattern}`, and begins at
+    /// `start`: quoted text and `\\` escapes become quoted strings, `$` and
+    /// backtick expansions are parsed, and the rest, blanks included, is
+    /// unquoted text.
+    pub fn parse_word_text(text: &str, dialect: ShellDialect, start: Span) -> Result<ASTNode> {
+        let mut parts = Vec::new();
+        let mut rest = text;
+        let mut position = start;
+        while let Some(c) = rest.chars().next() {
+            let len = match c {
+                '\\' => rest[1..].chars().next().map_or(1, |escaped| 1 + escaped.len_utf8()),
+                '\'' => rest[1..].find('\'').map_or(0, |end| end + 2),
+                '"' => quoted_len(rest, dialect),
+                '$' | '`' => expansion_len(rest, dialect),
+                _ => 0,
+            };
+            if len == 0 || (c == '\\' && len == 1) {
+                push_word_part(&mut parts, ASTNode::String(c.to_string(), StringType::Unquoted, position));
+                position = advance_span(position, &rest[..c.len_utf8()]);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            
+            let part = match c {
+                '\\' => ASTNode::String(rest[1..len].to_string(), StringType::SingleQuoted, position),
+                '\'' => ASTNode::String(rest[1..len - 1].to_string(), StringType::SingleQuoted, position),
+                _ => ShellParser::starting_at(rest[..len].to_string(), dialect, position)?.parse_word()?,
+            };
+            push_word_part(&mut parts, part);
+            position = advance_span(position, &rest[..len]);
+            rest = &rest[len..];
+        }
+        
+        Ok(match parts.len() {
+            0 => ASTNode::String(String::new(), StringType::Unquoted, start),
+            1 => parts.pop().unwrap(),
+            _ => ASTNode::Concat(parts.into_iter().map(Box::new).collect(), start),
+        })
+    }
+    
+    /// Parse the body of a here-document with an unquoted delimiter, which
+    /// begins at `start`, into literal text and expansions.
+    ///
+    /// `$` and backtick expansions are expanded, a backslash only quotes `$`,
+    /// `` ` ``, `\` and newline, and quotes are ordinary characters.
+    fn parse_heredoc_body(&mut self, text: &str, start: Span) -> Result<ASTNode> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut literal_start = start;
+        let mut rest = text;
+        let mut position = start;
+        
+        while let Some(c) = rest.chars().next() {
+            if literal.is_empty() {
+                literal_start = position;
+            }
+            let len = match c {
+                '$' | '`' => expansion_len(rest, self.dialect),
+                '\\' => {
+                    let len = match rest[1..].chars().next() {
+                        Some(escaped @ ('$' | '`' | '\\')) => {
+                            literal.push(escaped);
+                            2
+                        }
+                        Some('\n') => 2,
+                        _ => {
+                            literal.push('\\');
+                            1
+                        }
+                    };
+                    position = advance_span(position, &rest[..len]);
+                    rest = &rest[len..];
+                    continue;
+                }
+                _ => 0,
+            };
+            if len == 0 {
+                literal.push(c);
+                position = advance_span(position, &rest[..c.len_utf8()]);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            
+            if !literal.is_empty() {
+                parts.push(ASTNode::String(std::mem::take(&mut literal), StringType::SingleQuoted, literal_start));
+            }
+            let mut parser = ShellParser::starting_at(rest[..len].to_string(), self.dialect, position)?;
+            let word = parser.parse_word();
+            self.errors.append(&mut parser.errors);
+            self.warnings.append(&mut parser.warnings);
+            parts.push(word?);
+            position = advance_span(position, &rest[..len]);
+            rest = &rest[len..];
+        }
+        if !literal.is_empty() {
+            parts.push(ASTNode::String(literal, StringType::SingleQuoted, literal_start));
+        }
+        
+        Ok(match parts.len() {
+            0 => ASTNode::String(String::new(), StringType::SingleQuoted, start),
+            1 => parts.remove(0),
+            _ => ASTNode::Concat(parts.into_iter().map(Box::new).collect(), start),
+        })
+    }
+    
+    /// Parse the text between double quotes, with the opening quote at
+    /// `quote`, into literal text and expansions.
+    ///
+    /// A backslash only quotes `$`, `` ` ``, `"`, `\` and newline. Text
+    /// without expansions stays a single `DoubleQuoted` string.
+    fn parse_double_quoted(&mut self, text: &str, quote: Span) -> Result<ASTNode> {
+        let start = Span::new(quote.line, quote.column + 1);
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut literal_start = start;
+        let mut rest = text;
+        let mut position = start;
+        
+        while let Some(c) = rest.chars().next() {
+            if literal.is_empty() {
+                literal_start = position;
+            }
+            let len = match c {
+                '$' | '`' => expansion_len(rest, self.dialect),
+                '\\' => {
+                    let len = match rest[1..].chars().next() {
+                        Some(escaped @ ('$' | '`' | '"' | '\\')) => {
+                            literal.push(escaped);
+                            2
+                        }
+                        Some('\n') => 2,
+                        _ => {
+                            literal.push('\\');
+                            1
+                        }
+                    };
+                    position = advance_span(position, &rest[..len]);
+                    rest = &rest[len..];
+                    continue;
+                }
+                _ => 0,
+            };
+            if len == 0 {
+                literal.push(c);
+                position = advance_span(position, &rest[..c.len_utf8()]);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            
+            if !literal.is_empty() {
+                parts.push(Box::new(ASTNode::String(std::mem::take(&mut literal), StringType::DoubleQuoted, literal_start)));
+            }
+            let mut parser = ShellParser::starting_at(rest[..len].to_string(), self.dialect, position)?;
+            let word = parser.parse_word();
+            self.errors.append(&mut parser.errors);
+            self.warnings.append(&mut parser.warnings);
+            parts.push(Box::new(word?));
+            position = advance_span(position, &rest[..len]);
+            rest = &rest[len..];
+        }
+        
+        if parts.is_empty() {
+            return Ok(ASTNode::String(literal, StringType::DoubleQuoted, quote));
+        }
+        if !literal.is_empty() {
+            parts.push(Box::new(ASTNode::String(literal, StringType::DoubleQuoted, literal_start)));
+        }
+        Ok(ASTNode::Quoted(parts, quote))
+    }
+    
+    /// Turn a `let` argument, which starts at `start`, into an arithmetic
+    /// expression. Literal text is parsed now; words with expansions are
+    /// evaluated at runtime.
+    fn arithmetic_from_word(&self, word: ASTNode, start: Span) -> Result<ASTNode> {
+        let text = match &word {
+            ASTNode::String(text, _, _) => Some(text.clone()),
+            ASTNode::Number(n) => Some(n.to_string()),
+            ASTNode::Concat(parts, _) => parts
+                .iter()
+                .map(|part| match part.as_ref() {
+                    ASTNode::String(text, _, _) => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Option<String>>(),
+            _ => None,
+        };
+        
+        match text {
+            Some(text) => self.parse_arithmetic_text(&text, start),
+            None => Ok(word),
+        }
+    }
+    
+    /// Whether the current token starts a redirection, including an `n>` or
+    /// `n<` file descriptor prefix.
+    fn at_redirection(&mut self) -> bool {
+        match &self.current_token {
+            Token::Redirect(_) | Token::Heredoc(_) | Token::HereString => true,
+            Token::Number(n) if n.chars().all(|c| c.is_ascii_digit()) => {
+                let next = self.lexer.lookahead(1);
+                next == ">" || next == "<"
+            }
+            _ => false,
+        }
+    }
+    
+    /// Parse one redirection operator and its target.
+    ///
+    /// `&>file` and `>&file` come back as two redirections, `>file` then
+    /// `2>&1`, which is how bash defines them.
+    fn parse_redirection(&mut self) -> Result<Vec<Redirection>> {
+        let fd = match &self.current_token {
+            Token::Number(n) => {
+                let fd = n.parse::<i32>().context("Invalid file descriptor")?;
                 self.advance()?;
-                Ok(ASTNode::Variable(var))
+                Some(fd)
+            }
+            _ => None,
+        };
+        
+        let op = self.current_token.clone();
+        if matches!(op, Token::Redirect(RedirectOp::AllOut | RedirectOp::AllAppend)) {
+            self.use_feature(ShellFeature::CombinedRedirect, self.lexer.token_span());
+        }
+        self.advance()?;
+        
+        let redirect = |direction, target, append| Redirection { fd, direction, target, append };
+        let redirection = match op {
+            Token::Redirect(RedirectOp::Out) => {
+                redirect(RedirectDirection::Output, self.parse_redirection_file()?, false)
+            }
+            Token::Redirect(RedirectOp::OutAppend) => {
+                redirect(RedirectDirection::Output, self.parse_redirection_file()?, true)
+            }
+            Token::Redirect(RedirectOp::Clobber) => {
+                redirect(RedirectDirection::Clobber, self.parse_redirection_file()?, false)
             }
-            Token::LeftBrace => {
-                // TODO: Parse parameter expansion
-                bail!("Parameter expansion not yet implemented")
+            Token::Redirect(RedirectOp::In) => {
+                redirect(RedirectDirection::Input, self.parse_redirection_file()?, false)
+            }
+            Token::Redirect(RedirectOp::InOut) => {
+                redirect(RedirectDirection::ReadWrite, self.parse_redirection_file()?, false)
+            }
+            Token::Redirect(op @ (RedirectOp::OutErr | RedirectOp::InDup)) => {
+                let direction = if op == RedirectOp::InDup {
+                    RedirectDirection::Input
+                } else {
+                    RedirectDirection::Output
+                };
+                
+                match &self.current_token {
+                    Token::Number(n) => {
+                        let target = n.parse::<i32>().context("Invalid file descriptor")?;
+                        self.advance()?;
+                        redirect(direction, RedirectionTarget::Fd(target), false)
+                    }
+                    Token::Minus => {
+                        self.advance()?;
+                        redirect(direction, RedirectionTarget::Close, false)
+                    }
+                    _ if op == RedirectOp::OutErr && fd.is_none() => {
+                        return self.parse_output_and_error(false);
+                    }
+                    other => bail!("Expected file descriptor after redirection, found {}", other),
+                }
             }
-            Token::LeftParen => {
-                // TODO: Parse command substitution
-                bail!("Command substitution not yet implemented")
+            Token::Redirect(RedirectOp::AllOut) => return self.parse_output_and_error(false),
+            Token::Redirect(RedirectOp::AllAppend) => return self.parse_output_and_error(true),
+            Token::Heredoc(_) => {
+                let heredoc = self.lexer.take_heredoc().context("Missing here-document body")?;
+                let content = if heredoc.quoted {
+                    ASTNode::String(heredoc.content, StringType::SingleQuoted, heredoc.span)
+                } else {
+                    self.parse_heredoc_body(&heredoc.content, heredoc.span)?
+                };
+                redirect(
+                    RedirectDirection::Input,
+                    RedirectionTarget::Heredoc {
+                        delimiter: heredoc.delimiter,
+                        content: Box::new(content),
+                        strip_tabs: heredoc.strip_tabs,
+                    },
+                    false,
+                )
             }
-            _ => bail!("Unexpected token after $: {:?}", self.current_token),
+            Token::HereString => {
+                let word = self.parse_redirection_word()?;
+                redirect(RedirectDirection::Input, RedirectionTarget::HereString(Box::new(word)), false)
+            }
+            other => bail!("Unsupported redirection {}", other),
+        };
+        
+        Ok(vec![redirection])
+    }
+    
+    /// `&>file`, `&>>file` and `>&file`: send both stdout and stderr to a file.
+    fn parse_output_and_error(&mut self, append: bool) -> Result<Vec<Redirection>> {
+        Ok(vec![
+            Redirection {
+                fd: Some(1),
+                direction: RedirectDirection::Output,
+                target: self.parse_redirection_file()?,
+                append,
+            },
+            Redirection {
+                fd: Some(2),
+                direction: RedirectDirection::Output,
+                target: RedirectionTarget::Fd(1),
+                append: false,
+            },
+        ])
+    }
+    
+    fn parse_redirection_file(&mut self) -> Result<RedirectionTarget> {
+        Ok(RedirectionTarget::File(Box::new(self.parse_redirection_word()?)))
+    }
+    
+    fn parse_redirection_word(&mut self) -> Result<ASTNode> {
+        if !self.is_word_token() {
+            bail!("Expected redirection target, found {}", self.current_token);
+        }
+        self.parse_word()
+    }
+    
+    /// Name of the command starting at the current token, if any.
+    ///
+    /// Builtins such as `echo` or `cd` are lexed as keyword tokens and paths
+    /// start with `/` or `.`, so those are accepted alongside plain words.
+    fn command_name(&self) -> Option<String> {
+        match &self.current_token {
+            Token::Word(_) | Token::Number(_) | Token::Echo | Token::Printf |
+            Token::Read | Token::Cd | Token::Pwd | Token::Source | Token::Dot |
+            Token::Exec | Token::Eval | Token::Let | Token::Declare |
+            Token::Typeset | Token::Select | Token::Time | Token::Slash |
+            Token::Tilde | Token::LeftBracket => Some(self.lexer.token_text().to_string()),
+            _ => None,
+        }
+    }
+    
+    /// Whether the current token can be part of a shell word.
+    fn is_word_token(&self) -> bool {
+        !matches!(
+            self.current_token,
+            Token::Pipe | Token::PipeErr | Token::Redirect(_) | Token::Background |
+            Token::Semicolon | Token::DoubleSemicolon | Token::SemicolonAnd |
+            Token::DoubleSemicolonAnd | Token::Newline | Token::And | Token::Or |
+            Token::LeftParen | Token::DoubleLeftParen | Token::RightParen | Token::Heredoc(_) |
+            Token::HereString | Token::Eof
+        ) && !self.at_zsh_close_brace()
+    }
+    
+    /// A `}` standing alone closes zsh's braces even without a `;` before it.
+    fn at_zsh_close_brace(&self) -> bool {
+        self.dialect == ShellDialect::Zsh
+            && self.current_token == Token::RightBrace
+            && self.lexer.space_before()
+            && (self.lexer.space_after() || self.lexer.lookahead(1).starts_with([';', '|', '&', ')', '}']))
+    }
+    
+    /// Source text of the current token when it is plain literal text.
+    fn literal_text(&self) -> Option<&'static str> {
+        match self.current_token {
+            Token::String(..) | Token::Dollar | Token::DollarBrace | Token::DollarParen |
+            Token::DollarDoubleParen | Token::Backtick | Token::EqualParen |
+            Token::LessParen | Token::GreaterParen |
+            Token::GlobQualifiers(_) => None,
+            _ if self.is_word_token() => Some(self.lexer.token_text()),
+            _ => None,
         }
     }
     
@@ -520,7 +2050,7 @@ impl ShellParser {
                 continue;
             }
             
-            statements.push(Box::new(self.parse_statement()?));
+            statements.push(Box::new(self.parse_statement_or_recover()));
             self.skip_terminators();
         }
         
@@ -534,22 +2064,707 @@ impl ShellParser {
     
     fn expect(&mut self, expected: Token) -> Result<()> {
         if std::mem::discriminant(&self.current_token) != std::mem::discriminant(&expected) {
-            bail!("Expected {:?}, found {:?}", expected, self.current_token);
+            bail!("Expected {}, found {}", expected, self.current_token);
         }
         self.advance()
     }
     
     fn skip_newlines(&mut self) {
         while self.current_token == Token::Newline {
-            let _ = self.advance();
+            self.skip_token();
         }
     }
     
     fn skip_terminators(&mut self) {
         while matches!(self.current_token, Token::Semicolon | Token::Newline) {
-            let _ = self.advance();
+            self.skip_token();
+        }
+    }
+    
+    /// Record a warning if the dialect lacks `feature`, used at `span`.
+    fn use_feature(&mut self, feature: ShellFeature, span: Span) {
+        if !self.dialect.supports_feature(feature) {
+            self.warnings.push(unsupported(self.dialect, feature, span));
+        }
+    }
+    
+    /// Advance past a separator token. The lexer only fails on text that is
+    /// never closed, so its error is recorded and the rest of the script is
+    /// given up on.
+    fn skip_token(&mut self) {
+        if let Err(error) = self.advance() {
+            let error = self.diagnostic(error);
+            self.errors.push(error);
+            self.skipped_to_end = true;
+        }
+    }
+}
+
+use super::ast::{
+    StringType, ForItems, ExpansionType, CaseItem, CaseTerminator, GlobQualifier, ParameterFlag,
+    Redirection, RedirectDirection, RedirectionTarget, BinaryOperator, UnaryOperator,
+    VariableAttributes,
+};
+use super::arithmetic::parse_arithmetic;
+use super::lexer::RedirectOp;
+use super::test_expression;
+
+/// Whether an error with `message` is about text that is never closed, which
+/// the rest of the script was read into.
+fn unterminated(message: &str) -> bool {
+    message.contains("Unterminated")
+}
+
+/// Likely cause of a syntax error with `message`, found at `token`.
+fn hint(token: &Token, message: &str) -> String {
+    if unterminated(message) {
+        return message_hint(message);
+    }
+    
+    let hint = match token {
+        Token::Then | Token::Elif | Token::Else | Token::Fi => "this keyword is only valid in an `if` statement",
+        Token::Do | Token::Done => "this keyword is only valid in a `for`, `while` or `until` loop",
+        Token::Esac | Token::DoubleSemicolon | Token::SemicolonAnd | Token::DoubleSemicolonAnd => {
+            "this is only valid in a `case` statement"
+        }
+        Token::RightBrace | Token::RightParen => "there is no group or subshell open here",
+        Token::Eof => "the script ends in the middle of a command",
+        _ => return message_hint(message),
+    };
+    hint.to_string()
+}
+
+/// Likely cause of a syntax error with `message`, by the kind of error.
+pub(super) fn message_hint(message: &str) -> String {
+    let hint = if unterminated(message) {
+        "the rest of the script was read as part of it; add the missing closing quote or bracket"
+    } else if message.contains("not supported") || message.contains("Unsupported") || message.contains("supports") {
+        "the converter has no translation for this; rewrite it with a construct it supports"
+    } else if message.contains("arithmetic") || message.contains("operand") || message.contains("integer") {
+        "arithmetic takes integers, variable names and C operators like `+`, `<<` and `==`"
+    } else if message.starts_with("Unexpected") {
+        "quote it to use it as text"
+    } else if message.contains("xpected") {
+        "something is missing or misplaced before this point; check the statement against the shell's syntax"
+    } else {
+        "the statement is skipped; fix it to convert the script"
+    };
+    hint.to_string()
+}
+
+/// Turn the arguments of `[` or `test` into a test expression.
+pub(super) fn test_command(name: &str, mut args: Vec<ASTNode>, span: Span) -> Result<ASTNode> {
+    if name == "[" {
+        match args.pop() {
+            Some(ASTNode::String(close, _, _)) if close == "]" => {}
+            _ => bail!("[: missing `]'"),
+        }
+    }
+    // `test` compares text, so escapes are just quoting, and `\<` is the
+    // operator `<`
+    let args = args.into_iter()
+        .map(|arg| match arg {
+            ASTNode::String(text, StringType::Unquoted, span) if text.contains('\\') => {
+                ASTNode::String(unescape_text(&text), StringType::SingleQuoted, span)
+            }
+            other => other,
+        })
+        .collect();
+    
+    let expr = test_expression::parse_test_args(args).with_context(|| format!("in `{}` expression", name))?;
+    Ok(ASTNode::Test { expr: Box::new(expr), extended: false, span })
+}
+
+/// Unquoted text without its backslash escapes.
+pub fn unescape_text(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Text of a word that is a single unquoted literal, the only form in
+/// which `[[ ]]` recognizes operators.
+fn unquoted_literal(word: &ASTNode) -> Option<&str> {
+    match word {
+        ASTNode::String(text, StringType::Unquoted, _) => Some(text),
+        _ => None,
+    }
+}
+
+/// A word in a word list that undergoes pathname expansion: command
+/// arguments, `for` items and array elements. An unquoted literal with an
+/// unescaped `*`, `?` or `[...]` becomes a `Glob`.
+fn glob_word(word: ASTNode) -> ASTNode {
+    let ASTNode::String(text, StringType::Unquoted, span) = &word else {
+        return word;
+    };
+    let mut chars = text.chars();
+    let mut pattern = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' => pattern = true,
+            // A lone `[` is literal
+            '[' => pattern |= chars.as_str().contains(']'),
+            _ => {}
+        }
+    }
+    if pattern {
+        ASTNode::Glob(text.clone(), *span)
+    } else {
+        word
+    }
+}
+
+/// Add a `2>&1` redirection to a pipeline stage.
+pub(super) fn merge_stderr(stage: ASTNode) -> ASTNode {
+    let stderr_to_stdout = Redirection {
+        fd: Some(2),
+        direction: RedirectDirection::Output,
+        target: RedirectionTarget::Fd(1),
+        append: false,
+    };
+    
+    match stage {
+        ASTNode::Command { name, args, mut redirections, background, span } => {
+            redirections.push(stderr_to_stdout);
+            ASTNode::Command { name, args, redirections, background, span }
+        }
+        ASTNode::Redirected { body, mut redirections } => {
+            redirections.push(stderr_to_stdout);
+            ASTNode::Redirected { body, redirections }
+        }
+        other => ASTNode::Redirected {
+            body: Box::new(other),
+            redirections: vec![stderr_to_stdout],
+        },
+    }
+}
+
+/// Backslash-escape every character that is special in a glob pattern.
+pub(super) fn escape_glob(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\' | '(' | ')' | '|' | '$') {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// Undo the backslash escaping of a backtick command substitution body.
+///
+/// Inside backticks `\\`, `\`` and `\$` stand for the escaped character, so a
+/// nested `\`...\`` becomes a plain backtick pair for the inner parser. Every
+/// other backslash is kept as written.
+fn unescape_backticks(body: &str) -> String {
+    let mut result = String::with_capacity(body.len());
+    let mut chars = body.chars().peekable();
+    
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(&next) = chars.peek() {
+                if matches!(next, '\\' | '`' | '$') {
+                    result.push(next);
+                    chars.next();
+                    continue;
+                }
+            }
+        }
+        result.push(c);
+    }
+    
+    result
+}
+
+/// Recovery from syntax errors one statement at a time, the way
+/// `ShellParser::parse_with_diagnostics` does it, for the parsers of the
+/// dialects with a grammar of their own.
+pub(super) trait RecoveringParser {
+    /// Where an error that a nested parser didn't locate is reported.
+    fn error_span(&self) -> Span;
+    
+    fn at_end(&self) -> bool;
+    
+    fn errors_mut(&mut self) -> &mut Vec<ParseError>;
+    
+    /// Whether a bad statement already read the rest of the script.
+    fn skipped_to_end_mut(&mut self) -> &mut bool;
+    
+    /// Turn an error into a `ParseError` at the current position, unless it
+    /// already is one.
+    fn diagnostic(&self, error: anyhow::Error) -> ParseError {
+        match error.downcast::<ParseError>() {
+            Ok(error) => error,
+            Err(error) => {
+                let message = format!("{:#}", error);
+                ParseError { span: self.error_span(), hint: Some(message_hint(&message)), message }
+            }
+        }
+    }
+    
+    /// Record the `error` of the statement at `span`, `skip` the rest of the
+    /// statement and return the error node that stands in for it. Errors in
+    /// the skipped text are not reported.
+    fn recover(&mut self, span: Span, error: anyhow::Error, skip: impl FnOnce(&mut Self)) -> ASTNode
+    where
+        Self: Sized,
+    {
+        let error = self.diagnostic(error);
+        let node = ASTNode::Error { message: error.message.clone(), span };
+        let read_to_end = unterminated(&error.message);
+        if !(*self.skipped_to_end_mut() && self.at_end()) {
+            self.errors_mut().push(error);
+        }
+        skip(self);
+        let at_end = self.at_end();
+        *self.skipped_to_end_mut() |= read_to_end && at_end;
+        node
+    }
+}
+
+pub(super) fn unterminated_string(start: Span) -> anyhow::Error {
+    ParseError {
+        span: start,
+        message: "Unterminated string".to_string(),
+        hint: Some("the quote opened here is not closed".to_string()),
+    }.into()
+}
+
+/// Text of a word without expansions.
+pub(super) fn literal(word: &ASTNode) -> Option<&str> {
+    match word {
+        ASTNode::String(text, _, _) => Some(text),
+        _ => None,
+    }
+}
+
+/// Append a part to a word, merging adjacent literal text and flattening
+/// nested concatenations.
+pub(super) fn push_word_part(parts: &mut Vec<ASTNode>, part: ASTNode) {
+    match part {
+        ASTNode::Concat(inner, _) => {
+            for p in inner {
+                push_word_part(parts, *p);
+            }
+        }
+        ASTNode::String(text, StringType::Unquoted, span) => {
+            if let Some(ASTNode::String(prev, StringType::Unquoted, _)) = parts.last_mut() {
+                prev.push_str(&text);
+            } else {
+                parts.push(ASTNode::String(text, StringType::Unquoted, span));
+            }
+        }
+        other => parts.push(other),
+    }
+}
+
+/// Position just past `text` when it starts at `span`.
+pub(super) fn advance_span(span: Span, text: &str) -> Span {
+    text.chars().fold(span, |span, c| match c {
+        '\n' => Span::new(span.line + 1, 1),
+        _ => Span::new(span.line, span.column + 1),
+    })
+}
+
+/// Length of the double-quoted string at the start of `text`, quotes
+/// included, or 0 when it isn't closed.
+fn quoted_len(text: &str, dialect: ShellDialect) -> usize {
+    let mut i = 1;
+    while let Some(c) = text[i..].chars().next() {
+        match c {
+            '"' => return i + 1,
+            '\\' => i += 1 + text[i + 1..].chars().next().map_or(0, char::len_utf8),
+            '$' | '`' if expansion_len(&text[i..], dialect) > 0 => i += expansion_len(&text[i..], dialect),
+            _ => i += c.len_utf8(),
+        }
+    }
+    0
+}
+
+/// Length of the `$` or backtick expansion at the start of `text`, or 0 when
+/// the `$` is literal. zsh's `$name[index]` takes its subscript along.
+fn expansion_len(text: &str, dialect: ShellDialect) -> usize {
+    let bytes = text.as_bytes();
+    if bytes[0] == b'`' {
+        return text[1..].find('`').map(|end| end + 2).unwrap_or(0);
+    }
+    
+    match bytes.get(1) {
+        Some(b'{') => find_closing_brace(&text[2..]).map(|end| end + 3).unwrap_or(0),
+        Some(b'(') => {
+            // Parentheses in quotes and escapes don't count
+            let mut lexer = Lexer::new(&text[2..], dialect);
+            lexer.read_paren_body().map_or(0, |_| lexer.offset() + 2)
+        }
+        Some(c) if c.is_ascii_alphabetic() || *c == b'_' => {
+            let end = text[1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .map(|end| end + 1)
+                .unwrap_or(text.len());
+            let subscript = match text[end..].strip_prefix('[') {
+                Some(rest) if dialect == ShellDialect::Zsh => rest.find(']').map_or(0, |close| close + 2),
+                _ => 0,
+            };
+            end + subscript
+        }
+        Some(c) if c.is_ascii_digit() || b"@*#?-$!".contains(c) => 2,
+        _ => 0,
+    }
+}
+
+/// Parse the body of a `${...}` expansion (without the surrounding braces)
+/// that starts at `span`.
+///
+/// Handles `${#var}`, `${!var}`, `${!arr[@]}`, the `:-`/`:=`/`:?`/`:+` family
+/// (with or without the colon), `${var:offset:length}`, prefix/suffix removal
+/// and `${var/pattern/replacement}`. Anchored replacements keep their `#`/`%`
+/// marker at the start of the pattern, as written in bash.
+fn parse_parameter_expansion(body: &str, span: Span) -> Result<ASTNode> {
+    if body.is_empty() {
+        bail!("Bad substitution: ${{}}");
+    }
+    // Where `text`, the end of the body, starts in the script
+    let at = |text: &str| advance_span(Span::new(span.line, span.column + 2), &body[..body.len() - text.len()]);
+    
+    // ${#} and ${!} are the special parameters themselves
+    if body == "#" || body == "!" {
+        return Ok(ASTNode::Variable(body.to_string(), span));
+    }
+    
+    if let Some(rest) = body.strip_prefix('#') {
+        let (name, remainder) = split_parameter_name(rest)?;
+        if !remainder.is_empty() {
+            bail!("Bad substitution: ${{{}}}", body);
+        }
+        return Ok(ASTNode::ParameterExpansion {
+            name,
+            expansion_type: ExpansionType::Length,
+            span,
+        });
+    }
+    
+    if let Some(rest) = body.strip_prefix('!') {
+        let (name, remainder) = split_parameter_name(rest)?;
+        if !remainder.is_empty() {
+            bail!("Bad substitution: ${{{}}}", body);
+        }
+        if let Some(array) = name.strip_suffix("[@]").or_else(|| name.strip_suffix("[*]")) {
+            return Ok(ASTNode::ParameterExpansion {
+                name: array.to_string(),
+                expansion_type: ExpansionType::Keys,
+                span,
+            });
+        }
+        return Ok(ASTNode::ParameterExpansion {
+            name,
+            expansion_type: ExpansionType::Indirect,
+            span,
+        });
+    }
+    
+    let (name, rest) = split_parameter_name(body)?;
+    if rest.is_empty() {
+        return Ok(ASTNode::Variable(name, span));
+    }
+    
+    let colon = rest.starts_with(':');
+    let test = rest.strip_prefix(':').unwrap_or(rest);
+    let expansion_type = if let Some(word) = test.strip_prefix('-') {
+        ExpansionType::Default { word: Box::new(parse_expansion_word(word, at(word))?), colon }
+    } else if let Some(word) = test.strip_prefix('=') {
+        ExpansionType::Assign { word: Box::new(parse_expansion_word(word, at(word))?), colon }
+    } else if let Some(word) = test.strip_prefix('?') {
+        ExpansionType::Error { message: unquote(word), colon }
+    } else if let Some(word) = test.strip_prefix('+') {
+        ExpansionType::Alternative { word: Box::new(parse_expansion_word(word, at(word))?), colon }
+    } else if let Some(range) = rest.strip_prefix(':') {
+        let (offset, length) = match split_unnested(range, ':') {
+            Some((offset, length)) => (offset, Some(length)),
+            None => (range, None),
+        };
+        ExpansionType::Substring {
+            offset: Box::new(parse_offset(offset, at(range))),
+            length: length.map(|l| Box::new(parse_offset(l, at(l)))),
+        }
+    } else if let Some(pattern) = rest.strip_prefix("##") {
+        ExpansionType::RemovePrefixLong(pattern.to_string())
+    } else if let Some(pattern) = rest.strip_prefix('#') {
+        ExpansionType::RemovePrefix(pattern.to_string())
+    } else if let Some(pattern) = rest.strip_prefix("%%") {
+        ExpansionType::RemoveSuffixLong(pattern.to_string())
+    } else if let Some(pattern) = rest.strip_prefix('%') {
+        ExpansionType::RemoveSuffix(pattern.to_string())
+    } else if let Some(spec) = rest.strip_prefix('/') {
+        let (global, spec) = match spec.strip_prefix('/') {
+            Some(spec) => (true, spec),
+            None => (false, spec),
+        };
+        let (pattern, replacement) = split_unnested(spec, '/').unwrap_or((spec, ""));
+        ExpansionType::Replace {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            global,
+        }
+    } else {
+        bail!("Unsupported parameter expansion: ${{{}}}", body);
+    };
+    
+    Ok(ASTNode::ParameterExpansion { name, expansion_type, span })
+}
+
+/// Parse zsh's `${(flags)name}` at `span`; `body` is the text between the
+/// braces.
+///
+/// Flags only apply to a plain name here, not to subscripts or other
+/// expansions like `${(U)name:-default}`.
+fn parse_flagged_expansion(body: &str, span: Span) -> Result<ASTNode> {
+    let mut flags = Vec::new();
+    let mut chars = body[1..].char_indices();
+    let name_start = loop {
+        let Some((i, c)) = chars.next() else {
+            bail!("Unterminated parameter flags in ${{{}}}", body);
+        };
+        flags.push(match c {
+            ')' => break i + 2,
+            'j' | 's' => {
+                // The separator is delimited by any character, or by a pair
+                // of brackets
+                let open = chars.next().map(|(_, c)| c);
+                let close = match open {
+                    Some('(') => ')',
+                    Some('[') => ']',
+                    Some('{') => '}',
+                    Some('<') => '>',
+                    Some(c) => c,
+                    None => bail!("Unterminated parameter flags in ${{{}}}", body),
+                };
+                let mut separator = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, c)) if c == close => break,
+                        Some((_, c)) => separator.push(c),
+                        None => bail!("Unterminated separator of the `{}` flag in ${{{}}}", c, body),
+                    }
+                }
+                if c == 'j' {
+                    ParameterFlag::Join(separator)
+                } else {
+                    ParameterFlag::Split(separator)
+                }
+            }
+            'F' => ParameterFlag::JoinLines,
+            'f' => ParameterFlag::SplitLines,
+            'U' => ParameterFlag::Upper,
+            'L' => ParameterFlag::Lower,
+            'C' => ParameterFlag::Capitalize,
+            'o' => ParameterFlag::Sort,
+            'O' => ParameterFlag::SortDescending,
+            'u' => ParameterFlag::Unique,
+            // Elements are always kept apart until they are joined
+            '@' => continue,
+            c => bail!("Unsupported parameter flag `{}` in ${{{}}}", c, body),
+        });
+    };
+    
+    let (name, rest) = split_parameter_name(&body[name_start..])?;
+    if !rest.is_empty() || name.contains('[') {
+        bail!("Parameter flags are only supported on a plain name: ${{{}}}", body);
+    }
+    Ok(ASTNode::ParameterExpansion {
+        name,
+        expansion_type: ExpansionType::Flags(flags),
+        span,
+    })
+}
+
+/// Parse the qualifiers of a zsh glob, the text between the parentheses
+/// of `*(.om[1,3])`.
+fn parse_glob_qualifiers(text: &str) -> Result<Vec<GlobQualifier>> {
+    let mut qualifiers = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        qualifiers.push(match c {
+            '.' | '/' | '@' | '*' | '=' | 'p' => GlobQualifier::FileType(c),
+            '^' => GlobQualifier::Not,
+            'N' => GlobQualifier::NullGlob,
+            'D' => GlobQualifier::DotGlob,
+            'o' | 'O' => match chars.next() {
+                Some(key @ ('n' | 'm' | 'L')) => GlobQualifier::Sort { key, descending: c == 'O' },
+                other => bail!(
+                    "Unsupported glob qualifier `{}{}` in `({})`",
+                    c, other.map(String::from).unwrap_or_default(), text
+                ),
+            },
+            '[' => {
+                let range: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                let bound = |n: &str| n.trim().parse::<i64>().ok().filter(|&n| n != 0);
+                match range.split_once(',') {
+                    None => bound(&range).map(|n| GlobQualifier::Range(n, n)),
+                    Some((first, last)) => bound(first).zip(bound(last)).map(|(a, b)| GlobQualifier::Range(a, b)),
+                }
+                .with_context(|| format!("Bad glob qualifier range `[{}]` in `({})`", range, text))?
+            }
+            _ => bail!("Unsupported glob qualifier `{}` in `({})`", c, text),
+        });
+    }
+    Ok(qualifiers)
+}
+
+/// Split a parameter name (including any `[subscript]`) off the front of an
+/// expansion body, returning the name and the remaining operator text.
+fn split_parameter_name(body: &str) -> Result<(String, &str)> {
+    let mut chars = body.char_indices().peekable();
+    let end = match chars.peek() {
+        Some(&(_, c)) if c.is_ascii_alphabetic() || c == '_' => {
+            chars
+                .find(|&(_, c)| !(c.is_ascii_alphanumeric() || c == '_'))
+                .map(|(i, _)| i)
+                .unwrap_or(body.len())
+        }
+        Some(&(_, c)) if c.is_ascii_digit() => {
+            chars
+                .find(|&(_, c)| !c.is_ascii_digit())
+                .map(|(i, _)| i)
+                .unwrap_or(body.len())
+        }
+        Some(&(_, c)) if "@*#?-$!".contains(c) => c.len_utf8(),
+        _ => bail!("Bad substitution: ${{{}}}", body),
+    };
+    
+    let mut end = end;
+    if body[end..].starts_with('[') {
+        let mut depth = 0;
+        let close = body[end..]
+            .char_indices()
+            .find(|&(_, c)| {
+                match c {
+                    '[' => depth += 1,
+                    ']' => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            })
+            .map(|(i, _)| end + i);
+        match close {
+            Some(close) => end = close + 1,
+            None => bail!("Unterminated array subscript in ${{{}}}", body),
         }
     }
+    
+    Ok((body[..end].to_string(), &body[end..]))
 }
 
-use super::ast::{StringType, ForItems};
\ No newline at end of file
+/// Split `text` at the first `sep` that is not inside quotes, `${...}`,
+/// `$(...)` or escaped with a backslash.
+fn split_unnested(text: &str, sep: char) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut chars = text.char_indices().peekable();
+    
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                chars.next();
+            }
+            (Some(_), _) => {}
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (None, '$') if matches!(chars.peek(), Some((_, '{')) | Some((_, '('))) => {
+                depth += 1;
+                chars.next();
+            }
+            (None, '}') | (None, ')') if depth > 0 => depth -= 1,
+            (None, c) if c == sep && depth == 0 => {
+                return Some((&text[..i], &text[i + c.len_utf8()..]));
+            }
+            _ => {}
+        }
+    }
+    
+    None
+}
+
+/// Parse the word on the right-hand side of `:-`, `:=` and `:+`, which
+/// starts at `span`.
+///
+/// A word that is a single expansion becomes that node so nested forms like
+/// `${a:-${b:-c}}` keep their structure; anything else is kept as a string.
+fn parse_expansion_word(word: &str, span: Span) -> Result<ASTNode> {
+    if let Some(body) = word.strip_prefix("${") {
+        if find_closing_brace(body) == Some(body.len() - 1) {
+            return parse_parameter_expansion(&body[..body.len() - 1], span);
+        }
+    }
+    
+    if let Some(name) = word.strip_prefix('$') {
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Ok(ASTNode::Variable(name.to_string(), span));
+        }
+    }
+    
+    if word.contains(['$', '`', '"', '\'', '\\']) {
+        return ShellParser::parse_word_text(word, ShellDialect::Bash, span);
+    }
+    
+    Ok(ASTNode::String(word.to_string(), StringType::Unquoted, span))
+}
+
+/// Position of the `}` closing a `${` whose body starts at `text[0]`.
+fn find_closing_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '$' if matches!(chars.peek(), Some((_, '{'))) => {
+                depth += 1;
+                chars.next();
+            }
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parse an offset or length of `${var:offset:length}`, which starts at
+/// `span`.
+fn parse_offset(text: &str, span: Span) -> ASTNode {
+    let span = advance_span(span, &text[..text.len() - text.trim_start().len()]);
+    let text = text.trim();
+    if let Ok(n) = text.parse::<i64>() {
+        return ASTNode::Number(n as f64);
+    }
+    
+    let name = text.strip_prefix('$').unwrap_or(text);
+    if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return ASTNode::Variable(name.to_string(), span);
+    }
+    
+    // Arbitrary arithmetic is parsed now when it has no expansions and kept
+    // as text to be evaluated at runtime otherwise
+    match parse_arithmetic(text, span) {
+        Ok(expr) if !text.contains(['$', '`']) => expr,
+        _ => ASTNode::String(text.to_string(), StringType::Unquoted, span),
+    }
+}
+
+/// Strip one level of surrounding quotes from an error message word.
+fn unquote(word: &str) -> String {
+    for quote in ['\'', '"'] {
+        if word.len() >= 2 && word.starts_with(quote) && word.ends_with(quote) {
+            return word[1..word.len() - 1].to_string();
+        }
+    }
+    word.to_string()
+}
\ No newline at end of file
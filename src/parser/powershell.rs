@@ -0,0 +1,2461 @@
+use super::ast::{
+    ASTNode, BinaryOperator, ExpansionType, ForItems, RedirectDirection, Redirection, RedirectionTarget,
+    Span, StringType, UnaryOperator,
+};
+use super::parser::{ParseError, RecoveringParser, message_hint, push_word_part};
+use super::powershell_lexer::{PowerShellLexer, PsLexeme, PsToken, is_name_char, unescape};
+use anyhow::{Result, bail};
+use std::collections::{HashMap, HashSet};
+
+/// Statements the converter has no lowering for, and what to write instead.
+const UNSUPPORTED_STATEMENTS: &[(&str, Option<&str>)] = &[
+    ("try", Some("check `$LASTEXITCODE` after the command instead")),
+    ("catch", Some("check `$LASTEXITCODE` after the command instead")),
+    ("finally", Some("check `$LASTEXITCODE` after the command instead")),
+    ("trap", Some("check `$LASTEXITCODE` after the command instead")),
+    ("switch", Some("use `if`/`elseif` instead")),
+    ("do", Some("use a `while` loop instead")),
+    ("filter", Some("use a function with `ForEach-Object` instead")),
+    ("begin", Some("move the code out of the `begin`/`process`/`end` blocks")),
+    ("process", Some("move the code out of the `begin`/`process`/`end` blocks")),
+    ("end", Some("move the code out of the `begin`/`process`/`end` blocks")),
+    ("class", None),
+    ("enum", None),
+    ("data", None),
+    ("workflow", None),
+    ("configuration", None),
+    ("using", None),
+];
+
+/// Comparison operators, without the `-` and the `i`/`c` case prefix.
+const COMPARISONS: &[&str] = &["eq", "ne", "gt", "ge", "lt", "le", "like", "notlike", "match", "notmatch"];
+
+/// Operators that have no lowering.
+const UNSUPPORTED_OPERATORS: &[&str] = &[
+    "contains", "notcontains", "in", "notin", "replace", "split", "join", "is", "isnot", "as",
+    "band", "bor", "bxor", "bnot", "shl", "shr", "xor", "f",
+];
+
+/// Verbs of cmdlet names, to tell a cmdlet without an equivalent from a
+/// native command that has a dash in its name.
+const VERBS: &[&str] = &[
+    "add", "approve", "assert", "backup", "block", "build", "checkpoint", "clear", "close", "compare",
+    "complete", "compress", "confirm", "connect", "convert", "convertfrom", "convertto", "copy",
+    "debug", "deny", "deploy", "disable", "disconnect", "dismount", "edit", "enable", "enter", "exit",
+    "expand", "export", "find", "foreach", "format", "get", "grant", "group", "hide", "import",
+    "initialize", "install", "invoke", "join", "limit", "lock", "measure", "merge", "mount", "move",
+    "new", "open", "optimize", "out", "ping", "pop", "protect", "publish", "push", "read", "receive",
+    "redo", "register", "remove", "rename", "repair", "request", "reset", "resize", "resolve",
+    "restart", "restore", "resume", "revoke", "save", "search", "select", "send", "set", "show",
+    "skip", "sort", "split", "start", "step", "stop", "submit", "suspend", "switch", "sync", "tee",
+    "test", "trace", "unblock", "undo", "uninstall", "unlock", "unprotect", "unpublish",
+    "unregister", "update", "use", "wait", "watch", "where", "write",
+];
+
+/// A cmdlet with a POSIX counterpart.
+struct Cmdlet {
+    names: &'static [&'static str],              // lowercase name and aliases
+    command: &'static str,
+    switches: &'static [(&'static str, &'static str)],  // switch and the flag it becomes, if any
+    positional: &'static [&'static str],         // parameters that also bind by position, in order
+    named: &'static [&'static str],              // parameters that only bind by name
+    ignored: &'static [&'static str],            // parameters taking a value that has no counterpart
+}
+
+impl Cmdlet {
+    const fn new(names: &'static [&'static str], command: &'static str) -> Self {
+        Cmdlet { names, command, switches: &[], positional: &[], named: &[], ignored: &[] }
+    }
+}
+
+const WRITE_HOST: Cmdlet = Cmdlet {
+    switches: &[("nonewline", "-n"), ("noenumerate", "")],
+    positional: &["object", "inputobject"],
+    ignored: &["foregroundcolor", "backgroundcolor", "separator"],
+    ..Cmdlet::new(&["write-host", "write-output", "echo", "write"], "echo")
+};
+
+const CMDLETS: &[Cmdlet] = &[
+    Cmdlet {
+        switches: &[("recurse", "-R"), ("force", "-a"), ("name", "")],
+        positional: &["path"],
+        named: &["literalpath"],
+        ..Cmdlet::new(&["get-childitem", "gci", "ls", "dir"], "ls")
+    },
+    Cmdlet {
+        positional: &["path"],
+        named: &["literalpath"],
+        ..Cmdlet::new(&["set-location", "sl", "cd", "chdir"], "cd")
+    },
+    Cmdlet::new(&["get-location", "gl", "pwd"], "pwd"),
+    Cmdlet {
+        switches: &[("raw", "")],
+        positional: &["path"],
+        named: &["literalpath"],
+        ..Cmdlet::new(&["get-content", "gc", "cat", "type"], "cat")
+    },
+    Cmdlet {
+        switches: &[("recurse", "-r"), ("force", "-f")],
+        positional: &["path"],
+        named: &["literalpath"],
+        ..Cmdlet::new(&["remove-item", "ri", "rm", "del", "erase", "rmdir", "rd"], "rm")
+    },
+    Cmdlet {
+        switches: &[("recurse", "-r"), ("force", "-f")],
+        positional: &["path", "destination"],
+        named: &["literalpath"],
+        ..Cmdlet::new(&["copy-item", "cpi", "cp", "copy"], "cp")
+    },
+    Cmdlet {
+        switches: &[("force", "-f")],
+        positional: &["path", "destination"],
+        named: &["literalpath"],
+        ..Cmdlet::new(&["move-item", "mi", "mv", "move"], "mv")
+    },
+    Cmdlet {
+        positional: &["path", "newname"],
+        named: &["literalpath"],
+        ..Cmdlet::new(&["rename-item", "rni", "ren"], "mv")
+    },
+    Cmdlet {
+        positional: &["seconds"],
+        ..Cmdlet::new(&["start-sleep", "sleep"], "sleep")
+    },
+    Cmdlet {
+        switches: &[("descending", "-r"), ("unique", "-u")],
+        ..Cmdlet::new(&["sort-object", "sort"], "sort")
+    },
+    Cmdlet::new(&["clear-host", "cls", "clear"], "clear"),
+    Cmdlet::new(&["get-date", "date"], "date"),
+];
+
+/// Parameters every cmdlet takes. Switches among them are dropped, as are
+/// the values of the others.
+const COMMON_SWITCHES: &[&str] = &["verbose", "debug", "whatif", "confirm"];
+const COMMON_VALUES: &[&str] = &["erroraction", "warningaction", "informationaction", "errorvariable", "outvariable"];
+
+/// Parser for PowerShell scripts.
+///
+/// Scripts are lowered into the same AST as the POSIX shells, which only
+/// has text: objects flowing through a pipeline become lines, which
+/// `ForEach-Object` and `Where-Object` read into `$_` one at a time, and
+/// common cmdlets become the commands they correspond to. `param()`
+/// parameters become a `param` command of the runtime, which binds them by
+/// name or position. Comparisons ignore case through the `nocasematch`
+/// option, and arrays count from 1 in the generated code, so indexes are
+/// shifted. Constructs without a lowering are reported as errors.
+pub struct PowerShellParser {
+    tokens: Vec<PsLexeme>,
+    pos: usize,
+    // Set when the input could not be split into tokens
+    lex_error: Option<ParseError>,
+    // Errors recovered from so far, in script order
+    errors: Vec<ParseError>,
+    // Set once a bad statement read the rest of the script, after which
+    // errors about the missing rest would only repeat the first one
+    skipped_to_end: bool,
+    // Lowercase names of the functions the script defines, which are called
+    // rather than taken for cmdlets
+    functions: HashSet<String>,
+    // What each variable was last assigned, to tell appending to an array
+    // or a string from adding to a number with `+=`
+    kinds: HashMap<String, ValueKind>,
+    // Nesting of function bodies, where variables are local
+    function_depth: usize,
+}
+
+/// A PowerShell expression, kept until the place it is used decides
+/// whether it becomes a word, arithmetic or a condition.
+#[derive(Debug, Clone)]
+enum Expr {
+    Word(ASTNode),                  // text, a variable, a string or a substitution
+    Index(String, Box<Expr>, Span), // $name[index]
+    Array(Vec<Expr>),               // a, b or @(a, b) or 1..3
+    Bool(bool),
+    Null,
+    Arithmetic(BinaryOperator, Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Compare(&'static str, bool, Box<Expr>, Box<Expr>),  // `-eq` and the like without the case prefix, and whether it was `c`
+    Logical(bool, Box<Expr>, Box<Expr>),          // -and when true, -or when false
+    Not(Box<Expr>),
+    Statement(ASTNode),             // a command or pipeline used as a value
+}
+
+/// What a pipeline parsed to: a lone expression, which its use lowers, or
+/// commands.
+enum Output {
+    Expr(Expr),
+    Commands(ASTNode),
+}
+
+/// An argument of a command.
+enum Arg {
+    Parameter(String, bool, Span),  // -Name, without the `-`, and whether a value follows as `-Name:value`
+    Value(Expr),
+    Block(ASTNode),     // { ... }: statements, or a condition for Where-Object
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ValueKind {
+    Array,
+    Number,
+    Text,
+}
+
+/// How a parameter of a mapped cmdlet binds.
+enum Binding {
+    Switch(&'static str),
+    Value(&'static str),
+    Ignored,
+}
+
+/// The arguments of a mapped cmdlet, sorted out by parameter.
+struct Bound {
+    flags: Vec<&'static str>,
+    values: HashMap<&'static str, Expr>,
+    rest: Vec<Expr>,
+}
+
+impl PowerShellParser {
+    pub fn new(input: &str) -> Self {
+        Self::starting_at(input, Span::new(1, 1))
+    }
+
+    /// A parser for text embedded in a script at `start`, such as a `$(...)`
+    /// inside a string.
+    pub fn starting_at(input: &str, start: Span) -> Self {
+        let (tokens, lex_error) = match PowerShellLexer::starting_at(input, start).tokenize() {
+            Ok(tokens) => (tokens, None),
+            Err(error) => {
+                let error = match error.downcast::<ParseError>() {
+                    Ok(error) => error,
+                    Err(error) => {
+                        let message = format!("{:#}", error);
+                        ParseError { span: start, hint: Some(message_hint(&message)), message }
+                    }
+                };
+                (vec![PsLexeme { token: PsToken::Eof, span: start, spaced: true }], Some(error))
+            }
+        };
+
+        PowerShellParser {
+            tokens,
+            pos: 0,
+            lex_error,
+            errors: Vec::new(),
+            skipped_to_end: false,
+            functions: HashSet::new(),
+            kinds: HashMap::new(),
+            function_depth: 0,
+        }
+    }
+
+    /// Parse the script, recovering from syntax errors the way
+    /// `ShellParser::parse_with_diagnostics` does: a statement that fails
+    /// becomes an `ASTNode::Error` and parsing resumes after it.
+    pub fn parse_script(&mut self) -> (ASTNode, Vec<ParseError>) {
+        if let Some(error) = self.lex_error.take() {
+            let node = ASTNode::Error { message: error.message.clone(), span: error.span };
+            return (ASTNode::Script(vec![Box::new(node)]), vec![error]);
+        }
+
+        // Functions may be called above their definition from other functions
+        let functions: Vec<String> = self.tokens.windows(2)
+            .filter_map(|pair| match (&pair[0].token, &pair[1].token) {
+                (PsToken::Word(keyword), PsToken::Word(name)) if keyword.eq_ignore_ascii_case("function") => {
+                    Some(name.to_lowercase())
+                }
+                _ => None,
+            })
+            .collect();
+        self.functions.extend(functions);
+
+        let statements = self.parse_statements(None);
+        (ASTNode::Script(statements.into_iter().map(Box::new).collect()), std::mem::take(&mut self.errors))
+    }
+
+    /// Statements up to `closer`, which is left for the caller, or the end of
+    /// the input.
+    fn parse_statements(&mut self, closer: Option<PsToken>) -> Vec<ASTNode> {
+        let mut statements = Vec::new();
+        loop {
+            self.skip_separators();
+            if self.at_eof() || closer.as_ref() == Some(self.token()) {
+                return statements;
+            }
+
+            let start = self.pos;
+            let span = self.span();
+            let statement = self.parse_statement().and_then(|statement| {
+                self.end_statement(closer.as_ref())?;
+                Ok(statement)
+            });
+            let statement = statement.unwrap_or_else(|error| {
+                self.recover(span, error, |parser| {
+                    parser.skip_statement(closer.as_ref());
+                    if parser.pos == start {
+                        parser.advance();
+                    }
+                })
+            });
+            statements.push(statement);
+        }
+    }
+
+    /// A statement ends at `;`, a newline, the end of the input or the token
+    /// closing the block it is in.
+    fn end_statement(&self, closer: Option<&PsToken>) -> Result<()> {
+        match self.token() {
+            PsToken::Newline | PsToken::Semicolon | PsToken::Eof => Ok(()),
+            token if Some(token) == closer => Ok(()),
+            token => bail!("Unexpected {}", describe(token)),
+        }
+    }
+
+    /// Skip the rest of a statement that failed to parse, together with any
+    /// blocks it opened, but not past `closer`, the end of the block it is in.
+    /// The error may be inside parentheses or a block of the statement, whose
+    /// closing tokens are skipped too.
+    fn skip_statement(&mut self, closer: Option<&PsToken>) {
+        let mut depth = 0;
+        loop {
+            match self.token() {
+                PsToken::Eof => return,
+                PsToken::Newline | PsToken::Semicolon if depth == 0 => return,
+                PsToken::LeftBrace | PsToken::LeftParen | PsToken::SubExpression
+                    | PsToken::ArrayExpression | PsToken::HashLiteral => depth += 1,
+                token if depth == 0 && Some(token) == closer => return,
+                PsToken::RightBrace | PsToken::RightParen if depth > 0 => depth -= 1,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<ASTNode> {
+        let span = self.span();
+        if let Some(keyword) = self.keyword() {
+            match keyword.as_str() {
+                "if" => return self.parse_if(),
+                "while" => return self.parse_while(),
+                "for" => return self.parse_for(),
+                "foreach" if self.peek(1) == &PsToken::LeftParen => return self.parse_foreach(),
+                "function" => return self.parse_function(),
+                "param" => return self.parse_param_block(),
+                "return" | "exit" | "throw" => return self.parse_jump(&keyword),
+                "break" => {
+                    self.advance();
+                    return Ok(ASTNode::Break(1));
+                }
+                "continue" => {
+                    self.advance();
+                    return Ok(ASTNode::Continue(1));
+                }
+                "elseif" | "else" => {
+                    return Err(ParseError {
+                        span,
+                        message: format!("Unexpected `{}'", keyword),
+                        hint: Some("this keyword is only valid after the block of an `if`".to_string()),
+                    }.into());
+                }
+                _ => {
+                    if let Some((_, hint)) = UNSUPPORTED_STATEMENTS.iter().find(|(name, _)| *name == keyword) {
+                        return Err(ParseError {
+                            span,
+                            message: format!("`{}' statements are not supported", keyword),
+                            hint: hint.map(str::to_string),
+                        }.into());
+                    }
+                }
+            }
+        }
+
+        match self.token() {
+            PsToken::RightBrace => {
+                return Err(ParseError {
+                    span,
+                    message: "Unexpected `}'".to_string(),
+                    hint: Some("there is no block open here".to_string()),
+                }.into());
+            }
+            // Attributes such as [CmdletBinding()] before `param(...)`
+            PsToken::Type(_) if self.attributes_then_param() => {
+                while self.keyword().as_deref() != Some("param") {
+                    self.advance();
+                }
+                return self.parse_param_block();
+            }
+            // [void] discards the output of what follows
+            PsToken::Type(name) if name.eq_ignore_ascii_case("void") => {
+                self.advance();
+                let statement = self.parse_chain()?;
+                return Ok(discard(statement));
+            }
+            PsToken::Operator(op) if matches!(op.as_str(), "++" | "--") => {
+                let op = if op == "++" { UnaryOperator::PreIncrement } else { UnaryOperator::PreDecrement };
+                self.advance();
+                let PsToken::Variable(raw) = self.token().clone() else {
+                    bail!("Expected a variable after `{}'", if op == UnaryOperator::PreIncrement { "++" } else { "--" });
+                };
+                let name_span = self.span();
+                let name = self.assignment_name(&raw, name_span)?;
+                self.advance();
+                self.kinds.insert(name.clone(), ValueKind::Number);
+                let operand = Box::new(ASTNode::Variable(name, name_span));
+                return Ok(ASTNode::ArithmeticCommand(Box::new(ASTNode::UnaryOp { op, operand }), span));
+            }
+            _ => {}
+        }
+
+        if self.at_assignment() {
+            return self.parse_assignment();
+        }
+        self.parse_chain()
+    }
+
+    /// Whether only attributes and newlines come before a `param` here.
+    fn attributes_then_param(&self) -> bool {
+        let skipped = self.tokens[self.pos..]
+            .iter()
+            .take_while(|lexeme| matches!(lexeme.token, PsToken::Type(_) | PsToken::Newline))
+            .count();
+        matches!(&self.peek(skipped), PsToken::Word(word) if word.eq_ignore_ascii_case("param"))
+    }
+
+    /// Whether a variable, possibly after type casts, is assigned or
+    /// incremented here.
+    fn at_assignment(&self) -> bool {
+        let types = self.tokens[self.pos..]
+            .iter()
+            .take_while(|lexeme| matches!(lexeme.token, PsToken::Type(_)))
+            .count();
+        matches!(self.peek(types), PsToken::Variable(_))
+            && match self.peek(types + 1) {
+                PsToken::Assign(_) => true,
+                PsToken::Operator(op) => types == 0 && matches!(op.as_str(), "++" | "--"),
+                _ => false,
+            }
+    }
+
+    /// Pipelines joined by `&&` and `||`, grouping from the left.
+    fn parse_chain(&mut self) -> Result<ASTNode> {
+        let mut left = self.parse_pipeline_statement()?;
+        loop {
+            let is_and = match self.token() {
+                PsToken::AndAnd => true,
+                PsToken::OrOr => false,
+                _ => return Ok(left),
+            };
+            self.advance();
+            self.skip_newlines();
+            let right = Box::new(self.parse_pipeline_statement()?);
+            left = if is_and {
+                ASTNode::AndList(Box::new(left), right)
+            } else {
+                ASTNode::OrList(Box::new(left), right)
+            };
+        }
+    }
+
+    /// A pipeline as a statement. An expression on its own is output.
+    fn parse_pipeline_statement(&mut self) -> Result<ASTNode> {
+        let span = self.span();
+        match self.parse_pipeline()? {
+            Output::Commands(node) => Ok(node),
+            Output::Expr(expr) => output_command(expr, span),
+        }
+    }
+
+    /// Commands joined by `|`. Only the first stage may be an expression,
+    /// whose values go down the pipeline.
+    fn parse_pipeline(&mut self) -> Result<Output> {
+        let span = self.span();
+        let first = if self.at_command() {
+            Output::Commands(self.parse_command()?)
+        } else {
+            Output::Expr(self.parse_array_expression()?)
+        };
+        if self.token() != &PsToken::Pipe {
+            return Ok(first);
+        }
+
+        let mut stages = vec![match first {
+            Output::Commands(node) => node,
+            Output::Expr(expr) => output_command(expr, span)?,
+        }];
+        while self.token() == &PsToken::Pipe {
+            self.advance();
+            self.skip_newlines();
+            if !self.at_command() {
+                bail!("Expected a command after `|', found {}", describe(self.token()));
+            }
+            stages.push(self.parse_command()?);
+        }
+        Ok(Output::Commands(ASTNode::Pipeline(stages.into_iter().map(Box::new).collect())))
+    }
+
+    fn at_command(&self) -> bool {
+        match self.token() {
+            PsToken::Word(_) => true,
+            PsToken::Ampersand => true,
+            PsToken::Operator(op) => op == "%" || op == ".",
+            _ => false,
+        }
+    }
+
+    fn parse_if(&mut self) -> Result<ASTNode> {
+        let span = self.span();
+        self.advance();
+        let condition = self.parse_condition("if")?;
+        let then_block = self.parse_block()?;
+
+        let mut elif_blocks = Vec::new();
+        let mut else_block = None;
+        loop {
+            let before = self.pos;
+            self.skip_newlines();
+            match self.keyword().as_deref() {
+                Some("elseif") => {
+                    self.advance();
+                    let condition = self.parse_condition("elseif")?;
+                    let block = self.parse_block()?;
+                    elif_blocks.push((Box::new(condition), Box::new(block)));
+                }
+                Some("else") => {
+                    self.advance();
+                    else_block = Some(Box::new(self.parse_block()?));
+                    break;
+                }
+                _ => {
+                    self.pos = before;
+                    break;
+                }
+            }
+        }
+
+        Ok(ASTNode::If {
+            condition: Box::new(condition),
+            then_block: Box::new(then_block),
+            elif_blocks,
+            else_block,
+            span,
+        })
+    }
+
+    fn parse_while(&mut self) -> Result<ASTNode> {
+        let span = self.span();
+        self.advance();
+        let condition = self.parse_condition("while")?;
+        let body = self.parse_block()?;
+        Ok(ASTNode::While { condition: Box::new(condition), body: Box::new(body), span })
+    }
+
+    /// `(condition)` after `if`, `elseif` and `while`.
+    fn parse_condition(&mut self, keyword: &str) -> Result<ASTNode> {
+        let open = self.span();
+        if self.token() != &PsToken::LeftParen {
+            bail!("Expected `(' after `{}', found {}", keyword, describe(self.token()));
+        }
+        self.advance();
+        self.skip_newlines();
+        let span = self.span();
+        let condition = self.parse_pipeline()?;
+        self.skip_newlines();
+        self.expect_close(PsToken::RightParen, open, "(")?;
+
+        match condition {
+            Output::Expr(expr) => lower_condition(expr, span),
+            Output::Commands(node) => Ok(node),
+        }
+    }
+
+    /// `foreach ($name in values) { ... }`
+    fn parse_foreach(&mut self) -> Result<ASTNode> {
+        let span = self.span();
+        self.advance();
+        let open = self.span();
+        self.advance();
+        let PsToken::Variable(raw) = self.token().clone() else {
+            bail!("Expected a variable after `foreach (', found {}", describe(self.token()));
+        };
+        let variable = self.assignment_name(&raw, self.span())?;
+        self.advance();
+        if self.keyword().as_deref() != Some("in") {
+            bail!("Expected `in' after `foreach (${}', found {}", raw, describe(self.token()));
+        }
+        self.advance();
+        self.skip_newlines();
+        let values = self.parse_pipeline()?;
+        self.skip_newlines();
+        self.expect_close(PsToken::RightParen, open, "(")?;
+        let body = self.parse_block()?;
+
+        let items = match values {
+            Output::Expr(Expr::Array(items)) => items
+                .into_iter()
+                .map(|item| lower_value(item).map(Box::new))
+                .collect::<Result<Vec<_>>>()?,
+            Output::Expr(expr) => vec![Box::new(lower_value(expr)?)],
+            // The lines of output
+            Output::Commands(node) => vec![Box::new(substitution(node))],
+        };
+
+        Ok(ASTNode::For { variable, items: ForItems::List(items), body: Box::new(body), span })
+    }
+
+    /// `for (init; condition; update) { ... }`, which only counts.
+    fn parse_for(&mut self) -> Result<ASTNode> {
+        let span = self.span();
+        self.advance();
+        let open = self.span();
+        if self.token() != &PsToken::LeftParen {
+            bail!("Expected `(' after `for', found {}", describe(self.token()));
+        }
+        self.advance();
+
+        let init = self.parse_for_clause(PsToken::Semicolon, 0)?;
+        self.expect_separator(PsToken::Semicolon)?;
+        let condition = self.parse_for_clause(PsToken::Semicolon, 1)?;
+        self.expect_separator(PsToken::Semicolon)?;
+        let update = self.parse_for_clause(PsToken::RightParen, 0)?;
+        self.skip_newlines();
+        self.expect_close(PsToken::RightParen, open, "(")?;
+        let body = self.parse_block()?;
+
+        let items = ForItems::CStyle {
+            init: Box::new(init),
+            condition: Box::new(condition),
+            update: Box::new(update),
+        };
+        Ok(ASTNode::For { variable: String::new(), items, body: Box::new(body), span })
+    }
+
+    /// One clause of a `for` header as arithmetic; `empty` when there is
+    /// none.
+    fn parse_for_clause(&mut self, end: PsToken, empty: i64) -> Result<ASTNode> {
+        self.skip_newlines();
+        if self.token() == &end {
+            return Ok(ASTNode::Integer(empty));
+        }
+        if !self.at_assignment() {
+            return to_arith(self.parse_expression()?);
+        }
+
+        let span = self.span();
+        while let PsToken::Type(_) = self.token() {
+            self.advance();
+        }
+        let name_span = self.span();
+        let PsToken::Variable(raw) = self.advance() else {
+            unreachable!("at_assignment() saw a variable");
+        };
+        let name = self.assignment_name(&raw, span)?;
+        self.kinds.insert(name.clone(), ValueKind::Number);
+        match self.advance() {
+            PsToken::Operator(op) => {
+                let op = if op == "++" { UnaryOperator::PostIncrement } else { UnaryOperator::PostDecrement };
+                Ok(ASTNode::UnaryOp { op, operand: Box::new(ASTNode::Variable(name, name_span)) })
+            }
+            PsToken::Assign(op) => {
+                self.skip_newlines();
+                let value = to_arith(self.parse_expression()?)?;
+                Ok(ASTNode::ArithmeticAssignment { name, op: assignment_operator(&op), value: Box::new(value) })
+            }
+            _ => unreachable!("at_assignment() saw an assignment"),
+        }
+    }
+
+    fn expect_separator(&mut self, separator: PsToken) -> Result<()> {
+        self.skip_newlines();
+        if self.token() != &separator {
+            bail!("Expected {}, found {}", describe(&separator), describe(self.token()));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    /// `function name { ... }` or `function name($a, $b) { ... }`.
+    /// Parameters become locals holding the positional parameters.
+    fn parse_function(&mut self) -> Result<ASTNode> {
+        let span = self.span();
+        self.advance();
+        let name = match self.token() {
+            PsToken::Word(name) => name.to_lowercase(),
+            token => bail!("Expected a function name after `function', found {}", describe(token)),
+        };
+        self.advance();
+
+        self.function_depth += 1;
+        let body = self.parse_function_body(span);
+        self.function_depth -= 1;
+
+        Ok(ASTNode::Function { name, body: Box::new(body?), span })
+    }
+
+    fn parse_function_body(&mut self, span: Span) -> Result<ASTNode> {
+        let mut statements: Vec<Box<ASTNode>> = if self.token() == &PsToken::LeftParen {
+            self.parse_parameters(span)?.into_iter().map(Box::new).collect()
+        } else {
+            Vec::new()
+        };
+        match self.parse_block()? {
+            ASTNode::Block(body) => statements.extend(body),
+            other => statements.push(Box::new(other)),
+        }
+        Ok(ASTNode::Block(statements))
+    }
+
+    /// `param(...)`, as assignments of the positional parameters.
+    fn parse_param_block(&mut self) -> Result<ASTNode> {
+        let span = self.span();
+        self.advance();
+        self.skip_newlines();
+        if self.token() != &PsToken::LeftParen {
+            bail!("Expected `(' after `param', found {}", describe(self.token()));
+        }
+        Ok(ASTNode::Block(self.parse_parameters(span)?.into_iter().map(Box::new).collect()))
+    }
+
+    /// `($name, [type]$other = default, ...)`, as a `param` command that
+    /// binds the arguments to the parameters by name or else by position
+    /// when the script runs, followed by the assignments of the defaults.
+    /// A `[switch]` parameter is listed as `-name`.
+    fn parse_parameters(&mut self, span: Span) -> Result<Vec<ASTNode>> {
+        let open = self.span();
+        self.advance();
+        let local = self.function_depth > 0;
+        let mut names = Vec::new();
+        let mut defaults = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.token() == &PsToken::RightParen {
+                self.advance();
+                break;
+            }
+
+            let mut switch = false;
+            while let PsToken::Type(name) = self.token() {
+                switch |= name.eq_ignore_ascii_case("switch");
+                self.advance();
+                self.skip_newlines();
+            }
+            let PsToken::Variable(raw) = self.token().clone() else {
+                bail!("Expected a parameter, found {}", describe(self.token()));
+            };
+            let name_span = self.span();
+            let name = self.assignment_name(&raw, name_span)?;
+            self.advance();
+
+            let default = if self.token() == &PsToken::Assign("=".to_string()) {
+                self.advance();
+                self.skip_newlines();
+                Some(lower_value(self.parse_expression()?)?)
+            } else {
+                None
+            };
+            let word = if switch { format!("-{}", name) } else { name.clone() };
+            names.push(ASTNode::String(word, StringType::SingleQuoted, name_span));
+            if let Some(default) = default {
+                // Parameters without an argument are unset
+                let value = ASTNode::ParameterExpansion {
+                    name: name.clone(),
+                    expansion_type: ExpansionType::Default { word: Box::new(default), colon: false },
+                    span: name_span,
+                };
+                defaults.push(ASTNode::Assignment {
+                    name,
+                    index: None,
+                    value: Box::new(value),
+                    append: false,
+                    export: false,
+                    readonly: false,
+                    local,
+                    span,
+                });
+            }
+
+            self.skip_newlines();
+            match self.token() {
+                PsToken::Comma => self.advance(),
+                _ => {
+                    self.expect_close(PsToken::RightParen, open, "(")?;
+                    break;
+                }
+            };
+        }
+        defaults.insert(0, command("param", names, span));
+        Ok(defaults)
+    }
+
+    /// `return`, `exit` and `throw`, with an optional value.
+    fn parse_jump(&mut self, keyword: &str) -> Result<ASTNode> {
+        let span = self.span();
+        self.advance();
+        let value = if matches!(
+            self.token(),
+            PsToken::Newline | PsToken::Semicolon | PsToken::Eof | PsToken::RightBrace | PsToken::RightParen
+        ) {
+            None
+        } else {
+            Some(self.parse_pipeline()?)
+        };
+
+        Ok(match keyword {
+            // A function returns what it outputs; the value is more of it
+            "return" => match value {
+                Some(value) => {
+                    let output = match value {
+                        Output::Expr(expr) => output_command(expr, span)?,
+                        Output::Commands(node) => node,
+                    };
+                    ASTNode::Block(vec![Box::new(output), Box::new(ASTNode::Return(None))])
+                }
+                None => ASTNode::Return(None),
+            },
+            "exit" => ASTNode::Exit(value.map(value_word).transpose()?.map(Box::new)),
+            _ => {
+                let message = match value {
+                    Some(value) => value_word(value)?,
+                    None => ASTNode::String("ScriptHalted".to_string(), StringType::SingleQuoted, span),
+                };
+                let mut report = command("echo", vec![message], span);
+                if let ASTNode::Command { redirections, .. } = &mut report {
+                    redirections.push(to_stderr());
+                }
+                ASTNode::Block(vec![Box::new(report), Box::new(ASTNode::Exit(Some(Box::new(ASTNode::Number(1.0)))))])
+            }
+        })
+    }
+
+    /// `$name = value` and the arithmetic forms `+=`, `-=`, `++` and so on.
+    ///
+    /// `+=` appends to arrays and strings and adds to numbers, going by
+    /// what the variable was last assigned or else by the value.
+    fn parse_assignment(&mut self) -> Result<ASTNode> {
+        let span = self.span();
+        while let PsToken::Type(_) = self.token() {
+            self.advance();
+        }
+        let name_span = self.span();
+        let PsToken::Variable(raw) = self.advance() else {
+            unreachable!("at_assignment() saw a variable");
+        };
+        if raw.eq_ignore_ascii_case("null") {
+            return self.parse_discard();
+        }
+        let name = self.assignment_name(&raw, span)?;
+        let export = raw.to_lowercase().starts_with("env:");
+        // Variables are local to the function assigning them unless scoped
+        let local = self.function_depth > 0 && !raw.contains(':');
+
+        let op = match self.advance() {
+            PsToken::Operator(op) => {
+                let op = if op == "++" { UnaryOperator::PostIncrement } else { UnaryOperator::PostDecrement };
+                self.kinds.insert(name.clone(), ValueKind::Number);
+                let operand = Box::new(ASTNode::Variable(name, name_span));
+                return Ok(ASTNode::ArithmeticCommand(Box::new(ASTNode::UnaryOp { op, operand }), span));
+            }
+            PsToken::Assign(op) => op,
+            _ => unreachable!("at_assignment() saw an assignment"),
+        };
+        self.skip_newlines();
+
+        if op == "=" && matches!(self.token(), PsToken::Word(word) if word.eq_ignore_ascii_case("read-host")) {
+            let mut read = self.parse_command()?;
+            if let ASTNode::Command { args, .. } = &mut read {
+                args.push(Box::new(ASTNode::String(name.clone(), StringType::Unquoted, name_span)));
+            }
+            self.kinds.insert(name, ValueKind::Text);
+            return Ok(read);
+        }
+
+        let value = self.parse_pipeline()?;
+        let kind = match &value {
+            Output::Expr(expr) => value_kind(expr),
+            Output::Commands(_) => Some(ValueKind::Text),
+        };
+        let previous = self.kinds.get(&name).copied();
+        let assign = |value: ASTNode| ASTNode::Assignment {
+            name: name.clone(),
+            index: None,
+            value: Box::new(value),
+            append: false,
+            export,
+            readonly: false,
+            local,
+            span,
+        };
+
+        let (node, kind) = match op.as_str() {
+            "=" => (assign(value_word(value)?), kind),
+            "+=" if previous == Some(ValueKind::Array) => {
+                let mut items = vec![Box::new(ASTNode::Variable(name.clone(), name_span))];
+                match value {
+                    Output::Expr(Expr::Array(values)) => {
+                        for value in values {
+                            items.push(Box::new(lower_value(value)?));
+                        }
+                    }
+                    value => items.push(Box::new(value_word(value)?)),
+                }
+                (assign(ASTNode::Array(items)), previous)
+            }
+            "+=" if previous == Some(ValueKind::Text) || (previous.is_none() && kind == Some(ValueKind::Text)) => {
+                let mut parts = vec![ASTNode::Variable(name.clone(), name_span)];
+                push_word_part(&mut parts, value_word(value)?);
+                (assign(concat(parts)), Some(ValueKind::Text))
+            }
+            _ => {
+                let value = match value {
+                    Output::Expr(expr) => to_arith(expr)?,
+                    Output::Commands(node) => substitution(node),
+                };
+                let assignment = ASTNode::ArithmeticAssignment {
+                    name: name.clone(),
+                    op: assignment_operator(&op),
+                    value: Box::new(value),
+                };
+                (ASTNode::ArithmeticCommand(Box::new(assignment), span), Some(ValueKind::Number))
+            }
+        };
+
+        match kind {
+            Some(kind) => self.kinds.insert(name, kind),
+            None => self.kinds.remove(&name),
+        };
+        Ok(node)
+    }
+
+    /// `$null = ...` runs what follows for its effects only.
+    fn parse_discard(&mut self) -> Result<ASTNode> {
+        let span = self.span();
+        match self.advance() {
+            PsToken::Assign(op) if op == "=" => {}
+            token => bail!("Can't assign to `$null' with {}", describe(&token)),
+        }
+        self.skip_newlines();
+        Ok(match self.parse_pipeline()? {
+            Output::Commands(node) | Output::Expr(Expr::Statement(node)) => discard(node),
+            Output::Expr(_) => command("true", Vec::new(), span),
+        })
+    }
+
+    /// The shell name of an assigned variable.
+    fn assignment_name(&self, raw: &str, span: Span) -> Result<String> {
+        match variable_expr(raw, span) {
+            Ok(Expr::Word(ASTNode::Variable(name, _))) if !matches!(name.as_str(), "@" | "?" | "$" | "HOME" | "PWD") => Ok(name),
+            Ok(_) => Err(error_at(span, format!("Can't assign to `${}'", raw), None)),
+            Err(error) => Err(error_at(span, format!("{:#}", error), None)),
+        }
+    }
+
+    /// A command with its arguments. The name may follow the call operator
+    /// `&`, which also runs a script block in place.
+    fn parse_command(&mut self) -> Result<ASTNode> {
+        let span = self.span();
+        let name = match self.token().clone() {
+            PsToken::Word(word) => {
+                let keyword = word.to_lowercase();
+                if matches!(keyword.as_str(), "if" | "while" | "for" | "function" | "param" | "return" | "exit" | "throw")
+                    || UNSUPPORTED_STATEMENTS.iter().any(|(name, _)| *name == keyword)
+                {
+                    bail!("`{}' statements can't be used as values", word);
+                }
+                word
+            }
+            PsToken::Operator(op) if op == "%" => "ForEach-Object".to_string(),
+            PsToken::Operator(op) if op == "." => {
+                self.advance();
+                if self.token() == &PsToken::LeftBrace {
+                    return self.parse_block();
+                }
+                return Err(error_at(span, "Dot-sourcing other scripts is not supported", None));
+            }
+            PsToken::Ampersand => {
+                self.advance();
+                match self.token().clone() {
+                    // Variables set in the block leak out of it here
+                    PsToken::LeftBrace => return self.parse_block(),
+                    PsToken::Word(word) | PsToken::String(word) => word,
+                    PsToken::ExpandableString(text, _) if !text.contains(['$', '`']) => text,
+                    _ => return Err(error_at(span, "Calling a command through a variable is not supported", None)),
+                }
+            }
+            token => bail!("Expected a command, found {}", describe(&token)),
+        };
+        self.advance();
+
+        let key = name.to_lowercase();
+        let takes_condition = matches!(key.as_str(), "where-object" | "where" | "?");
+        let mut args = Vec::new();
+        let mut redirections = Vec::new();
+        loop {
+            match self.token().clone() {
+                _ if self.at_command_end() => break,
+                PsToken::Redirect(op) => {
+                    let span = self.span();
+                    self.advance();
+                    redirections.extend(self.parse_redirection(&op, span)?);
+                }
+                PsToken::Parameter(parameter) => {
+                    let span = self.span();
+                    self.advance();
+                    let attached = !self.spaced() && !self.at_command_end();
+                    args.push(Arg::Parameter(parameter, attached, span));
+                }
+                // `a, b` passes an array, which becomes separate words
+                PsToken::Comma => {
+                    self.advance();
+                    self.skip_newlines();
+                }
+                PsToken::LeftBrace if takes_condition => args.push(Arg::Block(self.parse_condition_block()?)),
+                PsToken::LeftBrace => args.push(Arg::Block(self.parse_block()?)),
+                PsToken::Ampersand => bail!("Background jobs (`&') are not supported"),
+                _ => args.push(Arg::Value(self.parse_argument()?)),
+            }
+        }
+
+        let node = self.lower_command(&name, args, span)?;
+        if redirections.is_empty() {
+            return Ok(node);
+        }
+        Ok(match node {
+            ASTNode::Command { name, args, redirections: mut own, background, span } => {
+                own.extend(redirections);
+                ASTNode::Command { name, args, redirections: own, background, span }
+            }
+            other => ASTNode::Redirected { body: Box::new(other), redirections },
+        })
+    }
+
+    /// `{ condition }` passed to Where-Object.
+    fn parse_condition_block(&mut self) -> Result<ASTNode> {
+        let open = self.span();
+        self.advance();
+        self.skip_separators();
+        let span = self.span();
+        let condition = match self.parse_pipeline()? {
+            Output::Expr(expr) => lower_condition(expr, span)?,
+            Output::Commands(node) => node,
+        };
+        self.skip_separators();
+        self.expect_close(PsToken::RightBrace, open, "{")?;
+        Ok(condition)
+    }
+
+    /// `{ statements }`. The `{` may be on the next line.
+    fn parse_block(&mut self) -> Result<ASTNode> {
+        self.skip_newlines();
+        let open = self.span();
+        if self.token() != &PsToken::LeftBrace {
+            bail!("Expected `{{', found {}", describe(self.token()));
+        }
+        self.advance();
+        let statements = self.parse_statements(Some(PsToken::RightBrace));
+        self.expect_close(PsToken::RightBrace, open, "{")?;
+        Ok(ASTNode::Block(statements.into_iter().map(Box::new).collect()))
+    }
+
+    /// A redirection, from the operator `op` at `span` on. Only the output
+    /// and error streams have file descriptors.
+    fn parse_redirection(&mut self, op: &str, span: Span) -> Result<Vec<Redirection>> {
+        let stream_length = op.find('>').unwrap_or(0);
+        let (stream, rest) = op.split_at(stream_length);
+        let fd = match stream {
+            "" | "1" => None,
+            "2" => Some(2),
+            "*" => None,
+            _ => {
+                return Err(error_at(
+                    span,
+                    format!("Stream {} can't be redirected", stream),
+                    Some("only the output (1) and error (2) streams have file descriptors"),
+                ));
+            }
+        };
+
+        if let Some(target) = rest.strip_prefix(">&") {
+            let target = match target {
+                "1" => 1,
+                "2" => 2,
+                _ => return Err(error_at(span, format!("Stream {} can't be redirected", target), None)),
+            };
+            return Ok(vec![Redirection {
+                fd: Some(fd.unwrap_or(1)),
+                direction: RedirectDirection::Output,
+                target: RedirectionTarget::Fd(target),
+                append: false,
+            }]);
+        }
+
+        if matches!(self.token(), PsToken::Newline | PsToken::Semicolon | PsToken::Eof | PsToken::Pipe) {
+            bail!("Expected a file after `{}', found {}", op, describe(self.token()));
+        }
+        let file = match self.parse_argument()? {
+            Expr::Null => ASTNode::String("/dev/null".to_string(), StringType::SingleQuoted, span),
+            target => lower_value(target)?,
+        };
+        let mut redirections = vec![Redirection {
+            fd,
+            direction: RedirectDirection::Output,
+            target: RedirectionTarget::File(Box::new(file)),
+            append: rest == ">>",
+        }];
+        if stream == "*" {
+            redirections.push(Redirection {
+                fd: Some(2),
+                direction: RedirectDirection::Output,
+                target: RedirectionTarget::Fd(1),
+                append: false,
+            });
+        }
+        Ok(redirections)
+    }
+
+    /// An argument in command mode: tokens without blanks between them such
+    /// as `$dir/file` make up one word, and operators are text.
+    fn parse_argument(&mut self) -> Result<Expr> {
+        let mut parts = vec![self.parse_argument_part()?];
+        while !self.spaced() && self.at_argument_part() {
+            parts.push(self.parse_argument_part()?);
+        }
+        if parts.len() == 1 {
+            return Ok(parts.pop().unwrap());
+        }
+
+        let mut word = Vec::new();
+        for part in parts {
+            push_word_part(&mut word, lower_value(part)?);
+        }
+        Ok(Expr::Word(concat(word)))
+    }
+
+    fn at_argument_part(&self) -> bool {
+        matches!(
+            self.token(),
+            PsToken::Word(_) | PsToken::Number(_) | PsToken::Variable(_) | PsToken::String(_)
+                | PsToken::ExpandableString(..) | PsToken::SubExpression | PsToken::ArrayExpression
+                | PsToken::LeftParen | PsToken::Operator(_) | PsToken::Assign(_)
+        )
+    }
+
+    fn parse_argument_part(&mut self) -> Result<Expr> {
+        match self.token().clone() {
+            PsToken::Word(word) | PsToken::Operator(word) | PsToken::Assign(word) => {
+                let span = self.span();
+                self.advance();
+                Ok(Expr::Word(ASTNode::String(word, StringType::Unquoted, span)))
+            }
+            PsToken::Number(n) => {
+                self.advance();
+                Ok(Expr::Word(ASTNode::Number(n as f64)))
+            }
+            _ => self.parse_postfix(),
+        }
+    }
+
+    /// Expressions separated by commas, which make an array.
+    fn parse_array_expression(&mut self) -> Result<Expr> {
+        let mut items = vec![self.parse_expression()?];
+        while self.token() == &PsToken::Comma {
+            self.advance();
+            self.skip_newlines();
+            items.push(self.parse_expression()?);
+        }
+        if items.len() == 1 {
+            Ok(items.pop().unwrap())
+        } else {
+            Ok(Expr::Array(items))
+        }
+    }
+
+    /// An expression in expression mode, joined by `-and` and `-or` which
+    /// bind equally and group from the left.
+    fn parse_expression(&mut self) -> Result<Expr> {
+        let mut left = self.parse_not()?;
+        loop {
+            let is_and = match self.token() {
+                PsToken::Parameter(op) if op.eq_ignore_ascii_case("and") => true,
+                PsToken::Parameter(op) if op.eq_ignore_ascii_case("or") => false,
+                _ => return Ok(left),
+            };
+            self.advance();
+            self.skip_newlines();
+            let right = self.parse_not()?;
+            left = Expr::Logical(is_and, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn parse_not(&mut self) -> Result<Expr> {
+        let negated = match self.token() {
+            PsToken::Parameter(op) => op.eq_ignore_ascii_case("not"),
+            PsToken::Operator(op) => op == "!",
+            _ => false,
+        };
+        if negated {
+            self.advance();
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr> {
+        let mut left = self.parse_additive()?;
+        while let PsToken::Parameter(op) = self.token() {
+            let op = op.to_lowercase();
+            let base = op.strip_prefix(['i', 'c']).filter(|base| {
+                COMPARISONS.contains(base) || UNSUPPORTED_OPERATORS.contains(base)
+            }).unwrap_or(&op);
+            let Some(comparison) = COMPARISONS.iter().find(|comparison| **comparison == base) else {
+                if UNSUPPORTED_OPERATORS.contains(&base) {
+                    bail!("The `-{}' operator is not supported", op);
+                }
+                return Ok(left);
+            };
+            let case_sensitive = base.len() < op.len() && op.starts_with('c');
+            self.advance();
+            self.skip_newlines();
+            let right = self.parse_additive()?;
+            left = Expr::Compare(comparison, case_sensitive, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let op = match self.token() {
+                PsToken::Operator(op) if op == "+" => BinaryOperator::Add,
+                PsToken::Operator(op) if op == "-" => BinaryOperator::Subtract,
+                // `$a -1` subtracts, though `-1` reads as a number
+                &PsToken::Number(n) if n < 0 && self.spaced() => {
+                    self.advance();
+                    let right = Expr::Word(ASTNode::Number(-n as f64));
+                    left = Expr::Arithmetic(BinaryOperator::Subtract, Box::new(left), Box::new(right));
+                    continue;
+                }
+                _ => return Ok(left),
+            };
+            self.advance();
+            self.skip_newlines();
+            let right = self.parse_multiplicative()?;
+            left = Expr::Arithmetic(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = match self.token() {
+                PsToken::Operator(op) if op == "*" => BinaryOperator::Multiply,
+                PsToken::Operator(op) if op == "/" => BinaryOperator::Divide,
+                PsToken::Operator(op) if op == "%" => BinaryOperator::Modulo,
+                _ => return Ok(left),
+            };
+            self.advance();
+            self.skip_newlines();
+            let right = self.parse_unary()?;
+            left = Expr::Arithmetic(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    /// Signs and casts. Values are text here, so casts change nothing.
+    fn parse_unary(&mut self) -> Result<Expr> {
+        match self.token().clone() {
+            PsToken::Operator(op) if op == "-" => {
+                self.advance();
+                Ok(Expr::Negate(Box::new(self.parse_unary()?)))
+            }
+            PsToken::Operator(op) if op == "+" => {
+                self.advance();
+                self.parse_unary()
+            }
+            PsToken::Type(name) => {
+                if matches!(self.peek(1), PsToken::Operator(op) if op == "::") {
+                    bail!("Static members of .NET types (`[{}]::...') are not supported", name);
+                }
+                self.advance();
+                self.parse_unary()
+            }
+            _ => self.parse_range(),
+        }
+    }
+
+    /// `a..b`, the numbers from `a` to `b`.
+    fn parse_range(&mut self) -> Result<Expr> {
+        let span = self.span();
+        let start = self.parse_postfix()?;
+        if !matches!(self.token(), PsToken::Operator(op) if op == "..") {
+            return Ok(start);
+        }
+        self.advance();
+        let end = self.parse_postfix()?;
+        match (integer(&start), integer(&end)) {
+            (Some(start), Some(end)) => Ok(numbers(start, end)),
+            // Bounds known only at runtime count down too, like the literal ones
+            _ => {
+                let step = ASTNode::ArithmeticExpansion(Box::new(ASTNode::Conditional {
+                    condition: Box::new(ASTNode::BinaryOp {
+                        left: Box::new(to_arith(start.clone())?),
+                        op: BinaryOperator::LessEqual,
+                        right: Box::new(to_arith(end.clone())?),
+                    }),
+                    then_expr: Box::new(ASTNode::Integer(1)),
+                    else_expr: Box::new(ASTNode::Integer(-1)),
+                }), span);
+                Ok(Expr::Statement(command("seq", vec![lower_value(start)?, step, lower_value(end)?], span)))
+            }
+        }
+    }
+
+    /// A value followed by indexes.
+    fn parse_postfix(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_primary()?;
+        loop {
+            let span = self.span();
+            match self.token().clone() {
+                PsToken::LeftBracket if !self.spaced() => {
+                    self.advance();
+                    self.skip_newlines();
+                    let index = self.parse_expression()?;
+                    self.skip_newlines();
+                    self.expect_close(PsToken::RightBracket, span, "[")?;
+                    expr = match expr {
+                        Expr::Word(ASTNode::Variable(name, at)) => Expr::Index(name, Box::new(index), at),
+                        _ => return Err(error_at(span, "Only variables can be indexed", None)),
+                    };
+                }
+                PsToken::Member(member) => {
+                    return Err(error_at(
+                        span,
+                        format!("Member access (`.{}') is not supported", member),
+                        Some("values are text once converted, without properties or methods"),
+                    ));
+                }
+                PsToken::Operator(op) if op == "::" => {
+                    return Err(error_at(span, "Static member access (`::') is not supported", None));
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        let span = self.span();
+        let token = self.token().clone();
+        let expr = match token {
+            PsToken::Number(n) => Expr::Word(ASTNode::Number(n as f64)),
+            PsToken::String(text) => Expr::Word(ASTNode::String(text, StringType::SingleQuoted, span)),
+            PsToken::ExpandableString(text, start) => Expr::Word(self.interpolate(&text, start, span)?),
+            PsToken::Variable(raw) => variable_expr(&raw, span).map_err(|error| error_at(span, format!("{:#}", error), None))?,
+            // Bare text, which PowerShell would take for a command here
+            PsToken::Word(word) => Expr::Word(ASTNode::String(word, StringType::Unquoted, span)),
+            PsToken::SubExpression => {
+                self.advance();
+                let statements = self.parse_statements(Some(PsToken::RightParen));
+                self.expect_close(PsToken::RightParen, span, "$(")?;
+                return Ok(Expr::Statement(ASTNode::Block(statements.into_iter().map(Box::new).collect())));
+            }
+            PsToken::LeftParen => {
+                self.advance();
+                self.skip_newlines();
+                let inner = self.parse_pipeline()?;
+                self.skip_newlines();
+                self.expect_close(PsToken::RightParen, span, "(")?;
+                return Ok(match inner {
+                    Output::Expr(expr) => expr,
+                    Output::Commands(node) => Expr::Statement(node),
+                });
+            }
+            PsToken::ArrayExpression => {
+                self.advance();
+                self.skip_separators();
+                let expr = if self.token() == &PsToken::RightParen {
+                    Expr::Array(Vec::new())
+                } else {
+                    match self.parse_pipeline()? {
+                        Output::Expr(Expr::Array(items)) => Expr::Array(items),
+                        Output::Expr(expr) => Expr::Array(vec![expr]),
+                        Output::Commands(node) => Expr::Statement(node),
+                    }
+                };
+                self.skip_separators();
+                self.expect_close(PsToken::RightParen, span, "@(")?;
+                return Ok(expr);
+            }
+            PsToken::HashLiteral => return Err(error_at(span, "Hashtables (`@{...}') are not supported", None)),
+            PsToken::LeftBrace => {
+                return Err(error_at(
+                    span,
+                    "Script blocks are only supported after `&' and as arguments of ForEach-Object and Where-Object",
+                    None,
+                ));
+            }
+            PsToken::Operator(op) if op == "@" => return Err(error_at(span, "Splatting (`@name') is not supported", None)),
+            token => bail!("Expected a value, found {}", describe(&token)),
+        };
+        self.advance();
+        Ok(expr)
+    }
+
+    /// The text, starting at `start`, of an expandable string written at
+    /// `quote` as a word. `$name`, `${name}` and `$(...)` expand;
+    /// `$name.Member` and `$name[0]` are only `$name` followed by text, as
+    /// in PowerShell.
+    fn interpolate(&mut self, text: &str, start: Span, quote: Span) -> Result<ASTNode> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut literal_start = start;
+        let mut span = start;
+        let mut i = 0;
+        while i < chars.len() {
+            if literal.is_empty() {
+                literal_start = span;
+            }
+            let next = chars.get(i + 1).copied();
+            let length = match (chars[i], next) {
+                ('`', Some(c)) => {
+                    literal.push(unescape(c));
+                    2
+                }
+                ('$', Some('(')) => {
+                    let end = subexpression_end(&chars, i + 2);
+                    let body: String = chars[i + 2..end].iter().collect();
+                    let body_start = advance_span(advance_span(span, '$'), '(');
+                    flush_literal(&mut parts, &mut literal, literal_start);
+                    parts.push(self.parse_nested(&body, body_start));
+                    end + 1 - i
+                }
+                ('$', Some('{')) => {
+                    let end = (i + 2..chars.len()).find(|&j| chars[j] == '}').unwrap_or(chars.len());
+                    let name: String = chars[i + 2..end].iter().collect();
+                    self.push_string_variable(&mut parts, &mut literal, literal_start, &name, span)?;
+                    (end + 1).min(chars.len()) - i
+                }
+                ('$', Some(c)) if is_name_char(c) => {
+                    let mut end = i + 1;
+                    while end < chars.len() && is_name_char(chars[end]) {
+                        end += 1;
+                    }
+                    // `$env:NAME` and other scopes
+                    if end + 1 < chars.len() && chars[end] == ':' && is_name_char(chars[end + 1]) {
+                        end += 1;
+                        while end < chars.len() && is_name_char(chars[end]) {
+                            end += 1;
+                        }
+                    }
+                    let name: String = chars[i + 1..end].iter().collect();
+                    self.push_string_variable(&mut parts, &mut literal, literal_start, &name, span)?;
+                    end - i
+                }
+                ('$', Some('?')) => return Err(error_at(span, "`$?' is not supported, test `$LASTEXITCODE' instead", None)),
+                (c, _) => {
+                    literal.push(c);
+                    1
+                }
+            };
+            for &c in &chars[i..(i + length).min(chars.len())] {
+                span = advance_span(span, c);
+            }
+            i += length;
+        }
+
+        if parts.is_empty() {
+            return Ok(ASTNode::String(literal, StringType::SingleQuoted, quote));
+        }
+        flush_literal(&mut parts, &mut literal, literal_start);
+        Ok(match parts.len() {
+            1 => parts.pop().unwrap(),
+            _ => ASTNode::Concat(parts.into_iter().map(Box::new).collect(), quote),
+        })
+    }
+
+    /// The variable `$raw` at `span` in an expandable string, after the
+    /// literal text starting at `literal_start`.
+    fn push_string_variable(
+        &self,
+        parts: &mut Vec<ASTNode>,
+        literal: &mut String,
+        literal_start: Span,
+        raw: &str,
+        span: Span,
+    ) -> Result<()> {
+        match variable_expr(raw, span).map_err(|error| error_at(span, format!("{:#}", error), None))? {
+            Expr::Bool(true) => literal.push_str("True"),
+            Expr::Bool(false) => literal.push_str("False"),
+            Expr::Null => {}
+            Expr::Word(word) => {
+                flush_literal(parts, literal, literal_start);
+                parts.push(word);
+            }
+            _ => unreachable!("variables are words, booleans or null"),
+        }
+        Ok(())
+    }
+
+    /// A `$(...)` inside a string, parsed on its own but located in the
+    /// script.
+    fn parse_nested(&mut self, body: &str, start: Span) -> ASTNode {
+        let mut parser = PowerShellParser::starting_at(body, start);
+        parser.functions = self.functions.clone();
+        parser.function_depth = self.function_depth;
+        let (root, errors) = parser.parse_script();
+        for mut error in errors {
+            error.message = format!("in subexpression `$({})`: {}", body.trim(), error.message);
+            self.errors.push(error);
+        }
+        let statements = match root {
+            ASTNode::Script(statements) => statements,
+            other => vec![Box::new(other)],
+        };
+        ASTNode::CommandSubstitution(Box::new(ASTNode::Block(statements)), start)
+    }
+
+    /// Lower a command: a cmdlet with an equivalent becomes it, a function
+    /// of the script is called and anything else runs as a native command.
+    fn lower_command(&self, name: &str, args: Vec<Arg>, span: Span) -> Result<ASTNode> {
+        let key = name.to_lowercase();
+        if self.functions.contains(&key) {
+            let mut words = Vec::new();
+            let mut args = args.into_iter();
+            while let Some(arg) = args.next() {
+                match arg {
+                    Arg::Value(value) => push_value(&mut words, value)?,
+                    // Bound to the parameters when the function runs
+                    Arg::Parameter(parameter, false, at) => {
+                        words.push(ASTNode::String(format!("-{}", parameter), StringType::SingleQuoted, at));
+                    }
+                    Arg::Parameter(parameter, true, at) => {
+                        let mut word = vec![ASTNode::String(format!("-{}:", parameter), StringType::SingleQuoted, at)];
+                        if let Some(Arg::Value(value)) = args.next() {
+                            push_word_part(&mut word, lower_value(value)?);
+                        }
+                        words.push(match word.len() {
+                            1 => word.pop().unwrap(),
+                            _ => concat(word),
+                        });
+                    }
+                    Arg::Block(_) => bail!("{}: script blocks can't be passed to functions", name),
+                }
+            }
+            return Ok(command(&key, words, span));
+        }
+
+        match key.as_str() {
+            "write-host" | "write-output" | "echo" | "write" => {
+                let mut bound = bind(&WRITE_HOST, name, args)?;
+                let no_newline = bound.flags.contains(&"-n");
+                bound.flags.retain(|flag| *flag != "-n");
+                let words = bound.into_words(&WRITE_HOST, span)?;
+                if !no_newline {
+                    return Ok(command("echo", words, span));
+                }
+                let mut text = Vec::new();
+                for (i, word) in words.into_iter().enumerate() {
+                    if i > 0 {
+                        push_word_part(&mut text, ASTNode::String(" ".to_string(), StringType::SingleQuoted, span));
+                    }
+                    push_word_part(&mut text, word);
+                }
+                let text = match text.len() {
+                    1 => text.pop().unwrap(),
+                    _ => concat(text),
+                };
+                Ok(command("printf", vec![ASTNode::String("%s".to_string(), StringType::SingleQuoted, span), text], span))
+            }
+            "write-error" | "write-warning" => {
+                const WRITE_ERROR: Cmdlet = Cmdlet {
+                    positional: &["message"],
+                    ignored: &["category", "errorid", "targetobject"],
+                    ..Cmdlet::new(&[], "echo")
+                };
+                let mut words = bind(&WRITE_ERROR, name, args)?.into_words(&WRITE_ERROR, span)?;
+                if key == "write-warning" {
+                    words.insert(0, ASTNode::String("WARNING:".to_string(), StringType::SingleQuoted, span));
+                }
+                let mut node = command("echo", words, span);
+                if let ASTNode::Command { redirections, .. } = &mut node {
+                    redirections.push(to_stderr());
+                }
+                Ok(node)
+            }
+            "test-path" => {
+                const TEST_PATH: Cmdlet = Cmdlet {
+                    positional: &["path"],
+                    named: &["literalpath", "pathtype"],
+                    ..Cmdlet::new(&[], "test")
+                };
+                let mut bound = bind(&TEST_PATH, name, args)?;
+                let Some(path) = bound.values.remove("path").or_else(|| bound.values.remove("literalpath")) else {
+                    bail!("Test-Path: a path is required");
+                };
+                let op = match bound.values.remove("pathtype").map(lower_value).transpose()? {
+                    None => UnaryOperator::FileExists,
+                    Some(ASTNode::String(kind, _, _)) if kind.eq_ignore_ascii_case("any") => UnaryOperator::FileExists,
+                    Some(ASTNode::String(kind, _, _)) if kind.eq_ignore_ascii_case("container") => UnaryOperator::FileDirectory,
+                    Some(ASTNode::String(kind, _, _)) if kind.eq_ignore_ascii_case("leaf") => UnaryOperator::FileRegular,
+                    Some(_) => bail!("Test-Path: -PathType must be Any, Container or Leaf"),
+                };
+                let expr = ASTNode::UnaryOp { op, operand: Box::new(lower_value(path)?) };
+                Ok(ASTNode::Test { expr: Box::new(expr), extended: false, span })
+            }
+            "new-item" | "ni" => {
+                const NEW_ITEM: Cmdlet = Cmdlet {
+                    switches: &[("force", "")],
+                    positional: &["path"],
+                    named: &["itemtype", "name", "value"],
+                    ..Cmdlet::new(&[], "touch")
+                };
+                let mut bound = bind(&NEW_ITEM, name, args)?;
+                let path = match (bound.values.remove("path"), bound.values.remove("name")) {
+                    (Some(path), Some(name)) => {
+                        let mut parts = Vec::new();
+                        push_word_part(&mut parts, lower_value(path)?);
+                        push_word_part(&mut parts, ASTNode::String("/".to_string(), StringType::SingleQuoted, span));
+                        push_word_part(&mut parts, lower_value(name)?);
+                        concat(parts)
+                    }
+                    (Some(path), None) | (None, Some(path)) => lower_value(path)?,
+                    (None, None) => bail!("New-Item: a path is required"),
+                };
+                let directory = match bound.values.remove("itemtype").map(lower_value).transpose()? {
+                    None => false,
+                    Some(ASTNode::String(kind, _, _)) if kind.eq_ignore_ascii_case("file") => false,
+                    Some(ASTNode::String(kind, _, _)) if kind.eq_ignore_ascii_case("directory") => true,
+                    Some(_) => bail!("New-Item: -ItemType must be File or Directory"),
+                };
+                if directory {
+                    return Ok(command("mkdir", vec![ASTNode::String("-p".to_string(), StringType::Unquoted, span), path], span));
+                }
+                match bound.values.remove("value") {
+                    Some(value) => {
+                        let mut node = command("echo", vec![lower_value(value)?], span);
+                        if let ASTNode::Command { redirections, .. } = &mut node {
+                            redirections.push(to_file(path, false));
+                        }
+                        Ok(node)
+                    }
+                    None => Ok(command("touch", vec![path], span)),
+                }
+            }
+            "set-content" | "add-content" | "sc" | "ac" => {
+                const SET_CONTENT: Cmdlet = Cmdlet {
+                    positional: &["path", "value"],
+                    named: &["literalpath"],
+                    ignored: &["encoding"],
+                    ..Cmdlet::new(&[], "echo")
+                };
+                let mut bound = bind(&SET_CONTENT, name, args)?;
+                let Some(path) = bound.values.remove("path").or_else(|| bound.values.remove("literalpath")) else {
+                    bail!("{}: a path is required", name);
+                };
+                let mut words = Vec::new();
+                if let Some(value) = bound.values.remove("value") {
+                    push_value(&mut words, value)?;
+                }
+                let mut node = command("echo", words, span);
+                if let ASTNode::Command { redirections, .. } = &mut node {
+                    redirections.push(to_file(lower_value(path)?, key.starts_with('a')));
+                }
+                Ok(node)
+            }
+            "out-file" => {
+                const OUT_FILE: Cmdlet = Cmdlet {
+                    switches: &[("append", "-a"), ("force", ""), ("noclobber", "")],
+                    positional: &["filepath"],
+                    named: &["literalpath"],
+                    ignored: &["encoding", "width"],
+                    ..Cmdlet::new(&[], "cat")
+                };
+                let mut bound = bind(&OUT_FILE, name, args)?;
+                let Some(path) = bound.values.remove("filepath").or_else(|| bound.values.remove("literalpath")) else {
+                    bail!("Out-File: a path is required");
+                };
+                let mut node = command("cat", Vec::new(), span);
+                if let ASTNode::Command { redirections, .. } = &mut node {
+                    redirections.push(to_file(lower_value(path)?, bound.flags.contains(&"-a")));
+                }
+                Ok(node)
+            }
+            "out-null" => {
+                bind(&Cmdlet::new(&[], "cat"), name, args)?;
+                Ok(discard(command("cat", Vec::new(), span)))
+            }
+            "select-object" | "select" => {
+                const SELECT_OBJECT: Cmdlet = Cmdlet { named: &["first", "last"], ..Cmdlet::new(&[], "head") };
+                let mut bound = bind(&SELECT_OBJECT, name, args)?;
+                let (command_name, count) = match (bound.values.remove("first"), bound.values.remove("last")) {
+                    (Some(count), None) => ("head", count),
+                    (None, Some(count)) => ("tail", count),
+                    _ => bail!("Select-Object: exactly one of -First and -Last is supported"),
+                };
+                if !bound.rest.is_empty() {
+                    bail!("Select-Object: selecting properties is not supported");
+                }
+                let count = lower_value(count)?;
+                Ok(command(command_name, vec![ASTNode::String("-n".to_string(), StringType::Unquoted, span), count], span))
+            }
+            "read-host" => {
+                const READ_HOST: Cmdlet = Cmdlet {
+                    switches: &[("assecurestring", "-s"), ("maskinput", "-s")],
+                    positional: &["prompt"],
+                    ..Cmdlet::new(&[], "read")
+                };
+                let mut bound = bind(&READ_HOST, name, args)?;
+                let mut words = vec![ASTNode::String(
+                    if bound.flags.contains(&"-s") { "-s" } else { "-r" }.to_string(),
+                    StringType::Unquoted,
+                    span,
+                )];
+                if let Some(prompt) = bound.values.remove("prompt") {
+                    let ASTNode::String(prompt, _, _) = lower_value(prompt)? else {
+                        bail!("Read-Host: only literal prompts are supported");
+                    };
+                    words.push(ASTNode::String("-p".to_string(), StringType::Unquoted, span));
+                    words.push(ASTNode::String(format!("{}: ", prompt), StringType::SingleQuoted, span));
+                }
+                Ok(command("read", words, span))
+            }
+            "foreach-object" | "foreach" => {
+                let body = script_block(name, args, &["process"])?;
+                Ok(each_line(body, span))
+            }
+            "where-object" | "where" | "?" => {
+                let condition = script_block(name, args, &["filterscript"])?;
+                let output = command("echo", vec![ASTNode::Variable("_".to_string(), span)], span);
+                let filter = ASTNode::If {
+                    condition: Box::new(condition),
+                    then_block: Box::new(ASTNode::Block(vec![Box::new(output)])),
+                    elif_blocks: Vec::new(),
+                    else_block: None,
+                    span,
+                };
+                Ok(each_line(filter, span))
+            }
+            _ => match CMDLETS.iter().find(|cmdlet| cmdlet.names.contains(&key.as_str())) {
+                Some(cmdlet) => {
+                    let words = bind(cmdlet, name, args)?.into_words(cmdlet, span)?;
+                    Ok(command(cmdlet.command, words, span))
+                }
+                None if is_cmdlet_name(&key) => Err(error_at(
+                    span,
+                    format!("Unsupported cmdlet `{}'", name),
+                    Some("only common cmdlets such as Get-ChildItem, Write-Host and Test-Path have equivalents; call a native command instead"),
+                )),
+                None => {
+                    let mut words = Vec::new();
+                    for arg in args {
+                        match arg {
+                            Arg::Parameter(parameter, _, at) => {
+                                words.push(ASTNode::String(format!("-{}", parameter), StringType::Unquoted, at));
+                            }
+                            Arg::Value(value) => push_value(&mut words, value)?,
+                            Arg::Block(_) => bail!("{}: script blocks can't be passed to native commands", name),
+                        }
+                    }
+                    Ok(command(name, words, span))
+                }
+            },
+        }
+    }
+
+    /// Consume `token`, which closes the `opener` at `open`.
+    fn expect_close(&mut self, token: PsToken, open: Span, opener: &str) -> Result<()> {
+        if self.token() != &token {
+            return Err(ParseError {
+                span: self.span(),
+                message: format!("Expected {}, found {}", describe(&token), describe(self.token())),
+                hint: Some(format!("the `{}` at {} is not closed", opener, open)),
+            }.into());
+        }
+        self.advance();
+        Ok(())
+    }
+
+    /// The current word in lowercase, which may be a keyword.
+    fn keyword(&self) -> Option<String> {
+        match self.token() {
+            PsToken::Word(word) => Some(word.to_lowercase()),
+            _ => None,
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.token() == &PsToken::Newline {
+            self.advance();
+        }
+    }
+
+    fn skip_separators(&mut self) {
+        while matches!(self.token(), PsToken::Newline | PsToken::Semicolon) {
+            self.advance();
+        }
+    }
+
+    fn token(&self) -> &PsToken {
+        self.peek(0)
+    }
+
+    fn peek(&self, offset: usize) -> &PsToken {
+        let index = (self.pos + offset).min(self.tokens.len() - 1);
+        &self.tokens[index].token
+    }
+
+    fn span(&self) -> Span {
+        self.tokens[self.pos].span
+    }
+
+    /// Whether blanks come before the current token.
+    fn spaced(&self) -> bool {
+        self.tokens[self.pos].spaced
+    }
+
+    /// Whether the current token ends the arguments of a command.
+    fn at_command_end(&self) -> bool {
+        matches!(
+            self.token(),
+            PsToken::Newline | PsToken::Semicolon | PsToken::Pipe | PsToken::AndAnd | PsToken::OrOr
+                | PsToken::RightParen | PsToken::RightBrace | PsToken::Eof
+        )
+    }
+
+    fn at_eof(&self) -> bool {
+        self.token() == &PsToken::Eof
+    }
+
+    fn advance(&mut self) -> PsToken {
+        let token = self.token().clone();
+        if token != PsToken::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+}
+
+impl RecoveringParser for PowerShellParser {
+    fn error_span(&self) -> Span {
+        self.span()
+    }
+
+    fn at_end(&self) -> bool {
+        self.at_eof()
+    }
+
+    fn errors_mut(&mut self) -> &mut Vec<ParseError> {
+        &mut self.errors
+    }
+
+    fn skipped_to_end_mut(&mut self) -> &mut bool {
+        &mut self.skipped_to_end
+    }
+}
+
+impl Bound {
+    /// The arguments of the cmdlet's command at `span`: the flags, then the
+    /// values in the order of the cmdlet's parameters, then any further
+    /// positional arguments.
+    fn into_words(mut self, cmdlet: &Cmdlet, span: Span) -> Result<Vec<ASTNode>> {
+        let mut words: Vec<ASTNode> = self.flags
+            .iter()
+            .filter(|flag| !flag.is_empty())
+            .map(|flag| ASTNode::String(flag.to_string(), StringType::Unquoted, span))
+            .collect();
+        for parameter in cmdlet.positional.iter().chain(cmdlet.named) {
+            if let Some(value) = self.values.remove(parameter) {
+                push_value(&mut words, value)?;
+            }
+        }
+        for value in self.rest {
+            push_value(&mut words, value)?;
+        }
+        Ok(words)
+    }
+}
+
+/// Sort the arguments of the cmdlet `name` out by parameter. Positional
+/// arguments fill the positional parameters not given by name. Parameter
+/// names may be shortened as long as they stay unambiguous.
+fn bind(cmdlet: &Cmdlet, name: &str, args: Vec<Arg>) -> Result<Bound> {
+    let mut bound = Bound { flags: Vec::new(), values: HashMap::new(), rest: Vec::new() };
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg {
+            Arg::Parameter(parameter, ..) => {
+                let mut value = || match args.next() {
+                    Some(Arg::Value(value)) => Ok(value),
+                    _ => bail!("{}: parameter `-{}' needs a value", name, parameter),
+                };
+                match lookup(cmdlet, name, &parameter)? {
+                    Binding::Switch(flag) => bound.flags.push(flag),
+                    Binding::Value(parameter) => {
+                        let value = value()?;
+                        bound.values.insert(parameter, value);
+                    }
+                    Binding::Ignored => {
+                        value()?;
+                    }
+                }
+            }
+            Arg::Value(value) => positional.push(value),
+            Arg::Block(_) => bail!("{}: script blocks are not supported here", name),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    for parameter in cmdlet.positional {
+        if !bound.values.contains_key(parameter) {
+            match positional.next() {
+                Some(value) => {
+                    bound.values.insert(parameter, value);
+                }
+                None => break,
+            }
+        }
+    }
+    bound.rest.extend(positional);
+    Ok(bound)
+}
+
+/// How the parameter `-parameter` of a mapped cmdlet binds.
+fn lookup(cmdlet: &Cmdlet, name: &str, parameter: &str) -> Result<Binding> {
+    let parameter = parameter.to_lowercase();
+    let candidates = cmdlet.switches.iter().map(|&(switch, flag)| (switch, Binding::Switch(flag)))
+        .chain(cmdlet.positional.iter().chain(cmdlet.named).map(|&value| (value, Binding::Value(value))))
+        .chain(cmdlet.ignored.iter().chain(COMMON_VALUES).map(|&ignored| (ignored, Binding::Ignored)))
+        .chain(COMMON_SWITCHES.iter().map(|&switch| (switch, Binding::Switch(""))));
+
+    let mut matches = Vec::new();
+    for (candidate, binding) in candidates {
+        if candidate == parameter {
+            return Ok(binding);
+        }
+        if candidate.starts_with(&parameter) {
+            matches.push(binding);
+        }
+    }
+    match matches.len() {
+        1 => Ok(matches.pop().unwrap()),
+        0 => bail!("{}: parameter `-{}' is not supported", name, parameter),
+        _ => bail!("{}: parameter `-{}' is ambiguous", name, parameter),
+    }
+}
+
+/// The script block of ForEach-Object or Where-Object, which may be given
+/// by one of the parameter names in `parameters`.
+fn script_block(name: &str, args: Vec<Arg>, parameters: &[&str]) -> Result<ASTNode> {
+    let mut blocks = Vec::new();
+    for arg in args {
+        match arg {
+            Arg::Block(block) => blocks.push(block),
+            Arg::Parameter(parameter, ..) if parameters.iter().any(|p| p.starts_with(&parameter.to_lowercase())) => {}
+            _ => bail!("{}: only a script block argument is supported", name),
+        }
+    }
+    if blocks.len() != 1 {
+        bail!("{}: expected one script block, found {}", name, blocks.len());
+    }
+    Ok(blocks.pop().unwrap())
+}
+
+/// Run `body` for each line of input with the line in `$_`, which is how
+/// objects flow through a pipeline once they are text.
+fn each_line(body: ASTNode, span: Span) -> ASTNode {
+    let read = command(
+        "read",
+        vec![
+            ASTNode::String("-r".to_string(), StringType::Unquoted, span),
+            ASTNode::String("_".to_string(), StringType::Unquoted, span),
+        ],
+        span,
+    );
+    ASTNode::While { condition: Box::new(read), body: Box::new(body), span }
+}
+
+/// What the variable reference at `span` stands for. `$true`, `$false` and
+/// `$null` are values; automatic variables map to their shell counterparts
+/// and other names are lowercase, since PowerShell ignores case in them.
+fn variable_expr(raw: &str, span: Span) -> Result<Expr> {
+    if let Some((scope, name)) = raw.split_once(':') {
+        return match scope.to_lowercase().as_str() {
+            "env" => Ok(Expr::Word(ASTNode::Variable(name.to_string(), span))),
+            "script" | "global" | "local" | "private" => variable_expr(name, span),
+            _ => bail!("`${}': the `{}:' drive is not supported", raw, scope),
+        };
+    }
+
+    let name = raw.to_lowercase();
+    let name = match name.as_str() {
+        "true" => return Ok(Expr::Bool(true)),
+        "false" => return Ok(Expr::Bool(false)),
+        "null" => return Ok(Expr::Null),
+        "args" => "@".to_string(),
+        "lastexitcode" => "?".to_string(),
+        "_" | "psitem" => "_".to_string(),
+        "home" => "HOME".to_string(),
+        "pwd" => "PWD".to_string(),
+        "pid" => "$".to_string(),
+        "?" => bail!("`$?' is not supported, test `$LASTEXITCODE' instead"),
+        "$" | "^" | "input" | "this" | "matches" | "error" | "psscriptroot" | "pscommandpath" | "myinvocation"
+            | "psboundparameters" | "host" | "psversiontable" | "executioncontext" => {
+            bail!("The automatic variable `${}' is not supported", raw);
+        }
+        _ => name,
+    };
+    Ok(Expr::Word(ASTNode::Variable(name, span)))
+}
+
+/// Lower an expression used as a word, such as an argument or an assigned
+/// value.
+fn lower_value(expr: Expr) -> Result<ASTNode> {
+    Ok(match expr {
+        Expr::Word(word) => word,
+        Expr::Index(name, index, span) => lower_index(name, *index, span)?,
+        Expr::Array(items) => ASTNode::Array(
+            items.into_iter().map(|item| lower_value(item).map(Box::new)).collect::<Result<_>>()?,
+        ),
+        // Booleans and `$null` don't record where they were written
+        Expr::Bool(true) => ASTNode::String("True".to_string(), StringType::SingleQuoted, Span::default()),
+        // Empty, so that they test false
+        Expr::Bool(false) | Expr::Null => ASTNode::String(String::new(), StringType::SingleQuoted, Span::default()),
+        Expr::Arithmetic(BinaryOperator::Add, left, right) if is_text(&left) || is_text(&right) => {
+            let mut parts = Vec::new();
+            push_word_part(&mut parts, lower_value(*left)?);
+            push_word_part(&mut parts, lower_value(*right)?);
+            concat(parts)
+        }
+        expr @ (Expr::Arithmetic(..) | Expr::Negate(_)) => {
+            let span = expr_span(&expr);
+            ASTNode::ArithmeticExpansion(Box::new(to_arith(expr)?), span)
+        }
+        Expr::Compare(..) | Expr::Logical(..) | Expr::Not(_) => {
+            bail!("Comparisons are only supported as conditions")
+        }
+        Expr::Statement(node) => substitution(node),
+    })
+}
+
+/// The word a pipeline stands for as a value: an expression's value or the
+/// output of commands.
+fn value_word(value: Output) -> Result<ASTNode> {
+    match value {
+        Output::Expr(expr) => lower_value(expr),
+        Output::Commands(node) => Ok(substitution(node)),
+    }
+}
+
+/// Lower an expression used as arithmetic.
+fn to_arith(expr: Expr) -> Result<ASTNode> {
+    Ok(match expr {
+        Expr::Word(ASTNode::Number(n)) if n.fract() == 0.0 => ASTNode::Integer(n as i64),
+        Expr::Word(ASTNode::Number(n)) => bail!("Only integer arithmetic is supported, found {}", n),
+        Expr::Word(ASTNode::String(text, _, _)) if text.parse::<i64>().is_ok() => ASTNode::Integer(text.parse()?),
+        // Other words are evaluated at runtime, like `$(( $x ))`
+        Expr::Word(word) => word,
+        Expr::Index(name, index, span) => lower_index(name, *index, span)?,
+        Expr::Bool(value) => ASTNode::Integer(value as i64),
+        Expr::Null => ASTNode::Integer(0),
+        Expr::Arithmetic(op, left, right) => ASTNode::BinaryOp {
+            left: Box::new(to_arith(*left)?),
+            op,
+            right: Box::new(to_arith(*right)?),
+        },
+        Expr::Negate(operand) => ASTNode::UnaryOp { op: UnaryOperator::Negate, operand: Box::new(to_arith(*operand)?) },
+        Expr::Compare(op, _, left, right) => {
+            let op = match op {
+                "eq" => BinaryOperator::Equal,
+                "ne" => BinaryOperator::NotEqual,
+                "gt" => BinaryOperator::Greater,
+                "ge" => BinaryOperator::GreaterEqual,
+                "lt" => BinaryOperator::Less,
+                "le" => BinaryOperator::LessEqual,
+                _ => bail!("`-{}' can't be used in arithmetic", op),
+            };
+            ASTNode::BinaryOp { left: Box::new(to_arith(*left)?), op, right: Box::new(to_arith(*right)?) }
+        }
+        Expr::Logical(is_and, left, right) => ASTNode::BinaryOp {
+            left: Box::new(to_arith(*left)?),
+            op: if is_and { BinaryOperator::And } else { BinaryOperator::Or },
+            right: Box::new(to_arith(*right)?),
+        },
+        Expr::Not(operand) => ASTNode::UnaryOp { op: UnaryOperator::Not, operand: Box::new(to_arith(*operand)?) },
+        Expr::Statement(node) => substitution(node),
+        Expr::Array(_) => bail!("Arrays can't be used in arithmetic"),
+    })
+}
+
+/// `$name[index]`, written at `span`. Arrays count from 1 in the generated
+/// code, so that `$name` is all of an array as in PowerShell, and indexes
+/// from 0 are shifted up; negative ones still count from the end.
+fn lower_index(name: String, index: Expr, span: Span) -> Result<ASTNode> {
+    let literal = integer(&index);
+    if name == "@" {
+        if let Some(n @ 0..) = literal {
+            return Ok(ASTNode::Variable((n + 1).to_string(), span));
+        }
+    }
+    let index = match literal {
+        Some(n) if n >= 0 => ASTNode::Integer(n + 1),
+        Some(n) => ASTNode::Integer(n),
+        None => ASTNode::BinaryOp {
+            left: Box::new(to_arith(index)?),
+            op: BinaryOperator::Add,
+            right: Box::new(ASTNode::Integer(1)),
+        },
+    };
+    Ok(ASTNode::ParameterExpansion { name, expansion_type: ExpansionType::Element(Box::new(index)), span })
+}
+
+/// Lower an expression used as a condition into a command whose status is
+/// its truth. Other values are true when not empty.
+fn lower_condition(expr: Expr, span: Span) -> Result<ASTNode> {
+    Ok(match expr {
+        Expr::Logical(true, left, right) => {
+            ASTNode::AndList(Box::new(lower_condition(*left, span)?), Box::new(lower_condition(*right, span)?))
+        }
+        Expr::Logical(false, left, right) => {
+            ASTNode::OrList(Box::new(lower_condition(*left, span)?), Box::new(lower_condition(*right, span)?))
+        }
+        Expr::Not(operand) => ASTNode::Negated(Box::new(lower_condition(*operand, span)?)),
+        Expr::Compare(op, case_sensitive, left, right) => compare(op, case_sensitive, *left, *right, span)?,
+        Expr::Bool(value) => command(if value { "true" } else { "false" }, Vec::new(), span),
+        Expr::Null => command("false", Vec::new(), span),
+        Expr::Statement(node) => node,
+        other => {
+            let expr = ASTNode::UnaryOp { op: UnaryOperator::StringNotEmpty, operand: Box::new(lower_value(other)?) };
+            ASTNode::Test { expr: Box::new(expr), extended: false, span }
+        }
+    })
+}
+
+/// A comparison as a test. `-eq` and `-ne` compare numbers when a side is
+/// a number, the ordering operators unless a side is text. `-like` and
+/// `-match` take their right side as a glob or regex pattern.
+///
+/// Text is compared ignoring case by `[[ ]]` tests under `nocasematch`,
+/// which the generated code turns on for PowerShell; `-ceq` and `-cne`
+/// become plain `[ ]` tests instead.
+fn compare(op: &str, case_sensitive: bool, left: Expr, right: Expr, span: Span) -> Result<ASTNode> {
+    let numeric = match op {
+        "eq" | "ne" => is_numeric(&left) || is_numeric(&right),
+        _ => !(is_text(&left) || is_text(&right)),
+    };
+    let pattern = matches!(op, "like" | "notlike" | "match" | "notmatch");
+    match (op, numeric, case_sensitive) {
+        ("like" | "notlike" | "match" | "notmatch", _, true) => {
+            bail!("`-c{}' is not supported, patterns are matched ignoring case", op);
+        }
+        ("gt" | "lt", false, false) => {
+            bail!("`-{}' compares text ignoring case, which is not supported; use `-c{}'", op, op);
+        }
+        _ => {}
+    }
+    let extended = pattern || (matches!(op, "eq" | "ne") && !numeric && !case_sensitive);
+    let negated = op == "notmatch";
+    let left = Box::new(lower_value(left)?);
+    let right = lower_value(right)?;
+    let right = Box::new(if pattern {
+        pattern_word(right)
+    } else if extended {
+        literal_word(right)
+    } else {
+        right
+    });
+
+    let op = match (op, numeric) {
+        ("eq", true) => BinaryOperator::Equal,
+        ("eq", false) => BinaryOperator::StringEqual,
+        ("ne", true) => BinaryOperator::NotEqual,
+        ("ne", false) => BinaryOperator::StringNotEqual,
+        ("gt", true) => BinaryOperator::Greater,
+        ("gt", false) => BinaryOperator::StringGreater,
+        ("lt", true) => BinaryOperator::Less,
+        ("lt", false) => BinaryOperator::StringLess,
+        ("ge", true) => BinaryOperator::GreaterEqual,
+        ("le", true) => BinaryOperator::LessEqual,
+        ("ge" | "le", false) => bail!("`-{}' only compares numbers", op),
+        ("like", _) => BinaryOperator::StringEqual,
+        ("notlike", _) => BinaryOperator::StringNotEqual,
+        ("match" | "notmatch", _) => BinaryOperator::Match,
+        _ => unreachable!("COMPARISONS lists every operator"),
+    };
+    let mut expr = ASTNode::BinaryOp { left, op, right };
+    if negated {
+        expr = ASTNode::UnaryOp { op: UnaryOperator::Not, operand: Box::new(expr) };
+    }
+    Ok(ASTNode::Test { expr: Box::new(expr), extended, span })
+}
+
+/// The right side of `-eq` in a `[[ ]]` test, quoted so that it is matched
+/// literally rather than as a pattern.
+fn literal_word(word: ASTNode) -> ASTNode {
+    match word {
+        ASTNode::String(text, StringType::Unquoted, span) => ASTNode::String(text, StringType::SingleQuoted, span),
+        word @ (ASTNode::String(..) | ASTNode::Quoted(..)) => word,
+        ASTNode::Concat(parts, span) => ASTNode::Quoted(parts, span),
+        other => {
+            let span = other.span().unwrap_or_default();
+            ASTNode::Quoted(vec![Box::new(other)], span)
+        }
+    }
+}
+
+/// A pattern on the right of `-like` or `-match`, whose literal text must
+/// stay unquoted to be matched as a pattern.
+fn pattern_word(word: ASTNode) -> ASTNode {
+    match word {
+        ASTNode::String(text, StringType::SingleQuoted | StringType::DoubleQuoted, span) => {
+            ASTNode::String(text, StringType::Unquoted, span)
+        }
+        ASTNode::Concat(parts, span) => ASTNode::Concat(parts.into_iter().map(|part| Box::new(pattern_word(*part))).collect(), span),
+        other => other,
+    }
+}
+
+/// An expression used as a statement, which outputs its value: one line
+/// per item of an array, and `True` or `False` for a comparison.
+fn output_command(expr: Expr, span: Span) -> Result<ASTNode> {
+    match expr {
+        Expr::Statement(node) => Ok(node),
+        Expr::Array(items) => {
+            let mut words = vec![ASTNode::String("%s\\n".to_string(), StringType::SingleQuoted, span)];
+            for item in items {
+                words.push(lower_value(item)?);
+            }
+            Ok(command("printf", words, span))
+        }
+        expr @ (Expr::Compare(..) | Expr::Logical(..) | Expr::Not(_)) => {
+            let condition = lower_condition(expr, span)?;
+            let yes = command("echo", vec![ASTNode::String("True".to_string(), StringType::SingleQuoted, span)], span);
+            let no = command("echo", vec![ASTNode::String("False".to_string(), StringType::SingleQuoted, span)], span);
+            Ok(ASTNode::OrList(Box::new(ASTNode::AndList(Box::new(condition), Box::new(yes))), Box::new(no)))
+        }
+        // Nothing is output for `$null`
+        Expr::Null => Ok(command("true", Vec::new(), span)),
+        expr => Ok(command("echo", vec![lower_value(expr)?], span)),
+    }
+}
+
+/// Add an argument to the words of a command; arrays give a word per item.
+fn push_value(words: &mut Vec<ASTNode>, value: Expr) -> Result<()> {
+    match value {
+        Expr::Array(items) => {
+            for item in items {
+                push_value(words, item)?;
+            }
+        }
+        Expr::Null => {}
+        value => words.push(lower_value(value)?),
+    }
+    Ok(())
+}
+
+fn command(name: &str, args: Vec<ASTNode>, span: Span) -> ASTNode {
+    ASTNode::Command {
+        name: name.to_string(),
+        args: args.into_iter().map(Box::new).collect(),
+        redirections: Vec::new(),
+        background: false,
+        span,
+    }
+}
+
+/// The output of commands as a word, located where they start.
+fn substitution(node: ASTNode) -> ASTNode {
+    let span = node.span().unwrap_or_default();
+    ASTNode::CommandSubstitution(Box::new(node), span)
+}
+
+/// Words joined into one, located where the first starts.
+fn concat(parts: Vec<ASTNode>) -> ASTNode {
+    let span = parts.first().and_then(ASTNode::span).unwrap_or_default();
+    ASTNode::Concat(parts.into_iter().map(Box::new).collect(), span)
+}
+
+/// Send the output of `node` to /dev/null.
+fn discard(node: ASTNode) -> ASTNode {
+    let span = node.span().unwrap_or_default();
+    let null = to_file(ASTNode::String("/dev/null".to_string(), StringType::SingleQuoted, span), false);
+    match node {
+        ASTNode::Command { name, args, mut redirections, background, span } => {
+            redirections.push(null);
+            ASTNode::Command { name, args, redirections, background, span }
+        }
+        other => ASTNode::Redirected { body: Box::new(other), redirections: vec![null] },
+    }
+}
+
+fn to_file(file: ASTNode, append: bool) -> Redirection {
+    Redirection {
+        fd: None,
+        direction: RedirectDirection::Output,
+        target: RedirectionTarget::File(Box::new(file)),
+        append,
+    }
+}
+
+fn to_stderr() -> Redirection {
+    Redirection {
+        fd: None,
+        direction: RedirectDirection::Output,
+        target: RedirectionTarget::Fd(2),
+        append: false,
+    }
+}
+
+fn assignment_operator(op: &str) -> Option<BinaryOperator> {
+    match op {
+        "+=" => Some(BinaryOperator::Add),
+        "-=" => Some(BinaryOperator::Subtract),
+        "*=" => Some(BinaryOperator::Multiply),
+        "/=" => Some(BinaryOperator::Divide),
+        "%=" => Some(BinaryOperator::Modulo),
+        _ => None,
+    }
+}
+
+/// What assigning `expr` makes a variable hold, when that is known.
+fn value_kind(expr: &Expr) -> Option<ValueKind> {
+    match expr {
+        Expr::Array(_) => Some(ValueKind::Array),
+        Expr::Word(ASTNode::Number(_)) | Expr::Arithmetic(..) | Expr::Negate(_) if !is_text(expr) => Some(ValueKind::Number),
+        _ if is_text(expr) => Some(ValueKind::Text),
+        _ => None,
+    }
+}
+
+/// Where an expression starts: its leftmost word, if that is located.
+fn expr_span(expr: &Expr) -> Span {
+    match expr {
+        Expr::Word(word) | Expr::Statement(word) => word.span().unwrap_or_default(),
+        Expr::Index(_, _, span) => *span,
+        Expr::Array(items) => items.first().map(expr_span).unwrap_or_default(),
+        Expr::Bool(_) | Expr::Null => Span::default(),
+        Expr::Arithmetic(_, left, _) | Expr::Compare(_, _, left, _) | Expr::Logical(_, left, _) => expr_span(left),
+        Expr::Negate(operand) | Expr::Not(operand) => expr_span(operand),
+    }
+}
+
+/// Whether an expression is a number, for comparisons.
+fn is_numeric(expr: &Expr) -> bool {
+    match expr {
+        Expr::Word(ASTNode::Number(_)) => true,
+        Expr::Arithmetic(_, left, right) => !is_text(left) && !is_text(right),
+        Expr::Negate(_) => true,
+        _ => false,
+    }
+}
+
+/// Whether an expression is a string, which `+` joins rather than adds.
+fn is_text(expr: &Expr) -> bool {
+    match expr {
+        Expr::Word(ASTNode::String(text, StringType::SingleQuoted | StringType::DoubleQuoted, _)) => {
+            text.parse::<i64>().is_err()
+        }
+        Expr::Word(ASTNode::Concat(_, _)) => true,
+        Expr::Arithmetic(BinaryOperator::Add, left, right) => is_text(left) || is_text(right),
+        _ => false,
+    }
+}
+
+/// The value of an integer literal.
+fn integer(expr: &Expr) -> Option<i64> {
+    match expr {
+        Expr::Word(ASTNode::Number(n)) if n.fract() == 0.0 => Some(*n as i64),
+        Expr::Negate(operand) => integer(operand).map(|n| -n),
+        _ => None,
+    }
+}
+
+/// The numbers from `start` to `end`, counting down if `end` is smaller.
+fn numbers(start: i64, end: i64) -> Expr {
+    let values: Vec<i64> = if start <= end { (start..=end).collect() } else { (end..=start).rev().collect() };
+    Expr::Array(values.into_iter().map(|n| Expr::Word(ASTNode::Number(n as f64))).collect())
+}
+
+/// Whether `name` looks like a cmdlet: an approved verb, a dash and a noun.
+fn is_cmdlet_name(name: &str) -> bool {
+    name.split_once('-')
+        .is_some_and(|(verb, noun)| VERBS.contains(&verb) && noun.chars().next().is_some_and(|c| c.is_ascii_alphabetic()))
+}
+
+fn flush_literal(parts: &mut Vec<ASTNode>, literal: &mut String, start: Span) {
+    if !literal.is_empty() {
+        parts.push(ASTNode::String(std::mem::take(literal), StringType::SingleQuoted, start));
+    }
+}
+
+/// The index of the `)` closing a `$(` whose text starts at `start`,
+/// skipping over quoted text.
+fn subexpression_end(chars: &[char], start: usize) -> usize {
+    let mut depth = 1;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            '`' => i += 1,
+            quote @ ('\'' | '"') => {
+                i += 1;
+                while i < chars.len() && chars[i] != quote {
+                    if chars[i] == '`' && quote == '"' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+fn advance_span(span: Span, c: char) -> Span {
+    match c {
+        '\n' => Span::new(span.line + 1, 1),
+        _ => Span::new(span.line, span.column + 1),
+    }
+}
+
+fn error_at(span: Span, message: impl Into<String>, hint: Option<&str>) -> anyhow::Error {
+    ParseError { span, message: message.into(), hint: hint.map(str::to_string) }.into()
+}
+
+/// How a token is named in error messages.
+fn describe(token: &PsToken) -> String {
+    match token {
+        PsToken::Word(text) | PsToken::Operator(text) | PsToken::Assign(text) | PsToken::Redirect(text) => {
+            format!("`{}'", text)
+        }
+        PsToken::Number(n) => format!("`{}'", n),
+        PsToken::Parameter(name) => format!("`-{}'", name),
+        PsToken::Variable(name) => format!("`${}'", name),
+        PsToken::String(_) | PsToken::ExpandableString(..) => "a string".to_string(),
+        PsToken::Type(name) => format!("`[{}]'", name),
+        PsToken::Member(name) => format!("`.{}'", name),
+        PsToken::SubExpression => "`$('".to_string(),
+        PsToken::ArrayExpression => "`@('".to_string(),
+        PsToken::HashLiteral => "`@{'".to_string(),
+        PsToken::LeftParen => "`('".to_string(),
+        PsToken::RightParen => "`)'".to_string(),
+        PsToken::LeftBrace => "`{'".to_string(),
+        PsToken::RightBrace => "`}'".to_string(),
+        PsToken::LeftBracket => "`['".to_string(),
+        PsToken::RightBracket => "`]'".to_string(),
+        PsToken::Comma => "`,'".to_string(),
+        PsToken::Semicolon => "`;'".to_string(),
+        PsToken::Newline => "end of line".to_string(),
+        PsToken::Pipe => "`|'".to_string(),
+        PsToken::AndAnd => "`&&'".to_string(),
+        PsToken::OrOr => "`||'".to_string(),
+        PsToken::Ampersand => "`&'".to_string(),
+        PsToken::Eof => "end of input".to_string(),
+    }
+}
@@ -0,0 +1,554 @@
+use super::ast::Span;
+use super::parser::ParseError;
+use anyhow::Result;
+
+/// Tokens of a PowerShell script.
+///
+/// PowerShell reads the same text differently as a command argument and
+/// as an expression, so the lexer only splits the script and the parser
+/// decides what a token means where it is used: `-eq` is a `Parameter`
+/// either way and `*` is an `Operator` only when a blank follows it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PsToken {
+    Word(String),                    // bare text: command names, keywords, arguments
+    Number(i64),
+    Parameter(String),               // -Name or -eq, without the `-`
+    Variable(String),                // $name, ${name} or $scope:name, as written
+    String(String),                  // '...' or @'...'@, with `''` already unescaped
+    ExpandableString(String, Span),  // "..." or @"..."@ and where its text starts; `$` and backticks are left to the parser
+    Type(String),                    // [string] or [Parameter(Mandatory)], without the brackets
+    Member(String),                  // .Name right after a value
+    SubExpression,                   // $(
+    ArrayExpression,                 // @(
+    HashLiteral,                     // @{
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,                     // [ right after a value, an index
+    RightBracket,
+    Comma,
+    Semicolon,
+    Newline,
+    Pipe,
+    AndAnd,
+    OrOr,
+    Ampersand,                       // the call operator, or `&` ending a command
+    Assign(String),                  // = += -= *= /= %=
+    Operator(String),                // + - * / % ! .. ++ -- :: . < @
+    Redirect(String),                // > >> 2> 2>> 2>&1 *> ...
+    Eof,
+}
+
+/// A token, where it starts and whether blanks separate it from the token
+/// before it. Adjacent tokens make up one argument, as in `$dir/file`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PsLexeme {
+    pub token: PsToken,
+    pub span: Span,
+    pub spaced: bool,
+}
+
+pub struct PowerShellLexer {
+    chars: Vec<char>,
+    pos: usize,
+    // Position of `chars[pos]` in the script
+    span: Span,
+}
+
+impl PowerShellLexer {
+    pub fn new(input: &str) -> Self {
+        Self::starting_at(input, Span::new(1, 1))
+    }
+
+    /// A lexer for text embedded in a script at `start`, such as a `$(...)`
+    /// inside a string.
+    pub fn starting_at(input: &str, start: Span) -> Self {
+        PowerShellLexer { chars: input.chars().collect(), pos: 0, span: start }
+    }
+
+    /// All tokens of the input, ending with `PsToken::Eof`.
+    pub fn tokenize(&mut self) -> Result<Vec<PsLexeme>> {
+        let mut lexemes: Vec<PsLexeme> = Vec::new();
+        loop {
+            let spaced = self.skip_blanks()? || lexemes.is_empty();
+            let span = self.span;
+            let after_value = !spaced && lexemes.last().is_some_and(|lexeme| is_value_end(&lexeme.token));
+            let token = self.next_token(after_value)?;
+            let eof = token == PsToken::Eof;
+            lexemes.push(PsLexeme { token, span, spaced });
+            if eof {
+                return Ok(lexemes);
+            }
+        }
+    }
+
+    fn next_token(&mut self, after_value: bool) -> Result<PsToken> {
+        let Some(c) = self.peek() else {
+            return Ok(PsToken::Eof);
+        };
+        let next = self.peek_at(1);
+
+        let token = match c {
+            '\n' => PsToken::Newline,
+            ';' => PsToken::Semicolon,
+            ',' => PsToken::Comma,
+            '(' => PsToken::LeftParen,
+            ')' => PsToken::RightParen,
+            '{' => PsToken::LeftBrace,
+            '}' => PsToken::RightBrace,
+            ']' => PsToken::RightBracket,
+            '[' if after_value => PsToken::LeftBracket,
+            '[' if next.is_some_and(|c| c.is_ascii_alphabetic()) => return self.read_type(),
+            '[' => PsToken::LeftBracket,
+            '|' if next == Some('|') => return Ok(self.symbol(2, PsToken::OrOr)),
+            '|' => PsToken::Pipe,
+            '&' if next == Some('&') => return Ok(self.symbol(2, PsToken::AndAnd)),
+            '&' => PsToken::Ampersand,
+            '=' => PsToken::Assign("=".to_string()),
+            '<' => PsToken::Operator("<".to_string()),
+            '>' => return Ok(self.read_redirect(String::new())),
+            '\'' => return self.read_string(),
+            '"' => return self.read_expandable_string(),
+            '$' => return self.read_variable(),
+            '@' => return self.read_at(),
+            '-' => return Ok(self.read_dash()),
+            '!' => PsToken::Operator("!".to_string()),
+            '+' if next == Some('+') => return Ok(self.symbol(2, PsToken::Operator("++".to_string()))),
+            '+' if next == Some('=') => return Ok(self.symbol(2, PsToken::Assign("+=".to_string()))),
+            '+' => PsToken::Operator("+".to_string()),
+            '*' if next == Some('>') => {
+                self.advance();
+                return Ok(self.read_redirect("*".to_string()));
+            }
+            '*' | '/' | '%' if next == Some('=') => {
+                return Ok(self.symbol(2, PsToken::Assign(format!("{}=", c))));
+            }
+            // Followed by text these start a glob or a path
+            '*' | '/' | '%' if next.is_none_or(|c| c.is_whitespace() || matches!(c, '(' | '$')) => {
+                PsToken::Operator(c.to_string())
+            }
+            '.' if after_value && next.is_some_and(|c| c.is_ascii_alphabetic() || c == '_') => {
+                self.advance();
+                return Ok(PsToken::Member(self.read_name()));
+            }
+            '.' if next == Some('.') => return Ok(self.symbol(2, PsToken::Operator("..".to_string()))),
+            '.' if next.is_none_or(|c| c.is_whitespace() || c == '{') => PsToken::Operator(".".to_string()),
+            ':' if next == Some(':') => return Ok(self.symbol(2, PsToken::Operator("::".to_string()))),
+            _ if c.is_ascii_digit() && self.digits_then('>') => {
+                let digits = self.read_while(|c| c.is_ascii_digit());
+                return Ok(self.read_redirect(digits));
+            }
+            _ => return Ok(self.read_word()),
+        };
+        self.advance();
+        Ok(token)
+    }
+
+    fn symbol(&mut self, length: usize, token: PsToken) -> PsToken {
+        self.advance_by(length);
+        token
+    }
+
+    /// Text up to a blank or an operator, with backtick escapes. A word that
+    /// is a whole number becomes a `Number`.
+    fn read_word(&mut self) -> PsToken {
+        let mut text = String::new();
+        let mut brackets = 0;
+        while let Some(c) = self.peek() {
+            match c {
+                '`' => {
+                    self.advance();
+                    match self.advance() {
+                        Some(c) => text.push(unescape(c)),
+                        None => text.push('`'),
+                    }
+                    continue;
+                }
+                '[' => brackets += 1,
+                ']' if brackets > 0 => brackets -= 1,
+                // The `..` of a range like `1..$n` after a number
+                '.' if self.peek_at(1) == Some('.') && text.parse::<i64>().is_ok() => break,
+                _ if is_word_end(c) => break,
+                _ => {}
+            }
+            self.advance();
+            text.push(c);
+        }
+
+        match text.parse::<i64>() {
+            Ok(n) if !text.starts_with('+') => PsToken::Number(n),
+            _ => PsToken::Word(text),
+        }
+    }
+
+    /// `-Name` is a parameter or an operator, `-5` a number and `--`, `-=`
+    /// and a lone `-` are operators. `--flag` is text for native commands.
+    fn read_dash(&mut self) -> PsToken {
+        match self.peek_at(1) {
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.advance();
+                let name = self.read_while(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+                // `-Path:value` passes the value right after the colon
+                if self.peek() == Some(':') && self.peek_at(1) != Some(':') {
+                    self.advance();
+                }
+                PsToken::Parameter(name)
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let start = (self.pos, self.span);
+                match self.read_word() {
+                    token @ PsToken::Number(_) => token,
+                    _ => {
+                        (self.pos, self.span) = start;
+                        self.symbol(1, PsToken::Operator("-".to_string()))
+                    }
+                }
+            }
+            Some('-') if self.peek_at(2).is_some_and(|c| c.is_ascii_alphabetic()) => self.read_word(),
+            Some('-') => self.symbol(2, PsToken::Operator("--".to_string())),
+            Some('=') => self.symbol(2, PsToken::Assign("-=".to_string())),
+            _ => self.symbol(1, PsToken::Operator("-".to_string())),
+        }
+    }
+
+    /// `$name`, `${name}`, `$scope:name`, `$(`, or the automatic `$?`, `$$`
+    /// and `$^`.
+    fn read_variable(&mut self) -> Result<PsToken> {
+        let start = self.span;
+        self.advance();
+        match self.peek() {
+            Some('(') => {
+                self.advance();
+                Ok(PsToken::SubExpression)
+            }
+            Some('{') => {
+                self.advance();
+                let mut name = String::new();
+                loop {
+                    match self.advance() {
+                        Some('}') => return Ok(PsToken::Variable(name)),
+                        Some('`') => name.extend(self.advance()),
+                        Some(c) => name.push(c),
+                        None => {
+                            return Err(ParseError {
+                                span: start,
+                                message: "Unterminated variable name".to_string(),
+                                hint: Some("add the missing `}`".to_string()),
+                            }.into());
+                        }
+                    }
+                }
+            }
+            Some(c @ ('?' | '$' | '^')) => {
+                self.advance();
+                Ok(PsToken::Variable(c.to_string()))
+            }
+            Some(c) if is_name_char(c) => {
+                let mut name = self.read_name();
+                if self.peek() == Some(':') && self.peek_at(1).is_some_and(is_name_char) {
+                    self.advance();
+                    name.push(':');
+                    name.push_str(&self.read_name());
+                }
+                Ok(PsToken::Variable(name))
+            }
+            // A lone `$` is text
+            _ => Ok(PsToken::Word("$".to_string())),
+        }
+    }
+
+    /// `@(`, `@{`, a here-string or splatting.
+    fn read_at(&mut self) -> Result<PsToken> {
+        match self.peek_at(1) {
+            Some('(') => Ok(self.symbol(2, PsToken::ArrayExpression)),
+            Some('{') => Ok(self.symbol(2, PsToken::HashLiteral)),
+            Some(quote @ ('"' | '\'')) if self.blank_line_after(2) => self.read_here_string(quote),
+            _ => Ok(self.symbol(1, PsToken::Operator("@".to_string()))),
+        }
+    }
+
+    /// Whether only blanks follow up to the end of the line from `offset`.
+    fn blank_line_after(&self, offset: usize) -> bool {
+        self.chars[(self.pos + offset).min(self.chars.len())..]
+            .iter()
+            .take_while(|&&c| c != '\n')
+            .all(|c| c.is_whitespace())
+    }
+
+    /// `@"` or `@'` at the end of a line, up to `"@` or `'@` at the start of
+    /// a line. The newlines next to the delimiters are not part of the text.
+    fn read_here_string(&mut self, quote: char) -> Result<PsToken> {
+        let start = self.span;
+        while self.advance().is_some_and(|c| c != '\n') {}
+        let text_start = self.span;
+
+        let mut text = String::new();
+        loop {
+            if self.column_is_first() && self.peek() == Some(quote) && self.peek_at(1) == Some('@') {
+                self.advance_by(2);
+                if text.ends_with('\n') {
+                    text.pop();
+                }
+                return Ok(if quote == '"' {
+                    PsToken::ExpandableString(text, text_start)
+                } else {
+                    PsToken::String(text)
+                });
+            }
+            match self.advance() {
+                Some(c) => text.push(c),
+                None => return Err(unterminated_string(start)),
+            }
+        }
+    }
+
+    /// `'...'`, where `''` is a quote.
+    fn read_string(&mut self) -> Result<PsToken> {
+        let start = self.span;
+        self.advance();
+        let mut text = String::new();
+        loop {
+            match self.advance() {
+                Some('\'') if self.peek() == Some('\'') => {
+                    self.advance();
+                    text.push('\'');
+                }
+                Some('\'') => return Ok(PsToken::String(text)),
+                Some(c) => text.push(c),
+                None => return Err(unterminated_string(start)),
+            }
+        }
+    }
+
+    /// `"..."`, kept as written except that `""` becomes the equivalent
+    /// `` `" ``. A `$(...)` inside may hold quotes of its own.
+    fn read_expandable_string(&mut self) -> Result<PsToken> {
+        let start = self.span;
+        self.advance();
+        let text_start = self.span;
+        let mut text = String::new();
+        loop {
+            match self.advance() {
+                Some('"') if self.peek() == Some('"') => {
+                    self.advance();
+                    text.push_str("`\"");
+                }
+                Some('"') => return Ok(PsToken::ExpandableString(text, text_start)),
+                Some('`') => {
+                    text.push('`');
+                    text.extend(self.advance());
+                }
+                Some('$') if self.peek() == Some('(') => {
+                    self.advance();
+                    text.push_str("$(");
+                    self.copy_subexpression(&mut text, start)?;
+                }
+                Some(c) => text.push(c),
+                None => return Err(unterminated_string(start)),
+            }
+        }
+    }
+
+    /// Copy the rest of a `$(...)` in a string up to its closing `)`,
+    /// skipping over quoted text.
+    fn copy_subexpression(&mut self, text: &mut String, start: Span) -> Result<()> {
+        let mut depth = 1;
+        while depth > 0 {
+            let Some(c) = self.advance() else {
+                return Err(unterminated_string(start));
+            };
+            text.push(c);
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                '`' => text.extend(self.advance()),
+                '\'' | '"' => loop {
+                    let Some(next) = self.advance() else {
+                        return Err(unterminated_string(start));
+                    };
+                    text.push(next);
+                    if next == '`' && c == '"' {
+                        text.extend(self.advance());
+                    } else if next == c {
+                        break;
+                    }
+                },
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// `[...]` before a name: a type or an attribute, which may hold
+    /// brackets and parentheses of its own.
+    fn read_type(&mut self) -> Result<PsToken> {
+        let start = self.span;
+        self.advance();
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            match self.advance() {
+                Some(']') if depth == 0 => return Ok(PsToken::Type(text)),
+                Some(c) => {
+                    match c {
+                        '[' | '(' => depth += 1,
+                        ']' | ')' => depth -= 1,
+                        _ => {}
+                    }
+                    text.push(c);
+                }
+                None => {
+                    return Err(ParseError {
+                        span: start,
+                        message: "Unterminated type name".to_string(),
+                        hint: Some("add the missing `]`".to_string()),
+                    }.into());
+                }
+            }
+        }
+    }
+
+    /// The rest of a redirection after its stream number or `*`: `>`, `>>`
+    /// or `>&1`.
+    fn read_redirect(&mut self, mut text: String) -> PsToken {
+        self.advance();
+        text.push('>');
+        if self.peek() == Some('>') {
+            self.advance();
+            text.push('>');
+        } else if self.peek() == Some('&') && self.peek_at(1).is_some_and(|c| c.is_ascii_digit()) {
+            self.advance();
+            text.push('&');
+            text.push_str(&self.read_while(|c| c.is_ascii_digit()));
+        }
+        PsToken::Redirect(text)
+    }
+
+    /// Skip blanks, comments and line continuations, but not newlines.
+    /// Returns whether anything was skipped.
+    fn skip_blanks(&mut self) -> Result<bool> {
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() && c != '\n' => {
+                    self.advance();
+                }
+                Some('`') if matches!(self.peek_at(1), Some('\n')) => self.advance_by(2),
+                Some('`') if matches!(self.peek_at(1), Some('\r')) && self.peek_at(2) == Some('\n') => {
+                    self.advance_by(3)
+                }
+                Some('#') => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.advance();
+                    }
+                }
+                Some('<') if self.peek_at(1) == Some('#') => {
+                    let start = self.span;
+                    self.advance_by(2);
+                    while !(self.peek() == Some('#') && self.peek_at(1) == Some('>')) {
+                        if self.advance().is_none() {
+                            return Err(ParseError {
+                                span: start,
+                                message: "Unterminated block comment".to_string(),
+                                hint: Some("add the missing `#>`".to_string()),
+                            }.into());
+                        }
+                    }
+                    self.advance_by(2);
+                }
+                _ => return Ok(self.pos > start),
+            }
+        }
+    }
+
+    /// Whether a run of digits followed by `c` starts here.
+    fn digits_then(&self, c: char) -> bool {
+        let digits = self.chars[self.pos..].iter().take_while(|c| c.is_ascii_digit()).count();
+        self.peek_at(digits) == Some(c)
+    }
+
+    fn column_is_first(&self) -> bool {
+        self.pos == 0 || self.chars[self.pos - 1] == '\n'
+    }
+
+    fn read_name(&mut self) -> String {
+        self.read_while(is_name_char)
+    }
+
+    fn read_while(&mut self, accept: impl Fn(char) -> bool) -> String {
+        let mut text = String::new();
+        while let Some(c) = self.peek().filter(|&c| accept(c)) {
+            self.advance();
+            text.push(c);
+        }
+        text
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        self.span = match c {
+            '\n' => Span::new(self.span.line + 1, 1),
+            _ => Span::new(self.span.line, self.span.column + 1),
+        };
+        Some(c)
+    }
+
+    fn advance_by(&mut self, count: usize) {
+        for _ in 0..count {
+            self.advance();
+        }
+    }
+}
+
+/// Whether a token can be indexed or have members when `[` or `.`
+/// follows it directly.
+fn is_value_end(token: &PsToken) -> bool {
+    matches!(
+        token,
+        PsToken::Variable(_) | PsToken::RightParen | PsToken::RightBracket | PsToken::Member(_)
+            | PsToken::String(_) | PsToken::ExpandableString(..)
+    )
+}
+
+/// Whether `c` ends a bare word.
+fn is_word_end(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ';' | ',' | '(' | ')' | '{' | '}' | '|' | '&' | '"' | '\'' | '$' | '<' | '>' | ']')
+}
+
+pub fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// The character a backtick escape stands for.
+pub fn unescape(c: char) -> char {
+    match c {
+        '0' => '\0',
+        'a' => '\x07',
+        'b' => '\x08',
+        'e' => '\x1b',
+        'f' => '\x0c',
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        'v' => '\x0b',
+        _ => c,
+    }
+}
+
+fn unterminated_string(start: Span) -> anyhow::Error {
+    ParseError {
+        span: start,
+        message: "Unterminated string".to_string(),
+        hint: Some("the quote opened here is not closed".to_string()),
+    }.into()
+}
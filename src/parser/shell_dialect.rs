@@ -1,7 +1,8 @@
 use std::path::Path;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum ShellDialect {
+    #[default]
     Bash,
     Zsh,
     Fish,
@@ -61,25 +62,53 @@ impl ShellDialect {
     /// Check if this dialect supports a specific feature
     pub fn supports_feature(&self, feature: ShellFeature) -> bool {
         use ShellFeature::*;
+        use ShellDialect::*;
         
         match (self, feature) {
-            // Arrays are supported by most shells except POSIX sh and csh
-            (ShellDialect::Posix | ShellDialect::Csh, Arrays) => false,
+            // Arrays are supported by most shells except POSIX sh and dash
+            (Posix | Dash, Arrays) => false,
             
             // Associative arrays only in bash 4+, zsh, and ksh
-            (ShellDialect::Bash | ShellDialect::Zsh | ShellDialect::Ksh, AssociativeArrays) => true,
+            (Bash | Zsh | Ksh, AssociativeArrays) => true,
             (_, AssociativeArrays) => false,
             
-            // Process substitution not in POSIX sh, dash, or Windows shells
-            (ShellDialect::Posix | ShellDialect::Dash | ShellDialect::PowerShell, ProcessSubstitution) => false,
+            // Process substitution not in POSIX sh, dash, csh or Windows shells
+            (Posix | Dash | Csh | Tcsh | PowerShell, ProcessSubstitution) => false,
+            
+            // [[ ]], `function name` and `select` are ksh syntax that bash and
+            // zsh adopted; fish and PowerShell have a `function` of their own
+            (Bash | Zsh | Ksh, ExtendedTest | FunctionKeyword | SelectLoop) => true,
+            (Fish | PowerShell, FunctionKeyword) => true,
+            (_, ExtendedTest | FunctionKeyword | SelectLoop) => false,
+            
+            // dash has `local` although POSIX doesn't
+            (Posix | Csh | Tcsh, LocalKeyword) => false,
             
-            // Extended test [[ ]] not in pure POSIX
-            (ShellDialect::Posix, ExtendedTest) => false,
+            // Bashisms that bash, zsh and ksh share; ksh has no `&>`, and
+            // its `|&` starts a coprocess
+            (Posix | Dash, PatternSubstitution | AnsiCQuoting | SourceBuiltin | HereString
+                | CombinedRedirect | PipeStderr | ArithmeticCommand) => false,
+            (Ksh, CombinedRedirect | PipeStderr) => false,
             
             // Most features are supported by default
             _ => true,
         }
     }
+    
+    /// The name of the shell in messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShellDialect::Bash => "bash",
+            ShellDialect::Zsh => "zsh",
+            ShellDialect::Fish => "fish",
+            ShellDialect::Dash => "dash",
+            ShellDialect::Ksh => "ksh",
+            ShellDialect::Tcsh => "tcsh",
+            ShellDialect::Csh => "csh",
+            ShellDialect::PowerShell => "PowerShell",
+            ShellDialect::Posix => "POSIX sh",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -91,4 +120,33 @@ pub enum ShellFeature {
     FunctionKeyword,
     LocalKeyword,
     SelectLoop,
-}
\ No newline at end of file
+    PatternSubstitution,
+    AnsiCQuoting,
+    SourceBuiltin,
+    HereString,
+    CombinedRedirect,
+    PipeStderr,
+    ArithmeticCommand,
+}
+
+impl ShellFeature {
+    /// What the feature is, as used in messages.
+    pub fn description(&self) -> &'static str {
+        match self {
+            ShellFeature::Arrays => "arrays",
+            ShellFeature::AssociativeArrays => "associative arrays",
+            ShellFeature::ProcessSubstitution => "process substitution",
+            ShellFeature::ExtendedTest => "`[[ ]]` tests",
+            ShellFeature::FunctionKeyword => "the `function` keyword",
+            ShellFeature::LocalKeyword => "`local` variables",
+            ShellFeature::SelectLoop => "`select` loops",
+            ShellFeature::PatternSubstitution => "`${name/pattern/string}` substitutions",
+            ShellFeature::AnsiCQuoting => "`$'...'` strings",
+            ShellFeature::SourceBuiltin => "`source`",
+            ShellFeature::HereString => "`<<<` here-strings",
+            ShellFeature::CombinedRedirect => "`&>` redirections",
+            ShellFeature::PipeStderr => "`|&` pipes",
+            ShellFeature::ArithmeticCommand => "`(( ))` and `let` arithmetic commands",
+        }
+    }
+}
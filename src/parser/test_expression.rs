@@ -0,0 +1,169 @@
+use super::ast::{ASTNode, BinaryOperator, Span, UnaryOperator};
+use anyhow::{Result, bail};
+
+/// Unary operator of `test`, `[ ]` and `[[ ]]` spelled `text`.
+pub fn unary_operator(text: &str) -> Option<UnaryOperator> {
+    Some(match text {
+        "-e" | "-a" => UnaryOperator::FileExists,
+        "-f" => UnaryOperator::FileRegular,
+        "-d" => UnaryOperator::FileDirectory,
+        "-L" | "-h" => UnaryOperator::FileSymlink,
+        "-r" => UnaryOperator::FileReadable,
+        "-w" => UnaryOperator::FileWritable,
+        "-x" => UnaryOperator::FileExecutable,
+        "-s" => UnaryOperator::FileNotEmpty,
+        "-b" => UnaryOperator::FileBlock,
+        "-c" => UnaryOperator::FileCharacter,
+        "-p" => UnaryOperator::FilePipe,
+        "-S" => UnaryOperator::FileSocket,
+        "-u" => UnaryOperator::FileSetuid,
+        "-g" => UnaryOperator::FileSetgid,
+        "-k" => UnaryOperator::FileSticky,
+        "-t" => UnaryOperator::Terminal,
+        "-n" => UnaryOperator::StringNotEmpty,
+        "-z" => UnaryOperator::StringEmpty,
+        "-v" => UnaryOperator::VariableSet,
+        _ => return None,
+    })
+}
+
+/// Binary operator of `test`, `[ ]` and `[[ ]]` spelled `text`. `=~` is
+/// only an operator inside `[[ ]]`.
+pub fn binary_operator(text: &str) -> Option<BinaryOperator> {
+    Some(match text {
+        "=" | "==" => BinaryOperator::StringEqual,
+        "!=" => BinaryOperator::StringNotEqual,
+        "<" => BinaryOperator::StringLess,
+        ">" => BinaryOperator::StringGreater,
+        "=~" => BinaryOperator::Match,
+        "-eq" => BinaryOperator::Equal,
+        "-ne" => BinaryOperator::NotEqual,
+        "-lt" => BinaryOperator::Less,
+        "-gt" => BinaryOperator::Greater,
+        "-le" => BinaryOperator::LessEqual,
+        "-ge" => BinaryOperator::GreaterEqual,
+        "-nt" => BinaryOperator::FileNewer,
+        "-ot" => BinaryOperator::FileOlder,
+        "-ef" => BinaryOperator::FileSame,
+        _ => return None,
+    })
+}
+
+/// Parse the arguments of `test` (or of `[` without its closing `]`) into
+/// an expression.
+///
+/// Operators are recognized by their literal text. Like bash, a word is
+/// only taken as `!`, `(` or a unary operator when it cannot be the left
+/// side of a binary comparison, so `[ ! = x ]` compares two strings. `-a`
+/// binds tighter than `-o`. No arguments is a false test.
+pub fn parse_test_args(args: Vec<ASTNode>) -> Result<ASTNode> {
+    if args.is_empty() {
+        return Ok(ASTNode::UnaryOp {
+            op: UnaryOperator::StringNotEmpty,
+            operand: Box::new(ASTNode::String(String::new(), super::ast::StringType::Unquoted, Span::default())),
+        });
+    }
+
+    let mut parser = TestArgs { args, pos: 0 };
+    let expr = parser.parse_or()?;
+    if let Some(arg) = parser.args.get(parser.pos) {
+        bail!("too many arguments (unexpected `{}')", literal(arg).unwrap_or("word"));
+    }
+    Ok(expr)
+}
+
+/// Text of a word without expansions.
+pub fn literal(word: &ASTNode) -> Option<&str> {
+    match word {
+        ASTNode::String(text, _, _) => Some(text),
+        _ => None,
+    }
+}
+
+struct TestArgs {
+    args: Vec<ASTNode>,
+    pos: usize,
+}
+
+impl TestArgs {
+    fn peek(&self, offset: usize) -> Option<&str> {
+        self.args.get(self.pos + offset).and_then(literal)
+    }
+
+    /// Whether a binary comparison starts at the current argument.
+    fn at_binary(&self) -> bool {
+        self.pos + 2 < self.args.len()
+            && self.peek(1).and_then(binary_operator).is_some_and(|op| op != BinaryOperator::Match)
+    }
+
+    fn take(&mut self) -> Result<ASTNode> {
+        match self.args.get(self.pos) {
+            Some(arg) => {
+                self.pos += 1;
+                Ok(arg.clone())
+            }
+            None => bail!("argument expected"),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<ASTNode> {
+        let mut left = self.parse_and()?;
+        while self.peek(0) == Some("-o") {
+            self.pos += 1;
+            let right = self.parse_and()?;
+            left = ASTNode::BinaryOp { left: Box::new(left), op: BinaryOperator::Or, right: Box::new(right) };
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<ASTNode> {
+        let mut left = self.parse_not()?;
+        while self.peek(0) == Some("-a") {
+            self.pos += 1;
+            let right = self.parse_not()?;
+            left = ASTNode::BinaryOp { left: Box::new(left), op: BinaryOperator::And, right: Box::new(right) };
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<ASTNode> {
+        if self.peek(0) == Some("!") && self.pos + 1 < self.args.len() && !self.at_binary() {
+            self.pos += 1;
+            let operand = self.parse_not()?;
+            return Ok(ASTNode::UnaryOp { op: UnaryOperator::Not, operand: Box::new(operand) });
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<ASTNode> {
+        if self.at_binary() {
+            let left = self.take()?;
+            let op = self.peek(0).and_then(binary_operator).unwrap();
+            self.pos += 1;
+            let right = self.take()?;
+            return Ok(ASTNode::BinaryOp { left: Box::new(left), op, right: Box::new(right) });
+        }
+
+        if self.peek(0) == Some("(") && self.pos + 1 < self.args.len() {
+            self.pos += 1;
+            let inner = self.parse_or()?;
+            if self.peek(0) != Some(")") {
+                bail!("`)' expected");
+            }
+            self.pos += 1;
+            return Ok(inner);
+        }
+
+        if self.pos + 1 < self.args.len() {
+            if let Some(op) = self.peek(0).and_then(unary_operator) {
+                self.pos += 1;
+                let operand = self.take()?;
+                return Ok(ASTNode::UnaryOp { op, operand: Box::new(operand) });
+            }
+        }
+
+        // A lone word is true when it is not empty
+        let word = self.take()?;
+        Ok(ASTNode::UnaryOp { op: UnaryOperator::StringNotEmpty, operand: Box::new(word) })
+    }
+}
@@ -0,0 +1,240 @@
+use super::ast::{ASTNode, ExpansionType, ForItems, Redirection, RedirectionTarget, Span, StringType, AST};
+use super::parser::ParseError;
+use super::shell_dialect::{ShellDialect, ShellFeature};
+
+/// Uses of features `dialect` lacks that show in the AST, such as arrays in
+/// a `#!/bin/sh` script, in script order. Features only the syntax shows,
+/// such as the `function` keyword, are reported by the parser.
+pub fn validate(ast: &AST, dialect: ShellDialect) -> Vec<ParseError> {
+    let mut validator = DialectValidator { dialect, span: Span::new(1, 1), warnings: Vec::new() };
+    validator.check_node(&ast.root);
+    validator.warnings
+}
+
+/// The warning for a use of `feature` at `span` in a `dialect` script.
+pub(super) fn unsupported(dialect: ShellDialect, feature: ShellFeature, span: Span) -> ParseError {
+    let hint = match feature {
+        ShellFeature::Arrays => "use separate variables or `set -- ...`, or run the script with bash",
+        ShellFeature::AssociativeArrays => "run the script with bash, zsh or ksh",
+        ShellFeature::ProcessSubstitution => "use a temporary file or a pipe",
+        ShellFeature::ExtendedTest => "use `[ ]`, and `case` to match patterns",
+        ShellFeature::FunctionKeyword => "drop `function` and write `name() { ...; }`",
+        ShellFeature::LocalKeyword => "use a subshell or a variable named after the function",
+        ShellFeature::SelectLoop => "print the choices and `read` the answer",
+        ShellFeature::PatternSubstitution => "use `sed`, or `${name%...}` and `${name#...}`",
+        ShellFeature::AnsiCQuoting => "write the escapes with `printf`",
+        ShellFeature::SourceBuiltin => "use `.`",
+        ShellFeature::HereString => "use a here-document or `printf '%s\\n' ... |`",
+        ShellFeature::CombinedRedirect => "use `>file 2>&1`",
+        ShellFeature::PipeStderr => "use `2>&1 |`",
+        ShellFeature::ArithmeticCommand => "use `$(( ))` in an assignment or a `[ ]` test",
+    };
+    ParseError {
+        span,
+        message: format!("{} does not support {}", dialect.name(), feature.description()),
+        hint: Some(hint.to_string()),
+    }
+}
+
+struct DialectValidator {
+    dialect: ShellDialect,
+    // Where the innermost located node being checked starts
+    span: Span,
+    warnings: Vec<ParseError>,
+}
+
+impl DialectValidator {
+    fn use_feature(&mut self, feature: ShellFeature) {
+        if self.dialect.supports_feature(feature) {
+            return;
+        }
+        // One warning per feature and place is enough
+        let warning = unsupported(self.dialect, feature, self.span);
+        if !self.warnings.iter().any(|seen| seen.span == warning.span && seen.message == warning.message) {
+            self.warnings.push(warning);
+        }
+    }
+
+    fn check_node(&mut self, node: &ASTNode) {
+        let outer = self.span;
+        // Nodes made up by the parser are located by what encloses them
+        if let Some(span) = node.span().filter(|span| *span != Span::default()) {
+            self.span = span;
+        }
+
+        match node {
+            ASTNode::Script(nodes) | ASTNode::Block(nodes) | ASTNode::Pipeline(nodes)
+            | ASTNode::Concat(nodes, _) | ASTNode::Quoted(nodes, _) => {
+                for node in nodes {
+                    self.check_node(node);
+                }
+            }
+            ASTNode::Command { name, args, redirections, .. } => {
+                if matches!(name.as_str(), "declare" | "typeset" | "local" | "readonly" | "export") {
+                    self.check_declaration(args);
+                }
+                if name == "source" {
+                    self.use_feature(ShellFeature::SourceBuiltin);
+                }
+                for arg in args {
+                    self.check_node(arg);
+                }
+                self.check_redirections(redirections);
+            }
+            ASTNode::Redirected { body, redirections } => {
+                self.check_node(body);
+                self.check_redirections(redirections);
+            }
+            ASTNode::AndList(left, right) | ASTNode::OrList(left, right) => {
+                self.check_node(left);
+                self.check_node(right);
+            }
+            ASTNode::Negated(node)
+            | ASTNode::Subshell(node)
+            | ASTNode::Background(node)
+            | ASTNode::CommandSubstitution(node, _)
+            | ASTNode::FileSubstitution(node, _)
+            | ASTNode::ArithmeticExpansion(node, _)
+            | ASTNode::ArithmeticCommand(node, _)
+            | ASTNode::Test { expr: node, .. }
+            | ASTNode::Function { body: node, .. }
+            | ASTNode::QualifiedGlob { pattern: node, .. }
+            | ASTNode::UnaryOp { operand: node, .. } => self.check_node(node),
+            ASTNode::If { condition, then_block, elif_blocks, else_block, .. } => {
+                self.check_node(condition);
+                self.check_node(then_block);
+                for (condition, block) in elif_blocks {
+                    self.check_node(condition);
+                    self.check_node(block);
+                }
+                if let Some(block) = else_block {
+                    self.check_node(block);
+                }
+            }
+            ASTNode::While { condition, body, .. }
+            | ASTNode::Until { condition, body, .. }
+            | ASTNode::Repeat { count: condition, body, .. } => {
+                self.check_node(condition);
+                self.check_node(body);
+            }
+            ASTNode::For { items, body, .. } => {
+                match items {
+                    ForItems::List(items) => {
+                        for item in items {
+                            self.check_node(item);
+                        }
+                    }
+                    ForItems::Command(command) => self.check_node(command),
+                    ForItems::CStyle { init, condition, update } => {
+                        self.check_node(init);
+                        self.check_node(condition);
+                        self.check_node(update);
+                    }
+                }
+                self.check_node(body);
+            }
+            ASTNode::Case { expr, cases, .. } => {
+                self.check_node(expr);
+                for case in cases {
+                    for pattern in &case.patterns {
+                        self.check_node(pattern);
+                    }
+                    self.check_node(&case.body);
+                }
+            }
+            ASTNode::AnonymousFunction { body, args, .. } => {
+                self.check_node(body);
+                for arg in args {
+                    self.check_node(arg);
+                }
+            }
+            ASTNode::Assignment { index, value, .. } => {
+                if index.is_some() || matches!(value.as_ref(), ASTNode::Array(_)) {
+                    self.use_feature(ShellFeature::Arrays);
+                }
+                if let Some(index) = index {
+                    self.check_node(index);
+                }
+                self.check_node(value);
+            }
+            ASTNode::Declaration { attributes, variables, .. } => {
+                if attributes.associative {
+                    self.use_feature(ShellFeature::AssociativeArrays);
+                } else if attributes.array {
+                    self.use_feature(ShellFeature::Arrays);
+                }
+                for variable in variables {
+                    self.check_node(variable);
+                }
+            }
+            ASTNode::Array(items) => {
+                for item in items {
+                    self.check_node(item);
+                }
+            }
+            ASTNode::Variable(name, _) if name.contains('[') => self.use_feature(ShellFeature::Arrays),
+            ASTNode::ParameterExpansion { name, expansion_type, .. } => {
+                if name.contains('[') || matches!(
+                    expansion_type,
+                    ExpansionType::Element(_) | ExpansionType::Subscript(_) | ExpansionType::Keys
+                ) {
+                    self.use_feature(ShellFeature::Arrays);
+                }
+                if matches!(expansion_type, ExpansionType::Replace { .. }) {
+                    self.use_feature(ShellFeature::PatternSubstitution);
+                }
+                match expansion_type {
+                    ExpansionType::Default { word, .. }
+                    | ExpansionType::Assign { word, .. }
+                    | ExpansionType::Alternative { word, .. }
+                    | ExpansionType::Element(word)
+                    | ExpansionType::Subscript(word) => self.check_node(word),
+                    _ => {}
+                }
+            }
+            ASTNode::BinaryOp { left, right, .. } => {
+                self.check_node(left);
+                self.check_node(right);
+            }
+            ASTNode::Conditional { condition, then_expr, else_expr } => {
+                self.check_node(condition);
+                self.check_node(then_expr);
+                self.check_node(else_expr);
+            }
+            ASTNode::ArithmeticAssignment { value, .. } => self.check_node(value),
+            ASTNode::String(_, StringType::AnsiC, _) => self.use_feature(ShellFeature::AnsiCQuoting),
+            _ => {}
+        }
+
+        self.span = outer;
+    }
+
+    /// `declare -a` and `-A` declare arrays.
+    fn check_declaration(&mut self, args: &[Box<ASTNode>]) {
+        for arg in args {
+            if let ASTNode::String(text, _, _) = arg.as_ref() {
+                if let Some(flags) = text.strip_prefix('-') {
+                    if flags.contains('A') {
+                        self.use_feature(ShellFeature::AssociativeArrays);
+                    } else if flags.contains('a') {
+                        self.use_feature(ShellFeature::Arrays);
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_redirections(&mut self, redirections: &[Redirection]) {
+        for redirection in redirections {
+            if matches!(redirection.target, RedirectionTarget::HereString(_)) {
+                self.use_feature(ShellFeature::HereString);
+            }
+            match &redirection.target {
+                RedirectionTarget::File(word)
+                | RedirectionTarget::HereString(word)
+                | RedirectionTarget::Heredoc { content: word, .. } => self.check_node(word),
+                _ => {}
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::parser::{AST, ASTNode};
+use crate::parser::ast::{Redirection, RedirectionTarget};
 use super::file_classifier::{FileClassifier, FileUsage, FileContext};
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
@@ -30,6 +31,8 @@ pub struct DependencyResolver {
     dependencies: HashMap<PathBuf, Dependency>,
     visited_sources: HashSet<PathBuf>,
     max_source_depth: usize,
+    // Line of the script statement being analyzed
+    current_line: Option<usize>,
 }
 
 // Regex patterns for detecting file paths and resources
@@ -57,6 +60,7 @@ impl DependencyResolver {
             dependencies: HashMap::new(),
             visited_sources: HashSet::new(),
             max_source_depth: 15,
+            current_line: None,
         })
     }
     
@@ -83,6 +87,15 @@ impl DependencyResolver {
             anyhow::bail!("Maximum source depth exceeded");
         }
         
+        // Dependencies of a sourced file are reported at the line of its
+        // `source` command in the main script
+        let outer_line = self.current_line;
+        if depth == 0 {
+            if let Some(span) = node.span() {
+                self.current_line = Some(span.line);
+            }
+        }
+        
         match node {
             ASTNode::Script(statements) | ASTNode::Block(statements) => {
                 for stmt in statements {
@@ -90,8 +103,14 @@ impl DependencyResolver {
                 }
             }
             
-            ASTNode::Command { name, args, .. } => {
+            ASTNode::Command { name, args, redirections, .. } => {
                 self.analyze_command(name, args, depth)?;
+                self.analyze_redirections(redirections, depth)?;
+            }
+            
+            ASTNode::Redirected { body, redirections } => {
+                self.analyze_ast_node(body, depth)?;
+                self.analyze_redirections(redirections, depth)?;
             }
             
             ASTNode::Pipeline(commands) => {
@@ -100,7 +119,16 @@ impl DependencyResolver {
                 }
             }
             
-            ASTNode::If { condition, then_block, elif_blocks, else_block } => {
+            ASTNode::AndList(left, right) | ASTNode::OrList(left, right) => {
+                self.analyze_ast_node(left, depth)?;
+                self.analyze_ast_node(right, depth)?;
+            }
+            
+            ASTNode::Negated(cmd) => {
+                self.analyze_ast_node(cmd, depth)?;
+            }
+            
+            ASTNode::If { condition, then_block, elif_blocks, else_block, .. } => {
                 self.analyze_ast_node(condition, depth)?;
                 self.analyze_ast_node(then_block, depth)?;
                 for (cond, block) in elif_blocks {
@@ -112,7 +140,7 @@ impl DependencyResolver {
                 }
             }
             
-            ASTNode::While { condition, body } | ASTNode::Until { condition, body } => {
+            ASTNode::While { condition, body, .. } | ASTNode::Until { condition, body, .. } => {
                 self.analyze_ast_node(condition, depth)?;
                 self.analyze_ast_node(body, depth)?;
             }
@@ -136,28 +164,104 @@ impl DependencyResolver {
                 self.analyze_ast_node(body, depth)?;
             }
             
-            ASTNode::Case { expr, cases } => {
+            ASTNode::Case { expr, cases, .. } => {
                 self.analyze_ast_node(expr, depth)?;
                 for case in cases {
+                    for pattern in &case.patterns {
+                        self.analyze_ast_node(pattern, depth)?;
+                    }
                     self.analyze_ast_node(&case.body, depth)?;
                 }
             }
             
+            ASTNode::Repeat { count, body, .. } => {
+                self.analyze_ast_node(count, depth)?;
+                self.analyze_ast_node(body, depth)?;
+            }
+            
             ASTNode::Function { body, .. } => {
                 self.analyze_ast_node(body, depth)?;
             }
             
-            ASTNode::CommandSubstitution(cmd) | ASTNode::Subshell(cmd) => {
+            ASTNode::AnonymousFunction { body, args, .. } => {
+                self.analyze_ast_node(body, depth)?;
+                for arg in args {
+                    self.analyze_ast_node(arg, depth)?;
+                }
+            }
+            
+            ASTNode::CommandSubstitution(cmd, _)
+            | ASTNode::FileSubstitution(cmd, _)
+            | ASTNode::Subshell(cmd)
+            | ASTNode::Background(cmd) => {
                 self.analyze_ast_node(cmd, depth)?;
             }
             
-            ASTNode::String(content, _) => {
+            ASTNode::ArithmeticExpansion(expr, _) | ASTNode::ArithmeticCommand(expr, _) => {
+                self.analyze_ast_node(expr, depth)?;
+            }
+            
+            ASTNode::Test { expr, .. } => {
+                self.analyze_ast_node(expr, depth)?;
+            }
+            
+            ASTNode::BinaryOp { left, right, .. } => {
+                self.analyze_ast_node(left, depth)?;
+                self.analyze_ast_node(right, depth)?;
+            }
+            
+            ASTNode::UnaryOp { operand, .. } => {
+                self.analyze_ast_node(operand, depth)?;
+            }
+            
+            ASTNode::Assignment { value, .. } => {
+                self.analyze_ast_node(value, depth)?;
+            }
+            
+            ASTNode::Declaration { variables: parts, .. } | ASTNode::Concat(parts, _) | ASTNode::Quoted(parts, _) | ASTNode::Array(parts) => {
+                for part in parts {
+                    self.analyze_ast_node(part, depth)?;
+                }
+            }
+            
+            ASTNode::QualifiedGlob { pattern, .. } => {
+                self.analyze_ast_node(pattern, depth)?;
+            }
+            
+            ASTNode::ParameterExpansion {
+                expansion_type:
+                    crate::parser::ast::ExpansionType::Default { word, .. }
+                    | crate::parser::ast::ExpansionType::Assign { word, .. }
+                    | crate::parser::ast::ExpansionType::Alternative { word, .. }
+                    | crate::parser::ast::ExpansionType::Element(word)
+                    | crate::parser::ast::ExpansionType::Subscript(word),
+                ..
+            } => {
+                self.analyze_ast_node(word, depth)?;
+            }
+            
+            ASTNode::String(content, _, _) => {
                 self.analyze_string_content(content)?;
             }
             
             _ => {}
         }
         
+        self.current_line = outer_line;
+        Ok(())
+    }
+    
+    fn analyze_redirections(&mut self, redirections: &[Redirection], depth: usize) -> Result<()> {
+        for redirection in redirections {
+            match &redirection.target {
+                RedirectionTarget::File(word)
+                | RedirectionTarget::HereString(word)
+                | RedirectionTarget::Heredoc { content: word, .. } => {
+                    self.analyze_ast_node(word, depth)?;
+                }
+                _ => {}
+            }
+        }
         Ok(())
     }
     
@@ -168,7 +272,7 @@ impl DependencyResolver {
                 PathBuf::from(name),
                 DependencyType::BinaryCommand,
                 FileUsage::default(),
-                vec![],
+                self.current_lines(),
             );
         }
         
@@ -176,7 +280,7 @@ impl DependencyResolver {
         match name {
             "source" | "." => {
                 if let Some(first_arg) = args.first() {
-                    if let ASTNode::String(path, _) = first_arg.as_ref() {
+                    if let ASTNode::String(path, _, _) = first_arg.as_ref() {
                         self.handle_source_command(path, depth)?;
                     }
                 }
@@ -184,11 +288,11 @@ impl DependencyResolver {
             
             "cat" | "less" | "more" | "head" | "tail" => {
                 for arg in args {
-                    if let ASTNode::String(path, _) = arg.as_ref() {
+                    if let ASTNode::String(path, _, _) = arg.as_ref() {
                         let mut usage = FileUsage::default();
                         usage.read_count += 1;
                         if name == "tail" && args.iter().any(|a| {
-                            matches!(a.as_ref(), ASTNode::String(s, _) if s == "-f")
+                            matches!(a.as_ref(), ASTNode::String(s, _, _) if s == "-f")
                         }) {
                             usage.is_monitored = true;
                         }
@@ -204,7 +308,7 @@ impl DependencyResolver {
             "cp" | "mv" if args.len() >= 2 => {
                 // Source file(s)
                 for arg in &args[..args.len()-1] {
-                    if let ASTNode::String(path, _) = arg.as_ref() {
+                    if let ASTNode::String(path, _, _) = arg.as_ref() {
                         let mut usage = FileUsage::default();
                         usage.read_count += 1;
                         self.add_file_dependency(path, usage)?;
@@ -214,7 +318,7 @@ impl DependencyResolver {
             
             "rm" | "unlink" => {
                 for arg in args {
-                    if let ASTNode::String(path, _) = arg.as_ref() {
+                    if let ASTNode::String(path, _, _) = arg.as_ref() {
                         let mut usage = FileUsage::default();
                         usage.write_count += 1; // Deletion counts as write
                         self.add_file_dependency(path, usage)?;
@@ -224,12 +328,12 @@ impl DependencyResolver {
             
             "mkdir" => {
                 for arg in args {
-                    if let ASTNode::String(path, _) = arg.as_ref() {
+                    if let ASTNode::String(path, _, _) = arg.as_ref() {
                         self.add_dependency(
                             PathBuf::from(path),
                             DependencyType::Directory,
                             FileUsage::default(),
-                            vec![],
+                            self.current_lines(),
                         );
                     }
                 }
@@ -245,7 +349,7 @@ impl DependencyResolver {
                     PathBuf::from("git"),
                     DependencyType::BinaryCommand,
                     FileUsage::default(),
-                    vec![],
+                    self.current_lines(),
                 );
             }
             
@@ -277,7 +381,7 @@ impl DependencyResolver {
             source_path.clone(),
             DependencyType::SourceFile,
             usage,
-            vec![],
+            self.current_lines(),
         );
         
         // Parse and analyze the sourced file
@@ -319,7 +423,7 @@ impl DependencyResolver {
                 PathBuf::from(url_match.as_str()),
                 DependencyType::NetworkResource,
                 FileUsage::default(),
-                vec![],
+                self.current_lines(),
             );
         }
         
@@ -328,13 +432,13 @@ impl DependencyResolver {
     
     fn analyze_network_command(&mut self, args: &[Box<ASTNode>]) -> Result<()> {
         for arg in args {
-            if let ASTNode::String(content, _) = arg.as_ref() {
+            if let ASTNode::String(content, _, _) = arg.as_ref() {
                 if URL_REGEX.is_match(content) {
                     self.add_dependency(
                         PathBuf::from(content),
                         DependencyType::NetworkResource,
                         FileUsage::default(),
-                        vec![],
+                        self.current_lines(),
                     );
                 }
             }
@@ -352,7 +456,7 @@ impl DependencyResolver {
             DependencyType::DataFile
         };
         
-        self.add_dependency(resolved_path, dep_type, usage, vec![]);
+        self.add_dependency(resolved_path, dep_type, usage, self.current_lines());
         Ok(())
     }
     
@@ -374,7 +478,11 @@ impl DependencyResolver {
                 dep.usage.in_condition |= usage.in_condition;
                 dep.usage.is_sourced |= usage.is_sourced;
                 dep.usage.is_monitored |= usage.is_monitored;
-                dep.line_numbers.extend(&line_numbers);
+                for &line in &line_numbers {
+                    if !dep.line_numbers.contains(&line) {
+                        dep.line_numbers.push(line);
+                    }
+                }
             })
             .or_insert(Dependency {
                 path,
@@ -384,6 +492,10 @@ impl DependencyResolver {
             });
     }
     
+    fn current_lines(&self) -> Vec<usize> {
+        self.current_line.into_iter().collect()
+    }
+    
     fn resolve_path(&self, path: &str) -> PathBuf {
         let path = PathBuf::from(path);
         
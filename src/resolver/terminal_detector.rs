@@ -76,11 +76,37 @@ impl TerminalDetector {
                 }
             }
             
-            ASTNode::String(content, _) => {
+            ASTNode::AndList(left, right) | ASTNode::OrList(left, right) => {
+                Self::analyze_node(left, analysis);
+                Self::analyze_node(right, analysis);
+            }
+            
+            ASTNode::Negated(cmd) => {
+                Self::analyze_node(cmd, analysis);
+            }
+            
+            ASTNode::String(content, _, _) => {
                 Self::analyze_string_content(content, analysis);
             }
             
-            ASTNode::If { condition, then_block, elif_blocks, else_block } => {
+            ASTNode::Concat(parts, _) | ASTNode::Quoted(parts, _) => {
+                for part in parts {
+                    Self::analyze_node(part, analysis);
+                }
+            }
+            
+            // Color variables and terminal size references
+            ASTNode::Variable(name, _) => match name.as_str() {
+                "RED" | "GREEN" | "BLUE" | "NC" => {
+                    analysis.features_used.insert(TerminalFeature::ColorOutput);
+                }
+                "COLUMNS" | "LINES" => {
+                    analysis.features_used.insert(TerminalFeature::TerminalSize);
+                }
+                _ => {}
+            },
+            
+            ASTNode::If { condition, then_block, elif_blocks, else_block, .. } => {
                 Self::analyze_node(condition, analysis);
                 Self::analyze_node(then_block, analysis);
                 for (cond, block) in elif_blocks {
@@ -92,7 +118,7 @@ impl TerminalDetector {
                 }
             }
             
-            ASTNode::While { condition, body } | ASTNode::Until { condition, body } => {
+            ASTNode::While { condition, body, .. } | ASTNode::Until { condition, body, .. } => {
                 Self::analyze_node(condition, analysis);
                 Self::analyze_node(body, analysis);
             }
@@ -126,10 +152,14 @@ impl TerminalDetector {
                 Self::analyze_node(body, analysis);
             }
             
-            ASTNode::CommandSubstitution(cmd) | ASTNode::Subshell(cmd) => {
+            ASTNode::CommandSubstitution(cmd, _) | ASTNode::Subshell(cmd) | ASTNode::Background(cmd) => {
                 Self::analyze_node(cmd, analysis);
             }
             
+            ASTNode::Redirected { body, .. } => {
+                Self::analyze_node(body, analysis);
+            }
+            
             _ => {}
         }
     }
@@ -144,7 +174,7 @@ impl TerminalDetector {
                 
                 // Check for password input
                 for arg in args {
-                    if let ASTNode::String(s, _) = arg.as_ref() {
+                    if let ASTNode::String(s, _, _) = arg.as_ref() {
                         if s == "-s" {
                             analysis.features_used.insert(TerminalFeature::PasswordInput);
                         }
@@ -165,7 +195,7 @@ impl TerminalDetector {
                 
                 // Check specific tput commands
                 if let Some(first_arg) = args.first() {
-                    if let ASTNode::String(cmd, _) = first_arg.as_ref() {
+                    if let ASTNode::String(cmd, _, _) = first_arg.as_ref() {
                         match cmd.as_str() {
                             "cols" | "lines" => {
                                 analysis.features_used.insert(TerminalFeature::TerminalSize);
@@ -206,7 +236,7 @@ impl TerminalDetector {
             
             // Live monitoring
             "watch" | "tail" if args.iter().any(|a| {
-                matches!(a.as_ref(), ASTNode::String(s, _) if s == "-f")
+                matches!(a.as_ref(), ASTNode::String(s, _, _) if s == "-f")
             }) => {
                 analysis.features_used.insert(TerminalFeature::LiveOutput);
                 analysis.interactive_commands.push(format!("{} -f", name));
@@ -221,7 +251,7 @@ impl TerminalDetector {
             "stty" => {
                 analysis.features_used.insert(TerminalFeature::RawMode);
                 for arg in args {
-                    if let ASTNode::String(s, _) = arg.as_ref() {
+                    if let ASTNode::String(s, _, _) = arg.as_ref() {
                         if s == "-echo" {
                             analysis.features_used.insert(TerminalFeature::PasswordInput);
                         }
@@ -1,5 +1,6 @@
 use cassh2rs::parser::{ShellParser, shell_dialect::ShellDialect};
 use cassh2rs::generator::code_gen::CodeGenerator;
+use std::path::PathBuf;
 
 #[test]
 fn test_generate_simple_echo() {
@@ -36,6 +37,64 @@ echo "Hello, $NAME"
     assert!(main_content.contains("runtime.get_var"));
 }
 
+#[test]
+fn test_generate_double_quoted_interpolation() {
+    let input = r#"
+echo "Hello, ${NAME} {$((n + 1))}"
+echo '$NAME'
+x="$HOME"
+[[ $f == "$prefix"* ]]
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    // `${NAME}` is read before the arithmetic runs
+    assert!(main_content.contains(r#"let word_1 = runtime.get_var("NAME")?;
+    let arith_0"#));
+    assert!(main_content.contains(r#"format!("Hello, {} {{{}}}", word_1, arith_0.to_string())"#));
+    assert!(main_content.contains(r#"println!("{}", "$NAME");"#));
+    assert!(main_content.contains(r#"runtime.set_var("x", runtime.get_var("HOME")?)?;"#));
+    assert!(main_content.contains(r#"shell_runtime::escape_glob(&runtime.get_var("prefix")?)"#));
+}
+
+#[test]
+fn test_generate_word_expansion() {
+    let input = r#"
+rm -f $files
+echo pre$x"$y" ~/bin
+ls ~
+shopt -s failglob
+for f in *.log; do echo "$f"; done
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains(
+        r#"runtime.execute_command("rm", &[vec!["-f".to_string()], runtime.expand_fields(&runtime.get_var("files")?.to_string())?].concat())?;"#
+    ));
+    assert!(main_content.contains(concat!(
+        r#"runtime.expand_word(vec![shell_runtime::WordPart::Literal("pre".to_string()), "#,
+        r#"shell_runtime::WordPart::Expanded(runtime.get_var("x")?.to_string()), "#,
+        r#"shell_runtime::WordPart::Quoted(runtime.get_var("y")?.to_string())])?"#,
+    )));
+    assert!(main_content.contains(r#"vec![format!("{}{}", runtime.tilde(""), "/bin").to_string()]"#));
+    assert!(main_content.contains(r#"runtime.execute_command("ls", &[&runtime.tilde("")])?;"#));
+    assert!(main_content.contains(r#"runtime.set_shopt(&["failglob"], true)?;"#));
+    assert!(main_content.contains(r#"for item in runtime.expand_glob("*.log")? {"#));
+    // A pattern without matches only abandons its top-level statement
+    assert!(main_content.contains("runtime.top_level(|runtime| {"));
+}
+
 #[test]
 fn test_generate_if_statement() {
     let input = r#"
@@ -175,4 +234,558 @@ jq '.data' file.json
     assert!(main_content.contains(r#""git""#));
     assert!(main_content.contains(r#""curl""#));
     assert!(main_content.contains(r#""jq""#));
-}
\ No newline at end of file
+}
+#[test]
+fn test_generate_command_substitution() {
+    let input = "TODAY=$(date +%F)";
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains("runtime.capture_output"));
+    assert!(main_content.contains(r#""date""#));
+    
+    let runtime_content = &project.files[&PathBuf::from("src/shell_runtime.rs")];
+    assert!(runtime_content.contains("pub fn capture_output"));
+}
+
+#[test]
+fn test_generate_case_statement() {
+    let input = r#"
+case "$1" in
+    start|run) echo starting ;;
+    "$prefix"*) echo prefixed ;;
+    *) echo "usage: $0 start" ;;
+esac
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains(r#"runtime.glob_match("start", &case_value)"#));
+    assert!(main_content.contains(r#"runtime.glob_match("*", &case_value)"#));
+    // A quoted expansion matches its value literally
+    assert!(main_content.contains(r#"runtime.glob_match(&format!("{}{}", shell_runtime::escape_glob(&runtime.get_var("prefix")?), "*"), &case_value)"#));
+    assert!(main_content.contains("break 'case_0;"));
+    
+    let runtime_content = &project.files[&PathBuf::from("src/shell_runtime.rs")];
+    assert!(runtime_content.contains("fn glob_match"));
+}
+
+#[test]
+fn test_generate_redirections() {
+    let input = r#"
+echo started > run.log
+make 2>&1 >> run.log
+pwd > dir.txt
+exec 3> fd3.log
+exec 3>&-
+exec make install
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains("runtime.with_redirects"));
+    assert!(main_content.contains(r#"Redirect::Write { fd: 1, path: "run.log".to_string(), append: false }"#));
+    assert!(main_content.contains("Redirect::Dup { fd: 2, target: 1 }"));
+    // Redirected builtins write through the runtime
+    assert!(main_content.contains("runtime.write_out"));
+    assert!(main_content.contains("runtime.pwd(&[] as &[&str])?;"));
+    assert!(!main_content.contains("commands::"));
+    // `exec` keeps its redirections, or ends the script with the command
+    assert!(main_content.contains(r#"runtime.exec_redirects(vec![shell_runtime::Redirect::Write { fd: 3, path: "fd3.log".to_string(), append: false }])?;"#));
+    assert!(main_content.contains("runtime.exec_redirects(vec![shell_runtime::Redirect::Close { fd: 3 }])?;"));
+    assert!(main_content.contains("runtime.execute_command(\"make\", &[\"install\"])?;\n    return Err(shell_runtime::Exit(runtime.last_exit_status()).into());"));
+}
+
+#[test]
+fn test_generate_heredocs() {
+    let input = "cat <<'EOF'\nraw $HOME\nEOF\ncat <<EOF\nhome is $HOME\nEOF\n";
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    // A quoted delimiter gives a literal body, an unquoted one a template
+    assert!(main_content.contains(r#"Redirect::Input { fd: 0, data: "raw $HOME\n".to_string() }"#));
+    assert!(main_content.contains(r#"data: format!("{}{}{}", "home is ", runtime.get_var("HOME")?, "\n").to_string()"#));
+}
+
+#[test]
+fn test_generate_pipeline() {
+    let input = r#"
+set -o pipefail
+cat data.txt | grep -v '^#' | sort
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains(r#"runtime.set_options(&[("pipefail", true)])?;"#));
+    assert!(main_content.contains("runtime.run_pipeline(vec!["));
+    assert_eq!(main_content.matches("shell_runtime::stage(").count(), 3);
+}
+
+#[test]
+fn test_generate_arithmetic() {
+    let input = r#"
+total=$(( count * 2 + 1 ))
+(( i++ ))
+echo $(( $expr / 2 ))
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    // Expressions with a known shape become native i64 code
+    assert!(main_content.contains(r#"i64::wrapping_add(i64::wrapping_mul(runtime.arith_var("count")?, 2i64), 1i64)"#));
+    assert!(main_content.contains(r#"runtime.arith_assign("i", i64::wrapping_add(value, 1))?"#));
+    assert!(main_content.contains("runtime.set_exit_status(if arith_"));
+    // Expanded text is evaluated at runtime
+    assert!(main_content.contains("runtime.eval_arith(&format!("));
+}
+
+#[test]
+fn test_generate_for_loops() {
+    let input = r#"
+for f in *.log "$@"; do
+  for ((i = 0; i < 3; i++)); do
+    continue
+    break 2
+  done
+  break
+done
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains(r#"[runtime.expand_glob("*.log")?, runtime.positional_args()].concat()"#));
+    assert!(main_content.contains(r#"runtime.set_var("f", item)?;"#));
+    // Loops are labelled so that break and continue inside a case still reach them
+    assert!(main_content.contains("break 'loop_"));
+    assert!(main_content.contains("continue 'loop_1;"));
+    // `break 2` leaves the outer loop
+    assert!(main_content.contains("continue 'loop_1;\n            break 'loop_0;"));
+    assert!(main_content.contains("let mut first_iteration = true;"));
+}
+
+#[test]
+fn test_generate_test_expressions() {
+    let input = r#"
+if [ $count -gt 3 -a ! -d "$dir" ]; then
+  echo big
+fi
+while [[ $name == *.txt && $line =~ ^#(.*)$ ]]; do
+  break
+done
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains(r#"shell_runtime::test_integer(&runtime.get_var("count")?)? > 3i64"#));
+    assert!(main_content.contains(r#"!std::path::Path::new(&runtime.get_var("dir")?).is_dir()"#));
+    assert!(main_content.contains(r#"runtime.glob_match("*.txt", &runtime.get_var("name")?)"#));
+    assert!(main_content.contains(r#"runtime.regex_match(&runtime.get_var("line")?, "^#(.*)$")?"#));
+    assert!(project.dependencies.iter().any(|dep| dep.name == "regex"));
+}
+
+#[test]
+fn test_generate_and_or_lists() {
+    let input = r#"
+cd build && make || echo "build failed"
+! grep -q done status.txt && echo pending
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains("if runtime.last_exit_status() == 0 {\n        runtime.execute_command(\"make\""));
+    assert!(main_content.contains("if runtime.last_exit_status() != 0 {\n        println!"));
+    assert!(main_content.contains("runtime.set_exit_status(if runtime.last_exit_status() == 0 { 1 } else { 0 });"));
+}
+
+#[test]
+fn test_generate_location_comments() {
+    let script = "echo one; echo two\nif true; then\n    ls /tmp\nfi";
+    let mut parser = ShellParser::new(script.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let mut generator = CodeGenerator::new(ast, "test_script");
+    generator.set_source_name("test_script.sh");
+    let project = generator.generate().unwrap();
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    
+    // One comment per script line, before the code generated from it
+    assert_eq!(main_content.matches("// test_script.sh:1:1\n").count(), 1);
+    assert!(main_content.contains("// test_script.sh:1:1\n    println!(\"{}\", \"one\");"));
+    assert!(!main_content.contains("test_script.sh:1:11"));
+    assert!(main_content.contains("// test_script.sh:2:1\n"));
+    assert!(main_content.contains("// test_script.sh:3:5\n"));
+}
+
+#[test]
+fn test_generate_bash_arrays() {
+    let input = r#"
+declare -A map
+map[key]=v
+arr=(a b c)
+arr[5]+=x
+arr+=(d)
+echo ${arr[i+1]} ${#arr[@]} ${!map[@]} ${arr[@]:1:2}
+printf '%s\n' "${arr[@]}"
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains("runtime.declare_array(\"map\", true)?;"));
+    assert!(main_content.contains("runtime.set_element(\"map\", \"key\", \"v\")?;"));
+    assert!(main_content.contains("runtime.append_element(\"arr\", \"5\", \"x\")?;"));
+    assert!(main_content.contains("runtime.append_array(\"arr\", vec![\"d\".to_string()])?;"));
+    assert!(main_content.contains("let element_0 = runtime.element(\"arr\", \"i+1\")?;"));
+    assert!(main_content.contains("runtime.array_len(\"arr\").to_string()"));
+    assert!(main_content.contains("runtime.array_keys(\"map\").join(\" \")"));
+    assert!(main_content.contains("runtime.array_slice(\"arr\", 1i64, Some(2i64))?.join(\" \")"));
+    assert!(main_content.contains("runtime.execute_command(\"printf\", &[vec![\"%s\\\\n\".to_string()], runtime.array_values(\"arr\")].concat())?;"));
+}
+
+#[test]
+fn test_generate_declarations() {
+    let input = r#"
+declare -i n=3+4
+readonly R=1
+function f {
+    local -u name=x
+    export -n PATH
+}
+unset n
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains("runtime.declare(\"n\", shell_runtime::VarAttributes { integer: true, ..Default::default() })?;\n    runtime.set_var(\"n\", \"3+4\")?;"));
+    assert!(main_content.contains("runtime.set_var(\"R\", \"1\")?;\n    runtime.declare(\"R\", shell_runtime::VarAttributes { readonly: true, ..Default::default() })?;"));
+    assert!(main_content.contains("runtime.declare_local(\"name\")?;\n    runtime.declare(\"name\", shell_runtime::VarAttributes { uppercase: true, ..Default::default() })?;"));
+    assert!(main_content.contains("runtime.remove_attributes(\"PATH\", shell_runtime::VarAttributes { export: true, ..Default::default() })?;"));
+    assert!(main_content.contains("runtime.unset_var(\"n\")?;"));
+}
+
+#[test]
+fn test_generate_function_frames() {
+    let input = r#"
+log() {
+    local level=$1
+    shift 2
+    return $level
+}
+function done_ {
+    return 300
+}
+in_tmp() ( cd /tmp )
+return
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains("runtime.declare_local(\"level\")?;\n    runtime.set_var(\"level\", runtime.get_var(\"1\")?)?;"));
+    assert!(main_content.contains("runtime.shift(\"2\")?;"));
+    assert!(main_content.contains("runtime.set_return_status(&runtime.get_var(\"level\")?)?;\n    return Ok(());"));
+    assert!(main_content.contains("runtime.set_exit_status(44);\n    return Ok(());"));
+    assert!(main_content.contains("fn shell_func_in_tmp(runtime: &mut shell_runtime::ShellRuntime, args: &[String]) -> Result<()> {\n    runtime.run_subshell("));
+    
+    // Outside a function `return` is an error, not the end of the script
+    assert!(main_content.contains("runtime.set_exit_status(2);"));
+}
+
+#[test]
+fn test_generate_shell_options() {
+    let input = r#"
+set -euo pipefail
+function f { false; }
+f && echo ok
+x=1
+set -x
+set -- a b
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains(r#"runtime.set_options(&[("errexit", true), ("nounset", true), ("pipefail", true)])?;"#));
+    assert!(main_content.contains("runtime.finish(result)?;"));
+    
+    // The left side of `&&` is a condition, exempt from errexit
+    assert!(main_content.contains(
+        "runtime.enter_condition();\n    runtime.execute_command(\"f\", &[] as &[&str])?;\n    runtime.leave_condition();"
+    ));
+    assert!(main_content.contains("runtime.set_exit_status(1);\n    runtime.check_errexit()?;"));
+    
+    // Once the script turns on xtrace, commands and assignments are traced
+    assert!(main_content.contains("runtime.set_var(\"x\", \"1\")?;\n    runtime.trace_assignment(\"x\")?;"));
+    assert!(main_content.contains(r#"runtime.trace("set", &["--", "a", "b"] as &[&str])?;"#));
+    assert!(main_content.contains(r#"runtime.set_positional_args([vec!["a".to_string()], vec!["b".to_string()]].concat());"#));
+    
+    // Options the runtime doesn't have are reported instead of left out
+    for (input, dialect, message) in [
+        ("set -o", ShellDialect::Bash, "Unsupported `set -o`"),
+        ("shopt -p", ShellDialect::Bash, "Unsupported `shopt -p`"),
+        ("setopt no_nomatch", ShellDialect::Zsh, "Unsupported `setopt no_nomatch`"),
+    ] {
+        let mut parser = ShellParser::new(input.to_string(), dialect).unwrap();
+        let error = CodeGenerator::new(parser.parse().unwrap(), "test_script").generate().unwrap_err();
+        assert!(format!("{:#}", error).contains(message), "{}: {:#}", input, error);
+    }
+}
+
+#[test]
+fn test_generate_traps() {
+    let input = r#"
+trap 'rm -f "$tmp"' EXIT INT
+trap '' TERM
+trap - INT
+trap -p
+exit 3
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains(r#"runtime.set_trap("rm -f \"$tmp\"", Some(shell_trap_0), &["EXIT", "INT"])?;"#));
+    assert!(main_content.contains("fn shell_trap_0(runtime: &mut shell_runtime::ShellRuntime, args: &[String]) -> Result<()> {"));
+    assert!(main_content.contains(r#"runtime.set_trap("", None, &["TERM"])?;"#));
+    assert!(main_content.contains(r#"runtime.reset_traps(&["INT"])?;"#));
+    assert!(main_content.contains("runtime.print_traps(&[] as &[&str])?;"));
+    
+    // Signals are handled between commands, and `exit` unwinds to the EXIT trap
+    assert!(main_content.contains("runtime.run_traps()?;"));
+    assert!(main_content.contains("return Err(shell_runtime::Exit(3).into());"));
+}
+
+#[test]
+fn test_generate_trap_lineno_and_errors() {
+    let input = "trap 'echo \"failed at $LINENO\"' ERR\nfalse\n";
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let project = CodeGenerator::new(parser.parse().unwrap(), "test_script").generate().unwrap();
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    // Each line tells the runtime where it is, but not those of the action
+    assert!(main_content.contains("runtime.set_line(2);"));
+    let trap_fn = &main_content[main_content.find("fn shell_trap_0").unwrap()..];
+    assert!(trap_fn.contains(r#"runtime.get_var("LINENO")?"#));
+    assert!(!trap_fn.contains("set_line"));
+    
+    // What can't be converted is reported instead of left out
+    for (input, message) in [
+        ("trap -l", "Unsupported `trap -l`"),
+        ("trap \"$(cat handler)\" EXIT", "Unsupported trap action `\"$(cat handler)\"`"),
+        ("trap 'if true' EXIT", "in trap action `if true`"),
+    ] {
+        let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+        let error = CodeGenerator::new(parser.parse().unwrap(), "test_script").generate().unwrap_err();
+        assert!(format!("{:#}", error).contains(message), "{}: {:#}", input, error);
+    }
+}
+
+#[test]
+fn test_generate_background_jobs() {
+    let input = r#"
+for url in "$@"; do
+    curl -sO "$url" &
+done
+{ sleep 1; echo done; } &
+wait -n
+echo "last job $!"
+jobs -l
+kill %1
+wait
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    // A command is spawned as a process, anything else runs on a thread
+    assert!(main_content.contains(r#"runtime.spawn_command("curl -sO \"$url\"", "curl", &["-sO", &runtime.get_var("url")?])?;"#));
+    assert!(main_content.contains(r#"runtime.spawn_job("{ sleep 1; echo done; }", shell_runtime::stage(move |runtime| {"#));
+    assert!(main_content.contains(r#"runtime.wait(&["-n"])?;"#));
+    assert!(main_content.contains(r#"runtime.get_var("!")?"#));
+    assert!(main_content.contains(r#"runtime.list_jobs(&["-l"])?;"#));
+    assert!(main_content.contains(r#"runtime.kill(&["%1"])?;"#));
+    assert!(main_content.contains("runtime.wait(&[] as &[&str])?;"));
+}
+
+#[test]
+fn test_generate_zsh_constructs() {
+    let input = r#"
+arr=(a b)
+echo ${arr[1]} ${(j:,:)arr} ${#arr}
+ls *(.om[1])
+repeat 2 echo hi
+diff =(sort x) y
+setopt NULL_GLOB
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Zsh).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains("runtime.set_ksh_arrays(false);"));
+    assert!(main_content.contains("runtime.set_array(\"arr\", [vec![\"a\".to_string()], vec![\"b\".to_string()]].concat())?;"));
+    assert!(main_content.contains("runtime.array_element(\"arr\", arith_0)"));
+    assert!(main_content.contains("runtime.expand_flags(\"arr\", &[shell_runtime::ParamFlag::Join(\",\")])"));
+    assert!(main_content.contains("runtime.zsh_length(\"arr\")?.to_string()"));
+    assert!(main_content.contains("runtime.expand_qualified_glob(\"*\", &[shell_runtime::GlobQualifier::FileType('.'), shell_runtime::GlobQualifier::Sort { key: 'm', descending: false }, shell_runtime::GlobQualifier::Range(1, 1)])"));
+    assert!(main_content.contains(": for _ in 0..arith_"));
+    assert!(main_content.contains("let file_"));
+    assert!(main_content.contains("runtime.remove_temp_file(&file_"));
+    assert!(main_content.contains("runtime.set_nullglob(true);"));
+}
+
+#[test]
+fn test_generate_fish_script() {
+    let input = r#"
+set -l names alice bob
+for n in $names
+    echo $n
+end
+set -e names
+test -d /tmp; and echo dir
+set -l files (ls)
+echo (count $files)
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Fish).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains("runtime.set_ksh_arrays(false);"));
+    assert!(main_content.contains("runtime.set_array(\"names\", [vec![\"alice\".to_string()], vec![\"bob\".to_string()]].concat())?;"));
+    assert!(main_content.contains("for item in runtime.array_words(\"names\") {"));
+    assert!(main_content.contains("runtime.unset_var(\"names\")?;"));
+    assert!(main_content.contains("if runtime.last_exit_status() == 0 {"));
+    // `(cmd)` gives a word for every line, and `count` is a builtin
+    assert!(main_content.contains(".lines().map(String::from).collect::<Vec<String>>())?;"));
+    assert!(main_content.contains("runtime.count(&[runtime.array_words(\"files\")].concat())?;"));
+}
+
+#[test]
+fn test_generate_powershell_script() {
+    let input = r#"
+$names = @("alice", "bob")
+foreach ($n in $names) {
+    Write-Host "hello $n"
+}
+Write-Host $names[1]
+if (Test-Path /tmp) { Write-Output "tmp" }
+function Say-Hi { Write-Host hi }
+Say-Hi
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::PowerShell).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains("runtime.set_ksh_arrays(false);"));
+    assert!(main_content.contains("runtime.set_array(\"names\", [vec![\"alice\".to_string()], vec![\"bob\".to_string()]].concat())?;"));
+    assert!(main_content.contains("for item in runtime.array_words(\"names\") {"));
+    assert!(main_content.contains("runtime.array_element(\"names\", arith_"));
+    assert!(main_content.contains("std::path::Path::new(\"/tmp\").exists()"));
+    assert!(main_content.contains("runtime.register_function(\"say-hi\", shell_func_say_2d_hi);"));
+}
+
+#[test]
+fn test_generate_csh_script() {
+    let input = r#"
+set names = (alice bob)
+foreach n ($names)
+    echo "hello $n"
+end
+@ count = $#argv + 1
+if (-d /tmp) then
+    echo tmp
+endif
+exit ($count - 1)
+"#;
+    
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Csh).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let generator = CodeGenerator::new(ast, "test_script");
+    let project = generator.generate().unwrap();
+    
+    let main_content = &project.files[&PathBuf::from("src/main.rs")];
+    assert!(main_content.contains("runtime.set_ksh_arrays(false);"));
+    assert!(main_content.contains("for item in runtime.array_words(\"names\") {"));
+    assert!(main_content.contains("std::path::Path::new(\"/tmp\").is_dir()"));
+    assert!(main_content.contains("return runtime.exit(&arith_"));
+}
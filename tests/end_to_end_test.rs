@@ -4,7 +4,7 @@ use tempfile::TempDir;
 use cassh2rs::parser::{ShellParser, shell_dialect::ShellDialect};
 use cassh2rs::generator::{RustGenerator, RustProject};
 use cassh2rs::resolver::DependencyResolver;
-use cassh2rs::cli::Args;
+use cassh2rs::cli::{Args, Commands};
 
 #[test]
 fn test_full_conversion_pipeline() {
@@ -196,6 +196,198 @@ fn test_multi_shell_dialects() {
     }
 }
 
+#[test]
+fn test_generated_project_builds_and_runs() {
+    let script = r#"#!/bin/bash
+file=archive.tar.gz
+echo "${file%.*}.bak ${file%%.*} ${file#*.} ${file##*.}"
+empty=
+echo "[${UNSET_V:-dflt}] [${empty-set}] [${empty:-null}] [${empty+alt}] [${UNSET_V+alt}]"
+echo "${NEW_V:=assigned} $NEW_V"
+path=/usr/local/bin
+echo "${path/local/LOCAL} ${path//\//_} ${path/#\/usr/X} ${path/%bin/Y}"
+v=abcdef
+echo "${v:1:2} ${v: -2} ${v:1:-2}"
+ref=v
+echo "${!ref}"
+tmp=/tmp/x.$$
+[ "$tmp" = "/tmp/x.${$}" ] && echo pid
+false | true
+echo "${PIPESTATUS[0]} ${PIPESTATUS[1]} ${#PIPESTATUS[@]}"
+pwd > dir.txt
+[ "$(pwd)" = "$(cat dir.txt)" ] && echo pwd
+pwd | grep -c /
+exec 3> fd3.txt
+echo three >&3
+exec 3>&-
+cat fd3.txt
+[[ "key=value" =~ ^([a-z]+)=(.*)$ ]] && echo "${BASH_REMATCH[1]} ${BASH_REMATCH[2]} ${#BASH_REMATCH[@]}"
+[ "abc" \< "abd" ] && echo less
+n=3; echo $n $((n++)) "n=$n $((n+=10))"
+set -- a b; pair=(x y)
+IFS=:; echo "$* ${pair[*]}"; IFS=; echo "$*"; unset IFS
+RED='\033[0;31m' NC='\033[0m'
+echo -e "${RED}red${NC}\tdone\c"
+echo "${REQUIRED_V:?must be set}"
+echo unreachable
+"#;
+    
+    let run = build_and_run("expansions.sh", script, ShellDialect::Bash, &[]);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "\
+archive.tar.bak archive tar.gz gz
+[dflt] [] [null] [alt] []
+assigned assigned
+/usr/LOCAL/bin _usr_local_bin X/local/bin /usr/local/Y
+bc ef bcd
+abcdef
+pid
+1 0 2
+pwd
+1
+three
+key value 3
+less
+3 3 n=4 14
+a:b x:y
+ab
+\x1b[0;31mred\x1b[0m\tdone");
+    assert_eq!(String::from_utf8_lossy(&run.stderr), "REQUIRED_V: must be set\n");
+    assert_eq!(run.status.code(), Some(1));
+}
+
+#[test]
+fn test_generated_nounset() {
+    // An unbound variable only ends the substitution it is in
+    let script = "#!/bin/bash\nset -u\necho \"[$(echo $MISSING_V)]\" x $?\necho $MISSING_V\necho unreachable\n";
+    let run = build_and_run("nounset.sh", script, ShellDialect::Bash, &[]);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "[] x 1\n");
+    assert_eq!(String::from_utf8_lossy(&run.stderr), "MISSING_V: unbound variable\nMISSING_V: unbound variable\n");
+    assert_eq!(run.status.code(), Some(1));
+}
+
+#[test]
+fn test_generated_positional_parameters() {
+    let zsh = r#"#!/bin/zsh
+echo a $1 b $# c $3 d
+false; echo status $?
+[ $$ -gt 0 ] && echo pid
+() { echo anonymous $1 $# } x y
+greet() { echo greet $2 $# }
+greet p q
+"#;
+    let run = build_and_run("positional.zsh", zsh, ShellDialect::Zsh, &["one", "two"]);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "\
+a one b 2 c d
+status 1
+pid
+anonymous x 2
+greet q 2
+");
+    
+    let csh = "#!/bin/csh\necho a $argv[1] b $2 c $#argv d $argv\n";
+    let run = build_and_run("positional.csh", csh, ShellDialect::Csh, &["one", "two"]);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "a one b two c 2 d one two\n");
+}
+
+#[test]
+fn test_generated_powershell_parameters() {
+    let script = r#"param([string]$Name = "world", [int]$Count, [switch]$Force)
+Write-Host "$Name $Count $Force $args"
+function Greet($Who, [switch]$Loud) {
+    if ($Loud) { Write-Host "HI $Who" } else { Write-Host "hi $Who" }
+}
+Greet -Loud bob
+Greet -who carol -loud:$false
+if ("ABC" -eq "abc") { Write-Host eq }
+if ("ABC" -ceq "abc") { Write-Host ceq }
+if ("Hello" -like "h*" -and "Hello" -match "^HEL") { Write-Host like }
+"#;
+    let run = build_and_run("parameters.ps1", script, ShellDialect::PowerShell, &["-Count", "3", "-f", "x", "y"]);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "x 3 True y\nHI bob\nhi carol\neq\nlike\n");
+    
+    let run = build_and_run("parameters.ps1", script, ShellDialect::PowerShell, &["--", "-Count"]);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "-Count   \nHI bob\nhi carol\neq\nlike\n");
+}
+
+#[test]
+fn test_generated_fish_function_scope() {
+    let script = r#"set shared outer
+function f
+    set tmp inner
+    set shared changed
+    set -g made global
+    echo "in $tmp $shared $made"
+end
+f
+echo "out [$tmp] $shared $made"
+set -q tmp; or echo "tmp unset"
+"#;
+    let run = build_and_run("scope.fish", script, ShellDialect::Fish, &[]);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "in inner changed global\nout [] changed global\ntmp unset\n");
+}
+
+#[test]
+fn test_check_fails_on_errors() {
+    let temp_dir = TempDir::new().unwrap();
+    let good = temp_dir.path().join("good.sh");
+    let bad = temp_dir.path().join("bad.sh");
+    fs::write(&good, "#!/bin/bash\necho ok\n").unwrap();
+    fs::write(&bad, "#!/bin/bash\nif true; then\n  foo )\n").unwrap();
+    
+    let check = |scripts: Vec<std::path::PathBuf>| {
+        let mut args = create_test_args(temp_dir.path(), temp_dir.path().join("out"));
+        args.command = Some(Commands::Check { scripts });
+        cassh2rs::cli::run(args)
+    };
+    assert!(check(vec![good.clone()]).is_ok());
+    let error = check(vec![good, bad]).unwrap_err();
+    assert_eq!(error.to_string(), "1 of 2 script(s) have errors");
+    
+    // Bashisms in a `#!/bin/sh` script only fail with --strict-dialect
+    let bashisms = temp_dir.path().join("bashisms.sh");
+    fs::write(&bashisms, "#!/bin/sh
+source ./lib.sh
+make |& tee log
+").unwrap();
+    assert!(check(vec![bashisms.clone()]).is_ok());
+    let mut args = create_test_args(temp_dir.path(), temp_dir.path().join("out"));
+    args.command = Some(Commands::Check { scripts: vec![bashisms] });
+    args.strict_dialect = true;
+    assert!(cassh2rs::cli::run(args).is_err());
+}
+
+/// Convert `script`, build the generated project and run it with `script_args`
+/// in the directory the script was written to.
+fn build_and_run(file_name: &str, script: &str, dialect: ShellDialect, script_args: &[&str]) -> std::process::Output {
+    let temp_dir = TempDir::new().unwrap();
+    let script_path = temp_dir.path().join(file_name);
+    fs::write(&script_path, script).unwrap();
+    
+    let mut parser = ShellParser::new(script.to_string(), dialect).unwrap();
+    let ast = parser.parse().unwrap();
+    let output_dir = temp_dir.path().join("output");
+    let args = create_test_args(&script_path, &output_dir);
+    let project = RustGenerator::new(ast, &args).generate().unwrap();
+    project.write_to_disk(&output_dir).unwrap();
+    
+    // Share a target directory so the dependencies are only built once
+    let target_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/generated");
+    let build = std::process::Command::new(env!("CARGO"))
+        .args(["build", "--quiet"])
+        .env("CARGO_TARGET_DIR", &target_dir)
+        .current_dir(&output_dir)
+        .output()
+        .unwrap();
+    assert!(build.status.success(), "{}", String::from_utf8_lossy(&build.stderr));
+    
+    let binary = target_dir.join("debug").join(&project.name);
+    std::process::Command::new(binary)
+        .args(script_args)
+        .current_dir(temp_dir.path())
+        .output()
+        .unwrap()
+}
+
 fn create_test_args(input: &Path, output: impl Into<std::path::PathBuf>) -> Args {
     Args {
         input: input.to_path_buf(),
@@ -214,5 +406,6 @@ fn create_test_args(input: &Path, output: impl Into<std::path::PathBuf>) -> Args
         enable_updates: false,
         update: false,
         command: None,
+        strict_dialect: false,
     }
 }
\ No newline at end of file
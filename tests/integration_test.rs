@@ -32,4 +32,20 @@ fn test_file_classification() {
     context.is_local_to_script = true;
     let info = classifier.classify(Path::new("config.toml"), &context);
     assert_eq!(info.classification, FileClassification::Static);
-}
\ No newline at end of file
+}
+#[test]
+fn test_dependency_line_numbers() {
+    use cassh2rs::parser::{ShellParser, shell_dialect::ShellDialect};
+    use cassh2rs::resolver::DependencyResolver;
+    
+    let script = "#!/bin/bash\ncurl -s https://example.com/a\n\nif true; then\n    curl https://example.com/b\nfi\n";
+    let mut parser = ShellParser::new(script.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let mut resolver = DependencyResolver::new(Path::new("/tmp/deploy.sh")).unwrap();
+    let dependencies = resolver.resolve(&ast).unwrap();
+    let curl = dependencies.iter().find(|dep| dep.path == Path::new("curl")).unwrap();
+    assert_eq!(curl.line_numbers, vec![2, 5]);
+    let url = dependencies.iter().find(|dep| dep.path == Path::new("https://example.com/b")).unwrap();
+    assert_eq!(url.line_numbers, vec![5]);
+}
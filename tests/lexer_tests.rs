@@ -1,4 +1,4 @@
-use cassh2rs::parser::{Lexer, Token, shell_dialect::ShellDialect, lexer::{QuoteType, RedirectOp}};
+use cassh2rs::parser::{Lexer, Token, shell_dialect::ShellDialect, lexer::{QuoteType, RedirectOp}, ast::Span};
 
 #[test]
 fn test_basic_tokens() {
@@ -123,4 +123,123 @@ fn test_test_brackets() {
     assert_eq!(lexer.next_token().unwrap(), Token::Equal);
     assert_eq!(lexer.next_token().unwrap(), Token::Word("pattern".to_string()));
     assert_eq!(lexer.next_token().unwrap(), Token::DoubleRightBracket);
-}
\ No newline at end of file
+}
+#[test]
+fn test_hash_inside_word() {
+    let input = "a#b # comment\n";
+    let mut lexer = Lexer::new(input, ShellDialect::Bash);
+    
+    assert_eq!(lexer.next_token().unwrap(), Token::Word("a".to_string()));
+    assert_eq!(lexer.next_token().unwrap(), Token::Hash);
+    assert_eq!(lexer.next_token().unwrap(), Token::Word("b".to_string()));
+    assert_eq!(lexer.next_token().unwrap(), Token::Newline);
+    assert_eq!(lexer.next_token().unwrap(), Token::Eof);
+}
+
+#[test]
+fn test_token_spans() {
+    let mut lexer = Lexer::new("echo  hi\n\tcat <file # comment\nfi", ShellDialect::Bash);
+    let mut spans = Vec::new();
+    loop {
+        let token = lexer.next_token().unwrap();
+        if token == Token::Eof {
+            break;
+        }
+        spans.push((lexer.token_text(), lexer.token_span()));
+    }
+    
+    assert_eq!(spans, vec![
+        ("echo", Span::new(1, 1)),
+        ("hi", Span::new(1, 7)),
+        ("\n", Span::new(1, 9)),
+        ("cat", Span::new(2, 2)),
+        ("<", Span::new(2, 6)),
+        ("file", Span::new(2, 7)),
+        ("\n", Span::new(2, 21)),
+        ("fi", Span::new(3, 1)),
+    ]);
+}
+
+#[test]
+fn test_heredoc_bodies() {
+    let input = "cat <<'A' <<-B; echo $x\nraw $x\nA\n\tstripped\n\tB\nls";
+    let mut lexer = Lexer::new(input, ShellDialect::Bash);
+    
+    assert_eq!(lexer.next_token().unwrap(), Token::Word("cat".to_string()));
+    assert_eq!(lexer.next_token().unwrap(), Token::Heredoc("A".to_string()));
+    assert_eq!(lexer.next_token().unwrap(), Token::Heredoc("B".to_string()));
+    
+    let first = lexer.take_heredoc().unwrap();
+    assert_eq!((first.content.as_str(), first.quoted, first.strip_tabs), ("raw $x\n", true, false));
+    assert_eq!(first.span, Span::new(2, 1));
+    let second = lexer.take_heredoc().unwrap();
+    assert_eq!((second.content.as_str(), second.quoted, second.strip_tabs), ("stripped\n", false, true));
+    assert_eq!(second.span, Span::new(4, 1));
+    assert!(lexer.take_heredoc().is_none());
+    
+    // The rest of the line is lexed before the bodies are skipped
+    assert_eq!(lexer.next_token().unwrap(), Token::Semicolon);
+    assert_eq!(lexer.next_token().unwrap(), Token::Echo);
+    assert_eq!(lexer.next_token().unwrap(), Token::Dollar);
+    assert_eq!(lexer.next_token().unwrap(), Token::Word("x".to_string()));
+    assert_eq!(lexer.next_token().unwrap(), Token::Newline);
+    assert_eq!(lexer.next_token().unwrap(), Token::Word("ls".to_string()));
+    assert_eq!(lexer.token_span(), Span::new(6, 1));
+}
+
+#[test]
+fn test_zsh_tokens() {
+    let mut lexer = Lexer::new("repeat 2 ls *(.N) =(date)", ShellDialect::Zsh);
+    assert_eq!(lexer.next_token().unwrap(), Token::Repeat);
+    assert_eq!(lexer.next_token().unwrap(), Token::Number("2".to_string()));
+    assert_eq!(lexer.next_token().unwrap(), Token::Word("ls".to_string()));
+    assert_eq!(lexer.next_token().unwrap(), Token::Star);
+    assert_eq!(lexer.next_token().unwrap(), Token::GlobQualifiers(".N".to_string()));
+    assert_eq!(lexer.next_token().unwrap(), Token::EqualParen);
+    
+    // In other shells these are ordinary words and parentheses
+    let mut lexer = Lexer::new("repeat *(.N)", ShellDialect::Bash);
+    assert_eq!(lexer.next_token().unwrap(), Token::Word("repeat".to_string()));
+    assert_eq!(lexer.next_token().unwrap(), Token::Star);
+    assert_eq!(lexer.next_token().unwrap(), Token::LeftParen);
+}
+
+#[test]
+fn test_powershell_tokens() {
+    use cassh2rs::parser::powershell_lexer::{PowerShellLexer, PsToken};
+    
+    let lexemes = PowerShellLexer::new("if ($x[0] -gt 2) { Write-Host \"n: $x\" *>> log } # done\n").tokenize().unwrap();
+    let tokens: Vec<_> = lexemes.iter().map(|lexeme| lexeme.token.clone()).collect();
+    assert_eq!(tokens, vec![
+        PsToken::Word("if".to_string()),
+        PsToken::LeftParen,
+        PsToken::Variable("x".to_string()),
+        PsToken::LeftBracket,
+        PsToken::Number(0),
+        PsToken::RightBracket,
+        PsToken::Parameter("gt".to_string()),
+        PsToken::Number(2),
+        PsToken::RightParen,
+        PsToken::LeftBrace,
+        PsToken::Word("Write-Host".to_string()),
+        PsToken::ExpandableString("n: $x".to_string(), Span::new(1, 32)),
+        PsToken::Redirect("*>>".to_string()),
+        PsToken::Word("log".to_string()),
+        PsToken::RightBrace,
+        PsToken::Newline,
+        PsToken::Eof,
+    ]);
+    // The index follows the variable without a blank
+    assert!(!lexemes[3].spaced);
+    assert_eq!(lexemes[10].span, Span::new(1, 20));
+    
+    // A number ends before the `..` of a range
+    let lexemes = PowerShellLexer::new("1..$n").tokenize().unwrap();
+    let tokens: Vec<_> = lexemes.iter().map(|lexeme| lexeme.token.clone()).collect();
+    assert_eq!(tokens, vec![
+        PsToken::Number(1),
+        PsToken::Operator("..".to_string()),
+        PsToken::Variable("n".to_string()),
+        PsToken::Eof,
+    ]);
+}
@@ -1,4 +1,9 @@
-use cassh2rs::parser::{ShellParser, AST, ASTNode, shell_dialect::ShellDialect};
+use cassh2rs::parser::{ShellParser, ParseError, AST, ASTNode, shell_dialect::ShellDialect};
+use cassh2rs::parser::arithmetic::{parse_arithmetic, parse_integer};
+use cassh2rs::parser::ast::{
+    BinaryOperator, CaseItem, CaseTerminator, ExpansionType, ForItems, GlobQualifier, ParameterFlag,
+    RedirectDirection, Redirection, RedirectionTarget, Span, StringType, UnaryOperator, VariableAttributes,
+};
 
 #[test]
 fn test_parse_simple_command() {
@@ -122,7 +127,7 @@ function greet() {
         ASTNode::Script(statements) => {
             assert_eq!(statements.len(), 1);
             match statements[0].as_ref() {
-                ASTNode::Function { name, body } => {
+                ASTNode::Function { name, body, .. } => {
                     assert_eq!(name, "greet");
                     assert!(!body.is_empty_block());
                 }
@@ -133,6 +138,36 @@ function greet() {
     }
 }
 
+#[test]
+fn test_parse_posix_function() {
+    let input = "greet() { echo \"hi $1\"; }; greet bob\nlog ()\n{\n    shift\n}\nsub() ( cd /tmp )";
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    match &ast.root {
+        ASTNode::Script(statements) => {
+            assert_eq!(statements.len(), 4);
+            assert!(matches!(statements[0].as_ref(), ASTNode::Function { name, .. } if name == "greet"));
+            assert!(matches!(statements[1].as_ref(), ASTNode::Command { name, .. } if name == "greet"));
+            assert!(matches!(statements[2].as_ref(), ASTNode::Function { name, .. } if name == "log"));
+            match statements[3].as_ref() {
+                ASTNode::Function { name, body, .. } => {
+                    assert_eq!(name, "sub");
+                    assert!(matches!(body.as_ref(), ASTNode::Subshell(_)));
+                }
+                _ => panic!("Expected function"),
+            }
+        }
+        _ => panic!("Expected script node"),
+    }
+    
+    // An empty `()` is neither a subshell nor a function
+    let mut parser = ShellParser::new("greet()".to_string(), ShellDialect::Bash).unwrap();
+    assert!(parser.parse().is_err());
+    let mut parser = ShellParser::new("echo; ()".to_string(), ShellDialect::Bash).unwrap();
+    assert!(parser.parse().is_err());
+}
+
 #[test]
 fn test_parse_pipeline() {
     let input = "cat file.txt | grep pattern | wc -l";
@@ -175,4 +210,1823 @@ echo "Script content"
     assert_eq!(ast.metadata.dependencies.len(), 2);
     assert!(ast.metadata.dependencies.contains(&"curl".to_string()));
     assert!(ast.metadata.dependencies.contains(&"jq".to_string()));
-}
\ No newline at end of file
+}
+
+fn parse_first_arg(input: &str) -> ASTNode {
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    match &ast.root {
+        ASTNode::Script(statements) => match statements[0].as_ref() {
+            ASTNode::Command { args, .. } => args[0].as_ref().clone(),
+            other => panic!("Expected command, got {:?}", other),
+        },
+        _ => panic!("Expected script node"),
+    }
+}
+
+fn expansion(input: &str) -> (String, ExpansionType) {
+    match parse_first_arg(input) {
+        ASTNode::ParameterExpansion { name, expansion_type, .. } => (name, expansion_type),
+        other => panic!("Expected parameter expansion for {}, got {:?}", input, other),
+    }
+}
+
+#[test]
+fn test_parse_braced_variable() {
+    assert_eq!(parse_first_arg("echo ${HOME}"), ASTNode::Variable("HOME".to_string(), Span::new(1, 6)));
+    assert_eq!(parse_first_arg("echo ${10}"), ASTNode::Variable("10".to_string(), Span::new(1, 6)));
+    assert_eq!(parse_first_arg("echo $$"), ASTNode::Variable("$".to_string(), Span::new(1, 6)));
+    assert_eq!(parse_first_arg("echo ${$}"), ASTNode::Variable("$".to_string(), Span::new(1, 6)));
+    assert_eq!(parse_first_arg("echo /tmp/x.$$"), ASTNode::Concat(vec![
+        Box::new(ASTNode::String("/tmp/x.".to_string(), StringType::Unquoted, Span::new(1, 6))),
+        Box::new(ASTNode::Variable("$".to_string(), Span::new(1, 13))),
+    ], Span::new(1, 6)));
+}
+
+#[test]
+fn test_parse_expansion_default() {
+    let (name, expansion_type) = expansion("echo ${VAR:-default}");
+    assert_eq!(name, "VAR");
+    assert_eq!(
+        expansion_type,
+        ExpansionType::Default {
+            word: Box::new(ASTNode::String("default".to_string(), StringType::Unquoted, Span::new(1, 13))),
+            colon: true,
+        }
+    );
+    
+    // Without the colon
+    let (_, expansion_type) = expansion("echo ${VAR-fallback}");
+    assert!(matches!(expansion_type, ExpansionType::Default { colon: false, .. }));
+    
+    // A default with expansions and quotes in it is a word
+    let (_, expansion_type) = expansion("echo ${CONFIG:-$HOME/\"my cfg\"}");
+    assert_eq!(expansion_type, ExpansionType::Default {
+        word: Box::new(ASTNode::Concat(vec![
+            Box::new(ASTNode::Variable("HOME".to_string(), Span::new(1, 16))),
+            Box::new(ASTNode::String("/".to_string(), StringType::Unquoted, Span::new(1, 21))),
+            Box::new(ASTNode::String("my cfg".to_string(), StringType::DoubleQuoted, Span::new(1, 22))),
+        ], Span::new(1, 16))),
+        colon: true,
+    });
+}
+
+#[test]
+fn test_parse_expansion_nested_default() {
+    let (name, expansion_type) = expansion("echo ${CONFIG:-${XDG_CONFIG_HOME:-$HOME}}");
+    assert_eq!(name, "CONFIG");
+    match expansion_type {
+        ExpansionType::Default { word, .. } => match *word {
+            ASTNode::ParameterExpansion { name, expansion_type: ExpansionType::Default { word: inner, .. }, .. } => {
+                assert_eq!(name, "XDG_CONFIG_HOME");
+                assert_eq!(*inner, ASTNode::Variable("HOME".to_string(), Span::new(1, 35)));
+            }
+            other => panic!("Expected nested expansion, got {:?}", other),
+        },
+        other => panic!("Expected default, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_expansion_assign_error_alternative() {
+    let (_, expansion_type) = expansion("echo ${COUNT:=0}");
+    assert_eq!(expansion_type, ExpansionType::Assign {
+        word: Box::new(ASTNode::String("0".to_string(), StringType::Unquoted, Span::new(1, 15))),
+        colon: true,
+    });
+    
+    let (_, expansion_type) = expansion("echo ${TARGET:?\"target is required\"}");
+    assert_eq!(expansion_type, ExpansionType::Error { message: "target is required".to_string(), colon: true });
+    
+    let (_, expansion_type) = expansion("echo ${DEBUG:+--verbose}");
+    assert_eq!(
+        expansion_type,
+        ExpansionType::Alternative {
+            word: Box::new(ASTNode::String("--verbose".to_string(), StringType::Unquoted, Span::new(1, 15))),
+            colon: true,
+        }
+    );
+}
+
+#[test]
+fn test_parse_expansion_substring() {
+    let (_, expansion_type) = expansion("echo ${VERSION:1:3}");
+    assert_eq!(expansion_type, ExpansionType::Substring {
+        offset: Box::new(ASTNode::Number(1.0)),
+        length: Some(Box::new(ASTNode::Number(3.0))),
+    });
+    
+    let (_, expansion_type) = expansion("echo ${PATH: -4}");
+    assert_eq!(expansion_type, ExpansionType::Substring {
+        offset: Box::new(ASTNode::Number(-4.0)),
+        length: None,
+    });
+    
+    let (_, expansion_type) = expansion("echo ${LINE:$start}");
+    assert_eq!(expansion_type, ExpansionType::Substring {
+        offset: Box::new(ASTNode::Variable("start".to_string(), Span::new(1, 13))),
+        length: None,
+    });
+}
+
+#[test]
+fn test_parse_expansion_prefix_suffix_removal() {
+    assert_eq!(expansion("echo ${file#*/}").1, ExpansionType::RemovePrefix("*/".to_string()));
+    assert_eq!(expansion("echo ${file##*/}").1, ExpansionType::RemovePrefixLong("*/".to_string()));
+    assert_eq!(expansion("echo ${file%.*}").1, ExpansionType::RemoveSuffix(".*".to_string()));
+    assert_eq!(expansion("echo ${file%%.*}").1, ExpansionType::RemoveSuffixLong(".*".to_string()));
+}
+
+#[test]
+fn test_parse_expansion_replace() {
+    assert_eq!(expansion("echo ${path/foo/bar}").1, ExpansionType::Replace {
+        pattern: "foo".to_string(),
+        replacement: "bar".to_string(),
+        global: false,
+    });
+    assert_eq!(expansion("echo ${path//\\//_}").1, ExpansionType::Replace {
+        pattern: "\\/".to_string(),
+        replacement: "_".to_string(),
+        global: true,
+    });
+    assert_eq!(expansion("echo ${name/#v}").1, ExpansionType::Replace {
+        pattern: "#v".to_string(),
+        replacement: String::new(),
+        global: false,
+    });
+}
+
+#[test]
+fn test_parse_expansion_length_indirect_keys() {
+    assert_eq!(expansion("echo ${#name}"), ("name".to_string(), ExpansionType::Length));
+    assert_eq!(expansion("echo ${#files[@]}"), ("files[@]".to_string(), ExpansionType::Length));
+    assert_eq!(expansion("echo ${!ref}"), ("ref".to_string(), ExpansionType::Indirect));
+    assert_eq!(expansion("echo ${!map[@]}"), ("map".to_string(), ExpansionType::Keys));
+}
+
+#[test]
+fn test_parse_expansion_array_subscript() {
+    assert_eq!(expansion("echo ${arr[2]}"), (
+        "arr".to_string(),
+        ExpansionType::Subscript(Box::new(ASTNode::String("2".to_string(), StringType::Unquoted, Span::new(1, 12)))),
+    ));
+    assert_eq!(expansion("echo ${map[\"a key\"]}").1, ExpansionType::Subscript(Box::new(
+        ASTNode::String("a key".to_string(), StringType::DoubleQuoted, Span::new(1, 12)),
+    )));
+    assert_eq!(expansion("echo ${arr[$i]}").1, ExpansionType::Subscript(Box::new(ASTNode::Variable("i".to_string(), Span::new(1, 12)))));
+    assert_eq!(parse_first_arg("echo ${arr[@]}"), ASTNode::Variable("arr[@]".to_string(), Span::new(1, 6)));
+    assert!(matches!(expansion("echo ${arr[@]:1:2}"), (name, ExpansionType::Substring { .. }) if name == "arr[@]"));
+    
+    let (name, expansion_type) = expansion("echo ${map[$key]:-none}");
+    assert_eq!(name, "map[$key]");
+    assert!(matches!(expansion_type, ExpansionType::Default { .. }));
+}
+
+#[test]
+fn test_parse_array_assignments() {
+    let input = "arr[3]=x\nmap[\"a b\"]+=y\narr+=(d e)\ndeclare -A map=([k]=v)\n";
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let statements = match parser.parse().unwrap().root {
+        ASTNode::Script(statements) => statements,
+        other => panic!("Expected script, got {:?}", other),
+    };
+    
+    match statements[0].as_ref() {
+        ASTNode::Assignment { name, index: Some(index), value, append: false, .. } => {
+            assert_eq!(name, "arr");
+            assert_eq!(**index, ASTNode::String("3".to_string(), StringType::Unquoted, Span::new(1, 5)));
+            assert_eq!(**value, ASTNode::String("x".to_string(), StringType::Unquoted, Span::new(1, 8)));
+        }
+        other => panic!("Expected element assignment, got {:?}", other),
+    }
+    match statements[1].as_ref() {
+        ASTNode::Assignment { name, index: Some(index), append: true, .. } => {
+            assert_eq!(name, "map");
+            assert_eq!(**index, ASTNode::String("a b".to_string(), StringType::DoubleQuoted, Span::new(2, 5)));
+        }
+        other => panic!("Expected element append, got {:?}", other),
+    }
+    assert!(matches!(
+        statements[2].as_ref(),
+        ASTNode::Assignment { index: None, value, append: true, .. } if matches!(value.as_ref(), ASTNode::Array(words) if words.len() == 2)
+    ));
+    
+    // Declarations take assignments, and `[key]=value` elements are
+    // assignments without a name
+    match statements[3].as_ref() {
+        ASTNode::Declaration { builtin, attributes, variables, .. } => {
+            assert_eq!(builtin, "declare");
+            assert!(attributes.associative);
+            match variables[0].as_ref() {
+                ASTNode::Assignment { name, value, .. } => {
+                    assert_eq!(name, "map");
+                    match value.as_ref() {
+                        ASTNode::Array(elements) => assert!(matches!(
+                            elements[0].as_ref(),
+                            ASTNode::Assignment { name, index: Some(_), .. } if name.is_empty()
+                        )),
+                        other => panic!("Expected array, got {:?}", other),
+                    }
+                }
+                other => panic!("Expected assignment, got {:?}", other),
+            }
+        }
+        other => panic!("Expected declare, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_declarations() {
+    let input = "declare -ix n=1 m\ndeclare -g +u -l name\nlocal a b=2\nexport -n PATH\nexport X=1\nreadonly R\n";
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let statements = match parser.parse().unwrap().root {
+        ASTNode::Script(statements) => statements,
+        other => panic!("Expected script, got {:?}", other),
+    };
+    
+    match statements[0].as_ref() {
+        ASTNode::Declaration { builtin, attributes, local, variables, .. } => {
+            assert_eq!(builtin, "declare");
+            assert_eq!(*attributes, VariableAttributes { integer: true, export: true, ..Default::default() });
+            assert!(*local);
+            assert!(matches!(variables[0].as_ref(), ASTNode::Assignment { name, .. } if name == "n"));
+            assert_eq!(*variables[1], ASTNode::String("m".to_string(), StringType::Unquoted, Span::new(1, 17)));
+        }
+        other => panic!("Expected declaration, got {:?}", other),
+    }
+    match statements[1].as_ref() {
+        ASTNode::Declaration { attributes, removed, local, .. } => {
+            assert_eq!(*attributes, VariableAttributes { lowercase: true, ..Default::default() });
+            assert_eq!(*removed, VariableAttributes { uppercase: true, ..Default::default() });
+            assert!(!*local);
+        }
+        other => panic!("Expected declaration, got {:?}", other),
+    }
+    match statements[2].as_ref() {
+        ASTNode::Declaration { builtin, local: true, variables, .. } => {
+            assert_eq!(builtin, "local");
+            assert_eq!(variables.len(), 2);
+        }
+        other => panic!("Expected local declaration, got {:?}", other),
+    }
+    match statements[3].as_ref() {
+        ASTNode::Declaration { attributes, removed, .. } => {
+            assert!(!attributes.export);
+            assert!(removed.export);
+        }
+        other => panic!("Expected export -n, got {:?}", other),
+    }
+    
+    // A single assignment stays an assignment with the builtin's attribute
+    assert!(matches!(
+        statements[4].as_ref(),
+        ASTNode::Assignment { name, export: true, .. } if name == "X"
+    ));
+    match statements[5].as_ref() {
+        ASTNode::Declaration { attributes, variables, .. } => {
+            assert!(attributes.readonly);
+            assert_eq!(*variables[0], ASTNode::String("R".to_string(), StringType::Unquoted, Span::new(6, 10)));
+        }
+        other => panic!("Expected readonly declaration, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_double_quoted_segments() {
+    match parse_first_arg(r#"echo "Hello, $USER ${HOME}/x $(id -u) $((1 + 2)) \$5 \"q\"""#) {
+        ASTNode::Quoted(parts, _) => {
+            assert_eq!(*parts[0], ASTNode::String("Hello, ".to_string(), StringType::DoubleQuoted, Span::new(1, 7)));
+            assert_eq!(*parts[1], ASTNode::Variable("USER".to_string(), Span::new(1, 14)));
+            assert_eq!(*parts[3], ASTNode::Variable("HOME".to_string(), Span::new(1, 20)));
+            assert_eq!(*parts[4], ASTNode::String("/x ".to_string(), StringType::DoubleQuoted, Span::new(1, 27)));
+            assert!(matches!(parts[5].as_ref(), ASTNode::CommandSubstitution(_, _)));
+            assert!(matches!(parts[7].as_ref(), ASTNode::ArithmeticExpansion(_, _)));
+            assert_eq!(*parts[8], ASTNode::String(" $5 \"q\"".to_string(), StringType::DoubleQuoted, Span::new(1, 49)));
+        }
+        other => panic!("Expected quoted word, got {:?}", other),
+    }
+    
+    // Quotes inside a substitution don't end the string, nor does a quoted
+    // or escaped `)` end the substitution, and text without expansions stays
+    // one string
+    for input in [r#"echo "$(echo "a b")""#, r#"echo "$(echo ")")""#, r#"echo "$(echo \")""#] {
+        assert!(matches!(
+            parse_first_arg(input),
+            ASTNode::Quoted(parts, _) if matches!(parts[..], [ref part] if matches!(part.as_ref(), ASTNode::CommandSubstitution(_, _)))
+        ));
+    }
+    assert_eq!(
+        parse_first_arg(r#"echo "a \b \\ c""#),
+        ASTNode::String("a \\b \\ c".to_string(), StringType::DoubleQuoted, Span::new(1, 6))
+    );
+    assert_eq!(parse_first_arg("echo '$HOME'"), ASTNode::String("$HOME".to_string(), StringType::SingleQuoted, Span::new(1, 6)));
+}
+
+#[test]
+fn test_parse_glob_words() {
+    assert_eq!(parse_first_arg("rm *.log"), ASTNode::Glob("*.log".to_string(), Span::new(1, 4)));
+    assert_eq!(parse_first_arg("ls file[12]"), ASTNode::Glob("file[12]".to_string(), Span::new(1, 4)));
+    // Escaped characters and a lone `[` don't make a pattern, and neither
+    // do quotes
+    assert_eq!(parse_first_arg("echo a\\*b"), ASTNode::String("a\\*b".to_string(), StringType::Unquoted, Span::new(1, 6)));
+    assert_eq!(parse_first_arg("echo a\\<b\\;"), ASTNode::String("a\\<b\\;".to_string(), StringType::Unquoted, Span::new(1, 6)));
+    assert_eq!(parse_first_arg("echo ["), ASTNode::String("[".to_string(), StringType::Unquoted, Span::new(1, 6)));
+    assert_eq!(parse_first_arg("echo '*'"), ASTNode::String("*".to_string(), StringType::SingleQuoted, Span::new(1, 6)));
+    
+    match first_statement("for f in *.log; do echo $f; done") {
+        ASTNode::For { items: ForItems::List(items), .. } => {
+            assert_eq!(*items[0], ASTNode::Glob("*.log".to_string(), Span::new(1, 10)));
+        }
+        other => panic!("Expected for loop, got {:?}", other),
+    }
+    // Assignment values aren't globbed
+    assert!(matches!(
+        first_statement("x=*.log"),
+        ASTNode::Assignment { value, .. } if *value == ASTNode::String("*.log".to_string(), StringType::Unquoted, Span::new(1, 3))
+    ));
+}
+
+#[test]
+fn test_parse_expansion_in_word() {
+    match parse_first_arg("mv ${file%.*}.bak") {
+        ASTNode::Concat(parts, _) => {
+            assert_eq!(parts.len(), 2);
+            assert!(matches!(parts[0].as_ref(), ASTNode::ParameterExpansion { .. }));
+            assert_eq!(*parts[1], ASTNode::String(".bak".to_string(), StringType::Unquoted, Span::new(1, 14)));
+        }
+        other => panic!("Expected concatenated word, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_unterminated_expansion() {
+    let mut parser = ShellParser::new("echo ${VAR:-oops".to_string(), ShellDialect::Bash).unwrap();
+    assert!(parser.parse().is_err());
+}
+
+fn substitution(input: &str) -> Vec<ASTNode> {
+    match parse_first_arg(input) {
+        ASTNode::CommandSubstitution(body, _) => match *body {
+            ASTNode::Block(statements) => statements.into_iter().map(|statement| *statement).collect(),
+            other => panic!("Expected block, got {:?}", other),
+        },
+        other => panic!("Expected command substitution for {}, got {:?}", input, other),
+    }
+}
+
+#[test]
+fn test_parse_command_substitution_pipeline() {
+    let statements = substitution("echo $(ls -la | grep foo)");
+    assert_eq!(statements.len(), 1);
+    match &statements[0] {
+        ASTNode::Pipeline(commands) => assert_eq!(commands.len(), 2),
+        other => panic!("Expected pipeline, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_nested_command_substitution() {
+    let statements = substitution("echo $(basename $(dirname \"$0\"))");
+    match &statements[0] {
+        ASTNode::Command { name, args, .. } => {
+            assert_eq!(name, "basename");
+            assert!(matches!(args[0].as_ref(), ASTNode::CommandSubstitution(_, _)));
+        }
+        other => panic!("Expected command, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_command_substitution_quoting() {
+    let statements = substitution("echo $(printf '%s)' \"a ) b\")");
+    match &statements[0] {
+        ASTNode::Command { name, args, .. } => {
+            assert_eq!(name, "printf");
+            assert_eq!(args.len(), 2);
+        }
+        other => panic!("Expected command, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_backtick_substitution() {
+    let statements = substitution("echo `cat \\`which foo\\``");
+    match &statements[0] {
+        ASTNode::Command { name, args, .. } => {
+            assert_eq!(name, "cat");
+            assert!(matches!(args[0].as_ref(), ASTNode::CommandSubstitution(_, _)));
+        }
+        other => panic!("Expected command, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_substitution_dependencies() {
+    let input = "VERSION=$(git describe --tags)\necho `jq -r .name package.json`";
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let deps = ast.root.get_dependencies();
+    assert!(deps.contains(&"git".to_string()));
+    assert!(deps.contains(&"jq".to_string()));
+}
+
+#[test]
+fn test_parse_unterminated_substitution() {
+    let mut parser = ShellParser::new("echo $(ls".to_string(), ShellDialect::Bash).unwrap();
+    assert!(parser.parse().is_err());
+}
+
+fn parse_case_items(input: &str) -> Vec<CaseItem> {
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    match &ast.root {
+        ASTNode::Script(statements) => match statements[0].as_ref() {
+            ASTNode::Case { cases, .. } => cases.clone(),
+            other => panic!("Expected case statement, got {:?}", other),
+        },
+        _ => panic!("Expected script node"),
+    }
+}
+
+#[test]
+fn test_parse_case_statement() {
+    let input = r#"
+case "$1" in
+    -h|--help)
+        usage
+        ;;
+    (-v) echo verbose ;;
+    *) echo "unknown: $1"
+esac
+"#;
+    
+    let cases = parse_case_items(input);
+    assert_eq!(cases.len(), 3);
+    assert_eq!(cases[0].patterns, vec![word("-h", 3, 5), word("--help", 3, 8)]);
+    assert_eq!(cases[1].patterns, vec![word("-v", 6, 6)]);
+    assert_eq!(cases[2].patterns, vec![word("*", 7, 5)]);
+    assert!(cases.iter().all(|c| c.terminator == CaseTerminator::Break));
+    assert!(!cases[0].body.is_empty_block());
+}
+
+#[test]
+fn test_parse_case_fall_through() {
+    let cases = parse_case_items("case $x in a) echo a ;& b) echo b ;;& *) ;; esac");
+    assert_eq!(cases[0].terminator, CaseTerminator::FallThrough);
+    assert_eq!(cases[1].terminator, CaseTerminator::Continue);
+    assert_eq!(cases[2].terminator, CaseTerminator::Break);
+    assert!(cases[2].body.is_empty_block());
+}
+
+#[test]
+fn test_parse_exit_and_return_before_operators() {
+    let cases = parse_case_items("case $1 in --help) usage; exit;; -q) return;; esac");
+    assert!(matches!(cases[0].body.as_ref(), ASTNode::Block(b) if b[1].as_ref() == &ASTNode::Exit(None)));
+    assert!(matches!(cases[1].body.as_ref(), ASTNode::Block(b) if b[0].as_ref() == &ASTNode::Return(None)));
+    
+    let mut parser = ShellParser::new("(exit)
+exit | cat
+".to_string(), ShellDialect::Bash).unwrap();
+    match parser.parse().unwrap().root {
+        ASTNode::Script(statements) => {
+            assert!(matches!(statements[0].as_ref(), ASTNode::Subshell(body) if body.as_ref() == &ASTNode::Block(vec![Box::new(ASTNode::Exit(None))])));
+            assert!(matches!(statements[1].as_ref(), ASTNode::Pipeline(stages) if stages[0].as_ref() == &ASTNode::Exit(None)));
+        }
+        other => panic!("Expected script, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_case_glob_patterns() {
+    let cases = parse_case_items(r#"case $f in [yY]*|*.txt) ;; @(a|b)*) ;; "*"|'?'x) ;; "$p"|${v%x}|$(echo x)) ;; esac"#);
+    assert_eq!(cases[0].patterns, vec![word("[yY]*", 1, 12), word("*.txt", 1, 18)]);
+    assert_eq!(cases[1].patterns, vec![word("@(a|b)*", 1, 28)]);
+    // Quoted glob characters only match literally
+    assert_eq!(cases[2].patterns[0], ASTNode::String("*".to_string(), StringType::DoubleQuoted, Span::new(1, 40)));
+    assert!(matches!(&cases[2].patterns[1], ASTNode::Concat(parts, _)
+        if parts[0].as_ref() == &ASTNode::String("?".to_string(), StringType::SingleQuoted, Span::new(1, 44))));
+    assert_eq!(cases[3].patterns[0], ASTNode::Quoted(vec![Box::new(ASTNode::Variable("p".to_string(), Span::new(1, 54)))], Span::new(1, 53)));
+    assert!(matches!(&cases[3].patterns[1], ASTNode::ParameterExpansion { name, .. } if name == "v"));
+    assert!(matches!(cases[3].patterns[2], ASTNode::CommandSubstitution(_, _)));
+}
+
+fn parse_redirections(input: &str) -> Vec<Redirection> {
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    match &ast.root {
+        ASTNode::Script(statements) => match statements[0].as_ref() {
+            ASTNode::Command { redirections, .. } => redirections.clone(),
+            ASTNode::Redirected { redirections, .. } => redirections.clone(),
+            other => panic!("Expected redirected command, got {:?}", other),
+        },
+        _ => panic!("Expected script node"),
+    }
+}
+
+#[test]
+fn test_parse_file_redirections() {
+    let redirections = parse_redirections("sort < in.txt > out.txt 2>> err.log");
+    assert_eq!(redirections.len(), 3);
+    
+    assert_eq!(redirections[0].fd, None);
+    assert_eq!(redirections[0].direction, RedirectDirection::Input);
+    assert_eq!(
+        redirections[0].target,
+        RedirectionTarget::File(Box::new(ASTNode::String("in.txt".to_string(), StringType::Unquoted, Span::new(1, 8))))
+    );
+    
+    assert_eq!(redirections[1].direction, RedirectDirection::Output);
+    assert!(!redirections[1].append);
+    
+    assert_eq!(redirections[2].fd, Some(2));
+    assert!(redirections[2].append);
+    
+    let redirections = parse_redirections("echo x >| out.txt");
+    assert_eq!(redirections[0].direction, RedirectDirection::Clobber);
+}
+
+#[test]
+fn test_parse_fd_redirections() {
+    let redirections = parse_redirections("cmd 2>&1 3<&- <> dev 1>&2");
+    assert_eq!(redirections[0].fd, Some(2));
+    assert_eq!(redirections[0].target, RedirectionTarget::Fd(1));
+    assert_eq!(redirections[1].fd, Some(3));
+    assert_eq!(redirections[1].target, RedirectionTarget::Close);
+    assert_eq!(redirections[2].direction, RedirectDirection::ReadWrite);
+    assert_eq!(redirections[3].target, RedirectionTarget::Fd(2));
+    
+    // `&>` is `>file 2>&1`
+    let redirections = parse_redirections("make &> build.log");
+    assert_eq!(redirections.len(), 2);
+    assert_eq!(redirections[0].fd, Some(1));
+    assert_eq!(redirections[1].target, RedirectionTarget::Fd(1));
+}
+
+#[test]
+fn test_parse_here_string_and_compound_redirection() {
+    let redirections = parse_redirections("grep foo <<< $line");
+    assert_eq!(
+        redirections[0].target,
+        RedirectionTarget::HereString(Box::new(ASTNode::Variable("line".to_string(), Span::new(1, 14))))
+    );
+    
+    let redirections = parse_redirections("while read l; do echo $l; done < list.txt");
+    assert_eq!(redirections.len(), 1);
+    assert_eq!(redirections[0].direction, RedirectDirection::Input);
+}
+
+#[test]
+fn test_parse_heredocs() {
+    let single = |text: &str, line, column| Box::new(ASTNode::String(text.to_string(), StringType::SingleQuoted, Span::new(line, column)));
+    
+    let redirections = parse_redirections("cat <<'EOF'\nraw $x\nEOF\n");
+    assert_eq!(
+        redirections[0].target,
+        RedirectionTarget::Heredoc { delimiter: "EOF".to_string(), content: single("raw $x\n", 2, 1), strip_tabs: false }
+    );
+    
+    // Unquoted bodies expand, but their quotes are literal
+    let redirections = parse_redirections("cat <<-END\n\thi \"$x\" \\$y\n\tEND\n");
+    assert_eq!(
+        redirections[0].target,
+        RedirectionTarget::Heredoc {
+            delimiter: "END".to_string(),
+            content: Box::new(ASTNode::Concat(vec![
+                single("hi \"", 2, 1),
+                Box::new(ASTNode::Variable("x".to_string(), Span::new(2, 5))),
+                single("\" $y\n", 2, 7),
+            ], Span::new(2, 1))),
+            strip_tabs: true,
+        }
+    );
+    
+    // Bodies follow the line in the order of their operators
+    let redirections = parse_redirections("paste <<A <<\"B\"\none\nA\ntwo\nB\n");
+    assert_eq!(redirections.len(), 2);
+    assert!(matches!(&redirections[0].target, RedirectionTarget::Heredoc { content, .. } if *content == single("one\n", 2, 1)));
+    assert!(matches!(&redirections[1].target, RedirectionTarget::Heredoc { content, .. } if *content == single("two\n", 4, 1)));
+}
+
+#[test]
+fn test_parse_assignment_redirections_and_process_substitution() {
+    // Redirections after assignments apply to the command without a name
+    match first_statement("v=$(cmd) 2>/dev/null") {
+        ASTNode::Redirected { body, redirections } => {
+            assert!(matches!(body.as_ref(), ASTNode::Assignment { name, .. } if name == "v"));
+            assert_eq!(redirections[0].fd, Some(2));
+        }
+        other => panic!("Expected redirected assignment, got {:?}", other),
+    }
+    
+    // `<(cmd)` is read from a file holding the output
+    match parse_first_arg("diff <(sort a) <(sort b)") {
+        ASTNode::FileSubstitution(body, _) => assert!(matches!(body.as_ref(), ASTNode::Block(_))),
+        other => panic!("Expected file substitution, got {:?}", other),
+    }
+    let redirections = parse_redirections("cat < <(sort a)");
+    assert!(matches!(&redirections[0].target, RedirectionTarget::File(word) if matches!(word.as_ref(), ASTNode::FileSubstitution(_, _))));
+    
+    let mut parser = ShellParser::new("tee >(gzip > out.gz)".to_string(), ShellDialect::Bash).unwrap();
+    let error = parser.parse().unwrap_err().to_string();
+    assert!(error.contains("`>(...)' is not supported"), "{}", error);
+}
+
+fn parse_pipeline_stages(input: &str) -> Vec<ASTNode> {
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    match &ast.root {
+        ASTNode::Script(statements) => match statements[0].as_ref() {
+            ASTNode::Pipeline(stages) => stages.iter().map(|s| s.as_ref().clone()).collect(),
+            other => panic!("Expected pipeline, got {:?}", other),
+        },
+        _ => panic!("Expected script node"),
+    }
+}
+
+#[test]
+fn test_parse_pipeline_with_compound_stage() {
+    let stages = parse_pipeline_stages("cat log.txt | while read line; do echo $line; done | sort");
+    assert_eq!(stages.len(), 3);
+    assert!(matches!(&stages[0], ASTNode::Command { name, .. } if name == "cat"));
+    assert!(matches!(&stages[1], ASTNode::While { .. }));
+    assert!(matches!(&stages[2], ASTNode::Command { name, .. } if name == "sort"));
+}
+
+#[test]
+fn test_parse_pipe_with_stderr() {
+    let stages = parse_pipeline_stages("make |& tee build.log");
+    assert_eq!(stages.len(), 2);
+    
+    match &stages[0] {
+        ASTNode::Command { redirections, .. } => {
+            assert_eq!(redirections.len(), 1);
+            assert_eq!(redirections[0].fd, Some(2));
+            assert_eq!(redirections[0].target, RedirectionTarget::Fd(1));
+        }
+        other => panic!("Expected command, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_loop_control_counts() {
+    match first_statement("while true; do break 2; continue; done") {
+        ASTNode::While { body, .. } => match body.as_ref() {
+            ASTNode::Block(statements) => {
+                assert_eq!(statements[0].as_ref(), &ASTNode::Break(2));
+                assert_eq!(statements[1].as_ref(), &ASTNode::Continue(1));
+            }
+            other => panic!("Expected block, got {:?}", other),
+        },
+        other => panic!("Expected while, got {:?}", other),
+    }
+    
+    let mut parser = ShellParser::new("while true; do continue 0; done".to_string(), ShellDialect::Bash).unwrap();
+    let error = parser.parse().unwrap_err().to_string();
+    assert!(error.contains("continue: 0: loop count out of range"), "{}", error);
+}
+
+fn first_statement(input: &str) -> ASTNode {
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    match ast.root {
+        ASTNode::Script(mut statements) => *statements.remove(0),
+        _ => panic!("Expected script node"),
+    }
+}
+
+fn binary(left: ASTNode, op: BinaryOperator, right: ASTNode) -> ASTNode {
+    ASTNode::BinaryOp { left: Box::new(left), op, right: Box::new(right) }
+}
+
+#[test]
+fn test_parse_arithmetic_precedence() {
+    let expr = parse_arithmetic("1 + 2 * 3 ** 2", Span::new(1, 1)).unwrap();
+    assert_eq!(expr, binary(
+        ASTNode::Integer(1),
+        BinaryOperator::Add,
+        binary(
+            ASTNode::Integer(2),
+            BinaryOperator::Multiply,
+            binary(ASTNode::Integer(3), BinaryOperator::Power, ASTNode::Integer(2)),
+        ),
+    ));
+    
+    // Unary minus binds tighter than `**`, which is right associative
+    let expr = parse_arithmetic("-2 ** 3 ** 2", Span::new(1, 1)).unwrap();
+    assert_eq!(expr, binary(
+        ASTNode::UnaryOp { op: UnaryOperator::Negate, operand: Box::new(ASTNode::Integer(2)) },
+        BinaryOperator::Power,
+        binary(ASTNode::Integer(3), BinaryOperator::Power, ASTNode::Integer(2)),
+    ));
+    
+    let expr = parse_arithmetic("a || b && c ? x : y", Span::new(1, 1)).unwrap();
+    assert!(matches!(expr, ASTNode::Conditional { ref condition, .. }
+        if matches!(condition.as_ref(), ASTNode::BinaryOp { op: BinaryOperator::Or, .. })));
+}
+
+#[test]
+fn test_parse_arithmetic_assignments() {
+    let expr = parse_arithmetic("x += y = 2, i++", Span::new(1, 1)).unwrap();
+    assert_eq!(expr, binary(
+        ASTNode::ArithmeticAssignment {
+            name: "x".to_string(),
+            op: Some(BinaryOperator::Add),
+            value: Box::new(ASTNode::ArithmeticAssignment {
+                name: "y".to_string(),
+                op: None,
+                value: Box::new(ASTNode::Integer(2)),
+            }),
+        },
+        BinaryOperator::Comma,
+        ASTNode::UnaryOp {
+            op: UnaryOperator::PostIncrement,
+            operand: Box::new(ASTNode::Variable("i".to_string(), Span::new(1, 13))),
+        },
+    ));
+    
+    assert!(parse_arithmetic("1 +", Span::new(1, 1)).is_err());
+    assert!(parse_arithmetic("(1", Span::new(1, 1)).is_err());
+    assert_eq!(parse_arithmetic("", Span::new(1, 1)).unwrap(), ASTNode::Integer(0));
+}
+
+#[test]
+fn test_parse_integer_bases() {
+    assert_eq!(parse_integer("42").unwrap(), 42);
+    assert_eq!(parse_integer("0x1f").unwrap(), 31);
+    assert_eq!(parse_integer("017").unwrap(), 15);
+    assert_eq!(parse_integer("2#1011").unwrap(), 11);
+    assert_eq!(parse_integer("36#zz").unwrap(), 1295);
+    assert_eq!(parse_integer("64#A").unwrap(), 36);
+    assert_eq!(parse_integer("0xFFFFFFFFFFFFFFFF").unwrap(), -1);
+    assert!(parse_integer("08").is_err());
+    assert!(parse_integer("65#1").is_err());
+}
+
+#[test]
+fn test_parse_arithmetic_expansion_and_command() {
+    match first_statement("echo $(( n + 1 ))") {
+        ASTNode::Command { args, .. } => assert_eq!(
+            *args[0],
+            ASTNode::ArithmeticExpansion(Box::new(binary(
+                ASTNode::Variable("n".to_string(), Span::new(1, 10)),
+                BinaryOperator::Add,
+                ASTNode::Integer(1),
+            )), Span::new(1, 6)),
+        ),
+        other => panic!("Expected command, got {:?}", other),
+    }
+    
+    assert_eq!(
+        first_statement("(( count > 0 ))"),
+        ASTNode::ArithmeticCommand(Box::new(binary(
+            ASTNode::Variable("count".to_string(), Span::new(1, 4)),
+            BinaryOperator::Greater,
+            ASTNode::Integer(0),
+        )), Span::new(1, 1)),
+    );
+    
+    // `let` arguments are separate expressions evaluated in order
+    assert!(matches!(
+        first_statement("let i++ 'j = i * 2'"),
+        ASTNode::ArithmeticCommand(expr, _) if matches!(*expr, ASTNode::BinaryOp { op: BinaryOperator::Comma, .. })
+    ));
+}
+
+#[test]
+fn test_parse_arithmetic_with_expansions() {
+    // `$` expansions are substituted as text, so the expression is evaluated at runtime
+    match first_statement("echo $(( $a * 2 ))") {
+        ASTNode::Command { args, .. } => assert_eq!(
+            *args[0],
+            ASTNode::ArithmeticExpansion(Box::new(ASTNode::Concat(vec![
+                Box::new(ASTNode::String(" ".to_string(), StringType::Unquoted, Span::new(1, 9))),
+                Box::new(ASTNode::Variable("a".to_string(), Span::new(1, 10))),
+                Box::new(ASTNode::String(" * 2 ".to_string(), StringType::Unquoted, Span::new(1, 12))),
+            ], Span::new(1, 9))), Span::new(1, 6)),
+        ),
+        other => panic!("Expected command, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_c_style_for() {
+    match first_statement("for ((i = 0; i < 3; i++)); do echo $i; done") {
+        ASTNode::For { items: ForItems::CStyle { init, condition, update }, .. } => {
+            assert!(matches!(*init, ASTNode::ArithmeticAssignment { ref name, .. } if name == "i"));
+            assert!(matches!(*condition, ASTNode::BinaryOp { op: BinaryOperator::Less, .. }));
+            assert!(matches!(*update, ASTNode::UnaryOp { op: UnaryOperator::PostIncrement, .. }));
+        }
+        other => panic!("Expected C-style for loop, got {:?}", other),
+    }
+    
+    // An empty condition is always true
+    match first_statement("for ((;;)); do break; done") {
+        ASTNode::For { items: ForItems::CStyle { condition, .. }, .. } => {
+            assert_eq!(*condition, ASTNode::Integer(1));
+        }
+        other => panic!("Expected C-style for loop, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_for_item_forms() {
+    match first_statement("for f in $(ls *.txt); do echo $f; done") {
+        ASTNode::For { variable, items: ForItems::Command(cmd), .. } => {
+            assert_eq!(variable, "f");
+            assert!(matches!(*cmd, ASTNode::CommandSubstitution(_, _)));
+        }
+        other => panic!("Expected for over command output, got {:?}", other),
+    }
+    
+    // Without `in`, the loop runs over "$@"
+    match first_statement("for arg\ndo\n  echo $arg\ndone") {
+        ASTNode::For { variable, items: ForItems::List(items), .. } => {
+            assert_eq!(variable, "arg");
+            assert_eq!(items, vec![Box::new(ASTNode::Quoted(vec![Box::new(ASTNode::Variable("@".to_string(), Span::new(1, 1)))], Span::new(1, 1)))]);
+        }
+        other => panic!("Expected for loop, got {:?}", other),
+    }
+}
+
+fn test_expression(input: &str) -> (ASTNode, bool) {
+    match first_statement(input) {
+        ASTNode::Test { expr, extended, .. } => (*expr, extended),
+        other => panic!("Expected test for {}, got {:?}", input, other),
+    }
+}
+
+fn word(text: &str, line: usize, column: usize) -> ASTNode {
+    ASTNode::String(text.to_string(), StringType::Unquoted, Span::new(line, column))
+}
+
+#[test]
+fn test_parse_test_command() {
+    assert_eq!(
+        test_expression("[ -f file.txt ]"),
+        (ASTNode::UnaryOp { op: UnaryOperator::FileRegular, operand: Box::new(word("file.txt", 1, 6)) }, false),
+    );
+    
+    // -a binds tighter than -o
+    let (expr, _) = test_expression("test -n a -o ! -d b -a c -nt d");
+    assert_eq!(expr, binary(
+        ASTNode::UnaryOp { op: UnaryOperator::StringNotEmpty, operand: Box::new(word("a", 1, 9)) },
+        BinaryOperator::Or,
+        binary(
+            ASTNode::UnaryOp {
+                op: UnaryOperator::Not,
+                operand: Box::new(ASTNode::UnaryOp { op: UnaryOperator::FileDirectory, operand: Box::new(word("b", 1, 19)) }),
+            },
+            BinaryOperator::And,
+            binary(word("c", 1, 24), BinaryOperator::FileNewer, word("d", 1, 30)),
+        ),
+    ));
+    
+    // A binary comparison wins over `!` and unary operators
+    assert_eq!(test_expression("[ ! = -f ]").0, binary(word("!", 1, 3), BinaryOperator::StringEqual, word("-f", 1, 7)));
+    assert_eq!(
+        test_expression("[ $n -ge 10 ]").0,
+        binary(ASTNode::Variable("n".to_string(), Span::new(1, 3)), BinaryOperator::GreaterEqual, ASTNode::Number(10.0)),
+    );
+    // Escaped `<` and `>` are operators, not redirections
+    let quoted = |text: &str, column| ASTNode::String(text.to_string(), StringType::DoubleQuoted, Span::new(1, column));
+    assert_eq!(test_expression("[ \"abc\" \\< \"abd\" ]").0, binary(quoted("abc", 3), BinaryOperator::StringLess, quoted("abd", 12)));
+    assert_eq!(test_expression("test b \\> a").0, binary(word("b", 1, 6), BinaryOperator::StringGreater, word("a", 1, 11)));
+    
+    let mut parser = ShellParser::new("[ -f file.txt".to_string(), ShellDialect::Bash).unwrap();
+    assert!(parser.parse().is_err());
+}
+
+#[test]
+fn test_parse_extended_test() {
+    let (expr, extended) = test_expression("[[ -e $f && ( $a < $b || ! $x == y* ) ]]");
+    assert!(extended);
+    assert_eq!(expr, binary(
+        ASTNode::UnaryOp { op: UnaryOperator::FileExists, operand: Box::new(ASTNode::Variable("f".to_string(), Span::new(1, 7))) },
+        BinaryOperator::And,
+        binary(
+            binary(ASTNode::Variable("a".to_string(), Span::new(1, 15)), BinaryOperator::StringLess, ASTNode::Variable("b".to_string(), Span::new(1, 20))),
+            BinaryOperator::Or,
+            ASTNode::UnaryOp {
+                op: UnaryOperator::Not,
+                operand: Box::new(binary(ASTNode::Variable("x".to_string(), Span::new(1, 28)), BinaryOperator::StringEqual, word("y*", 1, 34))),
+            },
+        ),
+    ));
+    
+    // The regular expression keeps its parentheses and `|`
+    assert_eq!(
+        test_expression("[[ $v =~ ^(a|b)+\"$\" ]]").0,
+        binary(
+            ASTNode::Variable("v".to_string(), Span::new(1, 4)),
+            BinaryOperator::Match,
+            ASTNode::Concat(vec![
+                Box::new(word("^(a|b)+", 1, 10)),
+                Box::new(ASTNode::String("$".to_string(), StringType::DoubleQuoted, Span::new(1, 17))),
+            ], Span::new(1, 10)),
+        ),
+    );
+    
+    // Quoted operators are plain words
+    let mut parser = ShellParser::new("[[ a \"==\" b ]]".to_string(), ShellDialect::Bash).unwrap();
+    assert!(parser.parse().is_err());
+}
+
+fn command(name: &str, line: usize, column: usize) -> Box<ASTNode> {
+    Box::new(ASTNode::Command {
+        name: name.to_string(),
+        args: vec![],
+        redirections: vec![],
+        background: false,
+        span: Span::new(line, column),
+    })
+}
+
+#[test]
+fn test_parse_and_or_lists() {
+    // && and || have equal precedence and group from the left
+    assert_eq!(
+        first_statement("a && b || c"),
+        ASTNode::OrList(Box::new(ASTNode::AndList(command("a", 1, 1), command("b", 1, 6))), command("c", 1, 11)),
+    );
+    
+    // ! negates a whole pipeline and binds tighter than the list operators
+    assert_eq!(
+        first_statement("! a | b ||\n  c"),
+        ASTNode::OrList(
+            Box::new(ASTNode::Negated(Box::new(ASTNode::Pipeline(vec![command("a", 1, 3), command("b", 1, 7)])))),
+            command("c", 2, 3),
+        ),
+    );
+    
+    match first_statement("if a && ! b; then c; fi") {
+        ASTNode::If { condition, .. } => assert_eq!(
+            *condition,
+            ASTNode::AndList(command("a", 1, 4), Box::new(ASTNode::Negated(command("b", 1, 11)))),
+        ),
+        other => panic!("Expected if, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_node_spans() {
+    let input = "echo start\nif [ -f x ]; then\n    cat x | sort\nfi\nv=$(\n  date)";
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    
+    let statements = match ast.root {
+        ASTNode::Script(statements) => statements,
+        other => panic!("Expected script, got {:?}", other),
+    };
+    let spans: Vec<_> = statements.iter().map(|stmt| stmt.span()).collect();
+    assert_eq!(spans, vec![Some(Span::new(1, 1)), Some(Span::new(2, 1)), Some(Span::new(5, 1))]);
+    
+    match statements[1].as_ref() {
+        ASTNode::If { condition, then_block, .. } => {
+            assert_eq!(condition.span(), Some(Span::new(2, 4)));
+            // A pipeline starts at its first command
+            assert_eq!(then_block.span(), Some(Span::new(3, 5)));
+        }
+        other => panic!("Expected if, got {:?}", other),
+    }
+    
+    // Commands inside a substitution are located in the script
+    match statements[2].as_ref() {
+        ASTNode::Assignment { value, .. } => match value.as_ref() {
+            ASTNode::CommandSubstitution(body, _) => assert_eq!(body.span(), Some(Span::new(6, 3))),
+            other => panic!("Expected substitution, got {:?}", other),
+        },
+        other => panic!("Expected assignment, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_error_location() {
+    let error_at = |input: &str| {
+        let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+        let error = parser.parse().unwrap_err();
+        let error = error.downcast_ref::<ParseError>().expect("parse errors are located");
+        error.span
+    };
+    
+    assert_eq!(error_at("echo ok\nif true; then\n  echo x\n"), Span::new(4, 1));
+    assert_eq!(error_at("for x in a b; echo $x; done"), Span::new(1, 15));
+    assert_eq!(error_at("echo ok\nx=$(echo a;\n  if)"), Span::new(3, 5));
+}
+
+#[test]
+fn test_parse_error_recovery() {
+    let input = "echo ok\nif true; then\n  echo )\n  ls\nfi\nfi\necho \"fine\"\nwhile true; do\n  echo a\n";
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let (ast, errors) = parser.parse_with_diagnostics();
+    
+    // Each error is reported once, where the parser stopped
+    let spans: Vec<_> = errors.iter().map(|error| error.span).collect();
+    assert_eq!(spans, vec![Span::new(3, 8), Span::new(6, 1), Span::new(10, 1)]);
+    assert_eq!(errors[1].hint.as_deref(), Some("this keyword is only valid in an `if` statement"));
+    assert_eq!(errors[2].hint.as_deref(), Some("the `while` at 8:1 is not closed"));
+    
+    // The statements around the errors are kept, with error nodes in place
+    // of the ones that failed
+    let statements = match ast.root {
+        ASTNode::Script(statements) => statements,
+        other => panic!("Expected script, got {:?}", other),
+    };
+    assert_eq!(statements.len(), 5);
+    match statements[1].as_ref() {
+        ASTNode::If { then_block, .. } => match then_block.as_ref() {
+            ASTNode::Block(body) => {
+                assert!(matches!(body[0].as_ref(), ASTNode::Command { name, .. } if name == "echo"));
+                assert!(matches!(body[1].as_ref(), ASTNode::Error { span, .. } if *span == Span::new(3, 8)));
+                assert!(matches!(body[2].as_ref(), ASTNode::Command { name, .. } if name == "ls"));
+            }
+            other => panic!("Expected block, got {:?}", other),
+        },
+        other => panic!("Expected if, got {:?}", other),
+    }
+    assert!(matches!(statements[2].as_ref(), ASTNode::Error { .. }));
+    assert!(matches!(statements[3].as_ref(), ASTNode::Command { name, .. } if name == "echo"));
+    assert!(matches!(statements[4].as_ref(), ASTNode::Error { .. }));
+    
+    // `parse` fails with the first error
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let error = parser.parse().unwrap_err();
+    assert_eq!(error.downcast_ref::<ParseError>().unwrap().span, Span::new(3, 8));
+}
+
+#[test]
+fn test_parse_error_recovery_at_end() {
+    // An unterminated quote swallows the rest of the script, so the missing
+    // `fi` after it is not reported again
+    let mut parser = ShellParser::new("if true; then\n  echo \"oops\n  ls\nfi\n".to_string(), ShellDialect::Bash).unwrap();
+    let (_, errors) = parser.parse_with_diagnostics();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].span, Span::new(2, 8));
+    assert_eq!(errors[0].message, "Unterminated string");
+    assert!(errors[0].hint.is_some());
+    
+    // An error that merely ends at the end of the script doesn't hide the
+    // blocks left open around it
+    for input in ["if true; then\n  case x in\n", "if true; then\n  foo )"] {
+        let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+        let (_, errors) = parser.parse_with_diagnostics();
+        assert_eq!(errors.len(), 2, "{}", input);
+        assert_eq!(errors[1].hint.as_deref(), Some("the `if` at 1:1 is not closed"));
+    }
+    
+    // Errors inside command substitutions are located in the script
+    let mut parser = ShellParser::new("a=$(echo )\nb=$(ls; fi)\n".to_string(), ShellDialect::Bash).unwrap();
+    let (_, errors) = parser.parse_with_diagnostics();
+    let spans: Vec<_> = errors.iter().map(|error| error.span).collect();
+    assert_eq!(spans, vec![Span::new(2, 9)]);
+    assert!(errors[0].message.starts_with("in command substitution `ls; fi`"));
+}
+
+#[test]
+fn test_parse_error_messages() {
+    // Tokens are shown as they are written, and every error has a hint
+    let input = "cat <
+foo() echo
+x=$(( 1 + ))
+while true; do
+  echo a
+";
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    let (_, errors) = parser.parse_with_diagnostics();
+    let messages: Vec<_> = errors.iter().map(|error| (error.span.line, error.message.as_str())).collect();
+    assert_eq!(messages[0], (1, "Expected redirection target, found end of line"));
+    assert_eq!(messages[1], (2, "Expected the body of function `foo', found `echo'"));
+    assert_eq!(messages[3], (6, "Expected `done', found end of file"));
+    assert!(errors.iter().all(|error| error.hint.is_some()), "{:?}", errors);
+    assert_eq!(errors[3].hint.as_deref(), Some("the `while` at 4:1 is not closed"));
+}
+
+#[test]
+fn test_parse_dialect_features() {
+    let input = "function greet {\n  local name=$1\n}\nif [[ -n $x ]]; then\n  list[1]=a\nfi\necho ${list[1]}\n";
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Posix).unwrap();
+    let (_, errors) = parser.parse_with_diagnostics();
+    assert!(errors.is_empty());
+
+    let warnings: Vec<_> = parser.warnings().iter().map(|warning| (warning.span, warning.message.as_str())).collect();
+    assert_eq!(warnings, vec![
+        (Span::new(1, 1), "POSIX sh does not support the `function` keyword"),
+        (Span::new(2, 3), "POSIX sh does not support `local` variables"),
+        (Span::new(4, 4), "POSIX sh does not support `[[ ]]` tests"),
+        (Span::new(5, 3), "POSIX sh does not support arrays"),
+        // At the expansion rather than the `echo` it belongs to
+        (Span::new(7, 6), "POSIX sh does not support arrays"),
+    ]);
+    assert_eq!(parser.warnings()[0].hint.as_deref(), Some("drop `function` and write `name() { ...; }`"));
+    assert_eq!(parser.warnings()[2].hint.as_deref(), Some("use `[ ]`, and `case` to match patterns"));
+
+    // The hinted form is POSIX
+    let mut parser = ShellParser::new("greet() {\n  echo \"hi $1\"\n}\ngreet bob\n".to_string(), ShellDialect::Posix).unwrap();
+    let ast = parser.parse().unwrap();
+    assert!(parser.warnings().is_empty());
+    match &ast.root {
+        ASTNode::Script(statements) => assert!(matches!(statements[0].as_ref(), ASTNode::Function { name, .. } if name == "greet")),
+        _ => panic!("Expected script node"),
+    }
+
+    // dash has `local` but no `[[ ]]`
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Dash).unwrap();
+    parser.parse_with_diagnostics();
+    assert_eq!(parser.warnings().len(), 4);
+
+    // The same script is fine as bash
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Bash).unwrap();
+    parser.parse_with_diagnostics();
+    assert!(parser.warnings().is_empty());
+
+    // Strict mode turns the warnings into errors
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Posix).unwrap();
+    parser.set_strict_dialect(true);
+    let (_, errors) = parser.parse_with_diagnostics();
+    assert_eq!(errors.len(), 5);
+    assert!(parser.warnings().is_empty());
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Posix).unwrap();
+    parser.set_strict_dialect(true);
+    assert!(parser.parse().is_err());
+}
+
+#[test]
+fn test_parse_bashisms_in_sh() {
+    let input = "echo ${x/a/b}\necho $'a\\tb'\nsource ./lib.sh\ncat <<< \"$x\"\nmake &> log\nmake |& tee log\n\
+                 (( n++ ))\nfor ((i = 0; i < 2; i++)); do :; done\ndiff <(ls) b\n";
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Posix).unwrap();
+    parser.set_strict_dialect(true);
+    let (_, errors) = parser.parse_with_diagnostics();
+    let errors: Vec<_> = errors.iter().map(|error| (error.span.line, error.message.as_str())).collect();
+    assert_eq!(errors, vec![
+        (1, "POSIX sh does not support `${name/pattern/string}` substitutions"),
+        (2, "POSIX sh does not support `$'...'` strings"),
+        (3, "POSIX sh does not support `source`"),
+        (4, "POSIX sh does not support `<<<` here-strings"),
+        (5, "POSIX sh does not support `&>` redirections"),
+        (6, "POSIX sh does not support `|&` pipes"),
+        (7, "POSIX sh does not support `(( ))` and `let` arithmetic commands"),
+        (8, "POSIX sh does not support `(( ))` and `let` arithmetic commands"),
+        (9, "POSIX sh does not support process substitution"),
+    ]);
+    
+    // ksh has the rest, but its `|&` starts a coprocess
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Ksh).unwrap();
+    parser.parse_with_diagnostics();
+    let warnings: Vec<_> = parser.warnings().iter().map(|warning| warning.span.line).collect();
+    assert_eq!(warnings, vec![5, 6]);
+}
+
+fn parse_zsh(input: &str) -> Vec<ASTNode> {
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Zsh).unwrap();
+    match parser.parse().unwrap().root {
+        ASTNode::Script(statements) => statements.into_iter().map(|statement| *statement).collect(),
+        other => panic!("Expected script, got {:?}", other),
+    }
+}
+
+fn zsh_first_arg(input: &str) -> ASTNode {
+    match &parse_zsh(input)[0] {
+        ASTNode::Command { args, .. } => args[0].as_ref().clone(),
+        other => panic!("Expected command, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_zsh_repeat_and_anonymous_function() {
+    let statements = parse_zsh("repeat 3 echo hi\nrepeat $n do\n  ls\ndone\n() { echo $1; } a b\n() { echo x }\n");
+    match &statements[0] {
+        ASTNode::Repeat { count, body, .. } => {
+            assert_eq!(count.as_ref(), &ASTNode::Integer(3));
+            assert!(matches!(body.as_ref(), ASTNode::Block(body) if body.len() == 1));
+        }
+        other => panic!("Expected repeat, got {:?}", other),
+    }
+    assert!(matches!(&statements[1], ASTNode::Repeat { span, .. } if *span == Span::new(2, 1)));
+    match &statements[2] {
+        ASTNode::AnonymousFunction { body, args, .. } => {
+            assert!(matches!(body.as_ref(), ASTNode::Block(_)));
+            assert_eq!(args.len(), 2);
+        }
+        other => panic!("Expected anonymous function, got {:?}", other),
+    }
+    // A lone `}` closes the braces without a `;`
+    match &statements[3] {
+        ASTNode::AnonymousFunction { body, args, .. } => {
+            assert!(matches!(body.as_ref(), ASTNode::Block(body) if body.len() == 1));
+            assert!(args.is_empty());
+        }
+        other => panic!("Expected anonymous function, got {:?}", other),
+    }
+    
+    // Neither is special in bash
+    let mut parser = ShellParser::new("repeat 3 echo hi".to_string(), ShellDialect::Bash).unwrap();
+    let ast = parser.parse().unwrap();
+    assert!(matches!(&ast.root, ASTNode::Script(s) if matches!(s[0].as_ref(), ASTNode::Command { name, .. } if name == "repeat")));
+}
+
+#[test]
+fn test_parse_zsh_parameter_flags_and_subscripts() {
+    assert_eq!(
+        zsh_first_arg("echo ${(j:,:)arr}"),
+        ASTNode::ParameterExpansion {
+            name: "arr".to_string(),
+            expansion_type: ExpansionType::Flags(vec![ParameterFlag::Join(",".to_string())]),
+            span: Span::new(1, 6),
+        }
+    );
+    assert_eq!(
+        zsh_first_arg("echo ${(s.:.Uou)path}"),
+        ASTNode::ParameterExpansion {
+            name: "path".to_string(),
+            expansion_type: ExpansionType::Flags(vec![
+                ParameterFlag::Split(":".to_string()),
+                ParameterFlag::Upper,
+                ParameterFlag::Sort,
+                ParameterFlag::Unique,
+            ]),
+            span: Span::new(1, 6),
+        }
+    );
+    
+    // Subscripts are arithmetic, counting from 1
+    for input in ["echo $arr[2]", "echo ${arr[2]}"] {
+        assert_eq!(
+            zsh_first_arg(input),
+            ASTNode::ParameterExpansion {
+                name: "arr".to_string(),
+                expansion_type: ExpansionType::Element(Box::new(ASTNode::Integer(2))),
+                span: Span::new(1, 6),
+            }
+        );
+    }
+    assert_eq!(
+        zsh_first_arg("echo \"$arr[2]\""),
+        ASTNode::Quoted(vec![Box::new(ASTNode::ParameterExpansion {
+            name: "arr".to_string(),
+            expansion_type: ExpansionType::Element(Box::new(ASTNode::Integer(2))),
+            span: Span::new(1, 7),
+        })], Span::new(1, 6))
+    );
+    
+    let statements = parse_zsh("arr=(one \"two three\")");
+    match &statements[0] {
+        ASTNode::Assignment { name, value, .. } => {
+            assert_eq!(name, "arr");
+            assert!(matches!(value.as_ref(), ASTNode::Array(elements) if elements.len() == 2));
+        }
+        other => panic!("Expected assignment, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_zsh_glob_qualifiers_and_file_substitution() {
+    assert_eq!(
+        zsh_first_arg("ls *.txt(.om[1,3])"),
+        ASTNode::QualifiedGlob {
+            pattern: Box::new(ASTNode::String("*.txt".to_string(), StringType::Unquoted, Span::new(1, 4))),
+            qualifiers: vec![
+                GlobQualifier::FileType('.'),
+                GlobQualifier::Sort { key: 'm', descending: false },
+                GlobQualifier::Range(1, 3),
+            ],
+            span: Span::new(1, 4),
+        }
+    );
+    assert!(matches!(
+        zsh_first_arg("ls *(^/N)"),
+        ASTNode::QualifiedGlob { qualifiers, .. }
+            if qualifiers == vec![GlobQualifier::Not, GlobQualifier::FileType('/'), GlobQualifier::NullGlob]
+    ));
+    
+    match zsh_first_arg("diff =(ls a) b") {
+        ASTNode::FileSubstitution(body, _) => assert!(matches!(body.as_ref(), ASTNode::Block(_))),
+        other => panic!("Expected file substitution, got {:?}", other),
+    }
+}
+
+fn parse_fish(input: &str) -> Vec<ASTNode> {
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Fish).unwrap();
+    match parser.parse().unwrap().root {
+        ASTNode::Script(statements) => statements.into_iter().map(|statement| *statement).collect(),
+        other => panic!("Expected script, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_fish_blocks() {
+    let statements = parse_fish(
+        "if test -f a\n    and test -f b\n    echo both\nelse if test -f c\n    echo c\nelse\n    echo none\nend\n\
+         for f in *.txt; echo $f; end\n\
+         while true; break; end\n",
+    );
+    match &statements[0] {
+        ASTNode::If { condition, elif_blocks, else_block, span, .. } => {
+            // The `and` line continues the condition
+            assert!(matches!(condition.as_ref(), ASTNode::AndList(left, right)
+                if matches!(left.as_ref(), ASTNode::Test { .. }) && matches!(right.as_ref(), ASTNode::Test { .. })));
+            assert_eq!(elif_blocks.len(), 1);
+            assert!(else_block.is_some());
+            assert_eq!(*span, Span::new(1, 1));
+        }
+        other => panic!("Expected if, got {:?}", other),
+    }
+    match &statements[1] {
+        ASTNode::For { variable, items: ForItems::List(items), span, .. } => {
+            assert_eq!(variable, "f");
+            assert_eq!(items[0].as_ref(), &ASTNode::String("*.txt".to_string(), StringType::Unquoted, Span::new(9, 10)));
+            assert_eq!(*span, Span::new(9, 1));
+        }
+        other => panic!("Expected for, got {:?}", other),
+    }
+    assert!(matches!(&statements[2], ASTNode::While { body, .. }
+        if matches!(body.as_ref(), ASTNode::Block(b) if b[0].as_ref() == &ASTNode::Break(1))));
+}
+
+#[test]
+fn test_parse_fish_switch_and_function() {
+    let statements = parse_fish(
+        "function greet -d 'Say hi' --argument-names who\n    echo hi $who\nend\n\
+         switch $argv[1]\n    case 'a*' b\\*\n        echo ab\n    case '*'\n        echo other\nend\n",
+    );
+    match &statements[0] {
+        ASTNode::Function { name, body, .. } => {
+            assert_eq!(name, "greet");
+            match body.as_ref() {
+                ASTNode::Block(body) => assert!(matches!(body[0].as_ref(),
+                    ASTNode::Assignment { name, value, local: true, .. }
+                        if name == "who" && value.as_ref() == &ASTNode::Variable("1".to_string(), Span::new(1, 1)))),
+                other => panic!("Expected block, got {:?}", other),
+            }
+        }
+        other => panic!("Expected function, got {:?}", other),
+    }
+    match &statements[1] {
+        ASTNode::Case { expr, cases, .. } => {
+            assert_eq!(expr.as_ref(), &ASTNode::Variable("1".to_string(), Span::new(4, 8)));
+            // Quoted and escaped wildcards still match
+            assert_eq!(cases[0].patterns[0], word("a*", 5, 10));
+            assert_eq!(cases[0].patterns[1], ASTNode::Concat(vec![Box::new(word("b", 5, 15)), Box::new(word("*", 5, 16))], Span::new(5, 15)));
+            assert_eq!(cases[1].patterns, vec![word("*", 7, 10)]);
+            assert_eq!(cases[1].terminator, CaseTerminator::Break);
+        }
+        other => panic!("Expected case, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_fish_commands() {
+    let statements = parse_fish(
+        "set -gx EDITOR vim\nset -l items a \"b c\"\nset -e EDITOR\n\
+         make; and echo ok; or echo failed\nnot grep -q x file && echo missing\n\
+         echo (date +%s) \"$HOME/$argv\" 2>&1 >>log\n",
+    );
+    assert!(matches!(&statements[0], ASTNode::Assignment { name, export: true, local: false, .. } if name == "EDITOR"));
+    match &statements[1] {
+        ASTNode::Assignment { name, value, local: true, .. } => {
+            assert_eq!(name, "items");
+            assert!(matches!(value.as_ref(), ASTNode::Array(values) if values.len() == 2));
+        }
+        other => panic!("Expected assignment, got {:?}", other),
+    }
+    assert!(matches!(&statements[2], ASTNode::Command { name, .. } if name == "unset"));
+    
+    // `and` and `or` take over the statement before them
+    match &statements[3] {
+        ASTNode::OrList(left, _) => assert!(matches!(left.as_ref(), ASTNode::AndList(..))),
+        other => panic!("Expected or list, got {:?}", other),
+    }
+    assert!(matches!(&statements[4], ASTNode::AndList(left, _) if matches!(left.as_ref(), ASTNode::Negated(_))));
+    
+    match &statements[5] {
+        ASTNode::Command { args, redirections, .. } => {
+            assert!(matches!(args[0].as_ref(), ASTNode::CommandSubstitution(_, _)));
+            assert_eq!(
+                args[1].as_ref(),
+                &ASTNode::Concat(vec![
+                    Box::new(ASTNode::Variable("HOME".to_string(), Span::new(6, 18))),
+                    Box::new(ASTNode::String("/".to_string(), StringType::SingleQuoted, Span::new(6, 23))),
+                    Box::new(ASTNode::Variable("@".to_string(), Span::new(6, 24))),
+                ], Span::new(6, 17))
+            );
+            assert_eq!(redirections.len(), 2);
+            assert_eq!(redirections[0].target, RedirectionTarget::Fd(1));
+            assert!(redirections[1].append);
+        }
+        other => panic!("Expected command, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_fish_errors() {
+    let input = "if true\n    set -z x\nend\necho ok\nend\nwhile false\n";
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Fish).unwrap();
+    let (ast, errors) = parser.parse_with_diagnostics();
+    
+    let spans: Vec<_> = errors.iter().map(|error| error.span).collect();
+    assert_eq!(spans, vec![Span::new(2, 13), Span::new(5, 1), Span::new(7, 1)]);
+    assert_eq!(errors[0].message, "set: unsupported option `-z'");
+    assert_eq!(errors[1].hint.as_deref(), Some("there is no block open here"));
+    assert_eq!(errors[2].hint.as_deref(), Some("the `while` at 6:1 is not closed"));
+    assert!(matches!(&ast.root, ASTNode::Script(statements) if statements.len() == 4));
+    
+    // An unterminated substitution swallows the rest, so the missing `end`
+    // after it is not reported again
+    let mut parser = ShellParser::new("if true\n    echo (ls\nend\n".to_string(), ShellDialect::Fish).unwrap();
+    let (_, errors) = parser.parse_with_diagnostics();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].span, Span::new(2, 10));
+    assert_eq!(errors[0].message, "Unterminated command substitution");
+}
+
+fn parse_powershell(input: &str) -> Vec<ASTNode> {
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::PowerShell).unwrap();
+    match parser.parse().unwrap().root {
+        ASTNode::Script(statements) => statements.into_iter().map(|statement| *statement).collect(),
+        other => panic!("Expected script, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_powershell_blocks() {
+    let statements = parse_powershell(
+        "if ($n -gt 2 -and $name -eq 'x') {\n    Write-Host big\n}\nelseif (Test-Path $f) { exit 1 }\nelse { break }\n\
+         foreach ($item in $items) { $item }\n\
+         foreach ($i in 1..3) { }\n\
+         foreach ($i in $n..1) { }\n",
+    );
+    match &statements[0] {
+        ASTNode::If { condition, elif_blocks, else_block, span, .. } => {
+            match condition.as_ref() {
+                ASTNode::AndList(left, right) => {
+                    assert!(matches!(left.as_ref(), ASTNode::Test { expr, .. }
+                        if matches!(expr.as_ref(), ASTNode::BinaryOp { op: BinaryOperator::Greater, .. })));
+                    // Compared with text, -eq compares strings, ignoring case in `[[ ]]`
+                    assert!(matches!(right.as_ref(), ASTNode::Test { expr, extended: true, .. }
+                        if matches!(expr.as_ref(), ASTNode::BinaryOp { op: BinaryOperator::StringEqual, .. })));
+                }
+                other => panic!("Expected and list, got {:?}", other),
+            }
+            assert!(matches!(elif_blocks[0].0.as_ref(), ASTNode::Test { expr, .. }
+                if matches!(expr.as_ref(), ASTNode::UnaryOp { op: UnaryOperator::FileExists, .. })));
+            assert!(else_block.is_some());
+            assert_eq!(*span, Span::new(1, 1));
+        }
+        other => panic!("Expected if, got {:?}", other),
+    }
+    match &statements[1] {
+        ASTNode::For { variable, items: ForItems::List(items), body, span } => {
+            assert_eq!(variable, "item");
+            assert_eq!(items[0].as_ref(), &ASTNode::Variable("items".to_string(), Span::new(6, 19)));
+            // A value on its own is output
+            assert!(matches!(body.as_ref(), ASTNode::Block(b) if matches!(b[0].as_ref(), ASTNode::Command { name, .. } if name == "echo")));
+            assert_eq!(*span, Span::new(6, 1));
+        }
+        other => panic!("Expected foreach, got {:?}", other),
+    }
+    assert!(matches!(&statements[2], ASTNode::For { items: ForItems::List(items), .. } if items.len() == 3));
+    // Bounds known only at runtime count up or down with `seq`
+    match &statements[3] {
+        ASTNode::For { items: ForItems::List(items), .. } => match items[0].as_ref() {
+            ASTNode::CommandSubstitution(seq, _) => assert!(matches!(seq.as_ref(),
+                ASTNode::Command { name, args, .. } if name == "seq" && args.len() == 3
+                    && matches!(args[1].as_ref(), ASTNode::ArithmeticExpansion(_, _)))),
+            other => panic!("Expected seq, got {:?}", other),
+        },
+        other => panic!("Expected foreach, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_powershell_functions_and_assignments() {
+    let statements = parse_powershell(
+        "param([string]$Path = 'out', [switch]$Force)\n\
+         function Add-One($n) { $total = $n + 1; return $total }\n\
+         $env:MODE = \"debug-$Path\"\n$list = @('a', 'b')\n$list += 'c'\n$count++\n$x = $list[1]\n",
+    );
+    match &statements[0] {
+        ASTNode::Block(params) => {
+            // The arguments are bound when the script runs, switches by name only
+            assert!(matches!(params[0].as_ref(), ASTNode::Command { name, args, .. } if name == "param"
+                && args[0].as_ref() == &ASTNode::String("path".to_string(), StringType::SingleQuoted, Span::new(1, 15))
+                && args[1].as_ref() == &ASTNode::String("-force".to_string(), StringType::SingleQuoted, Span::new(1, 38))));
+            assert!(matches!(params[1].as_ref(), ASTNode::Assignment { name, value, local: false, .. }
+                if name == "path" && matches!(value.as_ref(), ASTNode::ParameterExpansion { name, expansion_type: ExpansionType::Default { .. }, .. }
+                    if name == "path")));
+            assert_eq!(params.len(), 2);
+        }
+        other => panic!("Expected param block, got {:?}", other),
+    }
+    match &statements[1] {
+        ASTNode::Function { name, body, .. } => {
+            assert_eq!(name, "add-one");
+            match body.as_ref() {
+                ASTNode::Block(body) => {
+                    assert!(matches!(body[0].as_ref(), ASTNode::Command { name, args, .. } if name == "param" && args.len() == 1));
+                    assert!(matches!(body[1].as_ref(), ASTNode::Assignment { name, value, local: true, .. }
+                        if name == "total" && matches!(value.as_ref(), ASTNode::ArithmeticExpansion(_, _))));
+                    assert!(matches!(body[2].as_ref(), ASTNode::Block(b) if b[1].as_ref() == &ASTNode::Return(None)));
+                }
+                other => panic!("Expected block, got {:?}", other),
+            }
+        }
+        other => panic!("Expected function, got {:?}", other),
+    }
+    assert!(matches!(&statements[2], ASTNode::Assignment { name, export: true, value, .. }
+        if name == "MODE" && matches!(value.as_ref(), ASTNode::Concat(_, _))));
+    assert!(matches!(&statements[3], ASTNode::Assignment { value, .. } if matches!(value.as_ref(), ASTNode::Array(a) if a.len() == 2)));
+    // Appending to an array keeps its items
+    match &statements[4] {
+        ASTNode::Assignment { value, .. } => match value.as_ref() {
+            ASTNode::Array(items) => assert_eq!(items[0].as_ref(), &ASTNode::Variable("list".to_string(), Span::new(5, 1))),
+            other => panic!("Expected array, got {:?}", other),
+        },
+        other => panic!("Expected assignment, got {:?}", other),
+    }
+    assert!(matches!(&statements[5], ASTNode::ArithmeticCommand(expr, _)
+        if matches!(expr.as_ref(), ASTNode::UnaryOp { op: UnaryOperator::PostIncrement, .. })));
+    // Indexes count from 0 in PowerShell and from 1 in the generated code
+    match &statements[6] {
+        ASTNode::Assignment { value, .. } => assert_eq!(
+            value.as_ref(),
+            &ASTNode::ParameterExpansion {
+                name: "list".to_string(),
+                expansion_type: ExpansionType::Element(Box::new(ASTNode::Integer(2))),
+                span: Span::new(7, 6),
+            }
+        ),
+        other => panic!("Expected assignment, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_powershell_cmdlets_and_pipelines() {
+    let statements = parse_powershell(
+        "Get-ChildItem -Recurse -Path src | Where-Object { $_ -like '*.rs' } | Select-Object -First 5\n\
+         gci . | ForEach-Object { Write-Host \"file: $_\" }\n\
+         Remove-Item build -r -Force 2>$null\n\
+         New-Item -ItemType Directory -Path out\n\
+         Write-Error \"failed\"\n\
+         git status --short\n",
+    );
+    match &statements[0] {
+        ASTNode::Pipeline(stages) => {
+            match stages[0].as_ref() {
+                ASTNode::Command { name, args, .. } => {
+                    assert_eq!(name, "ls");
+                    assert_eq!(args[0].as_ref(), &ASTNode::String("-R".to_string(), StringType::Unquoted, Span::new(1, 1)));
+                    assert_eq!(args[1].as_ref(), &ASTNode::String("src".to_string(), StringType::Unquoted, Span::new(1, 30)));
+                }
+                other => panic!("Expected command, got {:?}", other),
+            }
+            // Objects flow through the pipeline as lines read into `$_`
+            match stages[1].as_ref() {
+                ASTNode::While { condition, body, .. } => {
+                    assert!(matches!(condition.as_ref(), ASTNode::Command { name, .. } if name == "read"));
+                    assert!(matches!(body.as_ref(), ASTNode::If { condition, .. }
+                        if matches!(condition.as_ref(), ASTNode::Test { extended: true, .. })));
+                }
+                other => panic!("Expected while, got {:?}", other),
+            }
+            assert!(matches!(stages[2].as_ref(), ASTNode::Command { name, .. } if name == "head"));
+        }
+        other => panic!("Expected pipeline, got {:?}", other),
+    }
+    assert!(matches!(&statements[1], ASTNode::Pipeline(stages) if matches!(stages[1].as_ref(), ASTNode::While { .. })));
+    match &statements[2] {
+        ASTNode::Command { name, args, redirections, .. } => {
+            assert_eq!(name, "rm");
+            assert_eq!(args.len(), 3);
+            assert_eq!(redirections[0].fd, Some(2));
+            assert_eq!(redirections[0].target, RedirectionTarget::File(Box::new(ASTNode::String("/dev/null".to_string(), StringType::SingleQuoted, Span::new(3, 29)))));
+        }
+        other => panic!("Expected command, got {:?}", other),
+    }
+    assert!(matches!(&statements[3], ASTNode::Command { name, args, .. } if name == "mkdir" && args.len() == 2));
+    assert!(matches!(&statements[4], ASTNode::Command { name, redirections, .. }
+        if name == "echo" && redirections[0].target == RedirectionTarget::Fd(2)));
+    // Native commands keep their arguments
+    assert!(matches!(&statements[5], ASTNode::Command { name, args, .. }
+        if name == "git" && args[1].as_ref() == &ASTNode::String("--short".to_string(), StringType::Unquoted, Span::new(6, 12))));
+}
+
+#[test]
+fn test_parse_powershell_comparisons_and_named_arguments() {
+    let statements = parse_powershell(
+        "function f($Name, [switch]$Force) { }\nf -Name x -Force:$false\nif ($a -ceq $b) { }\nif ($a -ieq $b) { }\n",
+    );
+    match &statements[1] {
+        ASTNode::Command { name, args, .. } => {
+            assert_eq!(name, "f");
+            assert_eq!(args[0].as_ref(), &ASTNode::String("-Name".to_string(), StringType::SingleQuoted, Span::new(2, 3)));
+            // `-Name:value` stays one word
+            assert!(matches!(args[2].as_ref(), ASTNode::Concat(parts, _)
+                if parts[0].as_ref() == &ASTNode::String("-Force:".to_string(), StringType::SingleQuoted, Span::new(2, 11))));
+            assert_eq!(args.len(), 3);
+        }
+        other => panic!("Expected command, got {:?}", other),
+    }
+    // `-ceq` compares case-sensitively, outside `[[ ]]`
+    assert!(matches!(&statements[2], ASTNode::If { condition, .. }
+        if matches!(condition.as_ref(), ASTNode::Test { extended: false, .. })));
+    // The right side of `-eq` matches literally
+    match &statements[3] {
+        ASTNode::If { condition, .. } => assert!(matches!(condition.as_ref(), ASTNode::Test { expr, extended: true, .. }
+            if matches!(expr.as_ref(), ASTNode::BinaryOp { right, .. }
+                if right.as_ref() == &ASTNode::Quoted(vec![Box::new(ASTNode::Variable("b".to_string(), Span::new(4, 13)))], Span::new(4, 13))))),
+        other => panic!("Expected if, got {:?}", other),
+    }
+    
+    for (input, message) in [
+        ("$a -gt 'b'", "`-gt' compares text ignoring case, which is not supported; use `-cgt'"),
+        ("$a -clike 'b*'", "`-clike' is not supported, patterns are matched ignoring case"),
+    ] {
+        let mut parser = ShellParser::new(format!("if ({}) {{ }}\n", input), ShellDialect::PowerShell).unwrap();
+        let (_, errors) = parser.parse_with_diagnostics();
+        assert_eq!(errors[0].message, message, "{}", input);
+    }
+}
+
+#[test]
+fn test_parse_powershell_errors() {
+    let input = "try { ls } catch { }\nGet-Process | Stop-Process\nif ($a -contains 1) {\n    $h = @{ a = 1 }\n}\nWrite-Host $s.Length\n}\nfunction f {\n";
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::PowerShell).unwrap();
+    let (ast, errors) = parser.parse_with_diagnostics();
+    
+    let spans: Vec<_> = errors.iter().map(|error| error.span).collect();
+    assert_eq!(spans, vec![Span::new(1, 1), Span::new(2, 1), Span::new(3, 8), Span::new(6, 14), Span::new(7, 1), Span::new(9, 1)]);
+    assert_eq!(errors[0].message, "`try' statements are not supported");
+    assert_eq!(errors[1].message, "Unsupported cmdlet `Get-Process'");
+    assert_eq!(errors[2].message, "The `-contains' operator is not supported");
+    assert_eq!(errors[3].message, "Member access (`.Length') is not supported");
+    assert_eq!(errors[4].hint.as_deref(), Some("there is no block open here"));
+    assert_eq!(errors[5].hint.as_deref(), Some("the `{` at 8:12 is not closed"));
+    assert!(matches!(&ast.root, ASTNode::Script(statements) if statements.len() == 6));
+}
+
+fn parse_csh(input: &str) -> Vec<ASTNode> {
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Tcsh).unwrap();
+    match parser.parse().unwrap().root {
+        ASTNode::Script(statements) => statements.into_iter().map(|statement| *statement).collect(),
+        other => panic!("Expected script, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_csh_blocks() {
+    let statements = parse_csh(
+        "if ($#argv == 0) then\n    echo none\nelse if (-d $argv[1] && ! $?quiet) then\n    echo dir\nelse\n    exit 1\nendif\n\
+         foreach f (*.c $extra)\n    cc -c $f:r.c\nend\n\
+         while ($n < 10)\n    @ n++\nend\n\
+         if ($n > 2 * $m) echo big\n",
+    );
+    match &statements[0] {
+        ASTNode::If { condition, elif_blocks, else_block, span, .. } => {
+            assert!(matches!(condition.as_ref(), ASTNode::Test { expr, .. }
+                if matches!(expr.as_ref(), ASTNode::BinaryOp { left, op: BinaryOperator::StringEqual, .. }
+                    if left.as_ref() == &ASTNode::Variable("#".to_string(), Span::new(1, 5)))));
+            match elif_blocks[0].0.as_ref() {
+                ASTNode::AndList(left, right) => {
+                    assert!(matches!(left.as_ref(), ASTNode::Test { expr, .. }
+                        if matches!(expr.as_ref(), ASTNode::UnaryOp { op: UnaryOperator::FileDirectory, operand }
+                            if operand.as_ref() == &ASTNode::Variable("1".to_string(), Span::new(3, 13)))));
+                    assert!(matches!(right.as_ref(), ASTNode::Negated(test)
+                        if matches!(test.as_ref(), ASTNode::Test { expr, .. }
+                            if matches!(expr.as_ref(), ASTNode::UnaryOp { op: UnaryOperator::VariableSet, .. }))));
+                }
+                other => panic!("Expected and list, got {:?}", other),
+            }
+            assert!(else_block.is_some());
+            assert_eq!(*span, Span::new(1, 1));
+        }
+        other => panic!("Expected if, got {:?}", other),
+    }
+    match &statements[1] {
+        ASTNode::For { variable, items: ForItems::List(items), body, span } => {
+            assert_eq!(variable, "f");
+            assert_eq!(items.len(), 2);
+            assert_eq!(*span, Span::new(8, 1));
+            // `:r` drops the extension
+            assert!(matches!(body.as_ref(), ASTNode::Block(body) if matches!(body[0].as_ref(), ASTNode::Command { args, .. }
+                if matches!(args[1].as_ref(), ASTNode::Concat(parts, _) if parts[0].as_ref() == &ASTNode::ParameterExpansion {
+                    name: "f".to_string(),
+                    expansion_type: ExpansionType::RemoveSuffix(".*".to_string()),
+                    span: Span::new(9, 11),
+                }))));
+        }
+        other => panic!("Expected foreach, got {:?}", other),
+    }
+    assert!(matches!(&statements[2], ASTNode::While { condition, .. }
+        if matches!(condition.as_ref(), ASTNode::Test { expr, .. }
+            if matches!(expr.as_ref(), ASTNode::BinaryOp { op: BinaryOperator::Less, .. }))));
+    // Comparing with arithmetic is an arithmetic command
+    match &statements[3] {
+        ASTNode::If { condition, then_block, .. } => {
+            assert!(matches!(condition.as_ref(), ASTNode::ArithmeticCommand(expr, _)
+                if matches!(expr.as_ref(), ASTNode::BinaryOp { op: BinaryOperator::Greater, .. })));
+            assert!(matches!(then_block.as_ref(), ASTNode::Block(body) if matches!(body[0].as_ref(), ASTNode::Command { name, .. } if name == "echo")));
+        }
+        other => panic!("Expected if, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_csh_variables_and_switch() {
+    let statements = parse_csh(
+        "set name = world count=3 list = (a \"b c\")\nsetenv BUILD_DIR $cwd/out\n@ total += $count * 2\nset files = `ls`\n\
+         switch ($1)\n    case -v:\n    case -q*:\n        echo flag\n        breaksw\n    case 'x*':\n        echo x\n    default:\n        echo other\nendsw\n\
+         cat << EOF >& log\nhello $name\nEOF\nrepeat 3 echo hi\n",
+    );
+    match &statements[0] {
+        ASTNode::Block(assignments) => {
+            assert_eq!(assignments.len(), 3);
+            assert!(matches!(assignments[1].as_ref(), ASTNode::Assignment { name, value, .. }
+                if name == "count" && value.as_ref() == &ASTNode::Number(3.0)));
+            assert!(matches!(assignments[2].as_ref(), ASTNode::Assignment { value, .. }
+                if matches!(value.as_ref(), ASTNode::Array(values) if values.len() == 2)));
+        }
+        other => panic!("Expected assignments, got {:?}", other),
+    }
+    assert!(matches!(&statements[1], ASTNode::Assignment { name, value, export: true, .. }
+        if name == "BUILD_DIR" && matches!(value.as_ref(), ASTNode::Concat(parts, _) if parts[0].as_ref() == &ASTNode::Variable("PWD".to_string(), Span::new(2, 18)))));
+    assert!(matches!(&statements[2], ASTNode::ArithmeticCommand(assignment, _)
+        if matches!(assignment.as_ref(), ASTNode::ArithmeticAssignment { name, op: Some(BinaryOperator::Add), value }
+            if name == "total" && matches!(value.as_ref(), ASTNode::BinaryOp { op: BinaryOperator::Multiply, .. }))));
+    // Backquotes in `set` keep the words of the output
+    assert!(matches!(&statements[3], ASTNode::Assignment { value, .. }
+        if matches!(value.as_ref(), ASTNode::Array(values) if matches!(values[0].as_ref(), ASTNode::CommandSubstitution(_, _)))));
+
+    match &statements[4] {
+        ASTNode::Case { cases, .. } => {
+            assert_eq!(cases.len(), 3);
+            assert_eq!(cases[0].patterns, vec![word("-v", 6, 10), word("-q*", 7, 10)]);
+            assert_eq!(cases[0].terminator, CaseTerminator::Break);
+            assert_eq!(cases[1].patterns, vec![ASTNode::String("x*".to_string(), StringType::SingleQuoted, Span::new(10, 10))]);
+            // Without `breaksw` a case runs on into the next
+            assert_eq!(cases[1].terminator, CaseTerminator::FallThrough);
+            assert_eq!(cases[2].patterns, vec![word("*", 12, 5)]);
+        }
+        other => panic!("Expected switch, got {:?}", other),
+    }
+    match &statements[5] {
+        ASTNode::Command { name, redirections, span, .. } => {
+            assert_eq!(name, "cat");
+            assert_eq!(*span, Span::new(15, 1));
+            assert!(matches!(&redirections[0].target, RedirectionTarget::Heredoc { delimiter, content, .. }
+                if delimiter == "EOF" && matches!(content.as_ref(), ASTNode::Concat(_, _))));
+            assert_eq!(redirections[2].target, RedirectionTarget::Fd(1));
+        }
+        other => panic!("Expected command, got {:?}", other),
+    }
+    // The here-document body is skipped
+    assert!(matches!(&statements[6], ASTNode::Repeat { span, .. } if *span == Span::new(18, 1)));
+}
+
+#[test]
+fn test_parse_csh_errors() {
+    let input = "foreach f (a b\n    echo $f\nend\ngoto done\nset path = (/bin)\nif ($x) then\n    echo x\n";
+    let mut parser = ShellParser::new(input.to_string(), ShellDialect::Csh).unwrap();
+    let (ast, errors) = parser.parse_with_diagnostics();
+
+    let spans: Vec<_> = errors.iter().map(|error| error.span).collect();
+    assert_eq!(spans, vec![Span::new(1, 15), Span::new(3, 1), Span::new(4, 1), Span::new(5, 9), Span::new(8, 1)]);
+    assert_eq!(errors[0].hint.as_deref(), Some("the `(` at 1:11 is not closed"));
+    assert_eq!(errors[1].hint.as_deref(), Some("there is no `while` or `foreach` open here"));
+    assert_eq!(errors[2].message, "`goto' is not supported");
+    assert_eq!(errors[3].message, "set: the `path' list is not supported, use `setenv PATH ...' instead");
+    assert_eq!(errors[4].message, "Expected `endif', found end of script");
+    assert_eq!(errors[4].hint.as_deref(), Some("the `if` at 6:1 is not closed"));
+    assert!(matches!(&ast.root, ASTNode::Script(statements) if statements.len() == 6));
+}
+
+#[test]
+fn test_parse_background_jobs() {
+    match first_statement("sleep 10 &") {
+        ASTNode::Command { name, background, .. } => {
+            assert_eq!(name, "sleep");
+            assert!(background);
+        }
+        other => panic!("Expected command, got {:?}", other),
+    }
+    
+    // `&` applies to the whole pipeline, subshell or list before it
+    match first_statement("curl -s $url | tar xz &") {
+        ASTNode::Background(body) => match body.as_ref() {
+            ASTNode::Pipeline(stages) => {
+                assert_eq!(stages.len(), 2);
+                assert!(matches!(stages[1].as_ref(), ASTNode::Command { background: false, .. }));
+            }
+            other => panic!("Expected pipeline, got {:?}", other),
+        },
+        other => panic!("Expected background job, got {:?}", other),
+    }
+    assert!(matches!(first_statement("( sleep 1; echo done ) &"), ASTNode::Background(body)
+        if matches!(body.as_ref(), ASTNode::Subshell(_))));
+    assert!(matches!(first_statement("make && make install &"), ASTNode::Background(body)
+        if matches!(body.as_ref(), ASTNode::AndList(_, right) if matches!(right.as_ref(), ASTNode::Command { background: false, .. }))));
+}